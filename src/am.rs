@@ -0,0 +1,516 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::Local;
+use hex::encode;
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    CommitObject, IndexEntry, IndexFile, Repository, StatData, TreeEntry,
+    apply::apply_patch,
+    config::Config,
+    diff::split_lines,
+    filter::Filters,
+    ident::{Ident, parse_date},
+    merge::{ensure_index_matches, tree_of},
+    messages::tr,
+    refs::parse_hash,
+    stripspace::stripspace_str,
+};
+
+/// Where a session keeps its mails, one per file from `0001`, with `next`
+/// and `last` counting through them and `orig-head` naming the commit it
+/// started from.
+const STATE_DIR: &str = "rebase-apply";
+
+const RESOLVE_HINT: &str =
+    "hint: When you have resolved this problem, run \"mini-git am --continue\".
+hint: If you prefer to skip this patch, run \"mini-git am --skip\" instead.
+hint: To restore the original branch and stop patching, run \"mini-git am --abort\".";
+
+/// One mail taken apart: who wrote the patch and when, the commit message
+/// it carries and the patch itself.
+struct Mail {
+    author: Ident,
+    subject: String,
+    message: String,
+    patch: Vec<u8>,
+}
+
+/// Applies the patches mailed in each of `mboxes`, or read from stdin, as
+/// commits on top of HEAD that keep their author and date. A patch that
+/// does not apply stops the session with its state kept, to be picked up
+/// with `resolved` once the index holds the result, to go on with the
+/// next patch with `skip`, or to be rolled back with `abort`.
+pub fn handle_am_command(
+    mboxes: Vec<PathBuf>,
+    resolved: bool,
+    skip: bool,
+    abort: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let state = repository.git_dir.join(STATE_DIR);
+
+    if resolved || skip || abort {
+        if !state.is_dir() {
+            return Err(anyhow!(
+                "fatal: Resolve operation not in progress, we are not resuming."
+            ));
+        }
+        if abort {
+            return abort_am(repository, &state);
+        }
+
+        let (next, _) = progress(&state)?;
+        if resolved {
+            let mail = read_mail(&state, next)?;
+            let (tree, _) = repository.write_tree()?;
+            if let Some(head) = repository.resolve_head()?
+                && tree_of(repository, &head)? == tree
+            {
+                return Err(anyhow!(
+                    "No changes - did you forget to use 'mini-git update-index --add'?\n\
+If there is nothing left to stage, chances are that something else\n\
+already introduced the same changes; you might want to skip this patch.\n{}",
+                    RESOLVE_HINT
+                ));
+            }
+            println!("{}", tr!("Applying: {}", mail.subject));
+            commit_mail(repository, &mail)?;
+        } else {
+            let head = match repository.resolve_head()? {
+                Some(head) => repository.read_tree_recursive(&tree_of(repository, &head)?)?,
+                None => Vec::new(),
+            };
+            reset_hard(repository, &head)?;
+        }
+        fs::write(state.join("next"), format!("{}\n", next + 1))
+            .context("Failed to write am state")?;
+        return apply_mails(repository, &state);
+    }
+
+    if state.is_dir() {
+        return Err(anyhow!(
+            "fatal: previous rebase directory {} still exists but mbox given.",
+            state.display()
+        ));
+    }
+
+    let mut mails = Vec::new();
+    if mboxes.is_empty() {
+        let mut input = Vec::new();
+        io::stdin()
+            .read_to_end(&mut input)
+            .context("Failed to read from stdin")?;
+        mails.extend(split_mbox(&input));
+    }
+    for mbox in &mboxes {
+        let input = fs::read(mbox)
+            .with_context(|| format!("fatal: could not open '{}'", mbox.display()))?;
+        mails.extend(split_mbox(&input));
+    }
+    if mails.is_empty() {
+        return Err(anyhow!("Patch format detection failed."));
+    }
+
+    let head = repository.resolve_head()?;
+    let head_entries = match head {
+        Some(head) => repository.read_tree_recursive(&tree_of(repository, &head)?)?,
+        None => Vec::new(),
+    };
+    ensure_index_matches(repository, &head_entries, "am")?;
+
+    fs::create_dir_all(&state).context("Failed to create am state")?;
+    for (i, mail) in mails.iter().enumerate() {
+        fs::write(state.join(format!("{:04}", i + 1)), mail).context("Failed to write am state")?;
+    }
+    fs::write(state.join("next"), "1\n").context("Failed to write am state")?;
+    fs::write(state.join("last"), format!("{}\n", mails.len()))
+        .context("Failed to write am state")?;
+    if let Some(head) = head {
+        fs::write(state.join("orig-head"), format!("{}\n", encode(head)))
+            .context("Failed to write am state")?;
+        repository.write_ref("ORIG_HEAD", &head)?;
+    }
+
+    apply_mails(repository, &state)
+}
+
+/// Applies and commits the mails from `next` on, forgetting the session
+/// once the last one is in.
+fn apply_mails(repository: &Repository, state: &Path) -> Result<()> {
+    loop {
+        let (next, last) = progress(state)?;
+        if next > last {
+            return fs::remove_dir_all(state).context("Failed to remove am state");
+        }
+
+        let mail = read_mail(state, next)?;
+        if mail.patch.iter().all(u8::is_ascii_whitespace) {
+            return Err(anyhow!("Patch is empty.\n{}", RESOLVE_HINT));
+        }
+
+        println!("{}", tr!("Applying: {}", mail.subject));
+        if let Err(error) = apply_patch(repository, &mail.patch, false, false, true, false, false) {
+            return Err(anyhow!(
+                "{}\nPatch failed at {:04} {}\n{}",
+                error,
+                next,
+                mail.subject,
+                RESOLVE_HINT
+            ));
+        }
+        commit_mail(repository, &mail)?;
+
+        fs::write(state.join("next"), format!("{}\n", next + 1))
+            .context("Failed to write am state")?;
+    }
+}
+
+/// Records the index as a commit on top of HEAD, written by the author of
+/// `mail`.
+fn commit_mail(repository: &Repository, mail: &Mail) -> Result<()> {
+    let (_, tree) = repository.write_tree()?;
+    let parents: Vec<[u8; 20]> = repository.resolve_head()?.into_iter().collect();
+    let committer = Ident::committer(&Config::load(repository)?)?;
+    let commit = CommitObject::new(&mail.message, &tree, &parents, &mail.author, &committer)?;
+    let hash = repository.write_raw_object("commit", &commit.raw_content)?;
+    repository.update_head(&hash)
+}
+
+/// Puts HEAD, the index and the working tree back to where they were
+/// before the session started.
+fn abort_am(repository: &Repository, state: &Path) -> Result<()> {
+    let orig_head = state.join("orig-head");
+    if orig_head.is_file() {
+        let orig_head = parse_hash(
+            fs::read_to_string(&orig_head)
+                .context("Failed to read am state")?
+                .trim(),
+        )?;
+        let entries = repository.read_tree_recursive(&tree_of(repository, &orig_head)?)?;
+        reset_hard(repository, &entries)?;
+        repository.update_head(&orig_head)?;
+    } else {
+        // The branch was born during the session, so it goes again.
+        reset_hard(repository, &[])?;
+        if let Some(head_ref) = repository.head_ref()?
+            && repository.read_ref(&head_ref)?.is_some()
+        {
+            repository.delete_ref(&head_ref)?;
+        }
+    }
+
+    fs::remove_dir_all(state).context("Failed to remove am state")
+}
+
+/// Makes the index and the tracked files in the working tree match
+/// `target`, throwing away whatever was changed in them.
+pub fn reset_hard(repository: &Repository, target: &[TreeEntry]) -> Result<()> {
+    let work_dir = repository.work_dir();
+    for entry in repository.read_index()?.entries {
+        if entry.mode == 160000 || target.iter().any(|target| target.path == entry.path) {
+            continue;
+        }
+        let file = work_dir.join(&entry.path);
+        if file.is_file() {
+            fs::remove_file(&file)
+                .with_context(|| format!("Failed to remove {}", file.display()))?;
+        }
+    }
+
+    let mut filters = Filters::load(repository)?;
+    for entry in target {
+        if entry.mode == 160000 {
+            continue;
+        }
+        let file = work_dir.join(&entry.path);
+        let (_, content) = repository.read_raw_object(&encode(entry.sha1))?;
+        let content = filters.smudge(&entry.path, content)?;
+        if fs::read(&file).ok().as_ref() == Some(&content) {
+            continue;
+        }
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, content).with_context(|| format!("Failed to write {}", file.display()))?;
+    }
+
+    let mut index = IndexFile {
+        entries: target
+            .iter()
+            .map(|entry| IndexEntry {
+                mode: entry.mode,
+                sha1: entry.sha1,
+                path: entry.path.clone(),
+                stat: StatData::default(),
+            })
+            .collect(),
+    };
+    repository.write_index(&mut index)
+}
+
+/// The number of the mail to apply next and of the last one.
+fn progress(state: &Path) -> Result<(usize, usize)> {
+    let read = |name: &str| -> Result<usize> {
+        fs::read_to_string(state.join(name))
+            .context("Failed to read am state")?
+            .trim()
+            .parse()
+            .with_context(|| format!("fatal: corrupt am state: {name}"))
+    };
+    Ok((read("next")?, read("last")?))
+}
+
+fn read_mail(state: &Path, number: usize) -> Result<Mail> {
+    let path = state.join(format!("{number:04}"));
+    parse_mail(
+        &fs::read(&path).with_context(|| format!("fatal: could not read {}", path.display()))?,
+    )
+}
+
+/// Splits a mailbox into its mails, each without its `From ` line. Input
+/// that does not start with one is taken as a single mail.
+fn split_mbox(input: &[u8]) -> Vec<Vec<u8>> {
+    let lines = split_lines(input);
+    let mut mails: Vec<Vec<u8>> = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        // A `From ` line in a message body is told apart from one that
+        // starts a mail by the blank line before it and the header after.
+        let separator = line.starts_with(b"From ")
+            && (i == 0 || trim_line(lines[i - 1]).is_empty())
+            && lines.get(i + 1).is_some_and(|next| is_header(next));
+        if separator {
+            mails.extend(current.take());
+            current = Some(Vec::new());
+            continue;
+        }
+        match &mut current {
+            Some(mail) => mail.extend_from_slice(line),
+            None if trim_line(line).is_empty() => {}
+            None => current = Some(line.to_vec()),
+        }
+    }
+    mails.extend(current);
+
+    mails
+}
+
+/// Reads the headers of a mail for the author, date and subject, and
+/// splits its body into the rest of the message and the patch, which
+/// starts at the `---` line or, without one, at the first diff.
+fn parse_mail(mail: &[u8]) -> Result<Mail> {
+    let lines = split_lines(mail);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = String::from_utf8_lossy(trim_line(lines[i])).into_owned();
+        i += 1;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let (name, email) = header("from")
+        .and_then(parse_address)
+        .ok_or_else(|| anyhow!("Patch does not have a valid e-mail address."))?;
+    let (timestamp, timezone) = match header("date") {
+        Some(date) => parse_date(date)?,
+        None => {
+            let now = Local::now();
+            (now.timestamp(), now.format("%z").to_string())
+        }
+    };
+    let subject = clean_subject(&decode_header(header("subject").unwrap_or_default()));
+
+    let mut body = String::new();
+    let mut patch = Vec::new();
+    for (j, line) in lines[i..].iter().enumerate() {
+        let trimmed = trim_line(line);
+        if trimmed.trim_ascii_end() == b"---"
+            || trimmed.starts_with(b"diff -")
+            || trimmed.starts_with(b"Index: ")
+        {
+            patch = lines[i + j..].concat();
+            break;
+        }
+        body.push_str(&String::from_utf8_lossy(line));
+    }
+
+    Ok(Mail {
+        author: Ident {
+            name,
+            email,
+            timestamp,
+            timezone,
+        },
+        message: stripspace_str(&format!("{subject}\n\n{body}"), false),
+        subject,
+        patch,
+    })
+}
+
+/// The name and address in a `From:` header, such as
+/// `"A. U. Thor" <author@example.com>`. A bare address is its own name.
+fn parse_address(value: &str) -> Option<(String, String)> {
+    let Some((name, rest)) = value.rsplit_once('<') else {
+        let email = value.trim();
+        return (!email.is_empty()).then(|| (email.to_string(), email.to_string()));
+    };
+    let email = rest.split_once('>')?.0.trim().to_string();
+    let name = name.trim();
+    let name = match name
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => decode_header(name),
+    };
+    let name = if name.is_empty() { email.clone() } else { name };
+    Some((name, email))
+}
+
+/// Drops the `[PATCH n/m]` tags and `Re:` prefixes mailers put in front of
+/// a subject.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if let Some(rest) = subject.strip_prefix('[')
+            && let Some((_, rest)) = rest.split_once(']')
+        {
+            subject = rest.trim_start();
+        } else if subject
+            .get(..3)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("re:"))
+        {
+            subject = subject[3..].trim_start();
+        } else {
+            return subject.to_string();
+        }
+    }
+}
+
+/// Decodes the RFC 2047 encoded words in a header, in either the `q` or
+/// the `b` encoding. Whitespace between two encoded words is dropped.
+fn decode_header(value: &str) -> String {
+    let mut decoded = Vec::new();
+    let mut space = String::new();
+    let mut after_word = false;
+    let mut rest = value;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some((word, used)) = decode_word(rest) {
+            if !after_word {
+                decoded.extend_from_slice(space.as_bytes());
+            }
+            space.clear();
+            decoded.extend_from_slice(&word);
+            after_word = true;
+            rest = &rest[used..];
+            continue;
+        }
+        if c.is_whitespace() {
+            space.push(c);
+        } else {
+            decoded.extend_from_slice(space.as_bytes());
+            space.clear();
+            decoded.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            after_word = false;
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    decoded.extend_from_slice(space.as_bytes());
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Decodes the `=?charset?encoding?text?=` word `value` starts with, if it
+/// does, returning its bytes and how much of `value` it took up.
+fn decode_word(value: &str) -> Option<(Vec<u8>, usize)> {
+    let inner = value.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let text = &inner[..end];
+    let used = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+
+    let word = match encoding {
+        "q" | "Q" => {
+            let mut word = Vec::with_capacity(text.len());
+            let mut bytes = text.bytes();
+            while let Some(byte) = bytes.next() {
+                match byte {
+                    b'_' => word.push(b' '),
+                    b'=' => {
+                        let hex = [bytes.next()?, bytes.next()?];
+                        let hex = std::str::from_utf8(&hex).ok()?;
+                        word.push(u8::from_str_radix(hex, 16).ok()?);
+                    }
+                    byte => word.push(byte),
+                }
+            }
+            word
+        }
+        "b" | "B" => decode_base64(text)?,
+        _ => return None,
+    };
+    Some((word, used))
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes().filter(|&byte| byte != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Whether `line` looks like a mail header, `Name: value`.
+fn is_header(line: &[u8]) -> bool {
+    line.iter().position(|&b| b == b':').is_some_and(|colon| {
+        colon > 0
+            && line[..colon]
+                .iter()
+                .all(|&b| b.is_ascii_graphic() && b != b':')
+    })
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
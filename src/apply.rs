@@ -0,0 +1,850 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    IndexEntry, Repository, StatData, base85, decompress_content,
+    delta::apply_delta,
+    diff::split_lines,
+    exit::ExitStatus,
+    merge::{CONFLICT_MARKER_SIZE, Resolution, merge_text, read_blob_bytes},
+    path_from_bytes, stream_object,
+};
+
+/// The changes a patch makes to one file. A side that is `None` is
+/// `/dev/null`: the file is created or deleted. The blobs are the
+/// abbreviated ids from the `index <old>..<new>` line, if there was one.
+struct FilePatch {
+    old_path: Option<Vec<u8>>,
+    new_path: Option<Vec<u8>>,
+    old_mode: Option<u32>,
+    new_mode: Option<u32>,
+    old_blob: Option<String>,
+    new_blob: Option<String>,
+    hunks: Vec<PatchHunk>,
+    binary: Option<BinaryPatch>,
+}
+
+/// A `GIT binary patch`: the data that makes the new content from the old,
+/// and the data that makes the old content from the new, when the patch
+/// has it.
+struct BinaryPatch {
+    forward: Option<BinaryHunk>,
+    reverse: Option<BinaryHunk>,
+}
+
+/// One block of a binary patch, inflated.
+enum BinaryHunk {
+    /// The whole of the content it makes.
+    Literal(Vec<u8>),
+    /// A delta against the content it applies to, as in packs.
+    Delta(Vec<u8>),
+}
+
+/// How a file's patch went in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Applied {
+    /// The hunks applied as they are.
+    Directly,
+    /// The hunks did not apply, but merging the blobs they were made
+    /// between into the file did.
+    Merged,
+    /// The merge left conflict markers in the file.
+    Conflicted,
+}
+
+/// One `@@` section: the lines it expects to find, starting at line
+/// `old_start`, and the lines that replace them. Each line keeps its
+/// newline unless the patch marked it as having none.
+struct PatchHunk {
+    old_start: usize,
+    new_start: usize,
+    old: Vec<Vec<u8>>,
+    new: Vec<Vec<u8>>,
+}
+
+impl FilePatch {
+    fn reverse(self) -> Self {
+        FilePatch {
+            old_path: self.new_path,
+            new_path: self.old_path,
+            old_mode: self.new_mode,
+            new_mode: self.old_mode,
+            old_blob: self.new_blob,
+            new_blob: self.old_blob,
+            hunks: self
+                .hunks
+                .into_iter()
+                .map(|hunk| PatchHunk {
+                    old_start: hunk.new_start,
+                    new_start: hunk.old_start,
+                    old: hunk.new,
+                    new: hunk.old,
+                })
+                .collect(),
+            binary: self.binary.map(|binary| BinaryPatch {
+                forward: binary.reverse,
+                reverse: binary.forward,
+            }),
+        }
+    }
+
+    /// The path the patch is about, for messages.
+    fn name(&self) -> String {
+        let path = self.new_path.as_ref().or(self.old_path.as_ref());
+        String::from_utf8_lossy(path.map_or(&[][..], Vec::as_slice)).into_owned()
+    }
+}
+
+/// Applies the unified diff in `patch`, or read from stdin, to the working
+/// tree, with `cached` to the index only, or with `index` to both. Every
+/// file is patched in memory first, so nothing is written unless all of
+/// them apply; `check` stops there. `reverse` undoes the patch instead.
+/// With `three_way`, a file the hunks do not apply to is merged with the
+/// blobs the patch was made between instead, which may leave conflict
+/// markers in it; the status is then `Differences`.
+pub fn handle_apply_command(
+    patch: Option<PathBuf>,
+    check: bool,
+    cached: bool,
+    index: bool,
+    reverse: bool,
+    three_way: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let input = match &patch {
+        Some(path) => fs::read(path)
+            .with_context(|| format!("fatal: can't open patch '{}'", path.display()))?,
+        None => {
+            let mut input = Vec::new();
+            io::stdin()
+                .read_to_end(&mut input)
+                .context("Failed to read from stdin")?;
+            input
+        }
+    };
+
+    apply_patch(repository, &input, check, cached, index, reverse, three_way)
+}
+
+/// Applies a patch already read into memory; see `handle_apply_command`.
+pub fn apply_patch(
+    repository: &Repository,
+    input: &[u8],
+    check: bool,
+    cached: bool,
+    index: bool,
+    reverse: bool,
+    three_way: bool,
+) -> Result<ExitStatus> {
+    // Like git, a three-way apply works on the index and the working tree.
+    let index = index || three_way;
+    let mut patches = parse_patch(input)?;
+    if reverse {
+        patches = patches.into_iter().map(FilePatch::reverse).collect();
+    }
+
+    let mut index_file = repository.read_index()?;
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    for file in &patches {
+        match apply_file(
+            repository,
+            &index_file.entries,
+            file,
+            cached,
+            index,
+            three_way,
+        ) {
+            Ok((result, applied)) => results.push((file, result, applied)),
+            Err(error) => errors.push(error.to_string()),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!(errors.join("\n")));
+    }
+    if check {
+        return Ok(ExitStatus::Success);
+    }
+
+    let work_dir = repository.work_dir();
+    let mut status = ExitStatus::Success;
+    for (file, result, applied) in results {
+        let old_path = file.old_path.as_deref().map(path_from_bytes);
+        let new_path = file.new_path.as_deref().map(path_from_bytes);
+
+        match applied {
+            Applied::Directly => {}
+            Applied::Merged => eprintln!("Applied patch to '{}' cleanly.", file.name()),
+            Applied::Conflicted => {
+                eprintln!("Applied patch to '{}' with conflicts.", file.name());
+                eprintln!("U {}", file.name());
+                status = ExitStatus::Differences;
+            }
+        }
+
+        // A conflicted file keeps its index entry until it is resolved.
+        if (cached || index) && applied != Applied::Conflicted {
+            // Without a mode in the patch, the file keeps the one it had.
+            let old_mode = index_file
+                .entries
+                .iter()
+                .find(|entry| Some(&entry.path) == old_path.as_ref())
+                .map(|entry| entry.mode);
+            index_file.entries.retain(|entry| {
+                Some(&entry.path) != old_path.as_ref() && Some(&entry.path) != new_path.as_ref()
+            });
+            if let (Some(path), Some(content)) = (&new_path, &result) {
+                let mode = file.new_mode.or(old_mode).unwrap_or(100644);
+                let sha1 = repository.write_raw_object("blob", content)?;
+                index_file.entries.push(IndexEntry {
+                    mode,
+                    sha1,
+                    path: path.clone(),
+                    stat: StatData::default(),
+                });
+            }
+        }
+        if cached {
+            continue;
+        }
+
+        if let Some(path) = &old_path
+            && new_path.as_ref() != Some(path)
+        {
+            let file = work_dir.join(path);
+            fs::remove_file(&file)
+                .with_context(|| format!("error: unable to remove {}", file.display()))?;
+        }
+        if let (Some(path), Some(content)) = (new_path, result) {
+            let path = work_dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, content)
+                .with_context(|| format!("error: unable to write {}", path.display()))?;
+            if let Some(mode) = file.new_mode {
+                set_executable(&path, mode == 100755)?;
+            }
+        }
+    }
+
+    if cached || index {
+        repository.write_index(&mut index_file)?;
+    }
+
+    Ok(status)
+}
+
+/// Patches one file in memory, returning its new content, or `None` when
+/// the patch deletes it. The file is read from the index with `cached` or
+/// `index`, and with `index` the working tree must agree with it. The error
+/// for a patch that does not apply names each failing hunk by the line it
+/// expected to start at. With `three_way`, such a file is merged instead;
+/// see `merge_file`.
+fn apply_file(
+    repository: &Repository,
+    entries: &[IndexEntry],
+    file: &FilePatch,
+    cached: bool,
+    index: bool,
+    three_way: bool,
+) -> Result<(Option<Vec<u8>>, Applied)> {
+    let name = file.name();
+    let location = if cached || index {
+        "index"
+    } else {
+        "working directory"
+    };
+
+    let read_worktree = |path: &Path| -> Result<Option<Vec<u8>>> {
+        let file = repository.work_dir().join(path);
+        if !file.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(&file).with_context(|| {
+            format!("error: unable to read {}", file.display())
+        })?))
+    };
+    let read = |path: &Path| -> Result<Option<Vec<u8>>> {
+        if cached || index {
+            return entries
+                .iter()
+                .find(|entry| entry.path == path)
+                .map(|entry| Ok(repository.read_raw_object(&encode(entry.sha1))?.1))
+                .transpose();
+        }
+        read_worktree(path)
+    };
+
+    let preimage = match &file.old_path {
+        Some(path) => {
+            let path = path_from_bytes(path);
+            let preimage = read(&path)?
+                .ok_or_else(|| anyhow!("error: {}: does not exist in {}", name, location))?;
+            if index && read_worktree(&path)?.as_ref() != Some(&preimage) {
+                return Err(anyhow!("error: {}: does not match index", name));
+            }
+            preimage
+        }
+        None => {
+            let path = file
+                .new_path
+                .as_deref()
+                .map(path_from_bytes)
+                .unwrap_or_default();
+            if read(&path)?.is_some() {
+                return Err(anyhow!("error: {}: already exists in {}", name, location));
+            }
+            if index && read_worktree(&path)?.is_some() {
+                return Err(anyhow!(
+                    "error: {}: already exists in working directory",
+                    name
+                ));
+            }
+            Vec::new()
+        }
+    };
+
+    let patched = match &file.binary {
+        Some(binary) => Ok(apply_binary(file, binary, &preimage)?),
+        None => apply_hunks(&preimage, &file.hunks),
+    };
+    let (postimage, applied) = match patched {
+        Ok(postimage) => (postimage, Applied::Directly),
+        Err(failed) => {
+            let mut message = String::new();
+            for line in failed {
+                message.push_str(&format!("error: patch failed: {name}:{line}\n"));
+            }
+            if !three_way {
+                return Err(anyhow!("{}error: {}: patch does not apply", message, name));
+            }
+            merge_file(repository, file, &preimage).map_err(|error| {
+                anyhow!(
+                    "{}{}\nerror: {}: patch does not apply",
+                    message,
+                    error,
+                    name
+                )
+            })?
+        }
+    };
+
+    if file.new_path.is_none() {
+        if !postimage.is_empty() {
+            return Err(anyhow!("error: removal patch leaves file contents"));
+        }
+        return Ok((None, applied));
+    }
+
+    Ok((Some(postimage), applied))
+}
+
+/// Makes a binary file's new content. The patch must name both blobs in
+/// full, as `format-patch` does, so that the file can be checked to be the
+/// one the patch was made against, and the result to be the one it makes.
+fn apply_binary(file: &FilePatch, binary: &BinaryPatch, preimage: &[u8]) -> Result<Vec<u8>> {
+    let name = file.name();
+    let full = |id: &Option<String>| id.as_ref().filter(|id| id.len() == 40).cloned();
+    let (Some(old_blob), Some(new_blob)) = (full(&file.old_blob), full(&file.new_blob)) else {
+        return Err(anyhow!(
+            "error: cannot apply binary patch to '{}' without full index line",
+            name
+        ));
+    };
+    let Some(hunk) = &binary.forward else {
+        return Err(anyhow!(
+            "error: cannot reverse-apply a binary patch without the reverse hunk to '{}'",
+            name
+        ));
+    };
+
+    if file.old_path.is_some() && blob_id(preimage)? != old_blob {
+        return Err(anyhow!(
+            "error: the patch applies to '{}' ({}), which does not match the current contents.",
+            name,
+            old_blob
+        ));
+    }
+    let postimage = match hunk {
+        BinaryHunk::Literal(content) => content.clone(),
+        BinaryHunk::Delta(delta) => apply_delta(preimage, delta)
+            .map_err(|_| anyhow!("error: binary patch does not apply to '{}'", name))?,
+    };
+    let result = match file.new_path {
+        Some(_) => blob_id(&postimage)?,
+        None => "0".repeat(40),
+    };
+    if new_blob != result {
+        return Err(anyhow!(
+            "error: binary patch to '{}' creates incorrect result (expecting {}, got {})",
+            name,
+            new_blob,
+            result
+        ));
+    }
+
+    Ok(postimage)
+}
+
+/// The id `content` has as a blob.
+fn blob_id(content: &[u8]) -> Result<String> {
+    let hash = stream_object("blob", content.len() as u64, content, |_| Ok(()))?;
+    Ok(encode(hash))
+}
+
+/// Falls back on a three-way merge for a file the hunks did not apply to:
+/// the blob the patch was made against is the base, `current` is ours and
+/// the blob it was made into is theirs. That blob is rebuilt from the base
+/// and the hunks when the repository does not have it.
+fn merge_file(
+    repository: &Repository,
+    file: &FilePatch,
+    current: &[u8],
+) -> Result<(Vec<u8>, Applied)> {
+    let lacking =
+        || anyhow!("error: repository lacks the necessary blob to fall back on 3-way merge.");
+    let read_blob = |id: &Option<String>| -> Result<Option<Vec<u8>>> {
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        match repository.resolve_object_prefix(id)? {
+            Some(sha1) => Ok(Some(read_blob_bytes(repository, &sha1)?)),
+            None => Ok(None),
+        }
+    };
+
+    let base = read_blob(&file.old_blob)?.ok_or_else(lacking)?;
+    let theirs = match read_blob(&file.new_blob)? {
+        Some(theirs) => theirs,
+        None => apply_hunks(&base, &file.hunks).map_err(|_| lacking())?,
+    };
+
+    let (merged, conflicted) = merge_text(
+        (&base, current, &theirs),
+        "ours",
+        "theirs",
+        Resolution::Conflict,
+        CONFLICT_MARKER_SIZE,
+    );
+    let applied = if conflicted {
+        Applied::Conflicted
+    } else {
+        Applied::Merged
+    };
+    Ok((merged, applied))
+}
+
+/// Replaces the lines each hunk expects with its new ones. A hunk whose
+/// lines moved, because earlier parts of the file grew or shrank, is
+/// looked for at the closest position where they all match. On failure,
+/// returns the starting lines of the hunks that could not be placed.
+fn apply_hunks(content: &[u8], hunks: &[PatchHunk]) -> Result<Vec<u8>, Vec<usize>> {
+    let lines = split_lines(content);
+    let mut output = Vec::with_capacity(content.len());
+    let mut failed = Vec::new();
+    let mut consumed = 0;
+    let mut offset: isize = 0;
+
+    for hunk in hunks {
+        // A hunk that removes nothing names the line it inserts after.
+        let expected = if hunk.old.is_empty() {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let expected = (expected as isize + offset).max(consumed as isize) as usize;
+
+        let fits = |at: usize| {
+            at + hunk.old.len() <= lines.len()
+                && hunk
+                    .old
+                    .iter()
+                    .zip(&lines[at..])
+                    .all(|(wanted, line)| wanted == line)
+        };
+        let last = lines.len().saturating_sub(hunk.old.len());
+        let position = (0..=lines.len()).find_map(|distance| {
+            [
+                expected.checked_sub(distance),
+                expected.checked_add(distance),
+            ]
+            .into_iter()
+            .flatten()
+            .find(|&at| at >= consumed && at <= last && fits(at))
+        });
+
+        let Some(position) = position else {
+            failed.push(hunk.old_start);
+            continue;
+        };
+        for line in &lines[consumed..position] {
+            output.extend_from_slice(line);
+        }
+        for line in &hunk.new {
+            output.extend_from_slice(line);
+        }
+        consumed = position + hunk.old.len();
+        offset += position as isize - expected as isize;
+    }
+
+    if !failed.is_empty() {
+        return Err(failed);
+    }
+    for line in &lines[consumed..] {
+        output.extend_from_slice(line);
+    }
+
+    Ok(output)
+}
+
+/// Reads every file patch in a unified diff, with or without the extended
+/// `diff --git` headers. Anything that is not part of a patch, such as the
+/// message around one mailed by `format-patch`, is skipped.
+fn parse_patch(input: &[u8]) -> Result<Vec<FilePatch>> {
+    let lines = split_lines(input);
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut in_header = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = trim_newline(lines[i]);
+        i += 1;
+
+        if let Some(paths) = line.strip_prefix(b"diff --git ") {
+            let (old_path, new_path) = split_git_paths(paths).ok_or_else(|| {
+                anyhow!(
+                    "fatal: bad git diff header: {}",
+                    String::from_utf8_lossy(line)
+                )
+            })?;
+            patches.push(FilePatch {
+                old_path: Some(old_path),
+                new_path: Some(new_path),
+                old_mode: None,
+                new_mode: None,
+                old_blob: None,
+                new_blob: None,
+                hunks: Vec::new(),
+                binary: None,
+            });
+            in_header = true;
+            continue;
+        }
+
+        if line.starts_with(b"--- ") && lines.get(i).is_some_and(|next| next.starts_with(b"+++ ")) {
+            let old_path = patch_path(&line[4..]);
+            let new_path = patch_path(trim_newline(&lines[i][4..]));
+            i += 1;
+            if !in_header {
+                patches.push(FilePatch {
+                    old_path: old_path.clone(),
+                    new_path: new_path.clone(),
+                    old_mode: None,
+                    new_mode: None,
+                    old_blob: None,
+                    new_blob: None,
+                    hunks: Vec::new(),
+                    binary: None,
+                });
+            }
+            let file = patches.last_mut().expect("a patch was just started");
+            file.old_path = old_path;
+            file.new_path = new_path;
+            in_header = false;
+            continue;
+        }
+
+        if line.starts_with(b"@@ ") {
+            let file = patches.last_mut().ok_or_else(|| {
+                anyhow!(
+                    "fatal: patch fragment without header: {}",
+                    String::from_utf8_lossy(line)
+                )
+            })?;
+            in_header = false;
+            let (hunk, used) = parse_hunk(line, &lines[i..])?;
+            file.hunks.push(hunk);
+            i += used;
+            continue;
+        }
+
+        if !in_header {
+            continue;
+        }
+        let Some(file) = patches.last_mut() else {
+            continue;
+        };
+        let mode = |value: &[u8]| {
+            std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.trim().parse::<u32>().ok())
+        };
+        if let Some(value) = line.strip_prefix(b"new file mode ") {
+            file.old_path = None;
+            file.new_mode = mode(value);
+        } else if let Some(value) = line.strip_prefix(b"deleted file mode ") {
+            file.new_path = None;
+            file.old_mode = mode(value);
+        } else if let Some(value) = line.strip_prefix(b"old mode ") {
+            file.old_mode = mode(value);
+        } else if let Some(value) = line.strip_prefix(b"new mode ") {
+            file.new_mode = mode(value);
+        } else if let Some(value) = line.strip_prefix(b"index ") {
+            // `index <old>..<new> <mode>` gives the blobs and the mode
+            // of both sides.
+            let mut fields = value.split(|&b| b == b' ');
+            if let Some((old, new)) = fields
+                .next()
+                .and_then(|blobs| std::str::from_utf8(blobs).ok())
+                .and_then(|blobs| blobs.trim().split_once(".."))
+            {
+                file.old_blob = Some(old.to_string());
+                file.new_blob = Some(new.to_string());
+            }
+            if let Some(value) = fields.next() {
+                file.old_mode = file.old_mode.or(mode(value));
+                file.new_mode = file.new_mode.or(mode(value));
+            }
+        } else if line.starts_with(b"GIT binary patch") {
+            let (binary, used) = parse_binary(&lines[i..], i)?;
+            file.binary = Some(binary);
+            i += used;
+            in_header = false;
+        } else if line.starts_with(b"Binary files ") {
+            return Err(anyhow!(
+                "error: cannot apply binary patch to '{}' without full index line",
+                file.name()
+            ));
+        }
+    }
+
+    if patches.is_empty() {
+        return Err(anyhow!("error: No valid patches in input"));
+    }
+
+    Ok(patches)
+}
+
+/// Parses the `literal` or `delta` blocks that follow `GIT binary patch`,
+/// each its size, its deflated data in base 85 and a blank line. Returns
+/// the patch and how many lines it used; `line_number` is where `lines`
+/// starts, for errors.
+fn parse_binary(lines: &[&[u8]], line_number: usize) -> Result<(BinaryPatch, usize)> {
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while hunks.len() < 2 && i < lines.len() {
+        let line = trim_newline(lines[i]);
+        let corrupt = |at: usize| {
+            anyhow!(
+                "error: corrupt binary patch at line {}: {}",
+                line_number + at + 1,
+                String::from_utf8_lossy(trim_newline(lines.get(at).copied().unwrap_or_default()))
+            )
+        };
+        let (literal, size) = if let Some(size) = line.strip_prefix(b"literal ") {
+            (true, size)
+        } else if let Some(size) = line.strip_prefix(b"delta ") {
+            (false, size)
+        } else {
+            break;
+        };
+        let size: usize = std::str::from_utf8(size)
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .ok_or_else(|| corrupt(i))?;
+        i += 1;
+
+        let mut deflated = Vec::new();
+        while let Some(line) = lines.get(i).map(|line| trim_newline(line)) {
+            i += 1;
+            if line.is_empty() {
+                break;
+            }
+            deflated.extend(base85::decode_line(line).map_err(|_| corrupt(i - 1))?);
+        }
+        let data = decompress_content(&deflated).map_err(|_| corrupt(i - 1))?;
+        if data.len() != size {
+            return Err(corrupt(i - 1));
+        }
+        hunks.push(if literal {
+            BinaryHunk::Literal(data)
+        } else {
+            BinaryHunk::Delta(data)
+        });
+    }
+
+    let mut hunks = hunks.into_iter();
+    let forward = hunks
+        .next()
+        .ok_or_else(|| anyhow!("error: unrecognized binary patch at line {}", line_number))?;
+    Ok((
+        BinaryPatch {
+            forward: Some(forward),
+            reverse: hunks.next(),
+        },
+        i,
+    ))
+}
+
+/// Parses a `@@ -a,b +c,d @@` header and the lines of the hunk that follow
+/// it, returning the hunk and how many lines it used.
+fn parse_hunk(header: &[u8], lines: &[&[u8]]) -> Result<(PatchHunk, usize)> {
+    let corrupt = || {
+        anyhow!(
+            "fatal: corrupt patch at '{}'",
+            String::from_utf8_lossy(header)
+        )
+    };
+    let text = std::str::from_utf8(header).map_err(|_| corrupt())?;
+    let mut ranges = text
+        .strip_prefix("@@ ")
+        .and_then(|rest| rest.split(" @@").next())
+        .ok_or_else(corrupt)?
+        .split(' ');
+    let range = |range: Option<&str>, sign: char| -> Result<(usize, usize)> {
+        let range = range
+            .and_then(|range| range.strip_prefix(sign))
+            .ok_or_else(corrupt)?;
+        let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((
+            start.parse().map_err(|_| corrupt())?,
+            count.parse().map_err(|_| corrupt())?,
+        ))
+    };
+    let (old_start, mut old_left) = range(ranges.next(), '-')?;
+    let (new_start, mut new_left) = range(ranges.next(), '+')?;
+
+    let mut hunk = PatchHunk {
+        old_start,
+        new_start,
+        old: Vec::new(),
+        new: Vec::new(),
+    };
+    // Which sides the previous line went to, for `\ No newline`.
+    let mut last = (false, false);
+    let mut used = 0;
+
+    while used < lines.len() {
+        let line = lines[used];
+        if line.starts_with(b"\\") {
+            if last.0
+                && let Some(previous) = hunk.old.last_mut()
+            {
+                strip_newline(previous);
+            }
+            if last.1
+                && let Some(previous) = hunk.new.last_mut()
+            {
+                strip_newline(previous);
+            }
+            used += 1;
+            continue;
+        }
+        if old_left == 0 && new_left == 0 {
+            break;
+        }
+
+        // Some editors strip the space off empty context lines.
+        let (kind, content) = match line.split_first() {
+            Some((&b'\n', _)) => (b' ', line),
+            Some((&kind, content)) => (kind, content),
+            None => break,
+        };
+        match kind {
+            b' ' if old_left > 0 && new_left > 0 => {
+                hunk.old.push(content.to_vec());
+                hunk.new.push(content.to_vec());
+                old_left -= 1;
+                new_left -= 1;
+                last = (true, true);
+            }
+            b'-' if old_left > 0 => {
+                hunk.old.push(content.to_vec());
+                old_left -= 1;
+                last = (true, false);
+            }
+            b'+' if new_left > 0 => {
+                hunk.new.push(content.to_vec());
+                new_left -= 1;
+                last = (false, true);
+            }
+            _ => return Err(corrupt()),
+        }
+        used += 1;
+    }
+
+    if old_left > 0 || new_left > 0 {
+        return Err(corrupt());
+    }
+
+    Ok((hunk, used))
+}
+
+/// Splits the `a/<old> b/<new>` of a `diff --git` line. When the two names
+/// are the same, which they are unless the file was renamed, the split is
+/// found even if the name itself contains ` b/`.
+fn split_git_paths(paths: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let paths = paths.strip_prefix(b"a/")?;
+    if paths.len() % 2 == 1 {
+        let half = paths.len() / 2;
+        let (old, new) = (&paths[..half], &paths[half..]);
+        if new.strip_prefix(b" b/") == Some(old) {
+            return Some((old.to_vec(), old.to_vec()));
+        }
+    }
+
+    let split = paths.windows(3).position(|window| window == b" b/")?;
+    Some((paths[..split].to_vec(), paths[split + 3..].to_vec()))
+}
+
+/// The path on a `---` or `+++` line without its leading directory, as
+/// `-p1` strips it, or `None` for `/dev/null`.
+fn patch_path(value: &[u8]) -> Option<Vec<u8>> {
+    // A tab separates an optional timestamp from the name.
+    let value = value.split(|&b| b == b'\t').next().unwrap_or_default();
+    if value == b"/dev/null" {
+        return None;
+    }
+    let stripped = value
+        .iter()
+        .position(|&b| b == b'/')
+        .map_or(value, |slash| &value[slash + 1..]);
+    Some(stripped.to_vec())
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+fn strip_newline(line: &mut Vec<u8>) {
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    let mode = if executable {
+        permissions.mode() | 0o111
+    } else {
+        permissions.mode() & !0o111
+    };
+    permissions.set_mode(mode);
+    fs::set_permissions(path, permissions)?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> Result<()> {
+    Ok(())
+}
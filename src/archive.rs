@@ -0,0 +1,448 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use flate2::{
+    Compression,
+    write::{DeflateEncoder, GzEncoder},
+};
+use hex::encode;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use crate::{Repository, refs::parse_hash, walk::commit_time};
+
+/// The size of a tar block; headers and contents are padded to it.
+const BLOCK: usize = 512;
+/// Tar archives are padded to a whole record of 20 blocks, as tar does.
+const RECORD: usize = 20 * BLOCK;
+/// Permission bits taken away from every entry, like git's default
+/// `tar.umask`.
+const UMASK: u32 = 0o002;
+
+pub struct ArchiveOptions {
+    /// `tar`, `tgz` or `zip`; guessed from the output file's name when not
+    /// given, and `tar` otherwise.
+    pub format: Option<String>,
+    /// Prepended to every path in the archive.
+    pub prefix: String,
+    /// Where to write the archive instead of stdout.
+    pub output: Option<PathBuf>,
+}
+
+enum Format {
+    Tar,
+    Tgz,
+    Zip,
+}
+
+/// What the tree-ish resolved to: the tree to archive, and when it came
+/// from a commit, that commit, whose id is recorded in the archive.
+struct Source {
+    tree: [u8; 20],
+    commit: Option<[u8; 20]>,
+    mtime: i64,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Directory,
+    File { executable: bool },
+    Symlink,
+}
+
+/// One path to put in the archive. Files and symlinks are read from
+/// `hash` as they are written.
+struct Entry {
+    path: String,
+    kind: Kind,
+    hash: [u8; 20],
+}
+
+pub fn handle_archive_command(
+    tree_ish: String,
+    options: ArchiveOptions,
+    repository: &Repository,
+) -> Result<()> {
+    let name = options
+        .output
+        .as_ref()
+        .map(|output| output.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let format = match options.format.as_deref() {
+        Some("tar") => Format::Tar,
+        Some("tgz" | "tar.gz") => Format::Tgz,
+        Some("zip") => Format::Zip,
+        Some(format) => return Err(anyhow!("fatal: Unknown archive format '{}'", format)),
+        None if name.ends_with(".zip") => Format::Zip,
+        None if name.ends_with(".tgz") || name.ends_with(".tar.gz") => Format::Tgz,
+        None => Format::Tar,
+    };
+
+    let source = resolve_tree_ish(repository, &tree_ish)?;
+
+    let mut entries = Vec::new();
+    if let Some(directory) = options.prefix.strip_suffix('/')
+        && !directory.is_empty()
+    {
+        entries.push(Entry {
+            path: options.prefix.clone(),
+            kind: Kind::Directory,
+            hash: [0u8; 20],
+        });
+    }
+    collect_entries(repository, &source.tree, &options.prefix, &mut entries)?;
+
+    let mut output: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).with_context(|| {
+            format!("fatal: could not create archive file '{}'", path.display())
+        })?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    match format {
+        Format::Tar => write_tar(repository, &source, &entries, &mut output)?,
+        Format::Tgz => {
+            let mut gzip = GzEncoder::new(&mut output, Compression::default());
+            write_tar(repository, &source, &entries, &mut gzip)?;
+            gzip.finish()?;
+        }
+        Format::Zip => write_zip(repository, &source, &entries, &mut output)?,
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Peels `name` through any tags to a commit or tree. Entries are dated by
+/// the commit when there is one, and by the current time otherwise.
+fn resolve_tree_ish(repository: &Repository, name: &str) -> Result<Source> {
+    let mut hash = repository.resolve_commitish(name)?;
+
+    loop {
+        let (object_type, content) = repository.read_raw_object(&encode(hash))?;
+        match object_type.as_str() {
+            "tag" => {
+                let target = String::from_utf8_lossy(&content)
+                    .lines()
+                    .find_map(|line| line.strip_prefix("object ").map(str::to_string))
+                    .ok_or_else(|| anyhow!("fatal: tag {} has no object", encode(hash)))?;
+                hash = parse_hash(&target)?;
+            }
+            "commit" => {
+                let commit = repository.read_commit(&hash)?;
+                return Ok(Source {
+                    tree: parse_hash(&commit.tree_hash()?)?,
+                    commit: Some(hash),
+                    mtime: commit_time(&commit),
+                });
+            }
+            "tree" => {
+                return Ok(Source {
+                    tree: hash,
+                    commit: None,
+                    mtime: Local::now().timestamp(),
+                });
+            }
+            _ => return Err(anyhow!("fatal: not a tree object: {}", encode(hash))),
+        }
+    }
+}
+
+/// Lists the tree at `tree` depth first, each directory before what it
+/// holds. Submodules appear as empty directories.
+fn collect_entries(
+    repository: &Repository,
+    tree: &[u8; 20],
+    prefix: &str,
+    entries: &mut Vec<Entry>,
+) -> Result<()> {
+    for entry in repository.read_tree(tree)? {
+        let path = format!("{prefix}{}", entry.path.display());
+
+        match entry.mode {
+            40000 => {
+                entries.push(Entry {
+                    path: format!("{path}/"),
+                    kind: Kind::Directory,
+                    hash: entry.sha1,
+                });
+                collect_entries(repository, &entry.sha1, &format!("{path}/"), entries)?;
+            }
+            160000 => entries.push(Entry {
+                path: format!("{path}/"),
+                kind: Kind::Directory,
+                hash: entry.sha1,
+            }),
+            120000 => entries.push(Entry {
+                path,
+                kind: Kind::Symlink,
+                hash: entry.sha1,
+            }),
+            mode => entries.push(Entry {
+                path,
+                kind: Kind::File {
+                    executable: mode == 100755,
+                },
+                hash: entry.sha1,
+            }),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_content(repository: &Repository, entry: &Entry) -> Result<Vec<u8>> {
+    match entry.kind {
+        Kind::Directory => Ok(Vec::new()),
+        _ => Ok(repository.read_raw_object(&encode(entry.hash))?.1),
+    }
+}
+
+/// Writes a POSIX tar archive. A commit's id goes in a global pax header,
+/// and paths or link targets too long for the ustar fields go in pax
+/// headers of their own.
+fn write_tar(
+    repository: &Repository,
+    source: &Source,
+    entries: &[Entry],
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut written = 0;
+
+    if let Some(commit) = source.commit {
+        let records = pax_record("comment", &encode(commit));
+        written += write_tar_entry(
+            out,
+            "pax_global_header",
+            0o666,
+            b'g',
+            source.mtime,
+            "",
+            records.as_bytes(),
+        )?;
+    }
+
+    for entry in entries {
+        let content = read_content(repository, entry)?;
+        let (mode, typeflag, link) = match entry.kind {
+            Kind::Directory => (0o777, b'5', String::new()),
+            Kind::File { executable: true } => (0o777, b'0', String::new()),
+            Kind::File { executable: false } => (0o666, b'0', String::new()),
+            Kind::Symlink => (0o777, b'2', String::from_utf8_lossy(&content).to_string()),
+        };
+
+        let mut records = String::new();
+        if entry.path.len() > 100 {
+            records.push_str(&pax_record("path", &entry.path));
+        }
+        if link.len() > 100 {
+            records.push_str(&pax_record("linkpath", &link));
+        }
+        if !records.is_empty() {
+            written += write_tar_entry(
+                out,
+                &format!("{}.paxheader", encode(entry.hash)),
+                0o666,
+                b'x',
+                source.mtime,
+                "",
+                records.as_bytes(),
+            )?;
+        }
+
+        let data: &[u8] = match entry.kind {
+            Kind::File { .. } => &content,
+            _ => &[],
+        };
+        written += write_tar_entry(
+            out,
+            &entry.path,
+            mode & !UMASK,
+            typeflag,
+            source.mtime,
+            &link,
+            data,
+        )?;
+    }
+
+    // Two empty blocks end the archive, which is then padded to a record.
+    let end = written + 2 * BLOCK;
+    out.write_all(&vec![0u8; end.next_multiple_of(RECORD) - written])?;
+
+    Ok(())
+}
+
+/// Writes one ustar header and its padded data, returning the number of
+/// bytes written. Fields too long for the header are cut short; the pax
+/// header written before it holds them whole.
+fn write_tar_entry(
+    out: &mut impl Write,
+    path: &str,
+    mode: u32,
+    typeflag: u8,
+    mtime: i64,
+    link: &str,
+    data: &[u8],
+) -> Result<usize> {
+    let mut header = [0u8; BLOCK];
+    let mut field = |offset: usize, length: usize, value: &[u8]| {
+        let length = value.len().min(length);
+        header[offset..offset + length].copy_from_slice(&value[..length]);
+    };
+
+    field(0, 100, path.as_bytes());
+    field(100, 8, format!("{mode:07o}").as_bytes());
+    field(108, 8, b"0000000");
+    field(116, 8, b"0000000");
+    field(124, 12, format!("{:011o}", data.len()).as_bytes());
+    field(136, 12, format!("{:011o}", mtime.max(0)).as_bytes());
+    field(148, 8, b"        ");
+    field(156, 1, &[typeflag]);
+    field(157, 100, link.as_bytes());
+    field(257, 6, b"ustar\0");
+    field(263, 2, b"00");
+    field(265, 32, b"root");
+    field(297, 32, b"root");
+    field(329, 8, b"0000000");
+    field(337, 8, b"0000000");
+
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{checksum:07o}\0").as_bytes());
+
+    out.write_all(&header)?;
+    out.write_all(data)?;
+    let padding = data.len().next_multiple_of(BLOCK) - data.len();
+    out.write_all(&vec![0u8; padding])?;
+
+    Ok(BLOCK + data.len() + padding)
+}
+
+/// A pax record, `<length> <key>=<value>\n`, where the length counts the
+/// whole record including its own digits.
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while (length.to_string().len() + rest) != length {
+        length = length.to_string().len() + rest;
+    }
+
+    format!("{length} {key}={value}\n")
+}
+
+/// Writes a zip archive, deflating file contents when that makes them
+/// smaller. Unix modes go in the external attributes and a commit's id in
+/// the archive comment.
+fn write_zip(
+    repository: &Repository,
+    source: &Source,
+    entries: &[Entry],
+    out: &mut impl Write,
+) -> Result<()> {
+    const VERSION_MADE_BY: u16 = 3 << 8 | 20;
+
+    let (time, date) = dos_date_time(source.mtime);
+    let mut central = Vec::new();
+    let mut offset: u32 = 0;
+
+    for entry in entries {
+        let content = read_content(repository, entry)?;
+        let crc = crc32fast::hash(&content);
+
+        let (mode, attributes): (u32, u32) = match entry.kind {
+            Kind::Directory => (0o040755, 0x10),
+            Kind::File { executable: true } => (0o100755, 0),
+            Kind::File { executable: false } => (0o100644, 0),
+            Kind::Symlink => (0o120777, 0),
+        };
+
+        let mut deflated = Vec::new();
+        if matches!(entry.kind, Kind::File { .. }) && !content.is_empty() {
+            let mut encoder = DeflateEncoder::new(&mut deflated, Compression::default());
+            encoder.write_all(&content)?;
+            encoder.finish()?;
+        }
+        let (method, version_needed, data): (u16, u16, &[u8]) =
+            if !deflated.is_empty() && deflated.len() < content.len() {
+                (8, 20, &deflated)
+            } else {
+                (0, 10, &content)
+            };
+
+        let name = entry.path.as_bytes();
+        // Bit 11 marks a name as UTF-8.
+        let flags: u16 = if entry.path.is_ascii() { 0 } else { 1 << 11 };
+        let sizes = || -> Result<(u32, u32, u16)> {
+            Ok((
+                u32::try_from(data.len())?,
+                u32::try_from(content.len())?,
+                u16::try_from(name.len())?,
+            ))
+        };
+        let (compressed_size, size, name_length) =
+            sizes().with_context(|| format!("fatal: '{}' is too large for zip", entry.path))?;
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        local.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        local.extend_from_slice(&version_needed.to_le_bytes());
+        local.extend_from_slice(&flags.to_le_bytes());
+        local.extend_from_slice(&method.to_le_bytes());
+        local.extend_from_slice(&time.to_le_bytes());
+        local.extend_from_slice(&date.to_le_bytes());
+        local.extend_from_slice(&crc.to_le_bytes());
+        local.extend_from_slice(&compressed_size.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&name_length.to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(name);
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        central.extend_from_slice(&local[4..28]);
+        central.extend_from_slice(&[0u8; 8]);
+        central.extend_from_slice(&(mode << 16 | attributes).to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name);
+
+        out.write_all(&local)?;
+        out.write_all(data)?;
+        offset = u32::try_from(local.len() + data.len())
+            .ok()
+            .and_then(|length| offset.checked_add(length))
+            .ok_or_else(|| anyhow!("fatal: archive is too large for zip"))?;
+    }
+
+    let comment = source.commit.map(encode).unwrap_or_default();
+    let count =
+        u16::try_from(entries.len()).map_err(|_| anyhow!("fatal: too many entries for zip"))?;
+
+    out.write_all(&central)?;
+    let mut end = Vec::with_capacity(22 + comment.len());
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    end.extend_from_slice(&[0u8; 4]);
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&offset.to_le_bytes());
+    end.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    end.extend_from_slice(comment.as_bytes());
+    out.write_all(&end)?;
+
+    Ok(())
+}
+
+/// The MS-DOS time and date fields zip uses, in local time. Dates before
+/// 1980 cannot be represented and are clamped to its start.
+fn dos_date_time(timestamp: i64) -> (u16, u16) {
+    let Some(time) = Local.timestamp_opt(timestamp, 0).single() else {
+        return (0, 0x21);
+    };
+    if time.year() < 1980 {
+        return (0, 0x21);
+    }
+
+    let dos_time = ((time.hour() << 11) | (time.minute() << 5) | (time.second() / 2)) as u16;
+    let dos_date = ((((time.year() - 1980) as u32) << 9) | (time.month() << 5) | time.day()) as u16;
+    (dos_time, dos_date)
+}
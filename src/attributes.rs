@@ -0,0 +1,337 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use crate::{Repository, config::Config, wildmatch::wildmatch};
+
+/// The macro every repository has: a binary file is neither diffed, merged
+/// nor converted as text.
+const BUILTIN_MACROS: &str = "[attr]binary -diff -merge -text";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrState {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified,
+}
+
+impl AttrState {
+    /// The state as `check-attr` prints it.
+    fn describe(&self) -> &str {
+        match self {
+            AttrState::Set => "set",
+            AttrState::Unset => "unset",
+            AttrState::Value(value) => value,
+            AttrState::Unspecified => "unspecified",
+        }
+    }
+}
+
+struct AttrRule {
+    pattern: String,
+    base_dir: PathBuf,
+    attrs: Vec<(String, AttrState)>,
+}
+
+impl AttrRule {
+    fn matches(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.base_dir) else {
+            return false;
+        };
+        let relative = relative.to_string_lossy();
+
+        match self.pattern.strip_prefix('/') {
+            Some(anchored) => wildmatch(anchored, &relative, true),
+            None if self.pattern.contains('/') => wildmatch(&self.pattern, &relative, true),
+            None => {
+                let basename = relative.rsplit('/').next().unwrap_or_default();
+                wildmatch(&self.pattern, basename, true)
+            }
+        }
+    }
+}
+
+/// A `[attr]<name>` line, kept as written for the warning given when it
+/// is found where macros are not allowed.
+struct AttrMacro {
+    name: String,
+    attrs: Vec<(String, AttrState)>,
+    line_number: usize,
+    text: String,
+}
+
+/// The rules read from one attributes file, the macros it defines and
+/// every attribute it names, in order.
+#[derive(Default)]
+struct AttrFile {
+    rules: Vec<AttrRule>,
+    macros: Vec<AttrMacro>,
+    names: Vec<String>,
+}
+
+/// Resolves `.gitattributes` rules for paths in the working tree. Rules from
+/// deeper directories take precedence over their parents and over
+/// `core.attributesFile`, and `.mini-git/info/attributes` overrides
+/// everything.
+///
+/// A line `[attr]<name> <attrs>...` defines a macro: a path given `<name>`
+/// also gets each of `<attrs>`. Macros may only be defined in the top-level
+/// `.gitattributes`, `core.attributesFile` and `info/attributes`.
+pub struct Attributes {
+    work_dir: PathBuf,
+    global_rules: Vec<AttrRule>,
+    info_rules: Vec<AttrRule>,
+    dir_rules: HashMap<PathBuf, Vec<AttrRule>>,
+    macros: HashMap<String, Vec<(String, AttrState)>>,
+    /// Attribute names in the order they were first read, which is the
+    /// order `check-attr --all` lists them in.
+    names: Vec<String>,
+}
+
+impl Attributes {
+    pub fn load(repository: &Repository) -> Result<Self> {
+        let work_dir = repository.work_dir();
+        let config = Config::load(repository)?;
+
+        let mut macros = HashMap::new();
+        let mut names = Vec::new();
+        let mut define = |file: AttrFile| {
+            for name in file.names {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            for definition in file.macros {
+                macros.insert(definition.name, definition.attrs);
+            }
+            file.rules
+        };
+        define(parse_rules(BUILTIN_MACROS, Path::new("")));
+        let global_rules = match config.get_path("core.attributesFile") {
+            Some(path) => define(read_rules(&path, Path::new(""))?),
+            None => Vec::new(),
+        };
+        let root_rules = define(read_rules(&work_dir.join(".gitattributes"), Path::new(""))?);
+        let info_rules = define(read_rules(
+            &repository.mini_git_dir.join("info").join("attributes"),
+            Path::new(""),
+        )?);
+
+        Ok(Attributes {
+            work_dir,
+            global_rules,
+            info_rules,
+            dir_rules: HashMap::from([(PathBuf::new(), root_rules)]),
+            macros,
+            names,
+        })
+    }
+
+    pub fn get(&mut self, path: &Path, name: &str) -> Result<AttrState> {
+        Ok(self
+            .all(path)?
+            .into_iter()
+            .find(|(attr, _)| attr == name)
+            .map_or(AttrState::Unspecified, |(_, state)| state))
+    }
+
+    /// Every attribute the rules give `path`, macros expanded, in the
+    /// order their names were first read.
+    pub fn all(&mut self, path: &Path) -> Result<Vec<(String, AttrState)>> {
+        let mut dirs = vec![PathBuf::new()];
+        for ancestor in path.ancestors().skip(1) {
+            if !ancestor.as_os_str().is_empty() {
+                dirs.insert(1, ancestor.to_path_buf());
+            }
+        }
+
+        for dir in &dirs {
+            if !self.dir_rules.contains_key(dir) {
+                let source = dir.join(".gitattributes");
+                let file = read_rules(&self.work_dir.join(&source), dir)?;
+                for definition in file.macros {
+                    eprintln!(
+                        "{} not allowed: {}:{}",
+                        definition.text,
+                        source.display(),
+                        definition.line_number
+                    );
+                }
+                for name in file.names {
+                    if !self.names.contains(&name) {
+                        self.names.push(name);
+                    }
+                }
+                self.dir_rules.insert(dir.clone(), file.rules);
+            }
+        }
+
+        let sources = std::iter::once(&self.global_rules)
+            .chain(dirs.iter().filter_map(|dir| self.dir_rules.get(dir)))
+            .chain(std::iter::once(&self.info_rules));
+
+        let mut states = Vec::new();
+        for rules in sources {
+            for rule in rules.iter().filter(|rule| rule.matches(path)) {
+                for (attr, state) in &rule.attrs {
+                    self.assign(&mut states, attr, state, &mut Vec::new());
+                }
+            }
+        }
+        states.sort_by_key(|(attr, _)| self.names.iter().position(|name| name == attr));
+
+        Ok(states)
+    }
+
+    /// Gives `name` its `state`, and when it is a macro being set, the
+    /// attributes it stands for. `expanding` holds the macros being
+    /// expanded, so one that refers back to itself stops there.
+    fn assign<'a>(
+        &'a self,
+        states: &mut Vec<(String, AttrState)>,
+        name: &'a str,
+        state: &AttrState,
+        expanding: &mut Vec<&'a str>,
+    ) {
+        match states.iter_mut().find(|(attr, _)| attr == name) {
+            Some((_, current)) => *current = state.clone(),
+            None => states.push((name.to_string(), state.clone())),
+        }
+
+        if *state != AttrState::Set || expanding.contains(&name) {
+            return;
+        }
+        if let Some(expansion) = self.macros.get(name) {
+            expanding.push(name);
+            for (attr, state) in expansion {
+                self.assign(states, attr, state, expanding);
+            }
+            expanding.pop();
+        }
+    }
+}
+
+/// Prints `<path>: <attr>: <state>` for each of `attrs` on each of `paths`,
+/// or on the paths read from stdin. With `all`, every attribute a path has
+/// is listed instead. As in git, without `--` the first argument is the
+/// attribute and the rest are paths.
+pub fn handle_check_attr_command(
+    mut attrs: Vec<String>,
+    mut paths: Vec<String>,
+    all: bool,
+    stdin: bool,
+    repository: &Repository,
+) -> Result<()> {
+    if paths.is_empty() && !stdin {
+        if all {
+            paths = std::mem::take(&mut attrs);
+        } else if !attrs.is_empty() {
+            paths = attrs.split_off(1);
+        }
+    }
+    if stdin {
+        for line in io::stdin().lock().lines() {
+            paths.push(line.context("Failed to read from stdin")?);
+        }
+    }
+    if all && !attrs.is_empty() {
+        return Err(anyhow!("error: Attributes and --all both specified"));
+    }
+    if !all && attrs.is_empty() {
+        return Err(anyhow!("error: No attribute specified"));
+    }
+    if paths.is_empty() {
+        return Err(anyhow!("error: No file specified"));
+    }
+
+    let mut attributes = Attributes::load(repository)?;
+    for path in &paths {
+        let states = attributes.all(Path::new(path))?;
+        if all {
+            for (attr, state) in &states {
+                if *state != AttrState::Unspecified {
+                    println!("{path}: {attr}: {}", state.describe());
+                }
+            }
+            continue;
+        }
+        for attr in &attrs {
+            let state = states
+                .iter()
+                .find(|(name, _)| name == attr)
+                .map_or(&AttrState::Unspecified, |(_, state)| state);
+            println!("{path}: {attr}: {}", state.describe());
+        }
+    }
+
+    Ok(())
+}
+
+fn read_rules(file: &Path, base_dir: &Path) -> Result<AttrFile> {
+    if !file.is_file() {
+        return Ok(AttrFile::default());
+    }
+
+    let content = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read attributes file {}", file.display()))?;
+
+    Ok(parse_rules(&content, base_dir))
+}
+
+fn parse_rules(content: &str, base_dir: &Path) -> AttrFile {
+    let mut file = AttrFile::default();
+    for (i, line) in content.lines().map(str::trim).enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next() else {
+            continue;
+        };
+        let attrs: Vec<(String, AttrState)> = fields.map(parse_attr).collect();
+
+        let name = pattern.strip_prefix("[attr]");
+        for name in name
+            .into_iter()
+            .chain(attrs.iter().map(|(name, _)| name.as_str()))
+        {
+            if !file.names.iter().any(|known| known == name) {
+                file.names.push(name.to_string());
+            }
+        }
+
+        match name {
+            Some(name) => file.macros.push(AttrMacro {
+                name: name.to_string(),
+                attrs,
+                line_number: i + 1,
+                text: line.to_string(),
+            }),
+            None => file.rules.push(AttrRule {
+                pattern: pattern.to_string(),
+                base_dir: base_dir.to_path_buf(),
+                attrs,
+            }),
+        }
+    }
+
+    file
+}
+
+fn parse_attr(field: &str) -> (String, AttrState) {
+    if let Some(name) = field.strip_prefix('-') {
+        (name.to_string(), AttrState::Unset)
+    } else if let Some(name) = field.strip_prefix('!') {
+        (name.to_string(), AttrState::Unspecified)
+    } else if let Some((name, value)) = field.split_once('=') {
+        (name.to_string(), AttrState::Value(value.to_string()))
+    } else {
+        (field.to_string(), AttrState::Set)
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::{Result, anyhow};
+
+/// The digits of git's base 85, in order of value.
+const ALPHABET: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// How many bytes one line of a binary patch holds at most.
+const LINE_BYTES: usize = 52;
+
+/// Encodes `data` as the lines of a binary patch: each starts with a letter
+/// giving how many bytes it holds, `A` to `Z` for 1 to 26 and `a` to `z`
+/// for 27 to 52, followed by five characters for every four bytes.
+pub fn encode_lines(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 5 / 4 + data.len() / LINE_BYTES * 2 + 8);
+    for line in data.chunks(LINE_BYTES) {
+        out.push(match line.len() {
+            n @ 1..=26 => b'A' + n as u8 - 1,
+            n => b'a' + n as u8 - 27,
+        });
+        for group in line.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..group.len()].copy_from_slice(group);
+            let mut value = u32::from_be_bytes(word);
+            let mut digits = [0u8; 5];
+            for digit in digits.iter_mut().rev() {
+                *digit = ALPHABET[(value % 85) as usize];
+                value /= 85;
+            }
+            out.extend_from_slice(&digits);
+        }
+        out.push(b'\n');
+    }
+    out
+}
+
+/// Decodes one line written by `encode_lines`, without its newline.
+pub fn decode_line(line: &[u8]) -> Result<Vec<u8>> {
+    let corrupt = || anyhow!("corrupt base85 line");
+    let (&length, digits) = line.split_first().ok_or_else(corrupt)?;
+    let length = match length {
+        b'A'..=b'Z' => (length - b'A') as usize + 1,
+        b'a'..=b'z' => (length - b'a') as usize + 27,
+        _ => return Err(corrupt()),
+    };
+    if digits.len() != length.div_ceil(4) * 5 {
+        return Err(corrupt());
+    }
+
+    let mut data = Vec::with_capacity(length.div_ceil(4) * 4);
+    for group in digits.chunks(5) {
+        let mut value: u32 = 0;
+        for &digit in group {
+            let digit = ALPHABET
+                .iter()
+                .position(|&c| c == digit)
+                .ok_or_else(corrupt)?;
+            value = value
+                .checked_mul(85)
+                .and_then(|value| value.checked_add(digit as u32))
+                .ok_or_else(corrupt)?;
+        }
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.truncate(length);
+    Ok(data)
+}
@@ -0,0 +1,98 @@
+use anyhow::{Result, anyhow};
+use hex::encode;
+
+use crate::{
+    Repository, merge::is_ancestor, messages::tr, refs::check_ref_format,
+    transport::short_ref_name, worktree::checked_out,
+};
+
+/// Lists the local branches, marking the one HEAD is on with `*`. A
+/// detached HEAD is listed first, as `(HEAD detached at <commit>)`.
+///
+/// With `delete`, `args` are branches to delete, which must be merged into
+/// HEAD unless `force` is set. Otherwise `args` is `<name> [<start>]` and a
+/// branch is created at the start commit, or HEAD.
+pub fn handle_branch_command(
+    args: Vec<String>,
+    delete: bool,
+    force: bool,
+    repository: &Repository,
+) -> Result<()> {
+    if delete {
+        if args.is_empty() {
+            return Err(anyhow!("fatal: branch name required"));
+        }
+        return args
+            .iter()
+            .try_for_each(|name| delete_branch(repository, name, force));
+    }
+
+    let (name, start) = match args.as_slice() {
+        [] => return list_branches(repository),
+        [name] => (name, "HEAD"),
+        [name, start] => (name, start.as_str()),
+        _ => return Err(anyhow!("fatal: too many arguments")),
+    };
+    let ref_name = format!("refs/heads/{name}");
+    if !check_ref_format(&ref_name) {
+        return Err(anyhow!("fatal: '{name}' is not a valid branch name"));
+    }
+    if repository.read_ref(&ref_name)?.is_some() {
+        return Err(anyhow!("fatal: a branch named '{name}' already exists"));
+    }
+
+    repository.write_ref(&ref_name, &repository.resolve_commitish(start)?)
+}
+
+fn list_branches(repository: &Repository) -> Result<()> {
+    let current = repository.head_ref()?;
+    if current.is_none()
+        && let Some(head) = repository.resolve_head()?
+    {
+        println!("* (HEAD detached at {})", &encode(head)[..7]);
+    }
+
+    for (name, _) in repository.list_refs()? {
+        if !name.starts_with("refs/heads/") {
+            continue;
+        }
+        let marker = if current.as_deref() == Some(name.as_str()) {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{marker} {}", short_ref_name(&name));
+    }
+
+    Ok(())
+}
+
+fn delete_branch(repository: &Repository, name: &str, force: bool) -> Result<()> {
+    let ref_name = format!("refs/heads/{name}");
+    let tip = repository
+        .read_ref(&ref_name)?
+        .ok_or_else(|| anyhow!("error: branch '{name}' not found."))?;
+
+    if let Some(path) = checked_out(repository, &ref_name, None)? {
+        return Err(anyhow!(
+            "error: cannot delete branch '{name}' used by worktree at '{}'",
+            path.display()
+        ));
+    }
+    if !force
+        && let Some(head) = repository.resolve_head()?
+        && !is_ancestor(repository, &tip, &head)?
+    {
+        return Err(anyhow!(
+            "error: the branch '{name}' is not fully merged\n\
+hint: If you are sure you want to delete it, run 'mini-git branch -D {name}'"
+        ));
+    }
+
+    repository.delete_ref(&ref_name)?;
+    println!(
+        "{}",
+        tr!("Deleted branch {} (was {}).", name, &encode(tip)[..7])
+    );
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Subcommand;
+use hex::encode;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashSet,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Repository,
+    messages::{tr, tr_n},
+    pack::{PackOptions, unpack_pack, write_pack},
+    refs::parse_hash,
+    transport::reachable_objects,
+};
+
+const SIGNATURE_V2: &str = "# v2 git bundle";
+const SIGNATURE_V3: &str = "# v3 git bundle";
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommands {
+    /// Write the refs and objects named by the revisions to a bundle file
+    Create {
+        /// Include every ref, and HEAD
+        #[arg(long)]
+        all: bool,
+        file: PathBuf,
+        /// Refs to include, `^<rev>` to leave out what a revision reaches,
+        /// or `<from>..<to>`
+        revisions: Vec<String>,
+    },
+    /// Check that a bundle is intact and can be unbundled here
+    Verify { file: PathBuf },
+    /// List the refs a bundle contains
+    ListHeads {
+        file: PathBuf,
+        /// Only list these refs
+        refs: Vec<String>,
+    },
+}
+
+/// A bundle file: the refs it carries, the commits a repository must
+/// already have to use it, and a pack of everything else.
+pub struct Bundle {
+    path: PathBuf,
+    pub refs: Vec<(String, [u8; 20])>,
+    /// Commits the pack was cut off at, each with the comment recorded
+    /// alongside it, usually its subject line.
+    pub prerequisites: Vec<([u8; 20], String)>,
+    data: Vec<u8>,
+    pack_offset: usize,
+}
+
+pub fn handle_bundle_command(command: BundleCommands, repository: &Repository) -> Result<()> {
+    match command {
+        BundleCommands::Create {
+            all,
+            file,
+            revisions,
+        } => create_bundle(repository, &file, &revisions, all),
+        BundleCommands::Verify { file } => {
+            let bundle = Bundle::open(&file)?;
+            bundle.verify(repository)?;
+
+            println!(
+                "{}",
+                tr_n!(
+                    bundle.refs.len(),
+                    "The bundle contains this ref:",
+                    "The bundle contains these {} refs:",
+                    bundle.refs.len()
+                )
+            );
+            for (name, hash) in &bundle.refs {
+                println!("{} {name}", encode(hash));
+            }
+            if bundle.prerequisites.is_empty() {
+                println!("{}", tr!("The bundle records a complete history."));
+            } else {
+                println!(
+                    "{}",
+                    tr_n!(
+                        bundle.prerequisites.len(),
+                        "The bundle requires this ref:",
+                        "The bundle requires these {} refs:",
+                        bundle.prerequisites.len()
+                    )
+                );
+                for (hash, _) in &bundle.prerequisites {
+                    println!("{}", encode(hash));
+                }
+            }
+            println!("{}", tr!("The bundle uses this hash algorithm: {}", "sha1"));
+            eprintln!("{}", tr!("{} is okay", file.display()));
+            Ok(())
+        }
+        BundleCommands::ListHeads { file, refs } => {
+            let bundle = Bundle::open(&file)?;
+            for (name, hash) in &bundle.refs {
+                if refs.is_empty() || refs.iter().any(|wanted| name.ends_with(wanted.as_str())) {
+                    println!("{} {name}", encode(hash));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Whether `path` is a bundle file rather than a repository.
+pub fn is_bundle(path: &Path) -> bool {
+    let mut signature = [0u8; SIGNATURE_V2.len() + 1];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| {
+            signature == format!("{SIGNATURE_V2}\n").as_bytes()
+                || signature == format!("{SIGNATURE_V3}\n").as_bytes()
+        })
+}
+
+impl Bundle {
+    /// Reads the bundle at `path`: the signature line, then any
+    /// capabilities, `-<hash> <comment>` prerequisites and `<hash> <ref>`
+    /// lines, and after a blank line the pack.
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = fs::read(path)
+            .with_context(|| format!("fatal: could not open '{}'", path.display()))?;
+        let not_a_bundle = || {
+            anyhow!(
+                "fatal: '{}' does not look like a v2 or v3 bundle file",
+                path.display()
+            )
+        };
+
+        let mut refs = Vec::new();
+        let mut prerequisites = Vec::new();
+        let mut offset = 0;
+        let mut first = true;
+
+        loop {
+            let end = data[offset..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or_else(not_a_bundle)?;
+            let line = String::from_utf8_lossy(&data[offset..offset + end]).to_string();
+            offset += end + 1;
+
+            if std::mem::take(&mut first) {
+                if line != SIGNATURE_V2 && line != SIGNATURE_V3 {
+                    return Err(not_a_bundle());
+                }
+                continue;
+            }
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some(capability) = line.strip_prefix('@') {
+                match capability.split_once('=') {
+                    Some(("object-format", "sha1")) => {}
+                    _ => {
+                        return Err(anyhow!(
+                            "fatal: unsupported bundle capability '{}'",
+                            capability
+                        ));
+                    }
+                }
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                let (hash, comment) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+                prerequisites.push((parse_hash(hash)?, comment.to_string()));
+            } else {
+                let (hash, name) = line.split_once(' ').ok_or_else(not_a_bundle)?;
+                refs.push((name.to_string(), parse_hash(hash)?));
+            }
+        }
+
+        Ok(Bundle {
+            path: path.to_path_buf(),
+            refs,
+            prerequisites,
+            data,
+            pack_offset: offset,
+        })
+    }
+
+    /// The branch the bundle's `HEAD` points to, judged by which branch it
+    /// records at the same commit, since bundles do not store symbolic refs.
+    pub fn head_ref(&self) -> Option<String> {
+        let (_, head) = self.refs.iter().find(|(name, _)| name == "HEAD")?;
+        self.refs
+            .iter()
+            .find(|(name, hash)| name.starts_with("refs/heads/") && hash == head)
+            .map(|(name, _)| name.clone())
+    }
+
+    /// Checks that the pack is intact and that `repository` has every
+    /// prerequisite commit.
+    pub fn verify(&self, repository: &Repository) -> Result<()> {
+        let pack = &self.data[self.pack_offset..];
+        if pack.len() < 20 || Sha1::digest(&pack[..pack.len() - 20])[..] != pack[pack.len() - 20..]
+        {
+            return Err(anyhow!(
+                "fatal: pack in bundle '{}' is corrupt",
+                self.path.display()
+            ));
+        }
+
+        let mut missing = String::new();
+        for (hash, comment) in &self.prerequisites {
+            if !repository.has_object(hash)? {
+                missing.push_str(&format!("\nerror: {} {comment}", encode(hash)));
+            }
+        }
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "error: Repository lacks these prerequisite commits:{}",
+                missing
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Stores the bundle's objects in `repository` unless it already has
+    /// all of `wants`. Returns the number of objects stored.
+    pub fn fetch_objects(&self, repository: &Repository, wants: &[[u8; 20]]) -> Result<usize> {
+        let mut missing = false;
+        for want in wants {
+            missing |= !repository.has_object(want)?;
+        }
+        if !missing {
+            return Ok(0);
+        }
+
+        self.verify(repository)?;
+        let (objects, _) = unpack_pack(repository, &self.data[self.pack_offset..], false)?;
+        Ok(objects)
+    }
+}
+
+/// Writes a bundle of the refs named among `revisions` and every object
+/// they reach, less what the `^<rev>` exclusions reach. The commits where
+/// the history is cut off are recorded as prerequisites.
+fn create_bundle(
+    repository: &Repository,
+    file: &Path,
+    revisions: &[String],
+    all: bool,
+) -> Result<()> {
+    let mut refs = Vec::new();
+    let mut tips = Vec::new();
+    let mut excluded = Vec::new();
+
+    if all {
+        if let Some(head) = repository.resolve_head()? {
+            refs.push(("HEAD".to_string(), head));
+        }
+        refs.extend(repository.list_refs()?);
+    }
+
+    for revision in revisions {
+        if let Some(revision) = revision.strip_prefix('^') {
+            excluded.push(repository.resolve_commitish(revision)?);
+        } else if let Some((from, to)) = revision.split_once("..") {
+            let or_head = |name: &'_ str| {
+                if name.is_empty() {
+                    "HEAD".to_string()
+                } else {
+                    name.to_string()
+                }
+            };
+            excluded.push(repository.resolve_commitish(&or_head(from))?);
+            add_revision(repository, &or_head(to), &mut refs, &mut tips)?;
+        } else {
+            add_revision(repository, revision, &mut refs, &mut tips)?;
+        }
+    }
+
+    if refs.is_empty() {
+        return Err(anyhow!("fatal: Refusing to create empty bundle."));
+    }
+    tips.extend(refs.iter().map(|(_, hash)| *hash));
+
+    let shared: HashSet<[u8; 20]> = reachable_objects(repository, &excluded, |_| Ok(false))?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    let objects = reachable_objects(repository, &tips, |hash| Ok(shared.contains(hash)))?;
+    if objects.is_empty() {
+        return Err(anyhow!("fatal: Refusing to create empty bundle."));
+    }
+
+    let mut prerequisites = Vec::new();
+    for (hash, path) in &objects {
+        if path.is_some() || repository.read_raw_object(&encode(hash))?.0 != "commit" {
+            continue;
+        }
+        for parent in repository.read_commit(hash)?.parents()? {
+            if shared.contains(&parent) && !prerequisites.contains(&parent) {
+                prerequisites.push(parent);
+            }
+        }
+    }
+
+    let mut bundle = format!("{SIGNATURE_V2}\n");
+    for prerequisite in &prerequisites {
+        let message = repository.read_commit(prerequisite)?.message();
+        let subject = message.lines().next().unwrap_or_default();
+        bundle.push_str(&format!("-{} {subject}\n", encode(prerequisite)));
+    }
+    for (name, hash) in &refs {
+        bundle.push_str(&format!("{} {name}\n", encode(hash)));
+    }
+    bundle.push('\n');
+
+    let mut data = bundle.into_bytes();
+    write_pack(repository, &objects, &PackOptions::default(), &mut data)?;
+    fs::write(file, data).with_context(|| format!("fatal: cannot create '{}'", file.display()))?;
+
+    Ok(())
+}
+
+/// Adds a positive revision: a ref is recorded under its full name, while
+/// a bare commit only contributes its objects.
+fn add_revision(
+    repository: &Repository,
+    revision: &str,
+    refs: &mut Vec<(String, [u8; 20])>,
+    tips: &mut Vec<[u8; 20]>,
+) -> Result<()> {
+    if revision == "HEAD" {
+        refs.push(("HEAD".to_string(), repository.resolve_commitish("HEAD")?));
+        return Ok(());
+    }
+
+    for candidate in [
+        format!("refs/heads/{revision}"),
+        format!("refs/tags/{revision}"),
+        revision.to_string(),
+    ] {
+        if let Some(hash) = repository.read_ref(&candidate)? {
+            if !refs.iter().any(|(name, _)| *name == candidate) {
+                refs.push((candidate, hash));
+            }
+            return Ok(());
+        }
+    }
+
+    tips.push(repository.resolve_commitish(revision)?);
+    Ok(())
+}
@@ -0,0 +1,842 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Args;
+use hex::encode;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    IndexEntry, Repository, StatData, base85, compress_content,
+    config::Config,
+    diff::unified_diff,
+    exit::ExitStatus,
+    filter::Filters,
+    hash_content,
+    log::{is_broken_pipe, print_commit, print_tag},
+    messages::tr_n,
+    notes::Notes,
+    path_bytes, path_from_bytes,
+    profile::{self, Phase},
+    refs::parse_hash,
+    signature::SignatureCache,
+    upload_pack::peel_tag,
+};
+
+// Output modes shared by the commands that print changes between trees.
+// Not a doc comment: clap would take it as the help of every command that
+// flattens these in.
+#[derive(Args, Debug, Clone, Copy)]
+pub struct DiffOutputArgs {
+    /// Show only the names of changed files
+    #[arg(long, conflicts_with = "name_status")]
+    pub name_only: bool,
+    /// Show the names and status letters of changed files
+    #[arg(long, conflicts_with = "stat")]
+    pub name_status: bool,
+    /// Show how many lines changed in each file
+    #[arg(long, conflicts_with = "name_only")]
+    pub stat: bool,
+    /// Terminate paths with NUL instead of newline
+    #[arg(short = 'z')]
+    pub nul_terminated: bool,
+    /// Write binary files as patches that apply, with full object ids
+    #[arg(long)]
+    pub binary: bool,
+}
+
+/// A path whose mode or content differs between two sides of a comparison.
+pub struct FileChange {
+    /// The path as stored in the tree or index, which need not be UTF-8.
+    pub path: Vec<u8>,
+    pub old: Option<(u32, [u8; 20])>,
+    pub new: Option<(u32, [u8; 20])>,
+}
+
+impl FileChange {
+    pub fn status(&self) -> char {
+        match (self.old, self.new) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            // Modes are stored as their octal digits, so the thousands
+            // carry the object type.
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode / 1000 != new_mode / 1000 => 'T',
+            _ => 'M',
+        }
+    }
+
+    /// The path as printed, with bytes that are not UTF-8 replaced.
+    pub fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.path)
+    }
+}
+
+pub type FileMap = BTreeMap<Vec<u8>, (u32, [u8; 20])>;
+
+/// Lists every blob under `tree` by full path, descending into subtrees.
+pub fn tree_files(repository: &Repository, tree: &[u8; 20]) -> Result<FileMap> {
+    let mut files = FileMap::new();
+    let mut pending = vec![(Vec::new(), *tree)];
+
+    while let Some((prefix, tree)) = pending.pop() {
+        for entry in repository.read_tree(&tree)? {
+            let mut path = prefix.clone();
+            path.extend_from_slice(&path_bytes(&entry.path));
+            if entry.mode == 40000 {
+                path.push(b'/');
+                pending.push((path, entry.sha1));
+            } else {
+                files.insert(path, (entry.mode, entry.sha1));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+pub fn index_files(repository: &Repository) -> Result<FileMap> {
+    Ok(repository
+        .read_index()?
+        .entries
+        .into_iter()
+        .map(|entry| {
+            (
+                path_bytes(&entry.path).into_owned(),
+                (entry.mode, entry.sha1),
+            )
+        })
+        .collect())
+}
+
+/// Hashes the working tree copy of every indexed path, cleaned as it would
+/// be stored, so that it can be compared with the index. Files missing from
+/// disk are left out, and files whose stat data still matches what the
+/// index recorded keep the index's hash without being read.
+pub fn worktree_files(repository: &Repository, index: &FileMap) -> Result<FileMap> {
+    let work_dir = repository.work_dir();
+    let mut filters = Filters::load(repository)?;
+    let mut files = FileMap::new();
+
+    let recorded: BTreeMap<Vec<u8>, IndexEntry> = repository
+        .read_index()?
+        .entries
+        .into_iter()
+        .map(|entry| (path_bytes(&entry.path).into_owned(), entry))
+        .collect();
+    let index_mtime = match fs::metadata(&repository.index_file) {
+        Ok(metadata) => StatData::of(&metadata).mtime,
+        Err(_) => (0, 0),
+    };
+
+    for (path, (mode, hash)) in index {
+        let file = work_dir.join(path_from_bytes(path));
+        if *mode == 160000 {
+            // A submodule stands for whatever its HEAD is at; one that was
+            // never checked out is taken to be where the index says.
+            let head = match Repository::open(&file) {
+                Ok(submodule) => submodule.resolve_head()?,
+                Err(_) => None,
+            };
+            files.insert(path.clone(), (*mode, head.unwrap_or(*hash)));
+            continue;
+        }
+        let metadata = match fs::metadata(&file) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        if let Some(entry) = recorded.get(path)
+            && (entry.mode, entry.sha1) == (*mode, *hash)
+            && entry.stat.unchanged(&StatData::of(&metadata), index_mtime)
+        {
+            files.insert(path.clone(), (*mode, *hash));
+            continue;
+        }
+        let content = {
+            let _span = profile::span(Phase::DiskIo);
+            fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?
+        };
+        let content = filters.clean(&path_from_bytes(path), content)?;
+        files.insert(path.clone(), (*mode, blob_hash(&content)));
+    }
+
+    Ok(files)
+}
+
+pub fn blob_hash(content: &[u8]) -> [u8; 20] {
+    let mut full_content = format!("blob {}\0", content.len()).into_bytes();
+    full_content.extend_from_slice(content);
+    hash_content(&full_content)
+}
+
+pub fn compare(old: &FileMap, new: &FileMap) -> Vec<FileChange> {
+    let mut paths: Vec<&Vec<u8>> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .into_iter()
+        .filter(|path| old.get(*path) != new.get(*path))
+        .map(|path| FileChange {
+            path: path.clone(),
+            old: old.get(path).copied(),
+            new: new.get(path).copied(),
+        })
+        .collect()
+}
+
+/// Prints `changes` in the format selected by `args`: a list of names, names
+/// with status letters, or a full patch.
+pub fn write_changes(
+    repository: &Repository,
+    changes: &[FileChange],
+    args: DiffOutputArgs,
+    out: &mut impl Write,
+) -> Result<()> {
+    if args.stat {
+        return write_stat(repository, changes, out);
+    }
+
+    let terminator = if args.nul_terminated { '\0' } else { '\n' };
+
+    for change in changes {
+        if args.name_only {
+            write!(out, "{}{terminator}", change.name())?;
+        } else if args.name_status {
+            let separator = if args.nul_terminated { '\0' } else { '\t' };
+            write!(
+                out,
+                "{}{separator}{}{terminator}",
+                change.status(),
+                change.name()
+            )?;
+        } else {
+            write_patch(repository, change, args.binary, out)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints one file's patch. With `binary`, the object ids are written in
+/// full and a binary file gets a `GIT binary patch` that `apply` can use
+/// rather than a note that it differs.
+fn write_patch(
+    repository: &Repository,
+    change: &FileChange,
+    binary: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let path = change.name();
+    writeln!(out, "diff --git a/{path} b/{path}")?;
+
+    let zero = [0u8; 20];
+    let (old_mode, old_hash) = change.old.unwrap_or((0, zero));
+    let (new_mode, new_hash) = change.new.unwrap_or((0, zero));
+    let abbreviated = |hash: [u8; 20]| {
+        let hash = encode(hash);
+        if binary { hash } else { hash[..7].to_string() }
+    };
+
+    match (change.old, change.new) {
+        (None, _) => writeln!(out, "new file mode {new_mode}")?,
+        (_, None) => writeln!(out, "deleted file mode {old_mode}")?,
+        _ if old_mode != new_mode => {
+            writeln!(out, "old mode {old_mode}")?;
+            writeln!(out, "new mode {new_mode}")?;
+        }
+        _ => {}
+    }
+
+    if old_hash == new_hash {
+        return Ok(());
+    }
+
+    if old_mode == new_mode {
+        writeln!(
+            out,
+            "index {}..{} {}",
+            abbreviated(old_hash),
+            abbreviated(new_hash),
+            old_mode
+        )?;
+    } else {
+        writeln!(
+            out,
+            "index {}..{}",
+            abbreviated(old_hash),
+            abbreviated(new_hash)
+        )?;
+    }
+
+    let old_content = match change.old {
+        Some((mode, hash)) => read_content(repository, &change.path, mode, &hash)?,
+        None => Vec::new(),
+    };
+    let new_content = match change.new {
+        Some((mode, hash)) => read_content(repository, &change.path, mode, &hash)?,
+        None => Vec::new(),
+    };
+
+    let old_name = match change.old {
+        Some(_) => format!("a/{path}"),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match change.new {
+        Some(_) => format!("b/{path}"),
+        None => "/dev/null".to_string(),
+    };
+
+    if old_content.contains(&0) || new_content.contains(&0) {
+        if binary {
+            // The data to go forward, then the data to go back.
+            writeln!(out, "GIT binary patch")?;
+            write_literal(&new_content, out)?;
+            write_literal(&old_content, out)?;
+        } else {
+            writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+        }
+        return Ok(());
+    }
+
+    writeln!(out, "--- {old_name}")?;
+    writeln!(out, "+++ {new_name}")?;
+    out.write_all(&unified_diff(&old_content, &new_content))?;
+
+    Ok(())
+}
+
+/// Prints one `literal` block of a binary patch: the size of `content`,
+/// then its deflated bytes in base 85, and a blank line.
+fn write_literal(content: &[u8], out: &mut impl Write) -> Result<()> {
+    writeln!(out, "literal {}", content.len())?;
+    out.write_all(&base85::encode_lines(&compress_content(content)?))?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Prints a diffstat: one line per file with its number of changed lines
+/// and a bar of `+` and `-`, scaled to fit in 80 columns, then a summary.
+fn write_stat(repository: &Repository, changes: &[FileChange], out: &mut impl Write) -> Result<()> {
+    enum Stat {
+        Text(usize, usize),
+        Binary(usize, usize),
+    }
+
+    let mut stats = Vec::new();
+    for change in changes {
+        let old = match change.old {
+            Some((mode, hash)) => read_content(repository, &change.path, mode, &hash)?,
+            None => Vec::new(),
+        };
+        let new = match change.new {
+            Some((mode, hash)) => read_content(repository, &change.path, mode, &hash)?,
+            None => Vec::new(),
+        };
+
+        if old.contains(&0) || new.contains(&0) {
+            stats.push(Stat::Binary(old.len(), new.len()));
+            continue;
+        }
+
+        let patch = unified_diff(&old, &new);
+        let count = |sign: u8| {
+            patch
+                .split(|&byte| byte == b'\n')
+                .filter(|line| line.first() == Some(&sign))
+                .count()
+        };
+        stats.push(Stat::Text(count(b'+'), count(b'-')));
+    }
+
+    let name_width = changes
+        .iter()
+        .map(|change| change.name().len())
+        .max()
+        .unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .map(|stat| match stat {
+            Stat::Text(added, removed) => added + removed,
+            Stat::Binary(..) => 0,
+        })
+        .max()
+        .unwrap_or(0);
+    let count_width = if stats.iter().any(|stat| matches!(stat, Stat::Binary(..))) {
+        max_change.to_string().len().max(3)
+    } else {
+        max_change.to_string().len()
+    };
+    let graph_width = 80usize.saturating_sub(name_width + count_width + 6).max(1);
+    let scale = |value: usize| {
+        if max_change <= graph_width || value == 0 {
+            value
+        } else {
+            1 + value * (graph_width - 1) / max_change
+        }
+    };
+
+    let (mut insertions, mut deletions) = (0, 0);
+    for (change, stat) in changes.iter().zip(&stats) {
+        let line = match stat {
+            Stat::Text(added, removed) => {
+                insertions += added;
+                deletions += removed;
+
+                let total = scale(added + removed);
+                let minus = scale(*removed).min(total);
+                format!(
+                    " {:<name_width$} | {:>count_width$} {}{}",
+                    change.name(),
+                    added + removed,
+                    "+".repeat(total - minus),
+                    "-".repeat(minus)
+                )
+            }
+            Stat::Binary(old, new) => format!(
+                " {:<name_width$} | {:<count_width$} {} -> {} bytes",
+                change.name(),
+                "Bin",
+                old,
+                new
+            ),
+        };
+        writeln!(out, "{}", line.trim_end())?;
+    }
+
+    let mut summary = tr_n!(
+        changes.len(),
+        " {} file changed",
+        " {} files changed",
+        changes.len()
+    );
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&tr_n!(
+            insertions,
+            ", {} insertion(+)",
+            ", {} insertions(+)",
+            insertions
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&tr_n!(
+            deletions,
+            ", {} deletion(-)",
+            ", {} deletions(-)",
+            deletions
+        ));
+    }
+    writeln!(out, "{summary}")?;
+
+    Ok(())
+}
+
+/// Prints the files `changes` creates or deletes and the modes they
+/// change, as `--summary` does below a diffstat.
+pub fn write_summary(changes: &[FileChange], out: &mut impl Write) -> Result<()> {
+    for change in changes {
+        match (change.old, change.new) {
+            (None, Some((mode, _))) => writeln!(out, " create mode {mode} {}", change.name())?,
+            (Some((mode, _)), None) => writeln!(out, " delete mode {mode} {}", change.name())?,
+            (Some((old, _)), Some((new, _))) if old != new => {
+                writeln!(out, " mode change {old} => {new} {}", change.name())?
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a blob from the object store, falling back to the working tree for
+/// content that was hashed from disk but never written as an object. A
+/// submodule reads as the commit it is at, the way git diffs one.
+fn read_content(
+    repository: &Repository,
+    path: &[u8],
+    mode: u32,
+    hash: &[u8; 20],
+) -> Result<Vec<u8>> {
+    if mode == 160000 {
+        return Ok(format!("Subproject commit {}\n", encode(hash)).into_bytes());
+    }
+    if repository.has_object(hash)? {
+        return Ok(repository.read_raw_object(&encode(hash))?.1);
+    }
+
+    let file = repository.work_dir().join(path_from_bytes(path));
+    let content = {
+        let _span = profile::span(Phase::DiskIo);
+        fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?
+    };
+    let content = Filters::load(repository)?.clean(&path_from_bytes(path), content)?;
+    if blob_hash(&content) != *hash {
+        return Err(anyhow!(
+            "fatal: unable to read {} for {}",
+            encode(hash),
+            String::from_utf8_lossy(path)
+        ));
+    }
+
+    Ok(content)
+}
+
+/// The files of a commit's first parent, or nothing for a root commit.
+pub fn parent_files(repository: &Repository, parents: &[[u8; 20]]) -> Result<FileMap> {
+    match parents.first() {
+        Some(parent) => commit_files(repository, parent),
+        None => Ok(FileMap::new()),
+    }
+}
+
+pub fn commit_files(repository: &Repository, commit: &[u8; 20]) -> Result<FileMap> {
+    let tree = parse_hash(&repository.read_commit(commit)?.tree_hash()?)?;
+    tree_files(repository, &tree)
+}
+
+/// Whether the index differs from HEAD or the working tree from the index.
+pub fn has_changes(repository: &Repository) -> Result<bool> {
+    let head = match repository.resolve_head()? {
+        Some(head) => commit_files(repository, &head)?,
+        None => FileMap::new(),
+    };
+    let index = index_files(repository)?;
+    let worktree = worktree_files(repository, &index)?;
+
+    Ok(!compare(&head, &index).is_empty() || !compare(&index, &worktree).is_empty())
+}
+
+/// The files of the tree `name` resolves to, whether it names a tree, a
+/// commit, or a tag of either.
+pub fn tree_ish_files(repository: &Repository, name: &str) -> Result<FileMap> {
+    tree_files(repository, &tree_ish(repository, name)?)
+}
+
+/// The tree `name` resolves to, whether it names a tree, a commit, or a tag
+/// of either.
+fn tree_ish(repository: &Repository, name: &str) -> Result<[u8; 20]> {
+    let hash = repository.resolve_commitish(name)?;
+    let hash = peel_tag(repository, &hash)?.unwrap_or(hash);
+
+    match repository.read_raw_object(&encode(hash))?.0.as_str() {
+        "commit" => parse_hash(&repository.read_commit(&hash)?.tree_hash()?),
+        "tree" => Ok(hash),
+        _ => Err(anyhow!("fatal: '{}' is not a tree-ish", name)),
+    }
+}
+
+/// Prints the changes between two tree-ishes as raw diff lines, or with one
+/// commit, the changes it makes to its parent after a line naming it. A
+/// root commit is compared with the empty tree only with `root`, and a merge
+/// prints nothing. With `stdin`, each line read names a commit, optionally
+/// followed by the parents to compare it with, or two trees; other lines are
+/// echoed. Without `recursive`, a changed directory is one line for its
+/// tree.
+pub fn handle_diff_tree_command(
+    tree_ishes: Vec<String>,
+    recursive: bool,
+    root: bool,
+    stdin: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let mut out = io::stdout().lock();
+    match tree_ishes.as_slice() {
+        [] if stdin => {
+            for line in io::stdin().lock().lines() {
+                let line = line.context("Failed to read from stdin")?;
+                diff_tree_line(repository, &line, recursive, root, &mut out)?;
+            }
+        }
+        [commit] if !stdin => {
+            let hash = repository.resolve_commitish(commit)?;
+            let hash = peel_tag(repository, &hash)?.unwrap_or(hash);
+            diff_commit(repository, &hash, None, recursive, root, &mut out)?;
+        }
+        [old, new] if !stdin => {
+            let changes = compare(
+                &raw_files(repository, &tree_ish(repository, old)?, recursive)?,
+                &raw_files(repository, &tree_ish(repository, new)?, recursive)?,
+            );
+            write_raw(&changes, &mut out)?;
+        }
+        _ => {
+            return Err(anyhow!(
+                "usage: mini-git diff-tree [-r] [--root] (--stdin | <tree-ish> [<tree-ish>])"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints as raw diff lines the changes between `tree_ish` and the working
+/// tree, or with `cached`, the index.
+pub fn handle_diff_index_command(
+    tree_ish: &str,
+    cached: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let tree = tree_ish_files(repository, tree_ish)?;
+    let index = index_files(repository)?;
+    let new = if cached {
+        index
+    } else {
+        raw_worktree_files(repository, &index)?
+    };
+
+    write_raw(&compare(&tree, &new), &mut io::stdout().lock())
+}
+
+/// Prints as raw diff lines the changes between the index and the working
+/// tree.
+pub fn handle_diff_files_command(repository: &Repository) -> Result<()> {
+    let index = index_files(repository)?;
+    let worktree = raw_worktree_files(repository, &index)?;
+
+    write_raw(&compare(&index, &worktree), &mut io::stdout().lock())
+}
+
+/// The working tree files as raw diff lines show them: a file that differs
+/// from the index has a zero hash, as in git, since its content is not in
+/// the object store.
+fn raw_worktree_files(repository: &Repository, index: &FileMap) -> Result<FileMap> {
+    Ok(worktree_files(repository, index)?
+        .into_iter()
+        .map(|(path, (mode, hash))| {
+            let hash = if index.get(&path) == Some(&(mode, hash)) {
+                hash
+            } else {
+                [0u8; 20]
+            };
+            (path, (mode, hash))
+        })
+        .collect())
+}
+
+/// Handles one line of `diff-tree --stdin`.
+fn diff_tree_line(
+    repository: &Repository,
+    line: &str,
+    recursive: bool,
+    root: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let hashes: Result<Vec<[u8; 20]>> = line.split(' ').map(parse_hash).collect();
+    let Some((first, rest)) = hashes.as_deref().ok().and_then(<[_]>::split_first) else {
+        writeln!(out, "{line}")?;
+        return Ok(());
+    };
+
+    match repository.read_raw_object(&encode(first))?.0.as_str() {
+        "commit" => {
+            let parents = (!rest.is_empty()).then(|| rest.to_vec());
+            diff_commit(repository, first, parents, recursive, root, out)
+        }
+        "tree" if rest.len() == 1 => {
+            let changes = compare(
+                &raw_files(repository, first, recursive)?,
+                &raw_files(repository, &rest[0], recursive)?,
+            );
+            if !changes.is_empty() {
+                writeln!(out, "{line}")?;
+                write_raw(&changes, out)?;
+            }
+            Ok(())
+        }
+        _ => {
+            writeln!(out, "{line}")?;
+            Ok(())
+        }
+    }
+}
+
+/// Prints the changes `commit` makes to `parents`, by default its own,
+/// after a line with its hash, when there are any.
+fn diff_commit(
+    repository: &Repository,
+    commit: &[u8; 20],
+    parents: Option<Vec<[u8; 20]>>,
+    recursive: bool,
+    root: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let parents = match parents {
+        Some(parents) => parents,
+        None => repository.read_commit(commit)?.parents()?,
+    };
+    let tree_of = |commit: &[u8; 20]| parse_hash(&repository.read_commit(commit)?.tree_hash()?);
+    let old = match parents.as_slice() {
+        [] if root => FileMap::new(),
+        [parent] => raw_files(repository, &tree_of(parent)?, recursive)?,
+        _ => return Ok(()),
+    };
+    let new = raw_files(repository, &tree_of(commit)?, recursive)?;
+
+    let changes = compare(&old, &new);
+    if !changes.is_empty() {
+        writeln!(out, "{}", encode(commit))?;
+        write_raw(&changes, out)?;
+    }
+
+    Ok(())
+}
+
+/// What diff-tree compares in `tree`: every file when `recursive`,
+/// otherwise only its entries, with a `/` after the name of each subtree.
+/// That sorts them the way git orders trees, and makes a file replaced by
+/// a directory a deletion and an addition rather than one change.
+fn raw_files(repository: &Repository, tree: &[u8; 20], recursive: bool) -> Result<FileMap> {
+    if recursive {
+        return tree_files(repository, tree);
+    }
+
+    Ok(repository
+        .read_tree(tree)?
+        .into_iter()
+        .map(|entry| {
+            let mut path = path_bytes(&entry.path).into_owned();
+            if entry.mode == 40000 {
+                path.push(b'/');
+            }
+            (path, (entry.mode, entry.sha1))
+        })
+        .collect())
+}
+
+/// Prints `changes` as raw diff lines: both modes, both hashes, the status
+/// letter and the path, with zeros for the side a path is missing from.
+fn write_raw(changes: &[FileChange], out: &mut impl Write) -> Result<()> {
+    let zero = [0u8; 20];
+    for change in changes {
+        let (old_mode, old_hash) = change.old.unwrap_or((0, zero));
+        let (new_mode, new_hash) = change.new.unwrap_or((0, zero));
+        let path = change.path.strip_suffix(b"/").unwrap_or(&change.path);
+        writeln!(
+            out,
+            ":{old_mode:06} {new_mode:06} {} {} {}\t{}",
+            encode(old_hash),
+            encode(new_hash),
+            change.status(),
+            String::from_utf8_lossy(path)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Prints the changes between two of HEAD, the index, the working tree or
+/// the given tree-ishes. With `exit_code`, the result tells whether there
+/// were any; `quiet` prints nothing.
+pub fn handle_diff_command(
+    cached: bool,
+    commits: Vec<String>,
+    args: DiffOutputArgs,
+    exit_code: bool,
+    quiet: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let (old, new) = match (cached, commits.as_slice()) {
+        (false, []) => {
+            let index = index_files(repository)?;
+            let worktree = worktree_files(repository, &index)?;
+            (index, worktree)
+        }
+        (true, []) => {
+            let head = match repository.resolve_head()? {
+                Some(head) => commit_files(repository, &head)?,
+                None => FileMap::new(),
+            };
+            (head, index_files(repository)?)
+        }
+        (true, [tree_ish]) => (
+            tree_ish_files(repository, tree_ish)?,
+            index_files(repository)?,
+        ),
+        // Like git, the paths compared are the ones in the tree and the
+        // index, so a file removed from the index shows up as deleted and
+        // untracked files are left out.
+        (false, [tree_ish]) => {
+            let index = index_files(repository)?;
+            (
+                tree_ish_files(repository, tree_ish)?,
+                worktree_files(repository, &index)?,
+            )
+        }
+        (false, [old, new]) => (
+            tree_ish_files(repository, old)?,
+            tree_ish_files(repository, new)?,
+        ),
+        _ => {
+            return Err(anyhow!(
+                "usage: mini-git diff [--cached] [<tree-ish> [<tree-ish>]]"
+            ));
+        }
+    };
+
+    let changes = compare(&old, &new);
+    if !quiet {
+        write_changes(repository, &changes, args, &mut io::stdout().lock())?;
+    }
+
+    if exit_code && !changes.is_empty() {
+        Ok(ExitStatus::Differences)
+    } else {
+        Ok(ExitStatus::Success)
+    }
+}
+
+/// Shows a commit and the changes it makes, after the tag when `revision`
+/// names an annotated one. With `show_signature`, the signatures of both
+/// are checked.
+pub fn handle_show_command(
+    revision: Option<String>,
+    show_signature: bool,
+    args: DiffOutputArgs,
+    repository: &Repository,
+) -> Result<()> {
+    let mut signatures = SignatureCache::new(Config::load(repository)?);
+    let mut signatures = show_signature.then_some(&mut signatures);
+    let mut hash = repository.resolve_object(revision.as_deref().unwrap_or("HEAD"))?;
+    let (object_type, content) = repository.read_raw_object(&encode(hash))?;
+
+    // A reader that stops early, such as `head`, closes the pipe; that is
+    // no reason to complain.
+    let mut out = io::stdout().lock();
+    let shown = (|| {
+        if object_type == "tag" {
+            print_tag(&mut out, &hash, &content, signatures.as_deref_mut())?;
+            writeln!(out)?;
+            hash = peel_tag(repository, &hash)?.unwrap_or(hash);
+        }
+
+        let commit = repository.read_commit(&hash)?;
+        let parents = commit.parents()?;
+        print_commit(
+            &mut out,
+            &hash,
+            &commit,
+            signatures,
+            &Notes::load(repository)?,
+        )?;
+
+        if parents.len() > 1 {
+            return Ok(());
+        }
+
+        let changes = compare(
+            &parent_files(repository, &parents)?,
+            &commit_files(repository, &hash)?,
+        );
+        if !changes.is_empty() {
+            writeln!(out)?;
+            write_changes(repository, &changes, args, &mut out)?;
+        }
+        Ok(())
+    })();
+
+    match shown {
+        Err(error) if is_broken_pipe(&error) => Ok(()),
+        shown => shown,
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{Repository, config::Config, ignore::Ignore, messages::tr};
+
+/// Finds what cleaning the working tree removes.
+struct Scan {
+    work_dir: PathBuf,
+    tracked_files: HashSet<PathBuf>,
+    tracked_dirs: HashSet<PathBuf>,
+    ignore: Ignore,
+    directories: bool,
+    ignored: bool,
+}
+
+impl Scan {
+    /// Adds what cleaning `dir` removes to `removable`, each path with
+    /// whether it is a directory removed whole, and returns whether that is
+    /// everything in `dir`.
+    fn dir(&mut self, dir: &Path, removable: &mut Vec<(PathBuf, bool)>) -> Result<bool> {
+        let full = self.work_dir.join(dir);
+        let mut children: Vec<_> = fs::read_dir(&full)
+            .with_context(|| format!("Failed to read directory {}", full.display()))?
+            .collect::<Result<_, _>>()?;
+        children.sort_by_key(|child| child.file_name());
+
+        let mut everything = true;
+        for child in children {
+            if child.file_name() == ".mini-git" {
+                everything = false;
+                continue;
+            }
+            let path = dir.join(child.file_name());
+            let is_dir = child.file_type()?.is_dir();
+
+            if self.tracked_files.contains(&path)
+                || (!self.ignored && self.ignore.is_ignored(&path, is_dir)?)
+            {
+                everything = false;
+            } else if !is_dir {
+                removable.push((path, false));
+            } else if self.tracked_dirs.contains(&path) {
+                self.dir(&path, removable)?;
+                everything = false;
+            } else if !self.directories || self.work_dir.join(&path).join(".mini-git").exists() {
+                // Untracked directories are left alone without -d, and
+                // another repository always is.
+                everything = false;
+            } else {
+                let mut inside = Vec::new();
+                if self.dir(&path, &mut inside)? {
+                    removable.push((path, true));
+                } else {
+                    removable.extend(inside);
+                    everything = false;
+                }
+            }
+        }
+
+        Ok(everything)
+    }
+}
+
+/// Removes the untracked files in the working tree, or with `dry_run` only
+/// lists them. Ignored files are kept unless `ignored` is set, and
+/// untracked directories unless `directories` is; one is removed whole
+/// when nothing in it is to be kept. Nested repositories are never
+/// removed. Unless `clean.requireForce` is false, nothing happens without
+/// `force` or `dry_run`.
+pub fn handle_clean_command(
+    dry_run: bool,
+    force: bool,
+    directories: bool,
+    ignored: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let require_force = Config::load(repository)?
+        .get("clean.requireforce")
+        .is_none_or(|value| !matches!(value, "false" | "no" | "off" | "0"));
+    if require_force && !force && !dry_run {
+        return Err(anyhow!(
+            "fatal: clean.requireForce defaults to true and neither -n nor -f given; refusing to clean"
+        ));
+    }
+
+    let tracked_files: HashSet<PathBuf> = repository
+        .read_index()?
+        .entries
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect();
+    let tracked_dirs = tracked_files
+        .iter()
+        .flat_map(|path| path.ancestors().skip(1))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect();
+
+    let mut scan = Scan {
+        work_dir: repository.work_dir(),
+        tracked_files,
+        tracked_dirs,
+        ignore: Ignore::load(repository)?,
+        directories,
+        ignored,
+    };
+    let mut removable = Vec::new();
+    scan.dir(Path::new(""), &mut removable)?;
+
+    let mut removable: Vec<(String, PathBuf, bool)> = removable
+        .into_iter()
+        .map(|(path, is_dir)| {
+            let name = path.to_string_lossy().into_owned();
+            let name = if is_dir { format!("{name}/") } else { name };
+            (name, path, is_dir)
+        })
+        .collect();
+    removable.sort();
+
+    for (name, path, is_dir) in removable {
+        if dry_run {
+            println!("{}", tr!("Would remove {}", name));
+            continue;
+        }
+
+        println!("{}", tr!("Removing {}", name));
+        let full = scan.work_dir.join(&path);
+        if is_dir {
+            fs::remove_dir_all(&full)
+        } else {
+            fs::remove_file(&full)
+        }
+        .with_context(|| format!("warning: failed to remove {name}"))?;
+    }
+
+    Ok(())
+}
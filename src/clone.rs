@@ -0,0 +1,203 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Repository,
+    config::Config,
+    fetch::fetch,
+    merge::checkout_entries,
+    messages::tr,
+    refs::parse_hash,
+    submodule::clone_submodules,
+    transport::{FetchSource, short_ref_name},
+};
+
+/// How `clone` sets up the new repository.
+#[derive(Default)]
+pub struct CloneOptions {
+    /// The branch to check out instead of the one the remote's HEAD is on.
+    pub branch: Option<String>,
+    /// Fetch only that branch.
+    pub single_branch: bool,
+    /// Fetch from a local repository as from any other, rather than
+    /// sharing its objects.
+    pub no_local: bool,
+    /// Copy a local repository's objects instead of hardlinking them.
+    pub no_hardlinks: bool,
+    /// Clone the submodules too, and theirs in turn.
+    pub recurse_submodules: bool,
+    /// How many submodules to fetch at once, instead of
+    /// `submodule.fetchJobs`.
+    pub jobs: Option<usize>,
+}
+
+pub fn handle_clone_command(
+    url: String,
+    directory: Option<PathBuf>,
+    options: CloneOptions,
+) -> Result<()> {
+    let directory = directory.unwrap_or_else(|| default_directory(&url));
+    let repository = Repository::at(directory.join(".mini-git"));
+    let remote = FetchSource::open(&Config::load(&repository)?, &url)?;
+
+    if directory.exists()
+        && fs::read_dir(&directory)
+            .with_context(|| format!("Failed to read directory {}", directory.display()))?
+            .next()
+            .is_some()
+    {
+        return Err(anyhow!(
+            "fatal: destination path '{}' already exists and is not an empty directory.",
+            directory.display()
+        ));
+    }
+
+    let branch = match options.branch {
+        Some(branch) => {
+            let name = format!("refs/heads/{branch}");
+            if !remote
+                .refs()?
+                .iter()
+                .any(|(remote_name, _)| *remote_name == name)
+            {
+                return Err(anyhow!(
+                    "fatal: Remote branch {} not found in upstream origin",
+                    branch
+                ));
+            }
+            branch
+        }
+        None => remote
+            .head_ref()?
+            .map(|head| short_ref_name(&head).to_string())
+            .unwrap_or_else(|| "main".to_string()),
+    };
+
+    let on_disk =
+        !url.contains("://") && matches!(remote, FetchSource::Local(_) | FetchSource::Bundle(_));
+    let local = !options.no_local && on_disk && matches!(remote, FetchSource::Local(_));
+
+    // Store local paths absolutely so fetches from inside the clone work.
+    // A URL that only names a path once rewritten is kept as it is.
+    let url = if !on_disk || !Path::new(&url).exists() {
+        url
+    } else {
+        fs::canonicalize(&url)
+            .with_context(|| format!("Failed to resolve {url}"))?
+            .display()
+            .to_string()
+    };
+
+    eprintln!("{}", tr!("Cloning into '{}'...", directory.display()));
+
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Failed to create directory {}", directory.display()))?;
+    repository.apply_template(None)?;
+    repository.create_layout()?;
+
+    let refspec = if options.single_branch {
+        format!("+refs/heads/{branch}:refs/remotes/origin/{branch}")
+    } else {
+        "+refs/heads/*:refs/remotes/origin/*".to_string()
+    };
+
+    Config::set_all(&repository, "remote.origin.url", &[&url])?;
+    Config::set_all(&repository, "remote.origin.fetch", &[&refspec])?;
+    Config::set_all(&repository, &format!("branch.{branch}.remote"), &["origin"])?;
+    Config::set_all(
+        &repository,
+        &format!("branch.{branch}.merge"),
+        &[&format!("refs/heads/{branch}")],
+    )?;
+
+    fs::write(
+        repository.mini_git_dir.join("HEAD"),
+        format!("ref: refs/heads/{branch}\n"),
+    )
+    .context("Failed to write HEAD")?;
+
+    // A local source can share its object store directly, after which the
+    // fetch below only has refs left to update.
+    if let FetchSource::Local(remote) = &remote
+        && local
+    {
+        copy_object_store(
+            &remote.objects_dir,
+            &repository.objects_dir,
+            !options.no_hardlinks,
+        )?;
+    }
+    fetch(&repository, &url, &[refspec])?;
+    if local {
+        eprintln!("{}", tr!("done."));
+    }
+
+    let Some(tip) = repository.read_ref(&format!("refs/remotes/origin/{branch}"))? else {
+        eprintln!(
+            "{}",
+            tr!("warning: You appear to have cloned an empty repository.")
+        );
+        return Ok(());
+    };
+
+    repository.write_ref(&format!("refs/heads/{branch}"), &tip)?;
+    let tree = parse_hash(&repository.read_commit(&tip)?.tree_hash()?)?;
+    checkout_entries(
+        &repository,
+        &[],
+        &repository.read_tree_recursive(&tree)?,
+        "checkout",
+    )?;
+
+    if options.recurse_submodules {
+        clone_submodules(&repository, options.jobs)?;
+    }
+
+    Ok(())
+}
+
+/// Hardlinks (or copies) every loose object and pack from `from` into `to`,
+/// copying whenever a link cannot be made, e.g. across filesystems.
+fn copy_object_store(from: &Path, to: &Path, hardlink: bool) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create {}", to.display()))?;
+
+    for entry in fs::read_dir(from).with_context(|| format!("Failed to read {}", from.display()))? {
+        let source = entry?.path();
+        let Some(name) = source.file_name() else {
+            continue;
+        };
+        let target = to.join(name);
+
+        if source.is_dir() {
+            copy_object_store(&source, &target, hardlink)?;
+        } else if !target.exists() && (!hardlink || fs::hard_link(&source, &target).is_err()) {
+            fs::copy(&source, &target).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    source.display(),
+                    target.display()
+                )
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn default_directory(url: &str) -> PathBuf {
+    let name = url
+        .trim_end_matches('/')
+        .trim_end_matches("/.mini-git")
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(url);
+
+    let name = name
+        .strip_suffix(".git")
+        .or_else(|| name.strip_suffix(".bundle"))
+        .unwrap_or(name);
+    Path::new(name).to_path_buf()
+}
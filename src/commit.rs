@@ -0,0 +1,239 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::fs;
+
+use crate::{
+    CommitObject, Repository,
+    config::Config,
+    hooks::run_hook,
+    ident::Ident,
+    merge::clear_merge_state,
+    messages::tr,
+    refs::parse_hash,
+    signature::{add_signature_header, sign_payload},
+    stripspace::stripspace_str,
+    transport::short_ref_name,
+};
+
+const DEFAULT_MAX_SUBJECT_LENGTH: usize = 50;
+
+/// Records the index as a new commit on top of HEAD and moves HEAD to it.
+/// While a merge is in progress, the merged commits become further parents
+/// and the merge message is used unless `message` is given.
+///
+/// The `pre-commit` hook runs first and may still change the index. The
+/// message is then written to `COMMIT_EDITMSG`, where `prepare-commit-msg`
+/// and `commit-msg` can rewrite it, and checked against the configured
+/// lint. `no_verify` skips `pre-commit`, `commit-msg` and the lint.
+/// `post-commit` runs once HEAD has moved.
+///
+/// The commit is signed with gpg when `gpg_sign` is given, with the key it
+/// names if it is not empty, or when `commit.gpgSign` is set and
+/// `no_gpg_sign` is not.
+pub fn handle_commit_command(
+    message: Option<String>,
+    no_verify: bool,
+    gpg_sign: Option<String>,
+    no_gpg_sign: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let merge_head_file = repository.git_dir.join("MERGE_HEAD");
+    let merge_heads = if merge_head_file.is_file() {
+        fs::read_to_string(&merge_head_file)
+            .context("Failed to read MERGE_HEAD")?
+            .lines()
+            .map(parse_hash)
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+
+    let env = ident_env(repository)?;
+    if !no_verify && !run_hook(repository, "pre-commit", &[], &env, &[])? {
+        return Err(anyhow!("fatal: pre-commit hook declined the commit"));
+    }
+
+    let head = repository.resolve_head()?;
+    let (tree, tree_hex) = repository.write_tree()?;
+
+    if merge_heads.is_empty()
+        && let Some(head) = head
+        && parse_hash(&repository.read_commit(&head)?.tree_hash()?)? == tree
+    {
+        return Err(anyhow!("nothing to commit, working tree clean"));
+    }
+
+    let (message, source) = match message {
+        Some(message) => (message, Some("message")),
+        None if !merge_heads.is_empty() => (
+            fs::read_to_string(repository.git_dir.join("MERGE_MSG"))
+                .context("Failed to read MERGE_MSG")?,
+            Some("merge"),
+        ),
+        None => (String::new(), None),
+    };
+    let message = edit_message(repository, message, source, &env, no_verify)?;
+    if message.is_empty() {
+        return Err(anyhow!("Aborting commit due to empty commit message."));
+    }
+    if !no_verify {
+        lint_message(&Config::load(repository)?, &message)?;
+    }
+
+    let parents: Vec<[u8; 20]> = head.into_iter().chain(merge_heads).collect();
+    let root = parents.is_empty();
+    let config = Config::load(repository)?;
+    let sign = match gpg_sign {
+        Some(key) => Some(Some(key).filter(|key| !key.is_empty())),
+        None if !no_gpg_sign
+            && config
+                .get("commit.gpgsign")
+                .is_some_and(|value| matches!(value, "true" | "yes" | "on" | "1")) =>
+        {
+            Some(None)
+        }
+        None => None,
+    };
+    let (commit, commit_hex) = match sign {
+        Some(key) => {
+            let unsigned = CommitObject::new(
+                &message,
+                &tree_hex,
+                &parents,
+                &Ident::author(&config)?,
+                &Ident::committer(&config)?,
+            )?;
+            let signature = sign_payload(&config, &unsigned.raw_content, key.as_deref())
+                .context("fatal: failed to write commit object")?;
+            let commit = repository.write_raw_object(
+                "commit",
+                &add_signature_header(&unsigned.raw_content, &signature),
+            )?;
+            (commit, encode(commit))
+        }
+        None => repository.commit_tree(message.clone(), tree_hex, parents)?,
+    };
+    repository.update_head(&commit)?;
+    clear_merge_state(repository)?;
+    run_hook(repository, "post-commit", &[], &env, &[])?;
+
+    let branch = match repository.head_ref()? {
+        Some(head_ref) => short_ref_name(&head_ref).to_string(),
+        None => tr!("detached HEAD"),
+    };
+    println!(
+        "[{branch}{} {}] {}",
+        if root { " (root-commit)" } else { "" },
+        &commit_hex[..7],
+        message.lines().next().unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+/// Passes `message` through `COMMIT_EDITMSG` to the `prepare-commit-msg`
+/// hook, told where the message came from, and then to `commit-msg`
+/// unless `no_verify` is set. Returns the message the hooks left behind,
+/// cleaned up by `stripspace`.
+fn edit_message(
+    repository: &Repository,
+    message: String,
+    source: Option<&str>,
+    env: &[(&str, String)],
+    no_verify: bool,
+) -> Result<String> {
+    let file = repository.git_dir.join("COMMIT_EDITMSG");
+    fs::write(&file, message).context("Failed to write COMMIT_EDITMSG")?;
+    let path = file.to_string_lossy();
+
+    let mut args = vec![path.as_ref()];
+    args.extend(source);
+    if !run_hook(repository, "prepare-commit-msg", &args, env, &[])? {
+        return Err(anyhow!(
+            "fatal: prepare-commit-msg hook declined the commit"
+        ));
+    }
+    if !no_verify && !run_hook(repository, "commit-msg", &[&path], env, &[])? {
+        return Err(anyhow!("fatal: commit-msg hook declined the commit"));
+    }
+
+    let message = fs::read_to_string(&file).context("Failed to read COMMIT_EDITMSG")?;
+    Ok(stripspace_str(&message, false))
+}
+
+/// The identities the commit is made with, for its hooks, as git exports
+/// them.
+fn ident_env(repository: &Repository) -> Result<Vec<(&'static str, String)>> {
+    let config = Config::load(repository)?;
+    let author = Ident::author(&config)?;
+    let committer = Ident::committer(&config)?;
+
+    Ok(vec![
+        ("GIT_AUTHOR_NAME", author.name),
+        ("GIT_AUTHOR_EMAIL", author.email),
+        (
+            "GIT_AUTHOR_DATE",
+            format!("@{} {}", author.timestamp, author.timezone),
+        ),
+        ("GIT_COMMITTER_NAME", committer.name),
+        ("GIT_COMMITTER_EMAIL", committer.email),
+    ])
+}
+
+/// Checks `message` against the `commitlint.*` settings: a subject no
+/// longer than `commitlint.maxSubjectLength` and, unless
+/// `commitlint.blankSecondLine` is off, a blank line between the subject
+/// and the body. `commitlint.mode` decides whether a problem is only
+/// warned about (`warn`), stops the commit (`error`), or is not looked
+/// for at all (`off`, the default).
+fn lint_message(config: &Config, message: &str) -> Result<()> {
+    let blocking = match config.get("commitlint.mode").unwrap_or("off") {
+        "off" => return Ok(()),
+        "warn" => false,
+        "error" => true,
+        mode => {
+            return Err(anyhow!(
+                "fatal: invalid value for commitlint.mode: '{}'",
+                mode
+            ));
+        }
+    };
+
+    let max_length = match config.get("commitlint.maxsubjectlength") {
+        Some(value) => value.parse().map_err(|_| {
+            anyhow!(
+                "fatal: bad numeric config value '{}' for 'commitlint.maxsubjectlength'",
+                value
+            )
+        })?,
+        None => DEFAULT_MAX_SUBJECT_LENGTH,
+    };
+    let blank_second_line = config
+        .get("commitlint.blanksecondline")
+        .is_none_or(|value| !matches!(value, "false" | "no" | "off" | "0"));
+
+    let mut lines = message.lines();
+    let subject = lines.next().unwrap_or_default();
+    let mut problems = Vec::new();
+    if subject.chars().count() > max_length {
+        problems.push(format!(
+            "subject is {} characters long, more than {max_length}",
+            subject.chars().count()
+        ));
+    }
+    if blank_second_line && lines.next().is_some_and(|line| !line.trim().is_empty()) {
+        problems.push("second line is not blank".to_string());
+    }
+
+    if blocking && !problems.is_empty() {
+        return Err(anyhow!(
+            "fatal: commit message does not pass the lint: {}",
+            problems.join("; ")
+        ));
+    }
+    for problem in problems {
+        eprintln!("{}", tr!("warning: commit message {}", problem));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{Repository, exit::ExitStatus};
+
+/// Key/value pairs parsed from INI-style config files. Keys are stored as
+/// `section.subsection.name`, with the section and name lowercased.
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+/// Prints the value of `key`, or sets it to `value`, in the repository's
+/// `.mini-git/config` or, with `global`, in `~/.minigitconfig`. Without
+/// `global` a lookup sees both files, the repository's winning. A key that
+/// is not set exits with status 1 and prints nothing, as in git.
+pub fn handle_config_command(
+    global: bool,
+    key: String,
+    value: Option<String>,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let Some(value) = value else {
+        let config = if global {
+            Config::load_global()?
+        } else {
+            Config::load(repository)?
+        };
+        return match config.get(&key) {
+            Some(value) => {
+                println!("{value}");
+                Ok(ExitStatus::Success)
+            }
+            None => Ok(ExitStatus::Differences),
+        };
+    };
+
+    let path = if global {
+        global_path().ok_or_else(|| anyhow!("fatal: $HOME not set"))?
+    } else if repository.mini_git_dir.is_dir() {
+        repository.mini_git_dir.join("config")
+    } else {
+        return Err(anyhow!("fatal: not in a mini-git directory"));
+    };
+    Config::set_all_in_file(&path, &key, &[&value])?;
+
+    Ok(ExitStatus::Success)
+}
+
+impl Config {
+    /// Loads the user's `~/.minigitconfig` and then the repository's own
+    /// config, so that a repository setting overrides a global one.
+    pub fn load(repository: &Repository) -> Result<Self> {
+        let mut config = Self::load_global()?;
+        config.read_file(&repository.mini_git_dir.join("config"))?;
+
+        Ok(config)
+    }
+
+    /// Loads the user's own `~/.minigitconfig`, for settings needed before
+    /// there is a repository to read them from.
+    pub fn load_global() -> Result<Self> {
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+        if let Some(path) = global_path() {
+            config.read_file(&path)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Loads a file in config syntax that is not a config file as such,
+    /// like `.gitmodules`.
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+        config.read_file(path)?;
+
+        Ok(config)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+
+        self.entries
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of `key` as a size in bytes, which may end in `k`, `m` or
+    /// `g` for units of 1024, 1024² or 1024³.
+    pub fn get_size(&self, key: &str) -> Result<Option<u64>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let invalid = || anyhow!("fatal: bad numeric config value '{}' for '{}'", value, key);
+
+        let (digits, scale) = match value.char_indices().last() {
+            Some((i, unit)) if unit.is_ascii_alphabetic() => {
+                let scale = match unit.to_ascii_lowercase() {
+                    'k' => 1 << 10,
+                    'm' => 1 << 20,
+                    'g' => 1 << 30,
+                    _ => return Err(invalid()),
+                };
+                (&value[..i], scale)
+            }
+            _ => (value, 1),
+        };
+        let size: u64 = digits.trim().parse().map_err(|_| invalid())?;
+        size.checked_mul(scale).map(Some).ok_or_else(invalid)
+    }
+
+    /// The value of `key` as a path, with a leading `~/` standing for the
+    /// home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        let path = self.get(key)?;
+        match (path.strip_prefix("~/"), env::var_os("HOME")) {
+            (Some(rest), Some(home)) => Some(PathBuf::from(home).join(rest)),
+            _ => Some(PathBuf::from(path)),
+        }
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+
+        self.entries
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Iterates over all entries in file order as `(key, value)` pairs.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Replaces every value of `key` in the repository's config file with
+    /// `values`, written where the first old value was, at the end of the
+    /// key's last section, or in a new section at the end of the file.
+    pub fn set_all(repository: &Repository, key: &str, values: &[&str]) -> Result<()> {
+        Self::set_all_in_file(&repository.mini_git_dir.join("config"), key, values)
+    }
+
+    /// Like `set_all`, for the config-syntax file at `path`.
+    pub fn set_all_in_file(path: &Path, key: &str, values: &[&str]) -> Result<()> {
+        let key = normalize_key(key);
+        let (section, name) = key
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow!("error: key does not contain a section: {}", key))?;
+
+        let content = if path.is_file() {
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file {}", path.display()))?
+        } else {
+            String::new()
+        };
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut in_section = false;
+        let mut insert_at = None;
+        let mut replaced_at = None;
+
+        for line in content.lines() {
+            let trimmed = line.trim();
+
+            if trimmed.starts_with('[') {
+                in_section = parse_section(trimmed).as_deref() == Some(section);
+            } else if in_section {
+                let line_name = trimmed.split('=').next().unwrap_or_default().trim();
+                if line_name.eq_ignore_ascii_case(name) {
+                    replaced_at.get_or_insert(lines.len());
+                    continue;
+                }
+            }
+
+            lines.push(line.to_string());
+            if in_section {
+                insert_at = Some(lines.len());
+            }
+        }
+
+        let new_lines = values
+            .iter()
+            .map(|value| format!("\t{name} = {}", quote_value(value)));
+        match replaced_at.or(insert_at) {
+            Some(position) => {
+                lines.splice(position..position, new_lines);
+            }
+            None if !values.is_empty() => {
+                lines.push(section_header(section));
+                lines.extend(new_lines);
+            }
+            None => {}
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write config file {}", path.display()))
+    }
+
+    /// Renames every `[section]` header matching `old` to `new`, or drops
+    /// those sections along with their entries when `new` is `None`.
+    pub fn rename_section(repository: &Repository, old: &str, new: Option<&str>) -> Result<()> {
+        let old = normalize_key(&format!("{old}.x"));
+        let old = &old[..old.len() - 2];
+
+        let path = repository.mini_git_dir.join("config");
+        if !path.is_file() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut in_section = false;
+
+        for line in content.lines() {
+            let trimmed = line.trim();
+
+            if trimmed.starts_with('[') {
+                in_section = parse_section(trimmed).as_deref() == Some(old);
+                if in_section {
+                    if let Some(new) = new {
+                        lines.push(section_header(new));
+                    }
+                    continue;
+                }
+            }
+
+            if !in_section || new.is_some() {
+                lines.push(line.to_string());
+            }
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write config file {}", path.display()))
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        let mut section = String::new();
+
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = parse_section(line).ok_or_else(|| {
+                    anyhow!(
+                        "fatal: bad config line {} in file {}",
+                        line_number + 1,
+                        path.display()
+                    )
+                })?;
+                continue;
+            }
+
+            if section.is_empty() {
+                return Err(anyhow!(
+                    "fatal: bad config line {} in file {}",
+                    line_number + 1,
+                    path.display()
+                ));
+            }
+
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), parse_value(value)),
+                None => (line, "true".to_string()),
+            };
+
+            self.entries
+                .push((format!("{section}.{}", name.to_lowercase()), value));
+        }
+
+        Ok(())
+    }
+}
+
+fn global_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".minigitconfig"))
+}
+
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => format!(
+            "{}{}{}",
+            key[..first].to_lowercase(),
+            &key[first..last],
+            key[last..].to_lowercase()
+        ),
+        _ => key.to_lowercase(),
+    }
+}
+
+fn section_header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!("[{name} \"{subsection}\"]"),
+        None => format!("[{section}]"),
+    }
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    if value.contains(['#', ';']) || value.starts_with(' ') || value.ends_with(' ') {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+fn parse_section(line: &str) -> Option<String> {
+    let inner = line.strip_prefix('[')?.split(']').next()?.trim();
+
+    match inner.split_once(' ') {
+        Some((name, subsection)) => {
+            let subsection = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+            Some(format!("{}.{}", name.to_lowercase(), subsection))
+        }
+        None => Some(inner.to_lowercase()),
+    }
+}
+
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = raw.trim().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(other) => value.push(other),
+                None => {}
+            },
+            '#' | ';' if !in_quotes => break,
+            c => value.push(c),
+        }
+    }
+
+    if in_quotes {
+        value
+    } else {
+        value.trim_end().to_string()
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    io::{BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use crate::{
+    Repository,
+    protocol::{read_pkt_line, write_pkt_line},
+    upload_pack::{requested_version, serve_upload_pack},
+};
+
+const EXPORT_OK_FILE: &str = "git-daemon-export-ok";
+
+/// Which repositories the daemon hands out, and where to find them.
+pub struct DaemonOptions {
+    /// Requested paths are looked up below this directory.
+    pub base_path: Option<PathBuf>,
+    /// Serve repositories even without a `git-daemon-export-ok` file.
+    pub export_all: bool,
+    /// When not empty, only repositories below these directories are served.
+    pub allowlist: Vec<PathBuf>,
+}
+
+/// Listens on `listen:port` and serves upload-pack requests from each
+/// client on its own thread until the process is stopped.
+pub fn handle_daemon_command(listen: &str, port: u16, options: DaemonOptions) -> Result<()> {
+    let listener = TcpListener::bind((listen, port))
+        .with_context(|| format!("fatal: unable to listen on {listen}:{port}"))?;
+    eprintln!("Ready to rumble on {listen}:{port}");
+
+    let options = Arc::new(options);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("error: failed to accept connection: {error}");
+                continue;
+            }
+        };
+
+        let options = Arc::clone(&options);
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+            eprintln!("Connection from {peer}");
+
+            if let Err(error) = serve_client(stream, &options) {
+                eprintln!("[{peer}] {error}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn serve_client(mut stream: TcpStream, options: &DaemonOptions) -> Result<()> {
+    let request = read_pkt_line(&mut stream)?
+        .ok_or_else(|| anyhow!("fatal: protocol error: expected a service request"))?;
+    let request = String::from_utf8_lossy(&request);
+    // Extra parameters, such as the protocol version, follow the host
+    // after a second NUL.
+    let mut fields = request.split('\0');
+    let command = fields.next().unwrap_or_default();
+    let version = requested_version(fields);
+
+    let (service, path) = command
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("fatal: protocol error: bad request '{}'", command))?;
+    if service != "git-upload-pack" {
+        write_pkt_line(&mut stream, b"ERR service not enabled\n")?;
+        return Err(anyhow!("refused {} of {}", service, path));
+    }
+
+    let Some(repository) = exported_repository(path, options) else {
+        let message = format!("ERR access denied or repository not exported: {path}\n");
+        write_pkt_line(&mut stream, message.as_bytes())?;
+        return Err(anyhow!("refused access to {}", path));
+    };
+    eprintln!("Request upload-pack for '{path}'");
+
+    let mut input = stream.try_clone()?;
+    let mut output = BufWriter::new(stream);
+    serve_upload_pack(&repository, version, &mut input, &mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Opens the repository a client asked for if the daemon may serve it. The
+/// path may not climb out of the base path, must fall below an allowlisted
+/// directory when there are any, and must be marked as exported.
+fn exported_repository(path: &str, options: &DaemonOptions) -> Option<Repository> {
+    let requested = Path::new(path);
+    if requested
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        return None;
+    }
+
+    let path = match &options.base_path {
+        Some(base_path) => base_path.join(requested.strip_prefix("/").unwrap_or(requested)),
+        None => requested.to_path_buf(),
+    };
+    let path = path.canonicalize().ok()?;
+
+    if !options.allowlist.is_empty()
+        && !options.allowlist.iter().any(|allowed| {
+            allowed
+                .canonicalize()
+                .is_ok_and(|allowed| path.starts_with(allowed))
+        })
+    {
+        return None;
+    }
+
+    let repository = Repository::open(&path).ok()?;
+    if !options.export_all && !repository.mini_git_dir.join(EXPORT_OK_FILE).is_file() {
+        return None;
+    }
+
+    Some(repository)
+}
@@ -0,0 +1,208 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+
+/// Reads a little-endian base-128 size as used in delta headers, returning
+/// the value and the position just past it.
+pub fn read_size(data: &[u8], mut pos: usize) -> Result<(usize, usize)> {
+    let mut size = 0usize;
+    let mut shift = 0;
+
+    loop {
+        let byte = *data
+            .get(pos)
+            .ok_or_else(|| anyhow!("Malformed delta: truncated size"))?;
+        pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok((size, pos));
+        }
+    }
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let (source_size, pos) = read_size(delta, 0)?;
+    let (target_size, mut pos) = read_size(delta, pos)?;
+
+    if source_size != base.len() {
+        return Err(anyhow!(
+            "Malformed delta: expected base of {} bytes, got {}",
+            source_size,
+            base.len()
+        ));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+
+    while pos < delta.len() {
+        let opcode = delta[pos];
+        pos += 1;
+
+        if opcode & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut size = 0usize;
+
+            for i in 0..4 {
+                if opcode & (1 << i) != 0 {
+                    let byte = *delta
+                        .get(pos)
+                        .ok_or_else(|| anyhow!("Malformed delta: truncated copy"))?;
+                    offset |= (byte as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if opcode & (0x10 << i) != 0 {
+                    let byte = *delta
+                        .get(pos)
+                        .ok_or_else(|| anyhow!("Malformed delta: truncated copy"))?;
+                    size |= (byte as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            let chunk = base
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow!("Malformed delta: copy outside of base"))?;
+            target.extend_from_slice(chunk);
+        } else if opcode != 0 {
+            let size = opcode as usize;
+            let chunk = delta
+                .get(pos..pos + size)
+                .ok_or_else(|| anyhow!("Malformed delta: truncated insert"))?;
+            target.extend_from_slice(chunk);
+            pos += size;
+        } else {
+            return Err(anyhow!("Malformed delta: reserved opcode 0"));
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(anyhow!(
+            "Malformed delta: expected {} bytes of output, got {}",
+            target_size,
+            target.len()
+        ));
+    }
+
+    Ok(target)
+}
+
+const BLOCK_SIZE: usize = 16;
+const MAX_COPY_SIZE: usize = 0xff_ffff;
+const MAX_INSERT_SIZE: usize = 0x7f;
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn flush_insert(out: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    for chunk in pending.chunks(MAX_INSERT_SIZE) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+    pending.clear();
+}
+
+fn push_copy(out: &mut Vec<u8>, offset: usize, size: usize) {
+    let mut opcode = 0x80u8;
+    let mut args = Vec::with_capacity(7);
+
+    for i in 0..4 {
+        let byte = ((offset >> (8 * i)) & 0xff) as u8;
+        if byte != 0 {
+            opcode |= 1 << i;
+            args.push(byte);
+        }
+    }
+    for i in 0..3 {
+        let byte = ((size >> (8 * i)) & 0xff) as u8;
+        if byte != 0 {
+            opcode |= 0x10 << i;
+            args.push(byte);
+        }
+    }
+
+    out.push(opcode);
+    out.extend_from_slice(&args);
+}
+
+/// Encodes `target` as a delta against `base`: copy instructions for runs
+/// found in the base (located through an index of fixed-size base blocks)
+/// and literal inserts for everything else.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_size(&mut out, base.len());
+    write_size(&mut out, target.len());
+
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&base[start..start + BLOCK_SIZE])
+            .or_default()
+            .push(start);
+    }
+
+    let mut pending = Vec::new();
+    let mut pos = 0;
+
+    while pos < target.len() {
+        let best = target
+            .get(pos..pos + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block))
+            .and_then(|candidates| {
+                candidates
+                    .iter()
+                    .map(|&start| {
+                        let len = base[start..]
+                            .iter()
+                            .zip(&target[pos..])
+                            .take(MAX_COPY_SIZE)
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                        (start, len)
+                    })
+                    .max_by_key(|&(_, len)| len)
+            });
+
+        match best {
+            Some((mut start, forward_len)) => {
+                let mut len = forward_len;
+
+                // Grow the match backwards over bytes queued for insertion.
+                while !pending.is_empty()
+                    && start > 0
+                    && len < MAX_COPY_SIZE
+                    && base[start - 1] == *pending.last().unwrap()
+                {
+                    pending.pop();
+                    start -= 1;
+                    len += 1;
+                }
+
+                flush_insert(&mut out, &mut pending);
+                push_copy(&mut out, start, len);
+                pos += forward_len;
+            }
+            None => {
+                pending.push(target[pos]);
+                pos += 1;
+            }
+        }
+    }
+
+    flush_insert(&mut out, &mut pending);
+    out
+}
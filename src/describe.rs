@@ -0,0 +1,102 @@
+use anyhow::{Result, anyhow};
+use hex::encode;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    Repository,
+    changes::has_changes,
+    upload_pack::peel_tag,
+    walk::{CommitWalk, WalkOrder},
+};
+
+/// How many tagged commits are weighed against each other before the
+/// closest is picked, as in git.
+const MAX_CANDIDATES: usize = 10;
+
+/// Names `commit_ish`, or HEAD, after the nearest tag it can reach: the tag
+/// alone when it points at the commit itself, otherwise
+/// `<tag>-<commits since>-g<abbreviated hash>`. Only annotated tags count
+/// unless `tags` is set. With `dirty`, its value is appended when the index
+/// or the working tree differ from HEAD.
+pub fn handle_describe_command(
+    commit_ish: Option<String>,
+    tags: bool,
+    dirty: Option<String>,
+    repository: &Repository,
+) -> Result<()> {
+    let name = commit_ish.as_deref().unwrap_or("HEAD");
+    let hash = repository.resolve_commitish(name)?;
+    let commit = peel_tag(repository, &hash)?.unwrap_or(hash);
+
+    let mut description = describe(repository, &commit, tags)?;
+    if let Some(mark) = dirty
+        && has_changes(repository)?
+    {
+        description.push_str(&mark);
+    }
+    println!("{description}");
+
+    Ok(())
+}
+
+fn describe(repository: &Repository, commit: &[u8; 20], tags: bool) -> Result<String> {
+    let mut names: HashMap<[u8; 20], String> = HashMap::new();
+    let mut unannotated = false;
+    for (name, hash) in repository.list_refs()? {
+        let Some(tag) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        let target = match peel_tag(repository, &hash)? {
+            Some(target) => target,
+            None if tags => hash,
+            None => {
+                unannotated = true;
+                continue;
+            }
+        };
+        names.entry(target).or_insert_with(|| tag.to_string());
+    }
+
+    if let Some(name) = names.get(commit) {
+        return Ok(name.clone());
+    }
+
+    // Everything `commit` reaches, newest first; the tagged commits met
+    // first are the likeliest to be close.
+    let mut reachable = HashSet::new();
+    let mut candidates = Vec::new();
+    for entry in CommitWalk::new(repository, &[*commit], WalkOrder::Date)? {
+        let (hash, _) = entry?;
+        reachable.insert(hash);
+        if candidates.len() < MAX_CANDIDATES && names.contains_key(&hash) {
+            candidates.push(hash);
+        }
+    }
+
+    // The distance from a tag is the number of commits `commit` reaches
+    // that the tag does not; the first candidate found wins a tie.
+    let mut best: Option<([u8; 20], usize)> = None;
+    for candidate in candidates {
+        let mut depth = reachable.len();
+        for entry in CommitWalk::new(repository, &[candidate], WalkOrder::Date)? {
+            if reachable.contains(&entry?.0) {
+                depth -= 1;
+            }
+        }
+        if best.is_none_or(|(_, best_depth)| depth < best_depth) {
+            best = Some((candidate, depth));
+        }
+    }
+
+    match best {
+        Some((tag, depth)) => Ok(format!("{}-{depth}-g{}", names[&tag], &encode(commit)[..7])),
+        None if unannotated => Err(anyhow!(
+            "fatal: No annotated tags can describe '{}'.\nHowever, there were unannotated tags: try --tags.",
+            encode(commit)
+        )),
+        None if names.is_empty() => {
+            Err(anyhow!("fatal: No names found, cannot describe anything."))
+        }
+        None => Err(anyhow!("fatal: No tags can describe '{}'.", encode(commit))),
+    }
+}
@@ -0,0 +1,216 @@
+use crate::profile::{self, Phase};
+
+/// A region where `old[old_start..old_start + old_len]` was replaced by
+/// `new[new_start..new_start + new_len]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Computes a minimal line diff between `old` and `new` using Myers' algorithm.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<Hunk> {
+    let _span = profile::span(Phase::Diffing);
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut matches: Vec<(usize, usize)> = myers_matches(old_mid, new_mid)
+        .into_iter()
+        .map(|(x, y)| (x + prefix, y + prefix))
+        .collect();
+    matches.push((old.len() - suffix, new.len() - suffix));
+
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (prefix, prefix);
+
+    for (match_x, match_y) in matches {
+        if match_x > x || match_y > y {
+            hunks.push(Hunk {
+                old_start: x,
+                old_len: match_x - x,
+                new_start: y,
+                new_len: match_y - y,
+            });
+        }
+        x = match_x + 1;
+        y = match_y + 1;
+    }
+
+    hunks
+}
+
+fn myers_matches(old: &[&[u8]], new: &[&[u8]]) -> Vec<(usize, usize)> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = (n + m) as usize;
+
+    if max == 0 {
+        return Vec::new();
+    }
+
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k =
+            if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+
+        if d > 0 {
+            x = prev_x;
+            y = prev_y;
+        }
+    }
+
+    matches.reverse();
+    matches
+}
+
+const CONTEXT_LINES: usize = 3;
+
+/// Renders the hunks of a unified diff between `old` and `new`, each
+/// starting with its `@@ -a,b +c,d @@` header and carrying three lines of
+/// context.
+pub fn unified_diff(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let hunks = diff_lines(&old_lines, &new_lines);
+
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < hunks.len() {
+        let mut j = i;
+        while j + 1 < hunks.len()
+            && hunks[j + 1].old_start - (hunks[j].old_start + hunks[j].old_len) <= 2 * CONTEXT_LINES
+        {
+            j += 1;
+        }
+
+        let (first, last) = (hunks[i], hunks[j]);
+        let old_begin = first.old_start.saturating_sub(CONTEXT_LINES);
+        let new_begin = first.new_start - (first.old_start - old_begin);
+        let old_end = (last.old_start + last.old_len + CONTEXT_LINES).min(old_lines.len());
+        let new_end = last.new_start + last.new_len + (old_end - last.old_start - last.old_len);
+
+        out.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@",
+                hunk_range(old_begin, old_end - old_begin),
+                hunk_range(new_begin, new_end - new_begin)
+            )
+            .as_bytes(),
+        );
+        if let Some(context) = function_context(&old_lines[..old_begin]) {
+            out.push(b' ');
+            out.extend_from_slice(context);
+        }
+        out.push(b'\n');
+
+        let mut old_pos = old_begin;
+        for hunk in &hunks[i..=j] {
+            for line in &old_lines[old_pos..hunk.old_start] {
+                push_line(&mut out, b' ', line);
+            }
+            for line in &old_lines[hunk.old_start..hunk.old_start + hunk.old_len] {
+                push_line(&mut out, b'-', line);
+            }
+            for line in &new_lines[hunk.new_start..hunk.new_start + hunk.new_len] {
+                push_line(&mut out, b'+', line);
+            }
+            old_pos = hunk.old_start + hunk.old_len;
+        }
+        for line in &old_lines[old_pos..old_end] {
+            push_line(&mut out, b' ', line);
+        }
+
+        i = j + 1;
+    }
+
+    out
+}
+
+/// Finds the line shown after a hunk header: like git's default, the
+/// closest preceding line that starts with a letter, `_` or `$`, cut to 80
+/// bytes.
+fn function_context<'a>(preceding: &[&'a [u8]]) -> Option<&'a [u8]> {
+    let line = preceding.iter().rev().find(|line| {
+        line.first()
+            .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+    })?;
+
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Some(&line[..line.len().min(80)])
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+fn push_line(out: &mut Vec<u8>, marker: u8, line: &[u8]) {
+    out.push(marker);
+    out.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        out.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
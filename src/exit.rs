@@ -0,0 +1,25 @@
+use std::process::ExitCode;
+
+/// The statuses mini-git exits with. Scripts can rely on them to tell
+/// "differences were found" apart from "the command failed".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The command succeeded and, if it compared anything, found no
+    /// differences.
+    Success = 0,
+    /// A comparison run with `--exit-code` or `--quiet` found differences,
+    /// a lookup such as `config <key>` found nothing, a check such as
+    /// `verify-commit` did not pass, or a merge stopped on conflicts.
+    Differences = 1,
+    /// The command failed, for example on a missing object or a refused
+    /// update. The error has been printed to stderr.
+    Failure = 128,
+    /// The command line could not be parsed.
+    Usage = 129,
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::fs;
+
+use crate::{
+    Repository,
+    config::Config,
+    merge::is_ancestor,
+    messages::tr,
+    transport::{FetchSource, Refspec, short_ref_name},
+};
+
+pub struct FetchResult {
+    /// Summary lines in the style of `git fetch`, one per updated ref.
+    pub lines: Vec<String>,
+    pub rejected: bool,
+}
+
+/// Fetches the remote refs matched by `refspecs` along with any tags that
+/// point into the fetched history, updates their local counterparts, and
+/// records everything fetched in `FETCH_HEAD`. Refspecs without a `:` only
+/// land in `FETCH_HEAD`.
+pub fn fetch(repository: &Repository, url: &str, refspecs: &[String]) -> Result<FetchResult> {
+    let remote = FetchSource::open(&Config::load(repository)?, url)?;
+    let remote_refs = remote.refs()?;
+
+    let merge_ref = match repository.head_ref()? {
+        Some(head) => Config::load(repository)?
+            .get(&format!("branch.{}.merge", short_ref_name(&head)))
+            .map(str::to_string),
+        None => None,
+    };
+
+    let mut updates: Vec<(String, [u8; 20], Option<String>, bool)> = Vec::new();
+    for spec in refspecs {
+        let store = spec.contains(':');
+        let refspec = Refspec::parse(spec)?;
+        let glob = refspec.src.ends_with('*');
+
+        let mut matched = false;
+        for (name, sha1) in &remote_refs {
+            let Some(dst) = refspec.map(name) else {
+                continue;
+            };
+            matched = true;
+            if updates.iter().any(|(existing, ..)| existing == name) {
+                continue;
+            }
+
+            updates.push((name.clone(), *sha1, store.then_some(dst), refspec.force));
+        }
+
+        if !matched && !glob {
+            return Err(anyhow!("fatal: couldn't find remote ref {}", refspec.src));
+        }
+    }
+
+    let tips: Vec<[u8; 20]> = updates.iter().map(|(_, sha1, ..)| *sha1).collect();
+    remote.fetch_objects(repository, &tips, None)?;
+
+    // Follow tags whose target is now available locally.
+    for (name, sha1) in &remote_refs {
+        if !name.starts_with("refs/tags/")
+            || repository.read_ref(name)?.is_some()
+            || updates.iter().any(|(existing, ..)| existing == name)
+        {
+            continue;
+        }
+        let target = remote.peel_tag(name, sha1)?;
+
+        if target.is_some_and(|target| repository.has_object(&target).unwrap_or(false)) {
+            remote.fetch_objects(repository, &[*sha1], None)?;
+            updates.push((name.clone(), *sha1, Some(name.clone()), false));
+        }
+    }
+
+    let mut result = FetchResult {
+        lines: Vec::new(),
+        rejected: false,
+    };
+    let mut fetch_head = String::new();
+
+    for (name, new, dst, force) in &updates {
+        let kind = if name.starts_with("refs/tags/") {
+            "tag"
+        } else {
+            "branch"
+        };
+        let for_merge = kind == "branch"
+            && (merge_ref.as_deref() == Some(name.as_str())
+                || refspecs.iter().all(|spec| !spec.contains('*')));
+        fetch_head.push_str(&format!(
+            "{}\t{}\t{} '{}' of {}\n",
+            encode(new),
+            if for_merge { "" } else { "not-for-merge" },
+            kind,
+            short_ref_name(name),
+            url
+        ));
+
+        let Some(dst) = dst else {
+            continue;
+        };
+        let label = format!("{} -> {}", short_ref_name(name), short_remote_name(dst));
+        let old = repository.read_ref(dst)?;
+
+        match old {
+            Some(old) if old == *new => {}
+            Some(old) => {
+                let fast_forward = is_ancestor(repository, &old, new)?;
+                if !fast_forward && !force {
+                    result.lines.push(format!(
+                        " ! {:<17} {} (non-fast-forward)",
+                        "[rejected]", label
+                    ));
+                    result.rejected = true;
+                    continue;
+                }
+
+                repository.write_ref(dst, new)?;
+                let range = format!("{}..{}", &encode(old)[..7], &encode(new)[..7]);
+                if fast_forward {
+                    result.lines.push(format!("   {range:<17} {label}"));
+                } else {
+                    result
+                        .lines
+                        .push(format!(" + {range:<17} {label} (forced update)"));
+                }
+            }
+            None => {
+                repository.write_ref(dst, new)?;
+                let kind = format!("[new {kind}]");
+                result.lines.push(format!(" * {kind:<17} {label}"));
+            }
+        }
+    }
+
+    let fetch_head_file = repository.git_dir.join("FETCH_HEAD");
+    fs::write(&fetch_head_file, fetch_head)
+        .with_context(|| format!("Failed to write {}", fetch_head_file.display()))?;
+
+    Ok(result)
+}
+
+fn short_remote_name(name: &str) -> &str {
+    name.strip_prefix("refs/remotes/")
+        .unwrap_or_else(|| short_ref_name(name))
+}
+
+/// Looks up the URL and configured fetch refspecs of `remote`, which may
+/// also be a URL or path given directly, in which case it has no refspecs.
+pub fn remote_source(config: &Config, remote: &str) -> Result<(String, Vec<String>)> {
+    match config.get(&format!("remote.{remote}.url")) {
+        Some(url) => Ok((
+            url.to_string(),
+            config
+                .get_all(&format!("remote.{remote}.fetch"))
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        )),
+        None if remote.contains('/') || remote.contains("://") => {
+            Ok((remote.to_string(), Vec::new()))
+        }
+        None => Err(anyhow!(
+            "fatal: '{}' does not appear to be a mini-git repository",
+            remote
+        )),
+    }
+}
+
+/// Prints the summary of a fetch from `url`, failing if any ref was refused.
+pub fn report_fetch(url: &str, result: &FetchResult) -> Result<()> {
+    if !result.lines.is_empty() {
+        eprintln!("{}", tr!("From {}", url));
+        for line in &result.lines {
+            eprintln!("{line}");
+        }
+    }
+
+    if result.rejected {
+        return Err(anyhow!("error: some local refs could not be updated"));
+    }
+
+    Ok(())
+}
+
+pub fn handle_fetch_command(
+    remote: Option<String>,
+    refspecs: Vec<String>,
+    repository: &Repository,
+) -> Result<()> {
+    let remote = remote.unwrap_or_else(|| "origin".to_string());
+    let config = Config::load(repository)?;
+
+    let (url, configured) = remote_source(&config, &remote)?;
+    let refspecs = if refspecs.is_empty() {
+        configured
+    } else {
+        refspecs
+    };
+
+    if refspecs.is_empty() {
+        return Err(anyhow!("fatal: no refspecs configured for '{}'", remote));
+    }
+
+    let result = fetch(repository, &url, &refspecs)?;
+    report_fetch(&url, &result)
+}
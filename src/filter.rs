@@ -0,0 +1,322 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::{
+    Repository,
+    attributes::{AttrState, Attributes},
+    config::Config,
+};
+
+/// Line endings as a text file has them in the working tree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// How the line endings of a path are converted. Text is stored with LF
+/// endings and checked out with the given ones.
+#[derive(Clone, Copy)]
+enum Conversion {
+    Binary,
+    Text(Eol),
+    /// Text unless the content looks binary.
+    Auto(Eol),
+}
+
+/// What `core.safecrlf` asks for when converting line endings on the way in
+/// would not survive a checkout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SafeCrlf {
+    Off,
+    Warn,
+    Fail,
+}
+
+/// The line endings in some content, counted by kind.
+#[derive(Default)]
+struct LineStats {
+    crlf: usize,
+    lone_lf: usize,
+    lone_cr: usize,
+    nul: usize,
+}
+
+impl LineStats {
+    fn of(content: &[u8]) -> Self {
+        let mut stats = LineStats::default();
+        for (i, &byte) in content.iter().enumerate() {
+            match byte {
+                b'\r' if content.get(i + 1) == Some(&b'\n') => stats.crlf += 1,
+                b'\r' => stats.lone_cr += 1,
+                b'\n' if i == 0 || content[i - 1] != b'\r' => stats.lone_lf += 1,
+                0 => stats.nul += 1,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Whether content of unknown kind is better left alone.
+    fn looks_binary(&self) -> bool {
+        self.nul > 0 || self.lone_cr > 0
+    }
+}
+
+/// Converts content between the working tree and the repository: the
+/// filter driver the `filter` attribute names, then line endings.
+///
+/// A driver `<name>` is configured as `filter.<name>.clean`, which turns
+/// working tree content into what is stored, and `filter.<name>.smudge`,
+/// which does the reverse on checkout. Either command may use `%f` for the
+/// path. A driver without the command needed passes content through
+/// unchanged, as does one that fails, unless `filter.<name>.required` is
+/// set.
+///
+/// Text, as the `text` attribute or `core.autocrlf` decide, is stored with
+/// LF line endings. It is checked out with the endings the `eol` attribute
+/// names, or else CRLF under `core.autocrlf=true` or `core.eol=crlf`.
+/// `text=auto`, and `core.autocrlf` for paths without a `text` attribute,
+/// only convert content that does not look binary.
+pub struct Filters {
+    work_dir: PathBuf,
+    config: Config,
+    attributes: Attributes,
+    safe_crlf: SafeCrlf,
+}
+
+impl Filters {
+    pub fn load(repository: &Repository) -> Result<Self> {
+        Ok(Filters {
+            work_dir: repository.work_dir(),
+            config: Config::load(repository)?,
+            attributes: Attributes::load(repository)?,
+            safe_crlf: SafeCrlf::Off,
+        })
+    }
+
+    /// Checks, as `core.safecrlf` asks, that the line endings `clean`
+    /// converts come back the same on checkout: by default a warning is
+    /// printed when they would not, and with `true` it is an error.
+    pub fn check_round_trip(mut self) -> Self {
+        self.safe_crlf = match self.config.get("core.safecrlf") {
+            Some("false" | "no" | "off" | "0") => SafeCrlf::Off,
+            Some("true" | "yes" | "on" | "1") => SafeCrlf::Fail,
+            _ => SafeCrlf::Warn,
+        };
+        self
+    }
+
+    /// The content of the working tree file `path` as it is to be stored.
+    pub fn clean(&mut self, path: &Path, content: Vec<u8>) -> Result<Vec<u8>> {
+        let attrs = self.attributes.all(path)?;
+        let content = self.run_driver(path, &attrs, "clean", content)?;
+        let text = self.text(&attrs);
+        self.crlf_to_lf(path, text, content)
+    }
+
+    /// Whether `clean` stores `path` exactly as it is in the working tree,
+    /// with no driver to run and line endings left alone, so its content
+    /// can be streamed rather than read whole.
+    pub fn passes_through(&mut self, path: &Path) -> Result<bool> {
+        let attrs = self.attributes.all(path)?;
+        Ok(!matches!(attr(&attrs, "filter"), AttrState::Value(_))
+            && matches!(self.text(&attrs), Conversion::Binary))
+    }
+
+    /// The content stored for `path` as it is to be written out.
+    pub fn smudge(&mut self, path: &Path, content: Vec<u8>) -> Result<Vec<u8>> {
+        let attrs = self.attributes.all(path)?;
+        let content = lf_to_crlf(self.text(&attrs), content);
+        self.run_driver(path, &attrs, "smudge", content)
+    }
+
+    fn run_driver(
+        &self,
+        path: &Path,
+        attrs: &[(String, AttrState)],
+        direction: &str,
+        content: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let AttrState::Value(driver) = attr(attrs, "filter") else {
+            return Ok(content);
+        };
+        let required = self
+            .config
+            .get(&format!("filter.{driver}.required"))
+            .is_some_and(|value| {
+                matches!(
+                    value.to_ascii_lowercase().as_str(),
+                    "true" | "yes" | "on" | "1"
+                )
+            });
+        let Some(command) = self.config.get(&format!("filter.{driver}.{direction}")) else {
+            if required {
+                return Err(anyhow!(
+                    "fatal: {}: {direction} filter '{driver}' failed",
+                    path.display()
+                ));
+            }
+            return Ok(content);
+        };
+
+        match run_command(&self.work_dir, command, path, &content) {
+            Ok(filtered) => Ok(filtered),
+            Err(error) if required => Err(error.context(format!(
+                "fatal: {}: {direction} filter '{driver}' failed",
+                path.display()
+            ))),
+            Err(error) => {
+                eprintln!("{error}");
+                Ok(content)
+            }
+        }
+    }
+
+    /// How line endings are converted for a path with `attrs`.
+    fn text(&self, attrs: &[(String, AttrState)]) -> Conversion {
+        let autocrlf = self
+            .config
+            .get("core.autocrlf")
+            .map(str::to_ascii_lowercase);
+        let eol_attr = match attr(attrs, "eol") {
+            AttrState::Value(value) if value == "crlf" => Some(Eol::Crlf),
+            AttrState::Value(value) if value == "lf" => Some(Eol::Lf),
+            _ => None,
+        };
+        let eol = eol_attr.unwrap_or(match autocrlf.as_deref() {
+            Some("true" | "yes" | "on" | "1") => Eol::Crlf,
+            Some("input") => Eol::Lf,
+            _ if self.config.get("core.eol") == Some("crlf") => Eol::Crlf,
+            _ => Eol::Lf,
+        });
+
+        match attr(attrs, "text") {
+            AttrState::Set => Conversion::Text(eol),
+            AttrState::Unset => Conversion::Binary,
+            AttrState::Value(value) if value == "auto" => Conversion::Auto(eol),
+            // An `eol` attribute makes a path text by itself.
+            _ if eol_attr.is_some() => Conversion::Text(eol),
+            _ => match autocrlf.as_deref() {
+                Some("true" | "yes" | "on" | "1" | "input") => Conversion::Auto(eol),
+                _ => Conversion::Binary,
+            },
+        }
+    }
+
+    /// Turns CRLF line endings into LF in text that is to be stored.
+    fn crlf_to_lf(&self, path: &Path, text: Conversion, content: Vec<u8>) -> Result<Vec<u8>> {
+        let stats = LineStats::of(&content);
+        let eol = match text {
+            Conversion::Binary => return Ok(content),
+            Conversion::Auto(_) if stats.looks_binary() => return Ok(content),
+            Conversion::Text(eol) | Conversion::Auto(eol) => eol,
+        };
+
+        if self.safe_crlf != SafeCrlf::Off {
+            let (from, to) = match eol {
+                Eol::Lf if stats.crlf > 0 => ("CRLF", "LF"),
+                Eol::Crlf if stats.lone_lf > 0 => ("LF", "CRLF"),
+                _ => ("", ""),
+            };
+            if !from.is_empty() {
+                if self.safe_crlf == SafeCrlf::Fail {
+                    return Err(anyhow!(
+                        "fatal: {from} would be replaced by {to} in {}",
+                        path.display()
+                    ));
+                }
+                eprintln!(
+                    "warning: in the working copy of '{}', {from} will be replaced by {to} \
+the next time mini-git touches it",
+                    path.display()
+                );
+            }
+        }
+
+        if stats.crlf == 0 {
+            return Ok(content);
+        }
+        let mut converted = Vec::with_capacity(content.len() - stats.crlf);
+        for (i, &byte) in content.iter().enumerate() {
+            if byte != b'\r' || content.get(i + 1) != Some(&b'\n') {
+                converted.push(byte);
+            }
+        }
+        Ok(converted)
+    }
+}
+
+/// Turns LF line endings into CRLF in text that is to be checked out with
+/// them. Text stored with CRs in it is left alone when its kind was only
+/// guessed, as it was not normalized on the way in.
+fn lf_to_crlf(text: Conversion, content: Vec<u8>) -> Vec<u8> {
+    let stats = LineStats::of(&content);
+    match text {
+        Conversion::Text(Eol::Crlf) => {}
+        Conversion::Auto(Eol::Crlf) if !stats.looks_binary() && stats.crlf == 0 => {}
+        _ => return content,
+    }
+    if stats.lone_lf == 0 {
+        return content;
+    }
+
+    let mut converted = Vec::with_capacity(content.len() + stats.lone_lf);
+    for (i, &byte) in content.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+/// The state `attrs` gives the attribute `name`.
+fn attr<'a>(attrs: &'a [(String, AttrState)], name: &str) -> &'a AttrState {
+    attrs
+        .iter()
+        .find(|(attr, _)| attr == name)
+        .map_or(&AttrState::Unspecified, |(_, state)| state)
+}
+
+/// Runs `command` with `%f` standing for `path`, feeding it `content` and
+/// returning what it prints.
+fn run_command(work_dir: &Path, command: &str, path: &Path, content: &[u8]) -> Result<Vec<u8>> {
+    let quoted = format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"));
+    let expanded = command.replace("%f", &quoted);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&expanded)
+        .current_dir(work_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("error: cannot fork to run external filter '{command}'"))?;
+
+    // Written from another thread so that a filter producing output before
+    // it has read all its input cannot block on a full pipe.
+    let mut stdin = child.stdin.take().context("Failed to open filter stdin")?;
+    let output = thread::scope(|scope| {
+        scope.spawn(move || {
+            // A filter may stop reading early; its exit status tells.
+            let _ = stdin.write_all(content);
+        });
+        child.wait_with_output()
+    })
+    .with_context(|| format!("error: external filter '{command}' failed"))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "error: external filter '{}' failed {}",
+            command,
+            output.status.code().unwrap_or(-1)
+        ));
+    }
+    Ok(output.stdout)
+}
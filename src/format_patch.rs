@@ -0,0 +1,285 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    CommitObject, Repository,
+    changes::{DiffOutputArgs, commit_files, compare, parent_files, write_changes, write_summary},
+    ident::Ident,
+    walk::{CommitWalk, WalkOrder},
+};
+
+/// The date every mbox `From ` line carries, as git writes it, so that the
+/// line marks the start of a message without saying anything about it.
+const MBOX_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+/// How wide a header line may get before it is folded.
+const MAIL_WIDTH: usize = 78;
+
+/// How long a patch file name may get, its suffix included.
+const NAME_MAX: usize = 64;
+
+/// Where the patches go and how they are labelled.
+pub struct FormatPatchOptions {
+    pub output_directory: Option<PathBuf>,
+    pub stdout: bool,
+    /// Number the subject even when there is a single patch.
+    pub numbered: bool,
+    /// Take `range` as a commit and include everything up to it, down to
+    /// the root.
+    pub root: bool,
+}
+
+/// Writes each non-merge commit in `range` as an email: `<since>` for the
+/// commits on HEAD since then, `<since>..<until>`, or with `root` all the
+/// history up to a commit. Every patch goes in its own numbered file,
+/// whose name is printed, or all of them to stdout.
+pub fn handle_format_patch_command(
+    range: &str,
+    options: FormatPatchOptions,
+    repository: &Repository,
+) -> Result<()> {
+    let (since, until) = match range.split_once("..") {
+        Some((since, until)) => (since, if until.is_empty() { "HEAD" } else { until }),
+        None if options.root => ("", range),
+        None => (range, "HEAD"),
+    };
+
+    let mut excluded = HashSet::new();
+    if !since.is_empty() {
+        let since = repository.resolve_commitish(since)?;
+        for entry in CommitWalk::new(repository, &[since], WalkOrder::Date)? {
+            excluded.insert(entry?.0);
+        }
+    }
+
+    let until = repository.resolve_commitish(until)?;
+    let mut commits = Vec::new();
+    for entry in CommitWalk::new(repository, &[until], WalkOrder::Topo)?.reverse(true) {
+        let (hash, commit) = entry?;
+        if !excluded.contains(&hash) && commit.parents()?.len() <= 1 {
+            commits.push((hash, commit));
+        }
+    }
+
+    if let Some(directory) = &options.output_directory {
+        fs::create_dir_all(directory).with_context(|| {
+            format!(
+                "fatal: could not create directory '{}'",
+                directory.display()
+            )
+        })?;
+    }
+
+    let total = commits.len();
+    let mut stdout = io::stdout().lock();
+    for (i, (hash, commit)) in commits.iter().enumerate() {
+        let number = if total > 1 || options.numbered {
+            format!(" {}/{total}", i + 1)
+        } else {
+            String::new()
+        };
+        let patch = format_patch(repository, hash, commit, &number)?;
+
+        if options.stdout {
+            if i > 0 {
+                stdout.write_all(b"\n")?;
+            }
+            stdout.write_all(&patch)?;
+            continue;
+        }
+
+        let name = patch_file_name(i + 1, &subject(&commit.message()));
+        let path = match &options.output_directory {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name),
+        };
+        fs::write(&path, patch)
+            .with_context(|| format!("fatal: could not open '{}' for writing", path.display()))?;
+        writeln!(stdout, "{}", path.display())?;
+    }
+
+    Ok(())
+}
+
+/// One commit as an email: mail headers, the rest of the message, a
+/// diffstat with the files created and deleted, and the patch itself.
+fn format_patch(
+    repository: &Repository,
+    hash: &[u8; 20],
+    commit: &CommitObject,
+    number: &str,
+) -> Result<Vec<u8>> {
+    let author = commit
+        .header("author")
+        .ok_or_else(|| anyhow!("fatal: commit {} has no author", encode(hash)))?;
+    let author = Ident::parse(&author)?;
+    let message = commit.message();
+
+    let mut out = Vec::new();
+    writeln!(out, "From {} {MBOX_DATE}", encode(hash))?;
+    writeln!(
+        out,
+        "From: {} <{}>",
+        encode_header(&author.name, true),
+        author.email
+    )?;
+    writeln!(out, "Date: {}", author.format_rfc2822())?;
+    let subject = format!(
+        "Subject: [PATCH{number}] {}",
+        encode_header(&subject(&message), false)
+    );
+    writeln!(out, "{}", fold_header(&subject))?;
+    if !message.is_ascii() {
+        writeln!(out, "MIME-Version: 1.0")?;
+        writeln!(out, "Content-Type: text/plain; charset=UTF-8")?;
+        writeln!(out, "Content-Transfer-Encoding: 8bit")?;
+    }
+    writeln!(out)?;
+    let body = body(&message);
+    if !body.is_empty() {
+        writeln!(out, "{body}")?;
+    }
+    writeln!(out, "---")?;
+
+    let changes = compare(
+        &parent_files(repository, &commit.parents()?)?,
+        &commit_files(repository, hash)?,
+    );
+    let args = DiffOutputArgs {
+        name_only: false,
+        name_status: false,
+        stat: false,
+        nul_terminated: false,
+        binary: true,
+    };
+    if !changes.is_empty() {
+        write_changes(
+            repository,
+            &changes,
+            DiffOutputArgs { stat: true, ..args },
+            &mut out,
+        )?;
+        write_summary(&changes, &mut out)?;
+        writeln!(out)?;
+        write_changes(repository, &changes, args, &mut out)?;
+    }
+
+    writeln!(out, "-- ")?;
+    writeln!(out, "mini-git {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out)?;
+
+    Ok(out)
+}
+
+/// The first paragraph of a message, joined into one line.
+fn subject(message: &str) -> String {
+    message
+        .trim_start()
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything after the first paragraph, without surrounding blank lines.
+fn body(message: &str) -> String {
+    let mut lines = message.trim_start().lines();
+    lines
+        .by_ref()
+        .take_while(|line| !line.trim().is_empty())
+        .for_each(drop);
+    let rest: Vec<&str> = lines.collect();
+    rest.join("\n").trim_matches('\n').trim_end().to_string()
+}
+
+/// `NNNN-<subject>.patch`, with the subject reduced to letters, digits,
+/// `.` and `_` and every run of anything else turned into one `-`.
+fn patch_file_name(number: usize, subject: &str) -> String {
+    const SUFFIX: &str = ".patch";
+
+    let mut name = format!("{number:04}-");
+    let start = name.len();
+    let mut separated = false;
+    let mut chars = subject.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if separated && name.len() > start {
+                name.push('-');
+            }
+            separated = false;
+            name.push(c);
+            // A run of dots would read as `..` in a path.
+            while c == '.' && chars.peek() == Some(&'.') {
+                chars.next();
+            }
+        } else {
+            separated = true;
+        }
+    }
+    while name.len() > start && name.ends_with(['.', '-']) {
+        name.pop();
+    }
+
+    name.truncate(NAME_MAX - SUFFIX.len() - 1);
+    name.push_str(SUFFIX);
+    name
+}
+
+/// Breaks a header longer than a mail line should be at spaces, going on
+/// in lines indented by one space.
+fn fold_header(header: &str) -> String {
+    let mut folded = String::with_capacity(header.len());
+    let mut line_length = 0;
+    for word in header.split(' ') {
+        if line_length == 0 {
+            line_length = word.len();
+        } else if line_length + 1 + word.len() > MAIL_WIDTH {
+            folded.push_str("\n ");
+            line_length = 1 + word.len();
+        } else {
+            folded.push(' ');
+            line_length += 1 + word.len();
+        }
+        folded.push_str(word);
+    }
+    folded
+}
+
+/// Makes `text` safe for a mail header. Text that is not ASCII becomes an
+/// RFC 2047 encoded word; a name in an address that contains characters
+/// special there is quoted instead.
+fn encode_header(text: &str, address: bool) -> String {
+    if text.is_ascii() {
+        if address
+            && text.contains([
+                '(', ')', '<', '>', '@', ',', ';', ':', '\\', '"', '.', '[', ']',
+            ])
+        {
+            return format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        return text.to_string();
+    }
+
+    let mut encoded = String::from("=?UTF-8?q?");
+    for &byte in text.as_bytes() {
+        let special = !byte.is_ascii()
+            || matches!(byte, b'=' | b'?' | b'_' | b' ' | b'\n')
+            || (address
+                && !(byte.is_ascii_alphanumeric()
+                    || matches!(byte, b'!' | b'*' | b'+' | b'-' | b'/')));
+        if special {
+            encoded.push_str(&format!("={byte:02X}"));
+        } else {
+            encoded.push(byte as char);
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
@@ -0,0 +1,250 @@
+use anyhow::Result;
+use hex::encode;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Repository,
+    exit::ExitStatus,
+    hash_content,
+    messages::tr,
+    pack::{PackObjectType, parse_pack, resolve_entries},
+    pack_index::verify_pack,
+    pack_reader::map_file,
+    refs::parse_hash,
+    transport::reachable_objects,
+};
+
+/// How many of the longest chains, widest trees and deepest paths the
+/// statistics list.
+const TOP: usize = 10;
+
+/// Checks that every loose object hashes to its name, that every pack
+/// agrees with its index, and that everything reachable from the refs,
+/// HEAD and the index is there. With `stats`, it then reports what
+/// maintenance might look at: the longest delta chains in the packs, the
+/// widest trees, the deepest paths, and files in `objects/pack` that no
+/// pack uses.
+pub fn handle_fsck_command(stats: bool, repository: &Repository) -> Result<ExitStatus> {
+    let mut status = ExitStatus::Success;
+
+    for hash in repository.loose_objects()? {
+        let name = encode(hash);
+        let checked = repository
+            .read_raw_object(&name)
+            .map(|(object_type, content)| {
+                let mut object = format!("{object_type} {}\0", content.len()).into_bytes();
+                object.extend_from_slice(&content);
+                hash_content(&object) == hash
+            });
+        match checked {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("error: hash mismatch for object {name}");
+                status = ExitStatus::Differences;
+            }
+            Err(_) => {
+                eprintln!("error: object {name} is corrupt");
+                status = ExitStatus::Differences;
+            }
+        }
+    }
+
+    for idx_path in pack_indexes(repository)? {
+        let pack_path = idx_path.with_extension("pack");
+        if !pack_path.is_file() {
+            continue;
+        }
+        if let Err(error) = verify_pack(&idx_path, &pack_path, false, false) {
+            eprintln!("error: {}: {}", pack_path.display(), error);
+            status = ExitStatus::Differences;
+        }
+    }
+
+    if let Err(error) =
+        reachable_objects(repository, &repository.reachability_roots()?, |_| Ok(false))
+    {
+        eprintln!("error: {error}");
+        status = ExitStatus::Differences;
+    }
+
+    if stats {
+        print_stats(repository)?;
+    }
+
+    Ok(status)
+}
+
+/// The `.idx` files in `objects/pack`, in name order.
+fn pack_indexes(repository: &Repository) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = pack_dir_files(repository)?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn pack_dir_files(repository: &Repository) -> Result<Vec<PathBuf>> {
+    let pack_dir = repository.objects_dir.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(&pack_dir)? {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn print_stats(repository: &Repository) -> Result<()> {
+    println!("{}", tr!("Longest delta chains:"));
+    for (depth, hash, pack) in delta_chains(repository)? {
+        println!("{depth:>6} {} {pack}", encode(hash));
+    }
+
+    let mut objects = objects_by_type(repository)?;
+
+    let mut widest: Vec<(usize, [u8; 20])> = Vec::new();
+    for tree in objects.remove("tree").unwrap_or_default() {
+        widest.push((repository.read_tree(&tree)?.len(), tree));
+    }
+    widest.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    println!("{}", tr!("Widest trees:"));
+    for (width, hash) in widest.into_iter().take(TOP) {
+        println!("{width:>6} {}", encode(hash));
+    }
+
+    let mut deepest = HashMap::new();
+    let mut paths: Vec<(usize, PathBuf)> = Vec::new();
+    for commit in objects.remove("commit").unwrap_or_default() {
+        let tree = parse_hash(&repository.read_commit(&commit)?.tree_hash()?)?;
+        let path = deepest_path(repository, &tree, &mut deepest)?;
+        if !path.as_os_str().is_empty() && !paths.iter().any(|(_, seen)| *seen == path) {
+            paths.push((path.components().count(), path));
+        }
+    }
+    paths.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    println!("{}", tr!("Deepest paths:"));
+    for (depth, path) in paths.into_iter().take(TOP) {
+        println!("{depth:>6} {}", path.display());
+    }
+
+    println!("{}", tr!("Stray pack files:"));
+    for (name, reason) in stray_pack_files(repository)? {
+        println!("  {name}: {reason}");
+    }
+
+    Ok(())
+}
+
+/// The objects at the end of the longest delta chains in the packs, with
+/// the chain's length and the pack's file name, longest first.
+fn delta_chains(repository: &Repository) -> Result<Vec<(usize, [u8; 20], String)>> {
+    let mut chains = Vec::new();
+    for idx_path in pack_indexes(repository)? {
+        let pack_path = idx_path.with_extension("pack");
+        if !pack_path.is_file() {
+            continue;
+        }
+        let data = map_file(&pack_path)?;
+        let name = file_name(&pack_path);
+        let entries = parse_pack(&data)?;
+        let external = |hash: &[u8; 20]| {
+            let (object_type, content) = repository.read_raw_object(&encode(hash))?;
+            Ok(Some((PackObjectType::from_name(&object_type)?, content)))
+        };
+        for object in resolve_entries(&entries, external)? {
+            if object.depth > 0 {
+                chains.push((object.depth, object.hash, name.clone()));
+            }
+        }
+    }
+
+    chains.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    chains.truncate(TOP);
+    Ok(chains)
+}
+
+/// Every object in the repository, loose or packed, by type.
+fn objects_by_type(repository: &Repository) -> Result<HashMap<String, Vec<[u8; 20]>>> {
+    let mut objects: HashSet<[u8; 20]> = repository.loose_objects()?.into_iter().collect();
+    for pack in repository.packs()? {
+        objects.extend(pack.index.entries.iter().map(|entry| entry.hash));
+    }
+    let mut objects: Vec<[u8; 20]> = objects.into_iter().collect();
+    objects.sort();
+
+    let mut by_type: HashMap<String, Vec<[u8; 20]>> = HashMap::new();
+    for hash in objects {
+        by_type
+            .entry(repository.read_object_type(&encode(hash))?)
+            .or_default()
+            .push(hash);
+    }
+    Ok(by_type)
+}
+
+/// The path under `tree` with the most components, remembering the
+/// answer for each tree in `known`, since most trees appear in many
+/// commits.
+fn deepest_path(
+    repository: &Repository,
+    tree: &[u8; 20],
+    known: &mut HashMap<[u8; 20], PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(path) = known.get(tree) {
+        return Ok(path.clone());
+    }
+
+    let mut deepest = PathBuf::new();
+    for entry in repository.read_tree(tree)? {
+        let path = if entry.mode == 40000 {
+            entry
+                .path
+                .join(deepest_path(repository, &entry.sha1, known)?)
+        } else {
+            entry.path
+        };
+        if path.components().count() > deepest.components().count() {
+            deepest = path;
+        }
+    }
+
+    known.insert(*tree, deepest.clone());
+    Ok(deepest)
+}
+
+/// Files in `objects/pack` that are not part of a complete pack: a pack
+/// without its index, an index or other companion without its pack, and
+/// temporary files left by an interrupted write.
+fn stray_pack_files(repository: &Repository) -> Result<Vec<(String, String)>> {
+    let mut stray = Vec::new();
+    for path in pack_dir_files(repository)? {
+        let name = file_name(&path);
+        let reason = if name.starts_with("tmp_") {
+            tr!("temporary file")
+        } else if path.extension().is_some_and(|ext| ext == "pack") {
+            if path.with_extension("idx").is_file() {
+                continue;
+            }
+            tr!("no index")
+        } else if path.with_extension("pack").is_file() {
+            continue;
+        } else {
+            tr!("no pack")
+        };
+        stray.push((name, reason));
+    }
+    Ok(stray)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
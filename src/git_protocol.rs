@@ -0,0 +1,131 @@
+use anyhow::{Context, Result, anyhow};
+use std::{io::Read, net::TcpStream};
+
+use crate::{
+    Repository,
+    pack::unpack_pack,
+    protocol::{
+        Advertisement, pack_from_response, packfile_from_response, read_advertisement,
+        read_ls_refs, write_fetch_request, write_fetch_request_v2, write_flush,
+        write_ls_refs_request, write_pkt_line,
+    },
+    transport::local_haves,
+};
+
+const DEFAULT_PORT: u16 = 9418;
+
+/// A repository served by a git daemon over `git://`, with the refs it
+/// advertised when first contacted. Every exchange uses its own connection,
+/// since the server hangs up once it has sent a pack.
+pub struct GitRemote {
+    address: String,
+    host: String,
+    path: String,
+    /// Whether to ask the daemon for protocol version 2.
+    version: u8,
+    pub advertisement: Advertisement,
+}
+
+impl GitRemote {
+    pub fn connect(url: &str, version: u8) -> Result<Self> {
+        let rest = url
+            .strip_prefix("git://")
+            .ok_or_else(|| anyhow!("fatal: unsupported protocol in '{}'", url))?;
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => return Err(anyhow!("fatal: no path specified in '{}'", url)),
+        };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:{DEFAULT_PORT}")
+        };
+
+        let path = path.trim_end_matches('/');
+
+        let (mut stream, mut advertisement) = upload_pack(&address, host, path, version)?;
+        if advertisement.version == 2 {
+            write_ls_refs_request(&mut stream)?;
+            read_ls_refs(&mut stream, &mut advertisement)?;
+        }
+        // An empty request tells the server we only wanted the refs, or
+        // under version 2 that there are no more commands.
+        write_flush(&mut stream)?;
+
+        Ok(GitRemote {
+            address,
+            host: host.to_string(),
+            path: path.to_string(),
+            version: advertisement.version,
+            advertisement,
+        })
+    }
+
+    /// Downloads a pack containing everything needed to reach `wants` that
+    /// `repository` lacks, less what `filter` leaves out, and stores its
+    /// objects. Returns the number of objects received.
+    pub fn fetch_objects(
+        &self,
+        repository: &Repository,
+        wants: &[[u8; 20]],
+        filter: Option<&str>,
+    ) -> Result<usize> {
+        let mut missing = Vec::new();
+        for want in wants {
+            if !repository.has_object(want)? && !missing.contains(want) {
+                missing.push(*want);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let filter = self.advertisement.accept_filter(filter);
+        let (mut stream, _) = upload_pack(&self.address, &self.host, &self.path, self.version)?;
+        if self.version == 2 {
+            write_fetch_request_v2(&mut stream, &missing, &local_haves(repository)?, filter)?;
+            write_flush(&mut stream)?;
+        } else {
+            write_fetch_request(
+                &mut stream,
+                &missing,
+                &local_haves(repository)?,
+                &["ofs-delta", "agent=mini-git/0.1"],
+            )?;
+        }
+
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .with_context(|| format!("Failed to read pack from {}", self.address))?;
+
+        let pack = match self.version {
+            2 => packfile_from_response(&response)?,
+            _ => pack_from_response(&response)?.to_vec(),
+        };
+        let (objects, _) = unpack_pack(repository, &pack, false)?;
+        Ok(objects)
+    }
+}
+
+/// Connects to the daemon at `address` and asks for `git-upload-pack` on
+/// `path`, returning the connection once the ref advertisement has been read.
+/// Protocol version 2 is asked for as an extra parameter after the host.
+fn upload_pack(
+    address: &str,
+    host: &str,
+    path: &str,
+    version: u8,
+) -> Result<(TcpStream, Advertisement)> {
+    let mut stream = TcpStream::connect(address)
+        .with_context(|| format!("fatal: unable to connect to {address}"))?;
+
+    let mut request = format!("git-upload-pack {path}\0host={host}\0");
+    if version == 2 {
+        request.push_str("\0version=2\0");
+    }
+    write_pkt_line(&mut stream, request.as_bytes())?;
+
+    let advertisement = read_advertisement(&mut stream)?;
+    Ok((stream, advertisement))
+}
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    fs,
+    io::{self, Write},
+};
+
+use crate::{
+    Repository,
+    changes::{index_files, tree_ish_files},
+    exit::ExitStatus,
+    path_from_bytes,
+};
+
+/// Prints the lines matching `pattern` in the tracked files: their working
+/// tree copies, the versions in the index with `cached`, or those in the
+/// tree-ish `revision`, whose name then prefixes each path. Exits with
+/// status 1 when nothing matches, as git does.
+pub fn handle_grep_command(
+    pattern: &str,
+    revision: Option<String>,
+    cached: bool,
+    line_number: bool,
+    ignore_case: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let regex = Regex::parse(pattern, ignore_case)?;
+
+    let (files, prefix, from_worktree) = match revision {
+        Some(revision) => {
+            let files = tree_ish_files(repository, &revision)?;
+            (files, format!("{revision}:"), false)
+        }
+        None => (index_files(repository)?, String::new(), !cached),
+    };
+
+    let mut out = io::stdout().lock();
+    let mut found = false;
+    for (path, (mode, hash)) in &files {
+        if *mode == 160000 {
+            continue;
+        }
+        let content = if from_worktree {
+            let file = repository.work_dir().join(path_from_bytes(path));
+            if !file.is_file() {
+                continue;
+            }
+            fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?
+        } else {
+            repository.read_raw_object(&encode(hash))?.1
+        };
+
+        found |= grep_file(
+            &regex,
+            &format!("{prefix}{}", String::from_utf8_lossy(path)),
+            &content,
+            line_number,
+            &mut out,
+        )?;
+    }
+
+    Ok(if found {
+        ExitStatus::Success
+    } else {
+        ExitStatus::Differences
+    })
+}
+
+/// Writes the lines of `content` that `regex` matches, each prefixed with
+/// `name` and, with `line_number`, its line number. A binary file is only
+/// reported as matching. Returns whether anything matched.
+fn grep_file(
+    regex: &Regex,
+    name: &str,
+    content: &[u8],
+    line_number: bool,
+    out: &mut impl Write,
+) -> Result<bool> {
+    if content.is_empty() {
+        return Ok(false);
+    }
+    let binary = content.contains(&0);
+    let mut found = false;
+
+    // A final newline ends the last line rather than starting another.
+    let content = content.strip_suffix(b"\n").unwrap_or(content);
+    for (i, line) in content.split(|&b| b == b'\n').enumerate() {
+        if !regex.is_match(line) {
+            continue;
+        }
+        found = true;
+        if binary {
+            writeln!(out, "Binary file {name} matches")?;
+            break;
+        }
+
+        write!(out, "{name}:")?;
+        if line_number {
+            write!(out, "{}:", i + 1)?;
+        }
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+    }
+
+    Ok(found)
+}
+
+/// A POSIX basic regular expression, as `git grep` takes by default:
+/// literal characters, `.`, bracket expressions, `*` after any of those,
+/// and the `^` and `$` anchors. A backslash makes the next character
+/// literal.
+struct Regex {
+    atoms: Vec<(Atom, bool)>,
+    anchored_start: bool,
+    anchored_end: bool,
+    ignore_case: bool,
+}
+
+enum Atom {
+    Byte(u8),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(u8, u8)>,
+    },
+}
+
+impl Regex {
+    fn parse(pattern: &str, ignore_case: bool) -> Result<Self> {
+        let mut bytes = pattern.as_bytes();
+        let anchored_start = bytes.first() == Some(&b'^');
+        if anchored_start {
+            bytes = &bytes[1..];
+        }
+        let anchored_end = bytes.last() == Some(&b'$') && !bytes.ends_with(b"\\$");
+        if anchored_end {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+
+        let mut atoms: Vec<(Atom, bool)> = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            let atom = match bytes[i] {
+                // A star with nothing before it stands for itself.
+                b'*' if atoms.is_empty() => Atom::Byte(b'*'),
+                b'*' => {
+                    if let Some(last) = atoms.last_mut() {
+                        last.1 = true;
+                    }
+                    i += 1;
+                    continue;
+                }
+                b'.' => Atom::Any,
+                b'\\' => {
+                    i += 1;
+                    let byte = *bytes
+                        .get(i)
+                        .ok_or_else(|| anyhow!("fatal: trailing backslash in '{}'", pattern))?;
+                    Atom::Byte(byte)
+                }
+                b'[' => {
+                    let (atom, end) = parse_class(bytes, i)
+                        .ok_or_else(|| anyhow!("fatal: unmatched [ in '{}'", pattern))?;
+                    i = end;
+                    atoms.push((atom, false));
+                    continue;
+                }
+                byte => Atom::Byte(byte),
+            };
+            atoms.push((atom, false));
+            i += 1;
+        }
+
+        Ok(Regex {
+            atoms,
+            anchored_start,
+            anchored_end,
+            ignore_case,
+        })
+    }
+
+    fn is_match(&self, text: &[u8]) -> bool {
+        if self.anchored_start {
+            return self.match_here(0, text);
+        }
+        (0..=text.len()).any(|start| self.match_here(0, &text[start..]))
+    }
+
+    fn match_here(&self, atom: usize, text: &[u8]) -> bool {
+        let Some((current, star)) = self.atoms.get(atom) else {
+            return !self.anchored_end || text.is_empty();
+        };
+
+        if *star {
+            // Take as many as possible, then give them back one at a time.
+            let longest = text
+                .iter()
+                .take_while(|&&byte| self.matches(current, byte))
+                .count();
+            return (0..=longest)
+                .rev()
+                .any(|taken| self.match_here(atom + 1, &text[taken..]));
+        }
+
+        match text.first() {
+            Some(&byte) if self.matches(current, byte) => self.match_here(atom + 1, &text[1..]),
+            _ => false,
+        }
+    }
+
+    fn matches(&self, atom: &Atom, byte: u8) -> bool {
+        match atom {
+            Atom::Byte(expected) if self.ignore_case => expected.eq_ignore_ascii_case(&byte),
+            Atom::Byte(expected) => *expected == byte,
+            Atom::Any => true,
+            Atom::Class { negated, ranges } => {
+                let contains = |byte: u8| {
+                    ranges
+                        .iter()
+                        .any(|&(low, high)| (low..=high).contains(&byte))
+                };
+                let member = if self.ignore_case {
+                    contains(byte.to_ascii_lowercase()) || contains(byte.to_ascii_uppercase())
+                } else {
+                    contains(byte)
+                };
+                member != *negated
+            }
+        }
+    }
+}
+
+/// Parses the bracket expression starting at `bytes[start]`, returning it
+/// and the index just past its closing `]`.
+fn parse_class(bytes: &[u8], start: usize) -> Option<(Atom, usize)> {
+    let mut i = start + 1;
+    let negated = bytes.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    // A `]` right after the opening bracket is a member, not the end.
+    let first = i;
+    while i < bytes.len() && (bytes[i] != b']' || i == first) {
+        let low = bytes[i];
+        if bytes.get(i + 1) == Some(&b'-') && bytes.get(i + 2).is_some_and(|&b| b != b']') {
+            ranges.push((low, bytes[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((low, low));
+            i += 1;
+        }
+    }
+
+    (i < bytes.len()).then_some((Atom::Class { negated, ranges }, i + 1))
+}
@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use hex::encode;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::Repository;
+
+/// Runs the hook `name` from `.mini-git/hooks` with `args` and `env`,
+/// feeding it `input` on stdin. Anything the hook prints goes to stderr, so
+/// that it cannot interfere with a protocol spoken on stdout. Returns
+/// whether the hook succeeded; a hook that does not exist or is not
+/// executable always does.
+///
+/// As in git, the hook finds the repository through `GIT_DIR`,
+/// `GIT_WORK_TREE` and `GIT_INDEX_FILE`, which name the paths this process
+/// is using, so that commands it runs see the same repository however it
+/// was found.
+pub fn run_hook(
+    repository: &Repository,
+    name: &str,
+    args: &[&str],
+    env: &[(&str, String)],
+    input: &[u8],
+) -> Result<bool> {
+    let hook = repository.mini_git_dir.join("hooks").join(name);
+    if !is_executable(&hook) {
+        return Ok(true);
+    }
+
+    // Hooks run from the top of the working tree, or from the repository
+    // itself when it has none.
+    let work_dir = if repository.is_bare() {
+        repository.mini_git_dir.clone()
+    } else {
+        repository.work_dir()
+    };
+
+    let mut command = Command::new(&hook);
+    command
+        .args(args)
+        .current_dir(&work_dir)
+        .env("GIT_DIR", &repository.git_dir)
+        .env("GIT_INDEX_FILE", &repository.index_file)
+        .envs(env.iter().map(|(name, value)| (name, value)));
+    if !repository.is_bare() {
+        command.env("GIT_WORK_TREE", &work_dir);
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::from(io::stderr()))
+        .spawn()
+        .with_context(|| format!("fatal: cannot run {} hook", name))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook may exit without reading its input.
+        let _ = stdin.write_all(input);
+    }
+
+    let status = child.wait()?;
+    // The hook may have moved refs behind this process's back.
+    repository.invalidate_refs();
+
+    Ok(status.success())
+}
+
+/// Runs the `post-checkout` hook after HEAD moved from `old`, if it pointed
+/// anywhere, to `new`, with `branch` telling a switch of branches from a
+/// checkout of paths. Like every `post-` hook, it cannot change the
+/// outcome, so its status is ignored.
+pub fn run_post_checkout(
+    repository: &Repository,
+    old: Option<[u8; 20]>,
+    new: [u8; 20],
+    branch: bool,
+) -> Result<()> {
+    let old = encode(old.unwrap_or_default());
+    let new = encode(new);
+    let flag = if branch { "1" } else { "0" };
+    run_hook(repository, "post-checkout", &[&old, &new, flag], &[], &[])?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+}
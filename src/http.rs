@@ -0,0 +1,528 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use crate::{
+    Repository, hash_untrusted,
+    pack::unpack_pack,
+    pack_index::PackIndex,
+    parse_loose_object,
+    protocol::{
+        Advertisement, RefUpdate, pack_from_response, packfile_from_response, read_advertisement,
+        read_ls_refs, read_pkt_line, read_report_status, write_fetch_request,
+        write_fetch_request_v2, write_ls_refs_request, write_push_request,
+    },
+    refs::parse_hash,
+    transport::{local_haves, write_push_pack},
+};
+
+struct HttpResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// A repository served over HTTP, with the refs it advertised when first
+/// contacted. Servers that do not speak the smart protocol are read as
+/// plain static files, the way the dumb protocol does.
+pub struct HttpRemote {
+    url: String,
+    smart: bool,
+    /// The `Git-Protocol` header sent with every request once the server
+    /// has agreed to protocol version 2.
+    git_protocol: Option<&'static str>,
+    pub advertisement: Advertisement,
+}
+
+impl HttpRemote {
+    /// Contacts the server's upload-pack service, asking for protocol
+    /// `version` 2 when given it. Under version 2 the refs are listed by a
+    /// second request.
+    pub fn connect(url: &str, version: u8) -> Result<Self> {
+        let git_protocol = (version == 2).then_some("version=2");
+        let mut remote = Self::discover(url, "git-upload-pack", git_protocol)?;
+
+        if remote.advertisement.version == 2 {
+            let mut body = Vec::new();
+            write_ls_refs_request(&mut body)?;
+            let response = remote.post("git-upload-pack", &body)?;
+            read_ls_refs(&mut response.as_slice(), &mut remote.advertisement)?;
+        } else {
+            remote.git_protocol = None;
+        }
+
+        Ok(remote)
+    }
+
+    /// Contacts the server's receive-pack service, whose advertisement lists
+    /// the refs a push would update.
+    pub fn connect_for_push(url: &str) -> Result<Self> {
+        Self::discover(url, "git-receive-pack", None)
+    }
+
+    fn discover(url: &str, service: &str, git_protocol: Option<&'static str>) -> Result<Self> {
+        let url = url.trim_end_matches('/').to_string();
+        let response = request(
+            "GET",
+            &format!("{url}/info/refs?service={service}"),
+            None,
+            git_protocol,
+            &[],
+        )?;
+        check_status(&url, &response)?;
+
+        if response.content_type.as_deref()
+            != Some(format!("application/x-{service}-advertisement").as_str())
+        {
+            if service != "git-upload-pack" {
+                return Err(anyhow!(
+                    "fatal: {} does not speak the smart protocol; pushing over dumb HTTP is not supported",
+                    url
+                ));
+            }
+            return Self::discover_dumb(url, &response.body);
+        }
+
+        // Servers answering in protocol version 2 may leave out the service
+        // announcement.
+        let mut body = response.body.as_slice();
+        let announcement = read_pkt_line(&mut body)?.unwrap_or_default();
+        if announcement == format!("# service={service}\n").as_bytes() {
+            read_pkt_line(&mut body)?;
+        } else if announcement.starts_with(b"version 2") {
+            body = response.body.as_slice();
+        } else {
+            return Err(anyhow!("fatal: invalid service announcement from {}", url));
+        }
+
+        Ok(HttpRemote {
+            advertisement: read_advertisement(&mut body)?,
+            smart: true,
+            git_protocol,
+            url,
+        })
+    }
+
+    /// Reads the refs of a dumb server from the `info/refs` file written by
+    /// `update-server-info`, and where `HEAD` points from the `HEAD` file.
+    fn discover_dumb(url: String, info_refs: &[u8]) -> Result<Self> {
+        let not_valid = || {
+            anyhow!(
+                "fatal: {}/info/refs not valid: is this a git repository?",
+                url
+            )
+        };
+
+        let mut advertisement = Advertisement {
+            refs: Vec::new(),
+            peeled: HashMap::new(),
+            capabilities: Vec::new(),
+            version: 0,
+        };
+        for line in String::from_utf8_lossy(info_refs).lines() {
+            let (hash, name) = line.split_once('\t').ok_or_else(not_valid)?;
+            let hash = parse_hash(hash).map_err(|_| not_valid())?;
+
+            match name.strip_suffix("^{}") {
+                Some(tag) => {
+                    advertisement.peeled.insert(tag.to_string(), hash);
+                }
+                None => advertisement.refs.push((name.to_string(), hash)),
+            }
+        }
+
+        let head = request("GET", &format!("{url}/HEAD"), None, None, &[])?;
+        if head.status == 200
+            && let Some(target) = String::from_utf8_lossy(&head.body)
+                .trim()
+                .strip_prefix("ref: ")
+        {
+            advertisement
+                .capabilities
+                .push(format!("symref=HEAD:{target}"));
+        }
+
+        Ok(HttpRemote {
+            advertisement,
+            smart: false,
+            git_protocol: None,
+            url,
+        })
+    }
+
+    /// Downloads a pack containing everything needed to reach `wants` that
+    /// `repository` lacks, less what `filter` leaves out, and stores its
+    /// objects. Returns the number of objects received.
+    pub fn fetch_objects(
+        &self,
+        repository: &Repository,
+        wants: &[[u8; 20]],
+        filter: Option<&str>,
+    ) -> Result<usize> {
+        let mut missing = Vec::new();
+        for want in wants {
+            if !repository.has_object(want)? && !missing.contains(want) {
+                missing.push(*want);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        let filter = self.advertisement.accept_filter(filter);
+        if !self.smart {
+            return DumbWalk::new(&self.url, repository).fetch(missing);
+        }
+
+        let mut body = Vec::new();
+        if self.advertisement.version == 2 {
+            write_fetch_request_v2(&mut body, &missing, &local_haves(repository)?, filter)?;
+            let response = self.post("git-upload-pack", &body)?;
+            let (objects, _) = unpack_pack(repository, &packfile_from_response(&response)?, false)?;
+            return Ok(objects);
+        }
+
+        write_fetch_request(
+            &mut body,
+            &missing,
+            &local_haves(repository)?,
+            &["ofs-delta", "agent=mini-git/0.1"],
+        )?;
+
+        let response = self.post("git-upload-pack", &body)?;
+        let (objects, _) = unpack_pack(repository, pack_from_response(&response)?, false)?;
+        Ok(objects)
+    }
+
+    /// Sends `updates` to the server's receive-pack service along with a
+    /// pack of every object it needs to accept them, and returns the
+    /// server's verdict for each ref: `None` when it was updated, or the
+    /// reason it was refused.
+    pub fn push(
+        &self,
+        repository: &Repository,
+        updates: &[RefUpdate],
+    ) -> Result<Vec<(String, Option<String>)>> {
+        if !self.smart {
+            return Err(anyhow!("fatal: pushing over dumb HTTP is not supported"));
+        }
+
+        let mut body = Vec::new();
+        write_push_request(&mut body, updates, &["report-status", "agent=mini-git/0.1"])?;
+
+        write_push_pack(repository, &self.advertisement.refs, updates, &mut body)?;
+
+        let response = self.post("git-receive-pack", &body)?;
+        read_report_status(&mut response.as_slice())
+    }
+
+    /// Sends `body` as a request to the server's `service` and returns the
+    /// body of its response.
+    fn post(&self, service: &str, body: &[u8]) -> Result<Vec<u8>> {
+        let response = request(
+            "POST",
+            &format!("{}/{service}", self.url),
+            Some(&format!("application/x-{service}-request")),
+            self.git_protocol,
+            body,
+        )?;
+        check_status(&self.url, &response)?;
+
+        Ok(response.body)
+    }
+}
+
+/// Fetches objects from a dumb server one at a time, following each commit,
+/// tree and tag to what it references. Objects the repository already has
+/// are taken to come with everything they reach. Objects that are not
+/// stored loose on the server are looked up in its packs, and the first
+/// pack holding one is downloaded whole.
+struct DumbWalk<'a> {
+    url: &'a str,
+    repository: &'a Repository,
+    /// The server's packs, listed in `objects/info/packs`, with their
+    /// indexes. Loaded on first use; a downloaded pack is removed.
+    packs: Option<Vec<(String, PackIndex)>>,
+}
+
+impl<'a> DumbWalk<'a> {
+    fn new(url: &'a str, repository: &'a Repository) -> Self {
+        DumbWalk {
+            url,
+            repository,
+            packs: None,
+        }
+    }
+
+    /// Walks from `wants`, returning the number of objects stored.
+    fn fetch(mut self, wants: Vec<[u8; 20]>) -> Result<usize> {
+        let mut stored = 0;
+        let mut seen = HashSet::new();
+        let mut pending = wants;
+
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash) || self.repository.has_object(&hash)? {
+                continue;
+            }
+            stored += self.download(&hash)?;
+
+            let (object_type, content) = self.repository.read_raw_object(&encode(hash))?;
+            match object_type.as_str() {
+                "commit" => {
+                    let commit = self.repository.read_commit(&hash)?;
+                    pending.extend(commit.parents()?);
+                    pending.push(parse_hash(&commit.tree_hash()?)?);
+                }
+                "tree" => pending.extend(
+                    self.repository
+                        .read_tree(&hash)?
+                        .into_iter()
+                        .filter(|entry| entry.mode != 160000)
+                        .map(|entry| entry.sha1),
+                ),
+                "tag" => {
+                    let target = String::from_utf8_lossy(&content)
+                        .lines()
+                        .find_map(|line| line.strip_prefix("object ").map(str::to_string))
+                        .ok_or_else(|| anyhow!("fatal: tag {} has no object", encode(hash)))?;
+                    pending.push(parse_hash(&target)?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Stores `hash` from the server's loose objects, or else from the pack
+    /// that holds it. Returns the number of objects stored.
+    fn download(&mut self, hash: &[u8; 20]) -> Result<usize> {
+        let name = encode(hash);
+        let (dir, file) = name.split_at(2);
+
+        let response = request(
+            "GET",
+            &format!("{}/objects/{dir}/{file}", self.url),
+            None,
+            None,
+            &[],
+        )?;
+        if response.status == 200 {
+            let (object_type, content) = parse_loose_object(&response.body)
+                .with_context(|| format!("fatal: object {name} from {} is corrupt", self.url))?;
+            let mut full_content = format!("{object_type} {}\0", content.len()).into_bytes();
+            full_content.extend_from_slice(&content);
+            hash_untrusted(&full_content)?;
+            if self.repository.write_raw_object(&object_type, &content)? != *hash {
+                return Err(anyhow!(
+                    "fatal: object {} from {} does not match its name",
+                    name,
+                    self.url
+                ));
+            }
+            return Ok(1);
+        }
+        if response.status != 404 {
+            check_status(self.url, &response)?;
+        }
+
+        let url = self.url;
+        let packs = self.packs()?;
+        let position = packs
+            .iter()
+            .position(|(_, index)| index.lookup(hash).is_some())
+            .ok_or_else(|| anyhow!("fatal: unable to find {} on {}", name, url))?;
+        let (pack, _) = packs.remove(position);
+
+        let response = request(
+            "GET",
+            &format!("{}/objects/pack/{pack}", self.url),
+            None,
+            None,
+            &[],
+        )?;
+        check_status(self.url, &response)?;
+        let (objects, _) = unpack_pack(self.repository, &response.body, false)?;
+
+        Ok(objects)
+    }
+
+    fn packs(&mut self) -> Result<&mut Vec<(String, PackIndex)>> {
+        if self.packs.is_none() {
+            let response = request(
+                "GET",
+                &format!("{}/objects/info/packs", self.url),
+                None,
+                None,
+                &[],
+            )?;
+            let listing = match response.status {
+                404 => Vec::new(),
+                _ => {
+                    check_status(self.url, &response)?;
+                    response.body
+                }
+            };
+
+            let mut packs = Vec::new();
+            for line in String::from_utf8_lossy(&listing).lines() {
+                let Some(pack) = line.strip_prefix("P ") else {
+                    continue;
+                };
+                let index_name = pack.trim().replace(".pack", ".idx");
+                let response = request(
+                    "GET",
+                    &format!("{}/objects/pack/{index_name}", self.url),
+                    None,
+                    None,
+                    &[],
+                )?;
+                check_status(self.url, &response)?;
+                packs.push((pack.trim().to_string(), PackIndex::parse(&response.body)?));
+            }
+            self.packs = Some(packs);
+        }
+
+        Ok(self.packs.get_or_insert_default())
+    }
+}
+
+fn check_status(url: &str, response: &HttpResponse) -> Result<()> {
+    if response.status == 200 {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "fatal: unable to access '{}/': The requested URL returned error: {}",
+        url,
+        response.status
+    ))
+}
+
+/// Sends a single HTTP/1.1 request on a fresh connection and reads the
+/// whole response. Only plain `http://` URLs are supported. A `git_protocol`
+/// value is sent in a `Git-Protocol` header, which is how a client asks a
+/// smart server for another protocol version.
+fn request(
+    method: &str,
+    url: &str,
+    content_type: Option<&str>,
+    git_protocol: Option<&str>,
+    body: &[u8],
+) -> Result<HttpResponse> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("fatal: unsupported protocol in '{}'", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+
+    let mut stream = TcpStream::connect(&address).with_context(|| {
+        format!("fatal: unable to access '{url}': Failed to connect to {address}")
+    })?;
+
+    let mut head = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: mini-git/0.1\r\nConnection: close\r\n"
+    );
+    if let Some(content_type) = content_type {
+        head.push_str(&format!(
+            "Content-Type: {content_type}\r\nContent-Length: {}\r\n",
+            body.len()
+        ));
+    }
+    if let Some(git_protocol) = git_protocol {
+        head.push_str(&format!("Git-Protocol: {git_protocol}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut raw = Vec::new();
+    stream
+        .read_to_end(&mut raw)
+        .with_context(|| format!("Failed to read response from {url}"))?;
+
+    parse_response(&raw).with_context(|| format!("fatal: invalid HTTP response from {url}"))
+}
+
+fn parse_response(raw: &[u8]) -> Result<HttpResponse> {
+    let header_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("response headers are incomplete"))?;
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let body = &raw[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| anyhow!("malformed status line"))?;
+
+    let mut content_type = None;
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-type" => content_type = Some(value.to_string()),
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => {}
+        }
+    }
+
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        match content_length {
+            Some(length) if length <= body.len() => body[..length].to_vec(),
+            Some(_) => return Err(anyhow!("response body is truncated")),
+            None => body.to_vec(),
+        }
+    };
+
+    Ok(HttpResponse {
+        status,
+        content_type,
+        body,
+    })
+}
+
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(|| anyhow!("chunk size is missing"))?;
+        let size = String::from_utf8_lossy(&data[..line_end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .map_err(|_| anyhow!("invalid chunk size '{}'", size))?;
+        data = &data[line_end + 2..];
+
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            return Err(anyhow!("chunk is truncated"));
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
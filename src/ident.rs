@@ -0,0 +1,203 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime};
+use std::{env, fmt};
+
+use crate::config::Config;
+
+/// A parsed `Name <email> <timestamp> <+hhmm>` identity line, as found in the
+/// author and committer headers of a commit.
+pub struct Ident {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    pub timezone: String,
+}
+
+impl Ident {
+    /// The author of a new commit; see `from_env`.
+    pub fn author(config: &Config) -> Result<Self> {
+        Self::from_env(config, "AUTHOR")
+    }
+
+    /// The committer of a new commit; see `from_env`.
+    pub fn committer(config: &Config) -> Result<Self> {
+        Self::from_env(config, "COMMITTER")
+    }
+
+    /// The identity new commits are made with: `GIT_<role>_NAME`,
+    /// `GIT_<role>_EMAIL` and `GIT_<role>_DATE` when they are set, and
+    /// otherwise `user.name` and `user.email` from the config, stamped with
+    /// the current local time.
+    fn from_env(config: &Config, role: &str) -> Result<Self> {
+        let var = |field: &str| env::var(format!("GIT_{role}_{field}")).ok();
+        let name = var("NAME").or_else(|| config.get("user.name").map(str::to_string));
+        let email = var("EMAIL").or_else(|| config.get("user.email").map(str::to_string));
+
+        let (Some(name), Some(email)) = (name, email) else {
+            return Err(anyhow!(
+                "{} identity unknown\n\n\
+*** Please tell me who you are.\n\n\
+Run\n\n  \
+mini-git config --global user.email \"you@example.com\"\n  \
+mini-git config --global user.name \"Your Name\"\n\n\
+to set your account's default identity.\n\
+Omit --global to set the identity only in this repository.\n\n\
+fatal: no name or email configured",
+                if role == "AUTHOR" {
+                    "Author"
+                } else {
+                    "Committer"
+                }
+            ));
+        };
+        if name.trim().is_empty() {
+            return Err(anyhow!(
+                "fatal: empty ident name (for <{}>) not allowed",
+                email
+            ));
+        }
+
+        let (timestamp, timezone) = match var("DATE") {
+            Some(date) => parse_date(&date)?,
+            None => {
+                let now = Local::now();
+                (
+                    now.timestamp(),
+                    format_offset(now.offset().local_minus_utc()),
+                )
+            }
+        };
+
+        Ok(Ident {
+            name: name.trim().to_string(),
+            email: email.trim().to_string(),
+            timestamp,
+            timezone,
+        })
+    }
+
+    pub fn parse(line: &str) -> Result<Self> {
+        let malformed = || anyhow!("Malformed identity line: {}", line);
+
+        let (name, rest) = line.split_once('<').ok_or_else(malformed)?;
+        let (email, rest) = rest.split_once('>').ok_or_else(malformed)?;
+        let mut fields = rest.split_whitespace();
+        let timestamp = fields
+            .next()
+            .ok_or_else(malformed)?
+            .parse()
+            .map_err(|_| malformed())?;
+        let timezone = fields.next().unwrap_or("+0000").to_string();
+
+        Ok(Ident {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            timestamp,
+            timezone,
+        })
+    }
+
+    pub fn offset(&self) -> FixedOffset {
+        let sign = if self.timezone.starts_with('-') {
+            -1
+        } else {
+            1
+        };
+        let digits = self.timezone.trim_start_matches(['+', '-']);
+        let hours: i32 = digits.get(0..2).and_then(|h| h.parse().ok()).unwrap_or(0);
+        let minutes: i32 = digits.get(2..4).and_then(|m| m.parse().ok()).unwrap_or(0);
+
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .unwrap_or(FixedOffset::east_opt(0).unwrap())
+    }
+
+    /// Formats the timestamp like git's default date format, e.g.
+    /// `Thu Oct 16 10:00:00 2026 +0200`.
+    pub fn format_date(&self) -> String {
+        DateTime::from_timestamp(self.timestamp, 0)
+            .map(|date| {
+                date.with_timezone(&self.offset())
+                    .format("%a %b %-d %H:%M:%S %Y %z")
+                    .to_string()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Formats the timestamp for a mail header, e.g.
+    /// `Thu, 16 Oct 2026 10:00:00 +0200`.
+    pub fn format_rfc2822(&self) -> String {
+        DateTime::from_timestamp(self.timestamp, 0)
+            .map(|date| date.with_timezone(&self.offset()).to_rfc2822())
+            .unwrap_or_default()
+    }
+}
+
+/// Parses a date given in the environment, in any of the forms git
+/// accepts there: its own `<timestamp> <+hhmm>`, optionally prefixed with
+/// `@`, RFC 2822, or ISO 8601 with or without a timezone. A date without
+/// one is taken as local time.
+pub fn parse_date(date: &str) -> Result<(i64, String)> {
+    let invalid = || anyhow!("fatal: invalid date format: {}", date);
+    let date = date.trim();
+
+    let raw = date.strip_prefix('@').unwrap_or(date);
+    let mut fields = raw.split_whitespace();
+    if let Some(timestamp) = fields.next().and_then(|field| field.parse::<i64>().ok()) {
+        let timezone = match fields.next() {
+            Some(timezone) => {
+                let digits = timezone.trim_start_matches(['+', '-']);
+                if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                if timezone.starts_with(['+', '-']) {
+                    timezone.to_string()
+                } else {
+                    format!("+{timezone}")
+                }
+            }
+            None => "+0000".to_string(),
+        };
+        return Ok((timestamp, timezone));
+    }
+
+    let parsed = DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"));
+    if let Ok(parsed) = parsed {
+        return Ok((
+            parsed.timestamp(),
+            format_offset(parsed.offset().local_minus_utc()),
+        ));
+    }
+
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S"))
+        .map_err(|_| invalid())?;
+    let local = naive
+        .and_local_timezone(Local)
+        .earliest()
+        .ok_or_else(invalid)?;
+    Ok((
+        local.timestamp(),
+        format_offset(local.offset().local_minus_utc()),
+    ))
+}
+
+/// Formats an offset from UTC in seconds as `+hhmm`.
+fn format_offset(offset: i32) -> String {
+    let sign = if offset >= 0 { '+' } else { '-' };
+    let offset = offset.abs();
+    format!("{sign}{:02}{:02}", offset / 3600, offset % 3600 / 60)
+}
+
+impl fmt::Display for Ident {
+    /// Writes the identity as it appears in a commit header.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.timestamp, self.timezone
+        )
+    }
+}
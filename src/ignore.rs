@@ -0,0 +1,246 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use crate::{Repository, config::Config, exit::ExitStatus, wildmatch::wildmatch};
+
+pub struct IgnoreRule {
+    pattern: String,
+    pub negated: bool,
+    dir_only: bool,
+    base_dir: PathBuf,
+    /// The file the rule was read from, as `check-ignore -v` names it.
+    pub source: PathBuf,
+    pub line_number: usize,
+    /// The line as written, `!` and trailing `/` included.
+    pub text: String,
+}
+
+impl IgnoreRule {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let Ok(relative) = path.strip_prefix(&self.base_dir) else {
+            return false;
+        };
+        let relative = relative.to_string_lossy();
+
+        match self.pattern.strip_prefix('/') {
+            Some(anchored) => wildmatch(anchored, &relative, true),
+            None if self.pattern.contains('/') => wildmatch(&self.pattern, &relative, true),
+            None => {
+                let basename = relative.rsplit('/').next().unwrap_or_default();
+                wildmatch(&self.pattern, basename, true)
+            }
+        }
+    }
+}
+
+/// Decides whether working tree paths are ignored. Sources are consulted from
+/// lowest to highest precedence — `core.excludesFile`, then
+/// `.mini-git/info/exclude`, then `.gitignore` files from the root down to the
+/// path's own directory — and the last matching pattern wins.
+pub struct Ignore {
+    work_dir: PathBuf,
+    global_rules: Vec<IgnoreRule>,
+    info_rules: Vec<IgnoreRule>,
+    dir_rules: HashMap<PathBuf, Vec<IgnoreRule>>,
+}
+
+impl Ignore {
+    pub fn load(repository: &Repository) -> Result<Self> {
+        let config = Config::load(repository)?;
+
+        let global_rules = match config.get_path("core.excludesFile") {
+            Some(path) => read_rules(&path, Path::new(""), &path)?,
+            None => Vec::new(),
+        };
+        let work_dir = repository.work_dir();
+        let info_exclude = repository.mini_git_dir.join("info").join("exclude");
+        let info_rules = read_rules(
+            &info_exclude,
+            Path::new(""),
+            info_exclude
+                .strip_prefix(&work_dir)
+                .unwrap_or(&info_exclude),
+        )?;
+
+        Ok(Ignore {
+            work_dir,
+            global_rules,
+            info_rules,
+            dir_rules: HashMap::new(),
+        })
+    }
+
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> Result<bool> {
+        Ok(self
+            .matching_rule(path, is_dir)?
+            .is_some_and(|rule| !rule.negated))
+    }
+
+    /// The rule that decides whether `path` is ignored: the one ignoring a
+    /// directory above it, since nothing inside an ignored directory can be
+    /// re-included, and otherwise the last one matching the path itself.
+    /// A negated rule means the path is not ignored.
+    pub fn matching_rule(&mut self, path: &Path, is_dir: bool) -> Result<Option<&IgnoreRule>> {
+        let mut dirs = vec![PathBuf::new()];
+        for ancestor in path.ancestors().skip(1) {
+            if !ancestor.as_os_str().is_empty() {
+                dirs.insert(1, ancestor.to_path_buf());
+            }
+        }
+
+        for dir in &dirs {
+            if !self.dir_rules.contains_key(dir) {
+                let source = dir.join(".gitignore");
+                let rules = read_rules(&self.work_dir.join(&source), dir, &source)?;
+                self.dir_rules.insert(dir.clone(), rules);
+            }
+        }
+
+        for (i, dir) in dirs.iter().enumerate().skip(1) {
+            if let Some(rule) = self.last_match(&dirs[..i], dir, true)
+                && !rule.negated
+            {
+                return Ok(Some(rule));
+            }
+        }
+        Ok(self.last_match(&dirs, path, is_dir))
+    }
+
+    /// The last rule matching `path` in the global and info sources and in
+    /// the `.gitignore` files of `dirs`, which must already be loaded.
+    fn last_match(&self, dirs: &[PathBuf], path: &Path, is_dir: bool) -> Option<&IgnoreRule> {
+        [&self.global_rules, &self.info_rules]
+            .into_iter()
+            .chain(dirs.iter().filter_map(|dir| self.dir_rules.get(dir)))
+            .flatten()
+            .rfind(|rule| rule.matches(path, is_dir))
+    }
+}
+
+/// Prints each of `paths`, or of those read from stdin, that is ignored.
+/// `verbose` adds the rule that decided it as `<source>:<line>:<pattern>`,
+/// which for a negated rule is printed even though the path is not
+/// ignored; with `non_matching` too, paths no rule matches are listed with
+/// empty fields. Tracked paths are never ignored unless `no_index` says to
+/// look at the rules alone.
+pub fn handle_check_ignore_command(
+    paths: Vec<String>,
+    verbose: bool,
+    non_matching: bool,
+    stdin: bool,
+    no_index: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let mut paths = paths;
+    if stdin {
+        for line in io::stdin().lock().lines() {
+            paths.push(line.context("Failed to read from stdin")?);
+        }
+    }
+    if paths.is_empty() {
+        return Err(anyhow!("fatal: no path specified"));
+    }
+
+    let tracked: HashSet<PathBuf> = if no_index {
+        HashSet::new()
+    } else {
+        repository
+            .read_index()?
+            .entries
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect()
+    };
+
+    let work_dir = repository.work_dir();
+    let mut ignore = Ignore::load(repository)?;
+    let mut status = ExitStatus::Differences;
+    for path in &paths {
+        let trimmed = Path::new(path.trim_end_matches('/'));
+        let is_dir = path.ends_with('/') || work_dir.join(trimmed).is_dir();
+        let rule = if tracked.contains(trimmed) {
+            None
+        } else {
+            ignore.matching_rule(trimmed, is_dir)?
+        };
+
+        match rule {
+            Some(rule) if verbose => {
+                println!(
+                    "{}:{}:{}\t{}",
+                    rule.source.display(),
+                    rule.line_number,
+                    rule.text,
+                    path
+                );
+            }
+            Some(rule) if !rule.negated => println!("{path}"),
+            None if verbose && non_matching => println!("::\t{path}"),
+            _ => {}
+        }
+        if rule.is_some_and(|rule| !rule.negated) {
+            status = ExitStatus::Success;
+        }
+    }
+
+    Ok(status)
+}
+
+fn read_rules(file: &Path, base_dir: &Path, source: &Path) -> Result<Vec<IgnoreRule>> {
+    if !file.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read ignore file {}", file.display()))?;
+
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| parse_rule(line, base_dir, source, i + 1))
+        .collect())
+}
+
+fn parse_rule(
+    line: &str,
+    base_dir: &Path,
+    source: &Path,
+    line_number: usize,
+) -> Option<IgnoreRule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+
+    if pattern.is_empty() {
+        return None;
+    }
+
+    Some(IgnoreRule {
+        pattern: pattern.to_string(),
+        negated,
+        dir_only,
+        base_dir: base_dir.to_path_buf(),
+        source: source.to_path_buf(),
+        line_number,
+        text: line.to_string(),
+    })
+}
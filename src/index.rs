@@ -0,0 +1,156 @@
+use anyhow::{Context, Result, anyhow};
+
+use crate::{IndexEntry, IndexFile, StatData, hash_content, path_bytes, path_from_bytes};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const VERSION: u32 = 2;
+
+/// The fixed part of an entry: ten 32-bit stat and mode fields, the object
+/// id and the flags.
+const ENTRY_HEADER_LEN: usize = 62;
+
+/// The flag bit marking a version 3 entry that has a second flags field.
+const EXTENDED_FLAG: u16 = 0x4000;
+
+/// Encodes `index` in git's version 2 index format: a `DIRC` header with
+/// the entry count, the entries in path order, each padded with NULs to a
+/// multiple of eight bytes, and a SHA-1 of all of it.
+pub fn encode_index(index: &IndexFile) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(SIGNATURE);
+    data.extend_from_slice(&VERSION.to_be_bytes());
+    data.extend_from_slice(&(index.entries.len() as u32).to_be_bytes());
+
+    for entry in &index.entries {
+        let path = path_bytes(&entry.path);
+        let start = data.len();
+
+        let stat = &entry.stat;
+        for field in [
+            stat.ctime.0,
+            stat.ctime.1,
+            stat.mtime.0,
+            stat.mtime.1,
+            stat.dev,
+            stat.ino,
+            octal_mode(entry.mode),
+            stat.uid,
+            stat.gid,
+            stat.size,
+        ] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        data.extend_from_slice(&entry.sha1);
+        // Names too long for the length field store 0xfff and are read up
+        // to their NUL.
+        data.extend_from_slice(&(path.len().min(0xfff) as u16).to_be_bytes());
+        data.extend_from_slice(&path);
+
+        let padded = (data.len() - start + 8) & !7;
+        data.resize(start + padded, 0);
+    }
+
+    let checksum = hash_content(&data);
+    data.extend_from_slice(&checksum);
+    data
+}
+
+/// Decodes an index in git's format, version 2 or 3. Extensions, such as
+/// the cached trees real git writes, are skipped.
+pub fn decode_index(data: &[u8]) -> Result<IndexFile> {
+    if data.len() < 32 {
+        return Err(anyhow!("fatal: index file smaller than expected"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 20);
+    if hash_content(body) != checksum {
+        return Err(anyhow!(
+            "fatal: index file corrupt: bad index file sha1 signature"
+        ));
+    }
+
+    let version = read_u32(body, 4)?;
+    if !matches!(version, 2 | 3) {
+        return Err(anyhow!(
+            "fatal: index file version {version} is not supported"
+        ));
+    }
+    let count = read_u32(body, 8)? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    let mut pos = 12;
+    for _ in 0..count {
+        let start = pos;
+        let field = |n: usize| read_u32(body, pos + 4 * n);
+        let stat = StatData {
+            ctime: (field(0)?, field(1)?),
+            mtime: (field(2)?, field(3)?),
+            dev: field(4)?,
+            ino: field(5)?,
+            uid: field(7)?,
+            gid: field(8)?,
+            size: field(9)?,
+        };
+        let mode = field(6)?;
+        let sha1: [u8; 20] = body
+            .get(pos + 40..pos + 60)
+            .ok_or_else(|| anyhow!("fatal: index file corrupt: truncated entry"))?
+            .try_into()?;
+        let flags = u16::from_be_bytes(
+            body.get(pos + 60..pos + 62)
+                .ok_or_else(|| anyhow!("fatal: index file corrupt: truncated entry"))?
+                .try_into()?,
+        );
+        pos += ENTRY_HEADER_LEN;
+        if version == 3 && flags & EXTENDED_FLAG != 0 {
+            pos += 2;
+        }
+
+        let name_len = body
+            .get(pos..)
+            .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+            .ok_or_else(|| anyhow!("fatal: index file corrupt: unterminated path"))?;
+        let path = &body[pos..pos + name_len];
+        pos = start + ((pos + name_len - start + 8) & !7);
+
+        entries.push(IndexEntry {
+            mode: decimal_mode(mode),
+            sha1,
+            path: path_from_bytes(path),
+            stat,
+        });
+    }
+
+    // What follows the entries is extensions, each a four-byte signature
+    // and a length; an uppercase first letter marks one that is optional.
+    while pos < body.len() {
+        let signature = body
+            .get(pos..pos + 4)
+            .ok_or_else(|| anyhow!("fatal: index file corrupt: truncated extension"))?;
+        if !signature[0].is_ascii_uppercase() {
+            return Err(anyhow!(
+                "fatal: index uses {} extension, which we do not understand",
+                String::from_utf8_lossy(signature)
+            ));
+        }
+        pos += 8 + read_u32(body, pos + 4)? as usize;
+    }
+
+    Ok(IndexFile { entries })
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .context("fatal: index file corrupt: truncated entry")
+}
+
+/// The mode as git stores it, from the octal digits mini-git keeps as a
+/// decimal number: 100644 becomes 0o100644.
+fn octal_mode(mode: u32) -> u32 {
+    u32::from_str_radix(&mode.to_string(), 8).unwrap_or(0o100644)
+}
+
+/// The reverse of `octal_mode`.
+fn decimal_mode(mode: u32) -> u32 {
+    format!("{mode:o}").parse().unwrap_or(100644)
+}
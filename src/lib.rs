@@ -0,0 +1,3152 @@
+//! mini-git, a small git written from scratch. The `mini-git` binary is a
+//! thin layer over `run`; other programs can open a `Repository` and
+//! stream its history with `CommitWalk`.
+
+mod am;
+mod apply;
+mod archive;
+mod attributes;
+mod base85;
+mod branch;
+mod bundle;
+mod changes;
+mod clean;
+mod clone;
+mod commit;
+mod config;
+mod daemon;
+mod delta;
+mod describe;
+mod diff;
+mod exit;
+mod fetch;
+mod filter;
+mod format_patch;
+mod fsck;
+mod git_protocol;
+mod grep;
+mod hooks;
+mod http;
+mod ident;
+mod ignore;
+mod index;
+mod log;
+mod merge;
+mod messages;
+mod name_rev;
+mod notes;
+mod pack;
+mod pack_index;
+mod pack_reader;
+mod packed_refs;
+mod pathspec;
+mod pool;
+mod profile;
+mod protocol;
+mod pull;
+mod push;
+mod rebase;
+mod receive_pack;
+mod ref_filter;
+mod refs;
+mod remote;
+mod repack;
+mod revision;
+mod self_test;
+mod shortlog;
+mod signature;
+mod ssh;
+mod stash;
+mod stripspace;
+mod submodule;
+mod switch;
+mod tag;
+mod transport;
+mod update_ref;
+mod upload_pack;
+mod walk;
+mod wildmatch;
+mod worktree;
+
+pub use exit::ExitStatus;
+#[doc(hidden)]
+pub use messages::translate_report;
+pub use walk::{CommitWalk, WalkCursor, WalkOrder};
+
+use anyhow::{Context, Result, anyhow};
+use bincode::Decode;
+use clap::{Parser, Subcommand};
+use config::Config;
+use filter::Filters;
+use flate2::write::{ZlibDecoder, ZlibEncoder};
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use hex::{decode_to_slice, encode};
+use ident::Ident;
+use ignore::Ignore;
+use index::{decode_index, encode_index};
+use messages::{tr, tr_n};
+use pack::{PackObjectType, PackOptions, write_object_pack};
+use pack_reader::{PackFile, load_packs, map_file};
+use profile::Phase;
+use sha1::{Digest, Sha1};
+use std::{
+    borrow::Cow,
+    cell::{OnceCell, RefCell},
+    collections::{BTreeMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+struct BlobObject {
+    hash: [u8; 20],
+    compressed_content: Vec<u8>,
+    raw_content: Vec<u8>,
+}
+
+impl BlobObject {
+    pub fn new(raw_content: &[u8]) -> Result<Self> {
+        let header = format!("blob {}\0", raw_content.len());
+
+        let mut object_content = Vec::new();
+        object_content.extend_from_slice(header.as_bytes());
+        object_content.extend_from_slice(raw_content);
+
+        let hash = hash_content(&object_content);
+        let compressed_content =
+            compress_content(&object_content).context("Failed to compress blob content")?;
+
+        Ok(BlobObject {
+            hash,
+            compressed_content,
+            raw_content: raw_content.to_vec(),
+        })
+    }
+}
+
+struct TreeObject {
+    hash: [u8; 20],
+    compressed_content: Vec<u8>,
+    raw_content: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TreeEntry {
+    mode: u32,
+    path: PathBuf,
+    sha1: [u8; 20],
+}
+
+impl TreeObject {
+    pub fn new(entries: &[IndexEntry]) -> Result<Self> {
+        let mut raw_content = Vec::new();
+
+        for entry in entries {
+            raw_content.extend_from_slice(entry.mode.to_string().as_bytes());
+            raw_content.push(b' ');
+            raw_content.extend_from_slice(&path_bytes(&entry.path));
+            raw_content.push(0);
+
+            raw_content.extend_from_slice(&entry.sha1);
+        }
+
+        Self::from_raw_content(raw_content)
+    }
+
+    pub fn from_raw_content(raw_content: Vec<u8>) -> Result<Self> {
+        let header = format!("tree {}\0", raw_content.len());
+        let mut full_content = header.as_bytes().to_vec();
+        full_content.extend_from_slice(&raw_content);
+
+        let hash = hash_content(&full_content);
+        let compressed_content = compress_content(&full_content)?;
+
+        Ok(TreeObject {
+            hash,
+            compressed_content,
+            raw_content,
+        })
+    }
+
+    pub fn entries(&self) -> Result<Vec<TreeEntry>> {
+        let raw = &self.raw_content;
+        let mut entries = Vec::new();
+        let mut i = 0;
+
+        while i < raw.len() {
+            let mode_start = i;
+            while i < raw.len() && raw[i] != b' ' {
+                i += 1;
+            }
+            let mode = std::str::from_utf8(&raw[mode_start..i])?;
+            i += 1;
+
+            let path_start = i;
+            while i < raw.len() && raw[i] != 0 {
+                i += 1;
+            }
+            let path = path_from_bytes(&raw[path_start..i]);
+            i += 1;
+
+            if i + 20 > raw.len() {
+                return Err(anyhow!("Malformed tree object: SHA-1 truncated"));
+            }
+            let mut sha1 = [0u8; 20];
+            sha1.copy_from_slice(&raw[i..i + 20]);
+            i += 20;
+
+            entries.push(TreeEntry {
+                mode: mode
+                    .parse()
+                    .with_context(|| format!("Malformed tree object: invalid mode {mode}"))?,
+                path,
+                sha1,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+pub struct CommitObject {
+    hash: [u8; 20],
+    compressed_content: Vec<u8>,
+    raw_content: Vec<u8>,
+}
+
+impl CommitObject {
+    pub fn new(
+        commit_message: &str,
+        tree_sha1_hex: &str,
+        parent_sha1s: &[[u8; 20]],
+        author: &Ident,
+        committer: &Ident,
+    ) -> Result<Self> {
+        let mut raw_content = Vec::new();
+        let mut metadata = format!("tree {}\n", tree_sha1_hex);
+        for parent in parent_sha1s {
+            metadata.push_str(&format!("parent {}\n", encode(parent)));
+        }
+        metadata.push_str(&format!("author {author}\ncommitter {committer}\n\n"));
+
+        raw_content.extend_from_slice(metadata.as_bytes());
+        raw_content.extend_from_slice(commit_message.as_bytes());
+
+        Self::from_raw_content(raw_content)
+    }
+
+    pub fn from_raw_content(raw_content: Vec<u8>) -> Result<Self> {
+        let header = format!("commit {}\0", raw_content.len());
+        let mut full_content = Vec::with_capacity(header.len() + raw_content.len());
+        full_content.extend_from_slice(header.as_bytes());
+        full_content.extend_from_slice(&raw_content);
+
+        let hash = hash_content(&full_content);
+        let compressed_content =
+            compress_content(&full_content).context("Failed to compress coommit object")?;
+
+        Ok(CommitObject {
+            hash,
+            compressed_content,
+            raw_content,
+        })
+    }
+
+    pub fn tree_hash(&self) -> Result<String> {
+        self.header("tree")
+            .ok_or_else(|| anyhow!("Malformed commit object: missing tree"))
+    }
+
+    pub fn parents(&self) -> Result<Vec<[u8; 20]>> {
+        self.headers()
+            .into_iter()
+            .filter(|(key, _)| key == "parent")
+            .map(|(_, value)| {
+                let mut parent = [0u8; 20];
+                decode_to_slice(&value, &mut parent)
+                    .with_context(|| format!("Malformed commit object: bad parent {value}"))?;
+                Ok(parent)
+            })
+            .collect()
+    }
+
+    pub fn header(&self, key: &str) -> Option<String> {
+        self.headers()
+            .into_iter()
+            .find_map(|(name, value)| (name == key).then_some(value))
+    }
+
+    /// Every header in order, including ones mini-git does not use itself,
+    /// with continuation lines joined to their header's value by newlines.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        self.header_lines()
+            .into_iter()
+            .filter_map(|lines| {
+                let text = self.decode(&lines.join(&b'\n'));
+                let (key, value) = text.split_once(' ')?;
+                let value = value.replace("\n ", "\n");
+                Some((key.to_string(), value))
+            })
+            .collect()
+    }
+
+    /// The message, decoded from the encoding its `encoding` header names.
+    pub fn message(&self) -> String {
+        self.decode(self.raw_message())
+    }
+
+    /// The raw content of a copy of this commit on `tree` with `parents`,
+    /// committed by `committer`. The author, the message and any other
+    /// headers, such as `encoding` or ones written by other tools, are kept
+    /// byte for byte; only a signature is dropped, as it would no longer
+    /// verify.
+    pub fn rewrite(&self, tree: &str, parents: &[[u8; 20]], committer: &Ident) -> Vec<u8> {
+        let mut content = format!("tree {tree}\n").into_bytes();
+        for parent in parents {
+            content.extend_from_slice(format!("parent {}\n", encode(parent)).as_bytes());
+        }
+
+        let committer = format!("committer {committer}\n");
+        let mut committer_written = false;
+        for lines in self.header_lines() {
+            let key = lines[0].split(|&b| b == b' ').next().unwrap_or_default();
+            match key {
+                b"tree" | b"parent" | b"committer" | b"gpgsig" | b"gpgsig-sha256" => continue,
+                _ => {}
+            }
+            for line in &lines {
+                content.extend_from_slice(line);
+                content.push(b'\n');
+            }
+            if key == b"author" {
+                content.extend_from_slice(committer.as_bytes());
+                committer_written = true;
+            }
+        }
+        if !committer_written {
+            content.extend_from_slice(committer.as_bytes());
+        }
+
+        content.push(b'\n');
+        content.extend_from_slice(self.raw_message());
+        content
+    }
+
+    /// The header section split into headers, each a line followed by its
+    /// continuation lines.
+    fn header_lines(&self) -> Vec<Vec<&[u8]>> {
+        let end = self
+            .raw_content
+            .windows(2)
+            .position(|pair| pair == b"\n\n")
+            .unwrap_or(self.raw_content.len());
+
+        let mut headers: Vec<Vec<&[u8]>> = Vec::new();
+        for line in self.raw_content[..end].split(|&b| b == b'\n') {
+            match headers.last_mut() {
+                Some(header) if line.starts_with(b" ") => header.push(line),
+                _ => headers.push(vec![line]),
+            }
+        }
+        headers
+    }
+
+    fn raw_message(&self) -> &[u8] {
+        self.raw_content
+            .windows(2)
+            .position(|pair| pair == b"\n\n")
+            .map(|position| &self.raw_content[position + 2..])
+            .unwrap_or_default()
+    }
+
+    /// Decodes text stored in the encoding the `encoding` header names:
+    /// ISO-8859-1 is converted, and anything else is read as UTF-8, which
+    /// is git's default.
+    fn decode(&self, bytes: &[u8]) -> String {
+        let latin1 = self.header_lines().iter().any(|lines| {
+            lines[0].strip_prefix(b"encoding ").is_some_and(|name| {
+                let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+                matches!(
+                    name.trim(),
+                    "iso-8859-1" | "iso8859-1" | "latin1" | "latin-1" | "l1"
+                )
+            })
+        });
+
+        if latin1 {
+            bytes.iter().map(|&b| b as char).collect()
+        } else {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+    }
+}
+
+enum GitObjects {
+    Blob(BlobObject),
+    Tree(TreeObject),
+    Commit(CommitObject),
+    Tag(TagObject),
+}
+
+/// An annotated tag: the object it names and that object's type, the
+/// tag's name, who made it, and the message, which ends with the signature
+/// when the tag is signed.
+struct TagObject {
+    object: [u8; 20],
+    object_type: String,
+    tag: String,
+    tagger: Option<String>,
+    message: String,
+}
+
+impl TagObject {
+    pub fn from_raw_content(raw_content: &[u8]) -> Result<Self> {
+        let text = String::from_utf8_lossy(raw_content);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let header = |key: &str| {
+            headers.lines().find_map(|line| {
+                line.strip_prefix(key)
+                    .and_then(|rest| rest.strip_prefix(' '))
+                    .map(str::to_string)
+            })
+        };
+        let missing = |key: &str| anyhow!("Malformed tag object: missing {key}");
+
+        let object = header("object").ok_or_else(|| missing("object"))?;
+        let mut object_sha1 = [0u8; 20];
+        decode_to_slice(&object, &mut object_sha1)
+            .with_context(|| format!("Malformed tag object: bad object {object}"))?;
+
+        Ok(TagObject {
+            object: object_sha1,
+            object_type: header("type").ok_or_else(|| missing("type"))?,
+            tag: header("tag").ok_or_else(|| missing("tag"))?,
+            // Tags from before git recorded taggers have none.
+            tagger: header("tagger"),
+            message: message.to_string(),
+        })
+    }
+}
+
+enum GitObjectsArgs {
+    Blob(Vec<u8>),
+    Tree,
+    Commit {
+        message: String,
+        tree_hash: String,
+        parent_hashes: Vec<[u8; 20]>,
+    },
+}
+
+pub struct Repository {
+    objects_dir: PathBuf,
+    /// The directory shared by every working tree: objects, refs, config
+    /// and hooks.
+    mini_git_dir: PathBuf,
+    /// The directory holding this working tree's own HEAD, index and merge
+    /// state. It is `mini_git_dir` itself except in a linked working tree,
+    /// where it is `.mini-git/worktrees/<name>` of the main one.
+    git_dir: PathBuf,
+    work_tree: PathBuf,
+    index_file: PathBuf,
+    packs: OnceCell<Vec<PackFile>>,
+    /// The refs under `refs/`, once something has needed them.
+    refs: RefCell<Option<BTreeMap<String, [u8; 20]>>>,
+}
+
+impl Repository {
+    /// Opens the repository mini-git was started in. Its directory is
+    /// `git_dir` when given, then `$GIT_DIR`, and otherwise `.mini-git` in
+    /// the current directory. A directory named either way has its working
+    /// tree at `work_tree`, `$GIT_WORK_TREE` or the current directory, as in
+    /// git. `$GIT_INDEX_FILE` names another index to use.
+    pub fn new(git_dir: Option<PathBuf>, work_tree: Option<PathBuf>) -> Result<Self> {
+        let current_dir = env::current_dir()?;
+        let git_dir = git_dir.or_else(|| env::var_os("GIT_DIR").map(PathBuf::from));
+        let work_tree = work_tree.or_else(|| env::var_os("GIT_WORK_TREE").map(PathBuf::from));
+
+        let mut repository = match git_dir {
+            Some(git_dir) => {
+                let mut repository = Self::from_git_dir(current_dir.join(git_dir))?;
+                repository.work_tree = match work_tree {
+                    Some(work_tree) => current_dir.join(work_tree),
+                    None => current_dir.clone(),
+                };
+                repository
+            }
+            None if current_dir.join(".mini-git").is_file() => Self::linked(&current_dir)?,
+            None => {
+                let mut repository = Self::at(current_dir.join(".mini-git"));
+                if let Some(work_tree) = work_tree {
+                    repository.work_tree = current_dir.join(work_tree);
+                }
+                repository
+            }
+        };
+        if let Some(index_file) = env::var_os("GIT_INDEX_FILE") {
+            repository.index_file = current_dir.join(index_file);
+        }
+
+        Ok(repository)
+    }
+
+    /// Opens the repository at `path`, which may be a working tree containing
+    /// a `.mini-git` directory or a bare repository directory itself.
+    pub fn open(path: &Path) -> Result<Self> {
+        if path.join(".mini-git").is_file() {
+            return Self::linked(path);
+        }
+
+        for mini_git_dir in [path.join(".mini-git"), path.to_path_buf()] {
+            if mini_git_dir.join("objects").is_dir() && mini_git_dir.join("HEAD").is_file() {
+                return Ok(Self::at(mini_git_dir));
+            }
+        }
+
+        Err(anyhow!(
+            "fatal: '{}' does not appear to be a mini-git repository",
+            path.display()
+        ))
+    }
+
+    fn at(mini_git_dir: PathBuf) -> Self {
+        let objects_dir = mini_git_dir.join("objects");
+        let index_file = mini_git_dir.join("index");
+        let work_tree = mini_git_dir
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        Repository {
+            objects_dir,
+            git_dir: mini_git_dir.clone(),
+            mini_git_dir,
+            work_tree,
+            index_file,
+            packs: OnceCell::new(),
+            refs: RefCell::new(None),
+        }
+    }
+
+    /// Opens the linked working tree at `work_tree`, whose `.mini-git` is a
+    /// file reading `gitdir: <path>`. That directory's `commondir` file
+    /// leads back to the repository it shares.
+    fn linked(work_tree: &Path) -> Result<Self> {
+        let link = work_tree.join(".mini-git");
+        let content = fs::read_to_string(&link)
+            .with_context(|| format!("Failed to read {}", link.display()))?;
+        let git_dir = content
+            .trim_end()
+            .strip_prefix("gitdir: ")
+            .map(|path| work_tree.join(path))
+            .ok_or_else(|| anyhow!("fatal: invalid gitfile format: {}", link.display()))?;
+
+        if !git_dir.join("commondir").is_file() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository: {}",
+                git_dir.display()
+            ));
+        }
+        let mut repository = Self::from_git_dir(git_dir)?;
+        repository.work_tree = work_tree.to_path_buf();
+
+        Ok(repository)
+    }
+
+    /// Opens the repository whose directory is `git_dir`: the repository's
+    /// own, or a linked working tree's, whose `commondir` file leads back
+    /// to the one it shares.
+    fn from_git_dir(git_dir: PathBuf) -> Result<Self> {
+        let common_dir_file = git_dir.join("commondir");
+        if !common_dir_file.is_file() {
+            return Ok(Self::at(git_dir));
+        }
+
+        let common_dir = fs::read_to_string(&common_dir_file)
+            .with_context(|| format!("fatal: not a mini-git repository: {}", git_dir.display()))?;
+        let mut repository = Self::at(fs::canonicalize(git_dir.join(common_dir.trim_end()))?);
+        repository.index_file = git_dir.join("index");
+        repository.git_dir = git_dir;
+
+        Ok(repository)
+    }
+
+    /// Whether the repository is a bare one, with no working tree of its
+    /// own around its `.mini-git` directory.
+    pub fn is_bare(&self) -> bool {
+        self.git_dir == self.mini_git_dir
+            && self.mini_git_dir.file_name() != Some(".mini-git".as_ref())
+    }
+
+    pub fn work_dir(&self) -> PathBuf {
+        self.work_tree.clone()
+    }
+
+    pub fn init(&self, template: Option<PathBuf>) -> Result<()> {
+        let mini_git_dir = &self.mini_git_dir;
+
+        if mini_git_dir.exists() {
+            self.apply_template(template)?;
+            self.create_layout()?;
+            println!(
+                "Reinitialized existing MiniGit repository in {}",
+                mini_git_dir.display()
+            );
+        } else {
+            self.apply_template(template)?;
+            self.create_layout()?;
+            println!(
+                "Initialized empty MiniGit repository in {}",
+                mini_git_dir.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Copies the files of a template directory, such as hooks,
+    /// `info/exclude` and `description`, into the repository without
+    /// replacing any it already has. The directory is `template` when given,
+    /// else `GIT_TEMPLATE_DIR`, else `init.templateDir` from the user's
+    /// config; an empty path means no template at all.
+    pub fn apply_template(&self, template: Option<PathBuf>) -> Result<()> {
+        let template = match template {
+            Some(template) => template,
+            None => match env::var_os("GIT_TEMPLATE_DIR") {
+                Some(template) => PathBuf::from(template),
+                None => match Config::load_global()?.get_path("init.templateDir") {
+                    Some(template) => template,
+                    None => return Ok(()),
+                },
+            },
+        };
+        if template.as_os_str().is_empty() {
+            return Ok(());
+        }
+        if !template.is_dir() {
+            eprintln!("warning: templates not found in {}", template.display());
+            return Ok(());
+        }
+
+        copy_template(&template, &self.mini_git_dir)
+    }
+
+    /// Creates the `.mini-git` directory skeleton, leaving existing files
+    /// untouched.
+    fn create_layout(&self) -> Result<()> {
+        let mini_git_dir = &self.mini_git_dir;
+        let objects_dir = &self.objects_dir;
+
+        fs::create_dir_all(mini_git_dir).with_context(|| {
+            format!(
+                "Failed to create .mini-git directory at {}",
+                mini_git_dir.display()
+            )
+        })?;
+        fs::create_dir_all(objects_dir).context("Failed to create objects directory")?;
+        fs::create_dir_all(objects_dir.join("pack"))
+            .context("Failed to create objects/pack directory")?;
+        fs::create_dir_all(mini_git_dir.join("refs").join("heads"))
+            .context("Failed to create refs/heads directory")?;
+        fs::create_dir_all(mini_git_dir.join("refs").join("tags"))
+            .context("Failed to create refs/tags directory")?;
+
+        let head_file = mini_git_dir.join("HEAD");
+        if !head_file.exists() {
+            fs::write(&head_file, "ref: refs/heads/main\n")
+                .with_context(|| format!("Failed to write HEAD file at {}", head_file.display()))?;
+        }
+
+        // Read by tools that list repositories, such as a web viewer.
+        let description_file = mini_git_dir.join("description");
+        if !description_file.exists() {
+            fs::write(
+                &description_file,
+                "Unnamed repository; edit this file 'description' to name the repository.\n",
+            )
+            .context("Failed to write description file")?;
+        }
+
+        let index_file = mini_git_dir.join("index");
+        if !index_file.exists() {
+            let empty = IndexFile {
+                entries: Vec::new(),
+            };
+            fs::write(&index_file, encode_index(&empty)).with_context(|| {
+                format!("Failed to write index file at {}", index_file.display())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_object(&self, object_args: &GitObjectsArgs) -> Result<([u8; 20], String)> {
+        let objects_dir = &self.objects_dir;
+
+        if !objects_dir.is_dir() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        let (compressed_content, sha1, encoded_hash) = match object_args {
+            GitObjectsArgs::Blob(data) => {
+                let blob_object = BlobObject::new(data)?;
+
+                (
+                    blob_object.compressed_content,
+                    blob_object.hash,
+                    encode(blob_object.hash),
+                )
+            }
+            GitObjectsArgs::Tree => {
+                let sha1 = self.write_tree_entries(&self.read_index()?.entries)?;
+                return Ok((sha1, encode(sha1)));
+            }
+            GitObjectsArgs::Commit {
+                message,
+                tree_hash,
+                parent_hashes,
+            } => {
+                let config = Config::load(self)?;
+                let commit_object = CommitObject::new(
+                    message,
+                    tree_hash,
+                    parent_hashes,
+                    &Ident::author(&config)?,
+                    &Ident::committer(&config)?,
+                )?;
+
+                (
+                    commit_object.compressed_content,
+                    commit_object.hash,
+                    encode(commit_object.hash),
+                )
+            }
+        };
+
+        self.store_object(&encoded_hash, &compressed_content)?;
+
+        Ok((sha1, encoded_hash))
+    }
+
+    pub fn write_raw_object(&self, object_type: &str, content: &[u8]) -> Result<[u8; 20]> {
+        if !self.objects_dir.is_dir() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        let header = format!("{} {}\0", object_type, content.len());
+        let mut full_content = Vec::with_capacity(header.len() + content.len());
+        full_content.extend_from_slice(header.as_bytes());
+        full_content.extend_from_slice(content);
+
+        let hash = hash_content(&full_content);
+        let compressed_content = compress_content(&full_content)
+            .with_context(|| format!("Failed to compress {object_type} object"))?;
+
+        self.store_object(&encode(hash), &compressed_content)?;
+
+        Ok(hash)
+    }
+
+    fn store_object(&self, encoded_hash: &str, compressed_content: &[u8]) -> Result<()> {
+        let objects_dir = &self.objects_dir;
+        let (dir_prefix, file_suffix) = encoded_hash.split_at(2);
+        let object_subdir = objects_dir.join(dir_prefix);
+        let object_file_path = object_subdir.join(file_suffix);
+
+        let mut hash = [0u8; 20];
+        decode_to_slice(encoded_hash, &mut hash)
+            .map_err(|_| anyhow!("fatal: invalid object name {}", encoded_hash))?;
+        if self.has_object(&hash)? {
+            return self
+                .verify_existing_object(&hash, flate2::read::ZlibDecoder::new(compressed_content));
+        }
+
+        if !object_subdir.exists() {
+            fs::create_dir(&object_subdir).with_context(|| {
+                format!(
+                    "Failed to create object subdirectory {}",
+                    object_subdir.display()
+                )
+            })?;
+        }
+
+        let _span = profile::span(Phase::DiskIo);
+        fs::write(&object_file_path, compressed_content)
+            .with_context(|| format!("Failed to write object file {}", object_file_path.display()))
+    }
+
+    /// Checks a newly written object against the stored one it shares the
+    /// name `hash` with, which must have identical content: a difference
+    /// means two inputs hashed to the same name. `new` yields the object
+    /// with its header, as a loose object inflates to, and the two are
+    /// compared a chunk at a time.
+    fn verify_existing_object(&self, hash: &[u8; 20], new: impl Read) -> Result<()> {
+        let encoded_hash = encode(hash);
+        let loose = self.get_object_path(&encoded_hash)?;
+        let stored: Box<dyn Read> = if loose.is_file() {
+            let file = File::open(&loose)
+                .with_context(|| format!("Failed to read object file {}", loose.display()))?;
+            Box::new(flate2::read::ZlibDecoder::new(file))
+        } else {
+            let (object_type, content) = self.read_packed_object(&encoded_hash)?;
+            let mut object = format!("{} {}\0", object_type, content.len()).into_bytes();
+            object.extend_from_slice(&content);
+            Box::new(io::Cursor::new(object))
+        };
+
+        if !same_bytes(stored, new)? {
+            return Err(anyhow!(
+                "fatal: SHA1 COLLISION FOUND WITH {} !",
+                encoded_hash
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stores `size` bytes of `content` as a loose object without holding
+    /// them in memory: they are hashed and compressed a chunk at a time into
+    /// a temporary file, which becomes the object once its name is known.
+    pub fn write_object_streaming(
+        &self,
+        object_type: &str,
+        size: u64,
+        content: impl Read,
+    ) -> Result<[u8; 20]> {
+        let temp_path = self.objects_dir.join(temp_object_name("tmp_obj"));
+        let file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+
+        let mut encoder = ZlibEncoder::new(io::BufWriter::new(file), Compression::default());
+        let written = encoder
+            .write_all(format!("{object_type} {size}\0").as_bytes())
+            .map_err(anyhow::Error::from)
+            .and_then(|()| {
+                stream_object(object_type, size, content, |chunk| encoder.write_all(chunk))
+            })
+            .and_then(|hash| {
+                encoder.finish()?.flush()?;
+                Ok(hash)
+            });
+        let hash = match written {
+            Ok(hash) => hash,
+            Err(error) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(error);
+            }
+        };
+
+        if self.has_object(&hash)? {
+            let verified = File::open(&temp_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| {
+                    self.verify_existing_object(&hash, flate2::read::ZlibDecoder::new(file))
+                });
+            fs::remove_file(&temp_path)
+                .with_context(|| format!("Failed to remove {}", temp_path.display()))?;
+            return verified.map(|()| hash);
+        }
+        let encoded_hash = encode(hash);
+        let (dir_prefix, file_suffix) = encoded_hash.split_at(2);
+        let object_subdir = self.objects_dir.join(dir_prefix);
+        fs::create_dir_all(&object_subdir).with_context(|| {
+            format!(
+                "Failed to create object subdirectory {}",
+                object_subdir.display()
+            )
+        })?;
+        let object_file_path = object_subdir.join(file_suffix);
+        fs::rename(&temp_path, &object_file_path).with_context(|| {
+            format!("Failed to write object file {}", object_file_path.display())
+        })?;
+
+        Ok(hash)
+    }
+
+    pub fn add_to_index(&self, file_path: &PathBuf) -> Result<()> {
+        let index_file = &self.index_file;
+        let file_path_buf = file_path.to_path_buf();
+
+        if !index_file.is_file() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        if !file_path.exists() {
+            return Err(anyhow!("Failed to read {:?}", file_path));
+        }
+
+        // A nested repository is recorded as a gitlink to the commit it has
+        // checked out.
+        let (mode, sha1, stat) = if file_path.join(".mini-git").exists() {
+            let head = Repository::open(file_path)?
+                .resolve_head()?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "error: '{}' does not have a commit checked out",
+                        file_path.display()
+                    )
+                })?;
+            (160000, head, StatData::default())
+        } else {
+            // Taken before the content is read, so that a change made while
+            // it is read shows up as a stat change later.
+            let metadata = fs::metadata(file_path)
+                .with_context(|| format!("Failed to read file {}", file_path.display()))?;
+            let size = metadata.len();
+            let threshold = PackOptions::from_config(&Config::load(self)?)?.big_file_threshold;
+            let big = size > threshold;
+            if big {
+                eprintln!(
+                    "warning: {} is {} bytes, over core.bigFileThreshold ({} bytes); \
+storing it whole in a pack of its own",
+                    file_path.display(),
+                    size,
+                    threshold
+                );
+            }
+            let mut filters = Filters::load(self)?.check_round_trip();
+            let sha1 = if filters.passes_through(file_path)? {
+                // Stored as it is, so the file need not be read whole.
+                let file = File::open(file_path)
+                    .with_context(|| format!("Failed to read file {}", file_path.display()))?;
+                if big {
+                    write_object_pack(self, PackObjectType::Blob, size, file)?
+                } else {
+                    self.write_object_streaming("blob", size, file)?
+                }
+            } else {
+                let data = fs::read(file_path)
+                    .with_context(|| format!("Failed to read file {}", file_path.display()))?;
+                let data = filters.clean(file_path, data)?;
+                if big {
+                    write_object_pack(self, PackObjectType::Blob, data.len() as u64, &data[..])?
+                } else {
+                    self.write_raw_object("blob", &data)?
+                }
+            };
+            (100644, sha1, StatData::of(&metadata))
+        };
+
+        let mut index = self.read_index()?;
+
+        // An entry with the same content is replaced all the same, for its
+        // fresh stat data.
+        let entry = IndexEntry {
+            mode,
+            sha1,
+            path: file_path_buf,
+            stat,
+        };
+        if let Some(pos) = index.entries.iter().position(|e| e.path == entry.path) {
+            index.entries[pos] = entry;
+        } else {
+            index.entries.push(entry);
+        }
+
+        self.write_index(&mut index)
+    }
+
+    pub(crate) fn write_index(&self, index: &mut IndexFile) -> Result<()> {
+        // git wants the entries in the byte order of their paths.
+        index
+            .entries
+            .sort_by(|a, b| path_bytes(&a.path).cmp(&path_bytes(&b.path)));
+
+        let encoded = encode_index(index);
+        let _span = profile::span(Phase::DiskIo);
+        fs::write(&self.index_file, encoded).context("Failed to write index file")?;
+
+        Ok(())
+    }
+
+    /// Whether the index is still bincode-encoded, as older versions left
+    /// it, rather than in git's format.
+    pub fn has_legacy_index(&self) -> bool {
+        let mut signature = Vec::new();
+        File::open(&self.index_file)
+            .and_then(|file| file.take(4).read_to_end(&mut signature))
+            .is_ok_and(|_| !signature.is_empty() && signature != b"DIRC")
+    }
+
+    pub(crate) fn read_index(&self) -> Result<IndexFile> {
+        let index_file = &self.index_file;
+
+        if !index_file.is_file() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        let index_data = {
+            let _span = profile::span(Phase::DiskIo);
+            fs::read(index_file)
+                .with_context(|| format!("Failed to read index file {}", index_file.display()))?
+        };
+
+        if index_data.is_empty() {
+            return Ok(IndexFile {
+                entries: Vec::new(),
+            });
+        }
+        if index_data.starts_with(b"DIRC") {
+            return decode_index(&index_data);
+        }
+
+        // Repositories from before the index was kept in git's format have
+        // it bincode-encoded; it is replaced the next time it is written.
+        let (index, _): (IndexFile, usize) =
+            bincode::decode_from_slice(&index_data, bincode::config::standard())
+                .context("Failed to decode index file")?;
+
+        Ok(index)
+    }
+
+    pub(crate) fn read_object(&self, object_hash_str: &str) -> Result<GitObjects> {
+        let (object_type, content) = self.read_raw_object(object_hash_str)?;
+
+        match object_type.as_str() {
+            "blob" => Ok(GitObjects::Blob(BlobObject::new(&content)?)),
+            "tree" => Ok(GitObjects::Tree(TreeObject::from_raw_content(content)?)),
+            "commit" => Ok(GitObjects::Commit(CommitObject::from_raw_content(content)?)),
+            "tag" => Ok(GitObjects::Tag(TagObject::from_raw_content(&content)?)),
+            _ => Err(anyhow!(
+                "Object type \"{}\" not yet implemented",
+                object_type
+            )),
+        }
+    }
+
+    pub fn read_raw_object(&self, object_hash_str: &str) -> Result<(String, Vec<u8>)> {
+        let objects_dir = &self.objects_dir;
+
+        if !objects_dir.is_dir() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        let object_file_path = self.get_object_path(object_hash_str)?;
+
+        if !object_file_path.exists() {
+            return self.read_packed_object(object_hash_str);
+        }
+
+        let compressed_data = {
+            let _span = profile::span(Phase::DiskIo);
+            map_file(&object_file_path).with_context(|| {
+                format!("Failed to read object file {}", object_file_path.display())
+            })?
+        };
+
+        parse_loose_object(&compressed_data)
+            .with_context(|| format!("fatal: loose object {} is corrupt", object_hash_str))
+    }
+
+    /// The type of the object `object_hash_str`, found without inflating
+    /// its content: only the header of a loose object is decompressed, and
+    /// a packed object's type is read from the pack's entry headers.
+    pub fn read_object_type(&self, object_hash_str: &str) -> Result<String> {
+        let object_file_path = self.get_object_path(object_hash_str)?;
+
+        if !object_file_path.exists() {
+            let mut sha1 = [0u8; 20];
+            decode_to_slice(object_hash_str, &mut sha1)
+                .map_err(|_| anyhow!("fatal: Not a valid object name: {}", object_hash_str))?;
+            for pack in self.packs()? {
+                if let Some(offset) = pack.index.lookup(&sha1) {
+                    return Ok(pack.type_at(offset as usize, self)?.name().to_string());
+                }
+            }
+            return Err(anyhow!("fatal: object {} does not exist", object_hash_str));
+        }
+
+        let compressed_data = map_file(&object_file_path).with_context(|| {
+            format!("Failed to read object file {}", object_file_path.display())
+        })?;
+        loose_object_type(&compressed_data)
+            .with_context(|| format!("fatal: loose object {} is corrupt", object_hash_str))
+    }
+
+    fn read_packed_object(&self, object_hash_str: &str) -> Result<(String, Vec<u8>)> {
+        let mut sha1 = [0u8; 20];
+        decode_to_slice(object_hash_str, &mut sha1)
+            .map_err(|_| anyhow!("fatal: Not a valid object name: {}", object_hash_str))?;
+
+        for pack in self.packs()? {
+            if let Some(offset) = pack.index.lookup(&sha1) {
+                let (object_type, content) = pack.read_at(offset as usize, self)?;
+                return Ok((object_type.name().to_string(), content));
+            }
+        }
+
+        Err(anyhow!("fatal: object {} does not exist", object_hash_str))
+    }
+
+    fn packs(&self) -> Result<&[PackFile]> {
+        if self.packs.get().is_none() {
+            let _ = self.packs.set(load_packs(&self.objects_dir)?);
+        }
+
+        Ok(self.packs.get().map(Vec::as_slice).unwrap_or_default())
+    }
+
+    pub(crate) fn read_blob(&self, sha1: &[u8; 20]) -> Result<BlobObject> {
+        match self.read_object(&encode(sha1))? {
+            GitObjects::Blob(blob) => Ok(blob),
+            _ => Err(anyhow!("fatal: object {} is not a blob", encode(sha1))),
+        }
+    }
+
+    pub(crate) fn read_tree(&self, sha1: &[u8; 20]) -> Result<Vec<TreeEntry>> {
+        match self.read_object(&encode(sha1))? {
+            GitObjects::Tree(tree) => tree.entries(),
+            _ => Err(anyhow!("fatal: object {} is not a tree", encode(sha1))),
+        }
+    }
+
+    /// Every file under the tree `sha1` by its full path, descending into
+    /// subtrees, in path order: what an index made from the tree holds.
+    pub(crate) fn read_tree_recursive(&self, sha1: &[u8; 20]) -> Result<Vec<TreeEntry>> {
+        let mut entries = Vec::new();
+        let mut pending = vec![(PathBuf::new(), *sha1)];
+
+        while let Some((prefix, tree)) = pending.pop() {
+            for entry in self.read_tree(&tree)? {
+                let path = prefix.join(&entry.path);
+                if entry.mode == 40000 {
+                    pending.push((path, entry.sha1));
+                } else {
+                    entries.push(TreeEntry { path, ..entry });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| path_bytes(&a.path).cmp(&path_bytes(&b.path)));
+        Ok(entries)
+    }
+
+    /// Writes `entries`, files by their full paths, as one tree per
+    /// directory, each subtree before the tree that lists it, and returns
+    /// the top tree.
+    pub(crate) fn write_tree_entries(&self, entries: &[IndexEntry]) -> Result<[u8; 20]> {
+        let files: Vec<(Cow<'_, [u8]>, u32, [u8; 20])> = entries
+            .iter()
+            .map(|entry| (path_bytes(&entry.path), entry.mode, entry.sha1))
+            .collect();
+        self.write_tree_level(
+            &files
+                .iter()
+                .map(|(path, mode, sha1)| (path.as_ref(), *mode, *sha1))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn write_tree_level(&self, files: &[(&[u8], u32, [u8; 20])]) -> Result<[u8; 20]> {
+        let mut entries = Vec::new();
+        let mut subdirs = BTreeMap::<&[u8], Vec<_>>::new();
+        for &(path, mode, sha1) in files {
+            match path.iter().position(|&byte| byte == b'/') {
+                Some(slash) => subdirs.entry(&path[..slash]).or_default().push((
+                    &path[slash + 1..],
+                    mode,
+                    sha1,
+                )),
+                None => entries.push(IndexEntry {
+                    mode,
+                    sha1,
+                    path: path_from_bytes(path),
+                    stat: StatData::default(),
+                }),
+            }
+        }
+        for (name, files) in subdirs {
+            entries.push(IndexEntry {
+                mode: 40000,
+                sha1: self.write_tree_level(&files)?,
+                path: path_from_bytes(name),
+                stat: StatData::default(),
+            });
+        }
+
+        // Git orders entries by name, a subtree's name compared as if it
+        // ended in `/`.
+        entries.sort_by_cached_key(|entry| {
+            let mut key = path_bytes(&entry.path).into_owned();
+            if entry.mode == 40000 {
+                key.push(b'/');
+            }
+            key
+        });
+
+        let tree = TreeObject::new(&entries)?;
+        self.store_object(&encode(tree.hash), &tree.compressed_content)?;
+        Ok(tree.hash)
+    }
+
+    pub fn read_commit(&self, sha1: &[u8; 20]) -> Result<CommitObject> {
+        match self.read_object(&encode(sha1))? {
+            GitObjects::Commit(commit) => Ok(commit),
+            _ => Err(anyhow!("fatal: object {} is not a commit", encode(sha1))),
+        }
+    }
+
+    pub fn write_tree(&self) -> Result<([u8; 20], String)> {
+        let objects_dir = &self.objects_dir;
+
+        if !objects_dir.is_dir() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        self.write_object(&GitObjectsArgs::Tree)
+    }
+
+    pub fn commit_tree(
+        &self,
+        message: String,
+        tree_hash: String,
+        parent_hashes: Vec<[u8; 20]>,
+    ) -> Result<([u8; 20], String)> {
+        let objects_dir = &self.objects_dir;
+
+        if !objects_dir.is_dir() {
+            return Err(anyhow!(
+                "fatal: not a mini-git repository (or any of the parent directories): .mini-git"
+            ));
+        }
+
+        let mut tree_sha1 = [0u8; 20];
+        decode_to_slice(&tree_hash, &mut tree_sha1)
+            .map_err(|_| anyhow!("Tree hash not a valid object"))?;
+        if !self.has_object(&tree_sha1)? {
+            return Err(anyhow!("Tree hash not a valid object"));
+        }
+
+        for parent_hash in &parent_hashes {
+            if !self.has_object(parent_hash)? {
+                return Err(anyhow!("Parent hash not a valid object"));
+            }
+        }
+
+        self.write_object(&GitObjectsArgs::Commit {
+            message,
+            tree_hash,
+            parent_hashes,
+        })
+    }
+
+    pub fn untracked_files(&self, mut ignore: Option<&mut Ignore>) -> Result<Vec<PathBuf>> {
+        let tracked: HashSet<PathBuf> = self
+            .read_index()?
+            .entries
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+
+        let work_dir = self.work_dir();
+        let mut untracked = Vec::new();
+        let mut pending = vec![PathBuf::new()];
+
+        while let Some(dir) = pending.pop() {
+            let mut children: Vec<_> = fs::read_dir(work_dir.join(&dir))
+                .with_context(|| format!("Failed to read directory {}", dir.display()))?
+                .collect::<Result<_, _>>()?;
+            children.sort_by_key(|child| child.file_name());
+
+            for child in children {
+                if child.file_name() == ".mini-git" {
+                    continue;
+                }
+
+                let path = dir.join(child.file_name());
+                let is_dir = child.file_type()?.is_dir();
+
+                if let Some(ignore) = ignore.as_deref_mut()
+                    && ignore.is_ignored(&path, is_dir)?
+                {
+                    continue;
+                }
+
+                // A tracked directory is a submodule, whose files belong
+                // to its own repository.
+                if is_dir && !tracked.contains(&path) {
+                    pending.push(path);
+                } else if !is_dir && !tracked.contains(&path) {
+                    untracked.push(path);
+                }
+            }
+        }
+
+        untracked.sort();
+        Ok(untracked)
+    }
+
+    pub fn has_object(&self, sha1: &[u8; 20]) -> Result<bool> {
+        if self.get_object_path(&encode(sha1))?.exists() {
+            return Ok(true);
+        }
+
+        Ok(self
+            .packs()?
+            .iter()
+            .any(|pack| pack.index.lookup(sha1).is_some()))
+    }
+
+    fn get_object_path(&self, hash_str: &str) -> Result<PathBuf> {
+        let objects_dir = &self.objects_dir;
+
+        if hash_str.len() != 40 {
+            return Err(anyhow!("Invalid hash length: '{}'", hash_str));
+        }
+
+        let (dir_prefix, file_suffix) = hash_str.split_at(2);
+        Ok(objects_dir.join(dir_prefix).join(file_suffix))
+    }
+}
+
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
+struct IndexEntry {
+    mode: u32,
+    sha1: [u8; 20],
+    path: PathBuf,
+    stat: StatData,
+}
+
+/// What the filesystem said about a file when it was added to the index,
+/// in the 32-bit fields git keeps: a file whose stat data is unchanged
+/// since need not be hashed again to know it is unchanged. All zero for
+/// entries that did not come from the working tree.
+#[derive(Debug, Default, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+struct StatData {
+    /// Seconds and nanoseconds.
+    ctime: (u32, u32),
+    mtime: (u32, u32),
+    dev: u32,
+    ino: u32,
+    uid: u32,
+    gid: u32,
+    size: u32,
+}
+
+impl StatData {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        StatData {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since| (since.as_secs() as u32, since.subsec_nanos()))
+            .unwrap_or_default();
+
+        StatData {
+            mtime,
+            size: metadata.len() as u32,
+            ..StatData::default()
+        }
+    }
+
+    /// Whether a file with stat data `current` is unchanged since this was
+    /// recorded, going by the fields git compares by default. An entry
+    /// modified no earlier than the index file itself, `index_mtime`, is
+    /// racily clean: the file may have changed again within the same tick
+    /// after it was added, so it never counts as unchanged.
+    fn unchanged(&self, current: &StatData, index_mtime: (u32, u32)) -> bool {
+        self.mtime < index_mtime
+            && self.ctime == current.ctime
+            && self.mtime == current.mtime
+            && self.size == current.size
+            && self.ino == current.ino
+            && self.dev == current.dev
+    }
+}
+
+// Index files mini-git wrote before it used git's format hold the path as
+// its raw bytes, a length followed by the bytes.
+impl<Context> Decode<Context> for IndexEntry {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        Ok(IndexEntry {
+            mode: u32::decode(decoder)?,
+            sha1: <[u8; 20]>::decode(decoder)?,
+            path: path_from_bytes(&Vec::<u8>::decode(decoder)?),
+            stat: StatData::default(),
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(IndexEntry);
+
+/// The bytes of `path` as the filesystem knows them, which is what trees
+/// and the index store. Only Unix can hand out names that are not UTF-8;
+/// elsewhere they are converted.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
+/// The path a tree or index entry names, from its stored bytes.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[derive(Decode, Debug, Ord, PartialOrd, Eq, PartialEq)]
+struct IndexFile {
+    entries: Vec<IndexEntry>,
+}
+
+#[doc(hidden)]
+#[derive(Parser, Debug)]
+#[command(name = "mini-git", version, about = "A simplified Git clone")]
+pub struct Cli {
+    /// Run as if started in <path>; each further -C is relative to the last
+    #[arg(short = 'C', value_name = "path", action = clap::ArgAction::Append)]
+    directories: Vec<PathBuf>,
+    /// Use <path> as the repository directory instead of `.mini-git`
+    #[arg(long, value_name = "path")]
+    git_dir: Option<PathBuf>,
+    /// Use <path> as the working tree of the repository named by --git-dir
+    /// or $GIT_DIR
+    #[arg(long, value_name = "path")]
+    work_tree: Option<PathBuf>,
+    /// Print how long the command spent on each phase of its work
+    #[arg(long, global = true)]
+    profile: bool,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    Init {
+        /// Directory whose hooks, `info/exclude` and other files are copied
+        /// into the new repository; empty for none
+        #[arg(long)]
+        template: Option<String>,
+    },
+    /// Upgrade a repository made by an older mini-git to git's index
+    /// format, and pack its refs
+    Migrate,
+    /// Print or set a config value
+    Config {
+        /// Use `~/.minigitconfig` instead of the repository's config
+        #[arg(long)]
+        global: bool,
+        key: String,
+        value: Option<String>,
+    },
+    HashObject {
+        file_path: Option<String>,
+        #[arg(short)]
+        write: bool,
+        #[arg(short = 't', default_value = "blob")]
+        object_type: String,
+        /// Skip checking that the content is a well-formed object of its type
+        #[arg(long)]
+        literally: bool,
+    },
+    CatFile {
+        object_hash_input: Option<String>,
+        #[arg(short = 't', conflicts_with = "print_content")]
+        show_type: bool,
+        #[arg(short = 'p', conflicts_with = "show_type")]
+        print_content: bool,
+    },
+    /// Record the content of files in the index
+    UpdateIndex {
+        /// Add files that are not in the index yet
+        #[arg(long)]
+        add: bool,
+        #[command(flatten)]
+        pathspec_args: pathspec::PathspecArgs,
+        paths: Vec<String>,
+    },
+    LsFiles {
+        #[arg(long)]
+        stage: bool,
+        #[arg(short, long)]
+        others: bool,
+        #[arg(long)]
+        exclude_standard: bool,
+        /// Print the stat data cached for each entry after it
+        #[arg(long)]
+        debug: bool,
+    },
+    /// Show the attributes `.gitattributes` gives paths
+    CheckAttr {
+        /// List every attribute each path has
+        #[arg(short, long)]
+        all: bool,
+        /// Read the paths from stdin, one per line
+        #[arg(long)]
+        stdin: bool,
+        /// The attributes, followed by the paths when no `--` separates them
+        attrs: Vec<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Show which paths are ignored, and with -v by which rule
+    CheckIgnore {
+        /// Also print the file, line and pattern of the deciding rule
+        #[arg(short, long)]
+        verbose: bool,
+        /// With -v, also list paths no rule matches
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+        /// Read the paths from stdin, one per line
+        #[arg(long)]
+        stdin: bool,
+        /// Look at the rules alone, even for tracked paths
+        #[arg(long)]
+        no_index: bool,
+        paths: Vec<String>,
+    },
+    WriteTree,
+    CommitTree {
+        #[arg(required_unless_present = "use_index")]
+        tree_hash_input: Option<String>,
+        #[arg(short)]
+        parent: Vec<String>,
+        /// Write the index out as a tree and commit that
+        #[arg(long, conflicts_with = "tree_hash_input")]
+        use_index: bool,
+    },
+    Commit {
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Skip the pre-commit and commit-msg hooks and the message lint
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+        /// Sign the commit with gpg, as the given key if any
+        #[arg(
+            short = 'S',
+            long,
+            value_name = "keyid",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "",
+            overrides_with = "no_gpg_sign"
+        )]
+        gpg_sign: Option<String>,
+        /// Do not sign the commit even if `commit.gpgSign` is set
+        #[arg(long, overrides_with = "gpg_sign")]
+        no_gpg_sign: bool,
+    },
+    /// Show the commits reachable from a revision, newest first
+    Log {
+        revision: Option<String>,
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        #[arg(long)]
+        show_signature: bool,
+        #[arg(long, conflicts_with = "date_order")]
+        topo_order: bool,
+        #[arg(long)]
+        date_order: bool,
+        #[arg(long)]
+        reverse: bool,
+        /// Continue a walk where an earlier `--show-cursor` left off
+        #[arg(long, conflicts_with_all = ["revision", "reverse"])]
+        cursor: Option<String>,
+        /// Print a cursor for resuming the walk to stderr
+        #[arg(long, conflicts_with = "reverse")]
+        show_cursor: bool,
+        #[command(flatten)]
+        diff_args: changes::DiffOutputArgs,
+    },
+    /// Show the changes between the working tree, the index and commits
+    Diff {
+        #[arg(long, alias = "staged")]
+        cached: bool,
+        /// Exit with 1 if there are differences and 0 if there are none
+        #[arg(long)]
+        exit_code: bool,
+        /// Print nothing; implies --exit-code
+        #[arg(long)]
+        quiet: bool,
+        commits: Vec<String>,
+        #[command(flatten)]
+        diff_args: changes::DiffOutputArgs,
+    },
+    /// Show the changes between two trees, or those a commit makes, as raw
+    /// diff lines
+    DiffTree {
+        /// Compare the files in subdirectories instead of the trees
+        #[arg(short)]
+        recursive: bool,
+        /// Show a root commit as adding every file
+        #[arg(long)]
+        root: bool,
+        /// Read commits, or pairs of trees, from stdin
+        #[arg(long)]
+        stdin: bool,
+        tree_ishes: Vec<String>,
+    },
+    /// Show the changes between a tree and the working tree, or the index,
+    /// as raw diff lines
+    DiffIndex {
+        /// Compare with the index instead of the working tree
+        #[arg(long)]
+        cached: bool,
+        tree_ish: String,
+    },
+    /// Show the changes between the index and the working tree as raw diff
+    /// lines
+    DiffFiles,
+    /// Remove untracked files from the working tree
+    Clean {
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Remove the files, as clean.requireForce asks
+        #[arg(short, long)]
+        force: bool,
+        /// Remove untracked directories too
+        #[arg(short = 'd')]
+        directories: bool,
+        /// Remove ignored files too
+        #[arg(short = 'x')]
+        ignored: bool,
+    },
+    /// Apply a unified diff to the working tree or the index
+    Apply {
+        /// Only check that the patch applies, changing nothing
+        #[arg(long)]
+        check: bool,
+        /// Apply the patch to the index, leaving the working tree alone
+        #[arg(long)]
+        cached: bool,
+        /// Apply the patch to both the index and the working tree
+        #[arg(long, conflicts_with = "cached")]
+        index: bool,
+        /// Undo the patch instead of applying it
+        #[arg(short = 'R', long)]
+        reverse: bool,
+        /// Merge files the patch does not apply to with the blobs it was
+        /// made between, leaving conflict markers where they clash
+        #[arg(short = '3', long = "3way", conflicts_with = "cached")]
+        three_way: bool,
+        /// The patch file; read from stdin when left out
+        patch: Option<PathBuf>,
+    },
+    /// Apply patches from a mailbox as commits, keeping their authors
+    Am {
+        /// Commit the index once a patch that failed has been applied by hand
+        #[arg(long = "continue", group = "resume")]
+        resolved: bool,
+        /// Leave out the patch that failed and go on with the next
+        #[arg(long, group = "resume")]
+        skip: bool,
+        /// Stop and put HEAD back where it was before the first patch
+        #[arg(long, group = "resume")]
+        abort: bool,
+        /// The mailboxes, such as `format-patch` output; read from stdin when
+        /// left out
+        #[arg(conflicts_with = "resume")]
+        mboxes: Vec<PathBuf>,
+    },
+    /// Write commits as patches ready to be sent by email
+    FormatPatch {
+        /// Write the patch files to this directory
+        #[arg(short, long, value_name = "DIR")]
+        output_directory: Option<PathBuf>,
+        /// Print all patches to stdout instead of writing files
+        #[arg(long, conflicts_with = "output_directory")]
+        stdout: bool,
+        /// Number the subject even when there is a single patch
+        #[arg(short, long)]
+        numbered: bool,
+        /// Include every commit down to the root, up to <range>
+        #[arg(long)]
+        root: bool,
+        /// `<since>`, `<since>..<until>`, or with --root a commit
+        range: String,
+    },
+    /// Summarize the commits reachable from a revision by author
+    Shortlog {
+        /// Print only each author's commit count
+        #[arg(short, long)]
+        summary: bool,
+        /// Sort authors by their number of commits instead of by name
+        #[arg(short, long)]
+        numbered: bool,
+        /// Show each author's email address
+        #[arg(short, long)]
+        email: bool,
+        revision: Option<String>,
+    },
+    /// Print lines of tracked files that match a pattern
+    Grep {
+        /// Prefix each match with its line number
+        #[arg(short = 'n', long)]
+        line_number: bool,
+        /// Match letters regardless of case
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Search the index instead of the working tree
+        #[arg(long, conflicts_with = "revision")]
+        cached: bool,
+        pattern: String,
+        /// Search this tree-ish instead of the working tree
+        revision: Option<String>,
+    },
+    /// Show a commit and the changes it makes
+    Show {
+        revision: Option<String>,
+        /// Check the signatures of the commit and the tag shown
+        #[arg(long)]
+        show_signature: bool,
+        #[command(flatten)]
+        diff_args: changes::DiffOutputArgs,
+    },
+    PackObjects {
+        #[arg(long)]
+        stdout: bool,
+        #[arg(long, default_value_t = 10)]
+        window: usize,
+        #[arg(long, default_value_t = 50)]
+        depth: usize,
+        /// Threads for the delta search; 0 uses one per core
+        #[arg(long)]
+        threads: Option<usize>,
+        base_name: Option<String>,
+    },
+    Remote {
+        #[arg(short, long)]
+        verbose: bool,
+        #[command(subcommand)]
+        command: Option<remote::RemoteCommands>,
+    },
+    PrunePacked {
+        #[arg(short = 'n')]
+        dry_run: bool,
+    },
+    Repack {
+        #[arg(short = 'a')]
+        all: bool,
+        #[arg(short = 'd')]
+        delete: bool,
+    },
+    /// Move loose refs into `packed-refs`: tags and refs already packed, or
+    /// every ref with --all
+    PackRefs {
+        #[arg(long)]
+        all: bool,
+        /// Keep the loose ref files
+        #[arg(long)]
+        no_prune: bool,
+    },
+    /// Pack every reachable object into a single pack and every ref into
+    /// `packed-refs`, removing what they replace
+    Gc,
+    /// Check the objects and packs, and with --stats report on them
+    Fsck {
+        /// Also list the longest delta chains, the widest trees, the
+        /// deepest paths and stray pack files
+        #[arg(long)]
+        stats: bool,
+    },
+    VerifyPack {
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print each object's id and offset in the pack
+        #[arg(long, conflicts_with = "verbose")]
+        object_offsets: bool,
+        #[arg(required = true)]
+        idx_files: Vec<PathBuf>,
+    },
+    Clone {
+        #[arg(short, long)]
+        branch: Option<String>,
+        #[arg(long)]
+        single_branch: bool,
+        #[arg(long)]
+        no_local: bool,
+        #[arg(long)]
+        no_hardlinks: bool,
+        /// Clone the submodules too, and theirs in turn
+        #[arg(long, alias = "recursive")]
+        recurse_submodules: bool,
+        /// How many submodules to fetch at once
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        repository: String,
+        directory: Option<PathBuf>,
+    },
+    Fetch {
+        remote: Option<String>,
+        refspecs: Vec<String>,
+    },
+    Pull {
+        #[arg(long)]
+        rebase: bool,
+        remote: Option<String>,
+        branch: Option<String>,
+    },
+    Push {
+        #[arg(short, long)]
+        force: bool,
+        remote: Option<String>,
+        refspecs: Vec<String>,
+    },
+    Daemon {
+        #[arg(long, default_value = "0.0.0.0")]
+        listen: String,
+        #[arg(long, default_value_t = 9418)]
+        port: u16,
+        #[arg(long)]
+        base_path: Option<PathBuf>,
+        #[arg(long)]
+        export_all: bool,
+        /// Only serve repositories below these directories
+        directories: Vec<PathBuf>,
+    },
+    UploadPack {
+        directory: PathBuf,
+    },
+    ReceivePack {
+        directory: PathBuf,
+    },
+    FetchPack {
+        #[arg(long)]
+        all: bool,
+        /// Ask the server to leave out objects, e.g. `blob:none`
+        #[arg(long)]
+        filter: Option<String>,
+        repository: String,
+        refs: Vec<String>,
+    },
+    SendPack {
+        #[arg(long)]
+        all: bool,
+        #[arg(short, long)]
+        force: bool,
+        repository: String,
+        refspecs: Vec<String>,
+    },
+    UnpackObjects {
+        #[arg(short = 'n')]
+        dry_run: bool,
+    },
+    /// Dump a pack index read from stdin
+    ShowIndex,
+    IndexPack {
+        pack_file: Option<PathBuf>,
+        #[arg(short)]
+        output: Option<PathBuf>,
+        #[arg(long, conflicts_with = "pack_file")]
+        stdin: bool,
+    },
+    /// List branches, or create one: `branch <name> [<start>]`
+    Branch {
+        /// Delete the given branches, if merged into HEAD
+        #[arg(short, long)]
+        delete: bool,
+        /// Delete the given branches, merged or not
+        #[arg(short = 'D', conflicts_with = "delete")]
+        force_delete: bool,
+        args: Vec<String>,
+    },
+    Switch {
+        /// Branch to switch to, or the start point of a new branch
+        target: Option<String>,
+        /// Create a new branch and switch to it
+        #[arg(short = 'c', long = "create")]
+        create: Option<String>,
+        /// Check out a commit on a detached HEAD
+        #[arg(short, long, conflicts_with = "create")]
+        detach: bool,
+    },
+    /// Switch branches, or restore files from the index or a tree-ish
+    Checkout {
+        /// Branch to switch to, or commit to check out on a detached HEAD
+        target: Option<String>,
+        /// Create a new branch and switch to it
+        #[arg(short = 'b')]
+        create: Option<String>,
+        /// Check out on a detached HEAD even when given a branch
+        #[arg(long, conflicts_with = "create")]
+        detach: bool,
+        /// Also delete tracked files that match the paths but are not in
+        /// the tree-ish
+        #[arg(long)]
+        no_overlay: bool,
+        #[command(flatten)]
+        pathspec_args: pathspec::PathspecArgs,
+        /// Check out only these paths, leaving HEAD where it is
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    Stash {
+        #[command(subcommand)]
+        command: stash::StashCommands,
+    },
+    Notes {
+        #[command(subcommand)]
+        command: notes::NotesCommands,
+    },
+    Worktree {
+        #[command(subcommand)]
+        command: worktree::WorktreeCommands,
+    },
+    Submodule {
+        #[command(subcommand)]
+        command: submodule::SubmoduleCommands,
+    },
+    Bundle {
+        #[command(subcommand)]
+        command: bundle::BundleCommands,
+    },
+    Archive {
+        /// tar, tgz or zip; guessed from the output file name by default
+        #[arg(long)]
+        format: Option<String>,
+        /// Prepend this to every path in the archive
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Write the archive to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        tree_ish: String,
+    },
+    /// List tags, or create a lightweight one: `tag <name> [<commit-ish>]`
+    Tag {
+        /// List tags, matching any of the given patterns
+        #[arg(short, long)]
+        list: bool,
+        /// Only list tags pointing at this object
+        #[arg(long, value_name = "object")]
+        points_at: Option<String>,
+        /// Make an annotated tag object
+        #[arg(short, long)]
+        annotate: bool,
+        /// The message of an annotated tag
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Make a gpg-signed tag object
+        #[arg(short, long)]
+        sign: bool,
+        /// Make a tag object signed with the given key
+        #[arg(short = 'u', long, value_name = "keyid")]
+        local_user: Option<String>,
+        args: Vec<String>,
+    },
+    /// Check the gpg or ssh signatures of commits
+    VerifyCommit {
+        /// Print the commit before its signature check
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print gpg's status lines instead of its messages
+        #[arg(long)]
+        raw: bool,
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+    /// Check the gpg or ssh signatures of tag objects
+    VerifyTag {
+        /// Print the tag before its signature check
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print gpg's status lines instead of its messages
+        #[arg(long)]
+        raw: bool,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// List refs as `<hash> <type>\t<name>`
+    ForEachRef {
+        /// Only list refs pointing at this object
+        #[arg(long, value_name = "object")]
+        points_at: Option<String>,
+        patterns: Vec<String>,
+    },
+    /// Point a ref at an object, or delete it, only if it is where it is
+    /// expected to be: `update-ref <ref> <new> [<old>]`
+    UpdateRef {
+        /// Delete the ref: `update-ref -d <ref> [<old>]`
+        #[arg(short)]
+        delete: bool,
+        /// Read update, create, delete and verify commands from stdin and
+        /// apply them all or none
+        #[arg(long)]
+        stdin: bool,
+        args: Vec<String>,
+    },
+    Describe {
+        /// Use lightweight tags as well as annotated ones
+        #[arg(long)]
+        tags: bool,
+        /// Append this, `-dirty` by default, when the working tree has
+        /// changes
+        #[arg(long, num_args = 0..=1, default_missing_value = "-dirty", require_equals = true, conflicts_with = "commit_ish")]
+        dirty: Option<String>,
+        commit_ish: Option<String>,
+    },
+    Merge {
+        #[arg(required_unless_present = "abort")]
+        branches: Vec<String>,
+        #[arg(short)]
+        message: Option<String>,
+        #[arg(long, conflicts_with_all = ["branches", "message"])]
+        abort: bool,
+        /// Skip the pre-merge-commit hook
+        #[arg(long)]
+        no_verify: bool,
+    },
+    /// Replay the commits of the current branch on top of another
+    Rebase {
+        #[arg(required_unless_present = "resume")]
+        upstream: Option<String>,
+        /// Commit the resolved conflicts and go on with the next commit
+        #[arg(long = "continue", group = "resume")]
+        resolved: bool,
+        /// Stop and put HEAD back on the branch where it was before
+        #[arg(long, group = "resume")]
+        abort: bool,
+        /// Stash local changes first and apply them again at the end
+        #[arg(long, conflicts_with = "resume")]
+        autostash: bool,
+    },
+    /// Name commits after the refs that reach them, like `main~3`
+    NameRev {
+        /// Print only the names
+        #[arg(long)]
+        name_only: bool,
+        /// Only name commits after tags
+        #[arg(long)]
+        tags: bool,
+        /// Name every commit hash in the lines read from stdin
+        #[arg(long, alias = "annotate-stdin", conflicts_with = "revisions")]
+        stdin: bool,
+        #[arg(required_unless_present = "stdin")]
+        revisions: Vec<String>,
+    },
+    /// Find the best common ancestor of two commits
+    MergeBase {
+        /// Print every best common ancestor, not only one
+        #[arg(short, long, conflicts_with = "is_ancestor")]
+        all: bool,
+        /// Exit with 0 if the first commit is an ancestor of the second and
+        /// 1 if not, printing nothing
+        #[arg(long)]
+        is_ancestor: bool,
+        first: String,
+        second: String,
+    },
+    /// Clean up text read from stdin the way commit messages are
+    Stripspace {
+        /// Also drop lines starting with `#`
+        #[arg(short, long)]
+        strip_comments: bool,
+        /// Prefix every line with `# ` instead
+        #[arg(short, long, conflicts_with = "strip_comments")]
+        comment_lines: bool,
+    },
+    /// Merge the changes from <base> to <other> into <current>
+    MergeFile {
+        /// Labels for the current, base and other versions in conflict
+        /// markers, in that order
+        #[arg(short = 'L', num_args = 1, action = clap::ArgAction::Append)]
+        label: Vec<String>,
+        /// Settle conflicting regions in favour of the current version
+        #[arg(long, group = "resolution")]
+        ours: bool,
+        /// Settle conflicting regions in favour of the other version
+        #[arg(long, group = "resolution")]
+        theirs: bool,
+        /// Keep both versions of conflicting regions, without markers
+        #[arg(long, group = "resolution")]
+        union: bool,
+        #[arg(long, default_value_t = merge::CONFLICT_MARKER_SIZE)]
+        marker_size: usize,
+        /// Print the result instead of writing it to <current>
+        #[arg(short = 'p', long)]
+        stdout: bool,
+        current: PathBuf,
+        base: PathBuf,
+        other: PathBuf,
+    },
+    /// Checks of mini-git against other implementations, for development
+    #[command(hide = true)]
+    SelfTest {
+        #[command(subcommand)]
+        command: self_test::SelfTestCommands,
+    },
+}
+
+fn hash_content(content_with_header: &[u8]) -> [u8; 20] {
+    let _span = profile::span(Phase::Hashing);
+    let mut hasher = Sha1::new();
+    hasher.update(content_with_header);
+    hasher.finalize().into()
+}
+
+/// Hashes the object header for `size` bytes of `object_type`, then reads
+/// that many bytes from `content` a chunk at a time, hashing each and
+/// handing it to `write`. Returns the object's name; content that turns out
+/// shorter or longer than `size`, as a file changing underneath would, is
+/// an error.
+fn stream_object(
+    object_type: &str,
+    size: u64,
+    content: impl Read,
+    mut write: impl FnMut(&[u8]) -> io::Result<()>,
+) -> Result<[u8; 20]> {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{object_type} {size}\0").as_bytes());
+
+    let mut content = content.take(size + 1);
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut read = 0u64;
+    loop {
+        let n = content.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        write(&buffer[..n])?;
+        read += n as u64;
+    }
+    if read != size {
+        return Err(anyhow!(
+            "fatal: content changed size while being read ({size} bytes expected)"
+        ));
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// A name for a temporary file under `objects`, unique to each write even
+/// among the threads of one process, such as the daemon's.
+fn temp_object_name(prefix: &str) -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{prefix}_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Whether `a` and `b` read to the same bytes, compared a chunk at a time.
+fn same_bytes(mut a: impl Read, mut b: impl Read) -> io::Result<bool> {
+    let mut chunk_a = Vec::with_capacity(64 * 1024);
+    let mut chunk_b = Vec::with_capacity(64 * 1024);
+    loop {
+        chunk_a.clear();
+        chunk_b.clear();
+        a.by_ref().take(64 * 1024).read_to_end(&mut chunk_a)?;
+        b.by_ref().take(64 * 1024).read_to_end(&mut chunk_b)?;
+        if chunk_a != chunk_b {
+            return Ok(false);
+        }
+        if chunk_a.is_empty() {
+            return Ok(true);
+        }
+    }
+}
+
+/// Hashes an object that came from another repository, such as one in a
+/// fetched pack, with SHA-1 collision detection: content built to collide
+/// with another object, as in the SHAttered attack, is refused rather than
+/// stored under a name it shares.
+fn hash_untrusted(content_with_header: &[u8]) -> Result<[u8; 20]> {
+    let _span = profile::span(Phase::Hashing);
+    let result = sha1_checked::Sha1::try_digest(content_with_header);
+    let hash: [u8; 20] = (*result.hash()).into();
+    if result.has_collision() {
+        return Err(anyhow!(
+            "fatal: SHA-1 appears to be part of a collision attack: {}",
+            encode(hash)
+        ));
+    }
+    Ok(hash)
+}
+
+fn compress_content(content: &[u8]) -> Result<Vec<u8>> {
+    let _span = profile::span(Phase::Compression);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish().map_err(anyhow::Error::from)
+}
+
+fn decompress_content(encoded_data: &[u8]) -> Result<Vec<u8>> {
+    let _span = profile::span(Phase::Compression);
+    let mut decoder = ZlibDecoder::new(Vec::new());
+    decoder.write_all(encoded_data)?;
+    let decompressed_bytes = decoder.finish()?;
+    Ok(decompressed_bytes)
+}
+
+/// Splits a loose object file into its type and content. Objects are
+/// normally one zlib stream holding a `type size\0` header and the content,
+/// but git before 1.5 could also write them with a pack-style type and size
+/// header in front of a zlib stream of the bare content.
+fn parse_loose_object(data: &[u8]) -> Result<(String, Vec<u8>)> {
+    if is_legacy_loose_object(data) {
+        let (object_type, size, pos) = pack::parse_entry_header(data, 0)?;
+        if matches!(
+            object_type,
+            pack::PackObjectType::OfsDelta | pack::PackObjectType::RefDelta
+        ) {
+            return Err(anyhow!("invalid object type {}", object_type.name()));
+        }
+
+        let (content, _) = pack::inflate_at(data, pos, size)?;
+        if content.len() != size {
+            return Err(anyhow!(
+                "size mismatch: header says {} bytes, found {}",
+                size,
+                content.len()
+            ));
+        }
+        return Ok((object_type.name().to_string(), content));
+    }
+
+    let decompressed = decompress_content(data)?;
+
+    let null_terminator_position = decompressed
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| anyhow!("object header is not terminated"))?;
+    let header = std::str::from_utf8(&decompressed[..null_terminator_position])?;
+    let (object_type, _) = header
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("malformed object header '{}'", header))?;
+    let content = decompressed[null_terminator_position + 1..].to_vec();
+
+    Ok((object_type.to_string(), content))
+}
+
+/// The type in the header of a loose object, inflating only the header.
+fn loose_object_type(data: &[u8]) -> Result<String> {
+    if is_legacy_loose_object(data) {
+        let (object_type, _, _) = pack::parse_entry_header(data, 0)?;
+        return Ok(object_type.name().to_string());
+    }
+
+    let mut decompress = Decompress::new(true);
+    let mut header = Vec::with_capacity(64);
+    while !header.contains(&0) {
+        let consumed = decompress.total_in() as usize;
+        let status = decompress
+            .decompress_vec(&data[consumed..], &mut header, FlushDecompress::None)
+            .context("corrupt zlib stream")?;
+        if status == Status::StreamEnd || header.len() == header.capacity() {
+            break;
+        }
+        if decompress.total_in() as usize == consumed {
+            return Err(anyhow!("truncated zlib stream"));
+        }
+    }
+
+    header
+        .split(|&byte| byte == b' ')
+        .next()
+        .filter(|_| header.contains(&0))
+        .map(|object_type| String::from_utf8_lossy(object_type).into_owned())
+        .ok_or_else(|| anyhow!("invalid object header"))
+}
+
+/// Whether a loose object uses the legacy encoding. As in git, anything
+/// that starts with a valid zlib header is taken to be a zlib stream: the
+/// first byte names deflate (low nibble 8) with any window size, and the
+/// first 16-bit word is divisible by 31.
+fn is_legacy_loose_object(data: &[u8]) -> bool {
+    match data {
+        [first, second, ..] => {
+            let word = u16::from_be_bytes([*first, *second]);
+            !(*first & 0x8f == 0x08 && word % 31 == 0)
+        }
+        _ => false,
+    }
+}
+
+fn handle_hash_object_command(
+    file_path: Option<String>,
+    write: bool,
+    object_type: &str,
+    literally: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let mut input_data = Vec::new();
+
+    if let Some(path) = file_path {
+        let file_path = Path::new(&path);
+
+        if !file_path.exists() {
+            return Err(anyhow!("fatal: file does not exist {}", path));
+        }
+
+        // A blob is taken as it is, so it is hashed as it is read.
+        if object_type == "blob" && !literally {
+            let file = File::open(file_path)
+                .with_context(|| format!("Failed to read file {}", file_path.display()))?;
+            let size = file.metadata()?.len();
+            let hash = if write {
+                repository.write_object_streaming("blob", size, file)?
+            } else {
+                stream_object("blob", size, file, |_| Ok(()))?
+            };
+            println!("{}", encode(hash));
+            return Ok(());
+        }
+
+        input_data = fs::read(file_path)?;
+    } else {
+        io::stdin()
+            .read_to_end(&mut input_data)
+            .context("Failed to read from stdin")?;
+        while input_data.last() == Some(&b'\n') {
+            input_data.pop();
+        }
+    }
+
+    if object_type != "blob" || literally {
+        if !literally {
+            validate_object(object_type, &input_data)?;
+        }
+
+        let hash = if write {
+            repository.write_raw_object(object_type, &input_data)?
+        } else {
+            let mut full_content = format!("{} {}\0", object_type, input_data.len()).into_bytes();
+            full_content.extend_from_slice(&input_data);
+            hash_content(&full_content)
+        };
+
+        println!("{}", encode(hash));
+        return Ok(());
+    }
+
+    let encoded_hash = if write {
+        let (_, hash_str) = repository.write_object(&GitObjectsArgs::Blob(input_data))?;
+        hash_str
+    } else {
+        let blob = BlobObject::new(&input_data)?;
+        encode(blob.hash)
+    };
+
+    println!("{}", encoded_hash);
+    Ok(())
+}
+
+/// Checks that `content` parses as an object of `object_type`, so that
+/// hash-object does not write corrupt objects by accident.
+fn validate_object(object_type: &str, content: &[u8]) -> Result<()> {
+    let valid = match object_type {
+        "blob" => true,
+        "tree" => TreeObject::from_raw_content(content.to_vec())?
+            .entries()
+            .is_ok(),
+        "commit" => CommitObject::from_raw_content(content.to_vec())?
+            .tree_hash()
+            .is_ok(),
+        "tag" => {
+            let text = String::from_utf8_lossy(content);
+            text.starts_with("object ") && text.lines().any(|line| line.starts_with("type "))
+        }
+        _ => return Err(anyhow!("fatal: invalid object type \"{}\"", object_type)),
+    };
+
+    if !valid {
+        return Err(anyhow!(
+            "fatal: corrupt {} object; use --literally to write it anyway",
+            object_type
+        ));
+    }
+
+    Ok(())
+}
+
+fn handle_cat_file_command(
+    object_hash_input: Option<String>,
+    show_type: bool,
+    print_content: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let object_hash_str = match object_hash_input {
+        Some(text) => text.trim().to_string(),
+        None => {
+            let mut buffer = String::new();
+            io::stdin()
+                .read_to_string(&mut buffer)
+                .context("Failed to read object hash from stdin")?;
+            buffer.trim().to_string()
+        }
+    };
+
+    if object_hash_str.is_empty() {
+        return Err(anyhow!("fatal: object hash cannot be empty"));
+    }
+
+    let object_hash_str = match repository.resolve_object_name(&object_hash_str)? {
+        Some(sha1) => encode(sha1),
+        None => {
+            return Err(anyhow!(
+                "fatal: Not a valid object name: {}",
+                object_hash_str
+            ));
+        }
+    };
+
+    if !show_type && !print_content {
+        return Err(anyhow!(
+            "Error: you must specify one of -t (type) or -p (print)"
+        ));
+    }
+
+    // Only the header is needed for the type, which also works for objects
+    // of unknown types written with `hash-object --literally`.
+    if show_type {
+        let object_type = repository.read_object_type(&object_hash_str)?;
+        println!("{object_type}");
+        return Ok(());
+    }
+
+    let object = repository.read_object(&object_hash_str)?;
+
+    match object {
+        GitObjects::Blob(blob_object) => {
+            if print_content {
+                io::stdout()
+                    .write_all(&blob_object.raw_content)
+                    .context("Failed to write blob to stdout")?;
+            }
+        }
+
+        GitObjects::Tree(tree_object) => {
+            if print_content {
+                for entry in tree_object.entries()? {
+                    println!(
+                        "{} {} {}",
+                        entry.mode,
+                        hex::encode(entry.sha1),
+                        entry.path.display()
+                    );
+                }
+            }
+        }
+
+        GitObjects::Commit(commit_object) => {
+            if print_content {
+                let content_str = std::str::from_utf8(&commit_object.raw_content)?;
+                println!("{}", content_str);
+            }
+        }
+
+        GitObjects::Tag(tag_object) => {
+            if print_content {
+                println!("object {}", encode(tag_object.object));
+                println!("type {}", tag_object.object_type);
+                println!("tag {}", tag_object.tag);
+                if let Some(tagger) = &tag_object.tagger {
+                    println!("tagger {tagger}");
+                }
+                print!("\n{}", tag_object.message);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Stores each of `paths` and points its index entry at the new blob.
+/// Without `add`, only files already in the index may be updated.
+fn handle_update_index_command(
+    paths: Vec<String>,
+    add: bool,
+    repository: &Repository,
+) -> Result<()> {
+    if !add {
+        let index = repository.read_index()?;
+        if let Some(path) = paths.iter().find(|path| {
+            !index
+                .entries
+                .iter()
+                .any(|entry| entry.path == Path::new(path))
+        }) {
+            return Err(anyhow!(
+                "error: {}: cannot add to the index - missing --add option?",
+                path
+            ));
+        }
+    }
+
+    for path in paths {
+        repository.add_to_index(&PathBuf::from(path))?;
+    }
+    Ok(())
+}
+
+fn handle_ls_files_command(
+    stage: bool,
+    others: bool,
+    exclude_standard: bool,
+    debug: bool,
+    repository: &Repository,
+) -> Result<()> {
+    if stage || debug {
+        let index_file = repository.read_index()?;
+
+        for entry in index_file.entries {
+            if stage {
+                println!(
+                    "{} {} {}",
+                    entry.mode,
+                    encode(entry.sha1),
+                    entry.path.display()
+                );
+            } else {
+                println!("{}", entry.path.display());
+            }
+
+            if debug {
+                let stat = &entry.stat;
+                println!("  ctime: {}:{}", stat.ctime.0, stat.ctime.1);
+                println!("  mtime: {}:{}", stat.mtime.0, stat.mtime.1);
+                println!("  dev: {}\tino: {}", stat.dev, stat.ino);
+                println!("  uid: {}\tgid: {}", stat.uid, stat.gid);
+                // Git shows the stage and its assume-valid and other
+                // in-memory bits here, none of which an entry has.
+                println!("  size: {}\tflags: 0", stat.size);
+            }
+        }
+    }
+
+    if others {
+        let mut ignore = if exclude_standard {
+            Some(Ignore::load(repository)?)
+        } else {
+            None
+        };
+
+        for path in repository.untracked_files(ignore.as_mut())? {
+            println!("{}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites a bincode index in git's format and moves every loose ref into
+/// `packed-refs`, which is what repositories from older versions need for
+/// git to take them as its own. Running it again changes nothing.
+fn handle_migrate_command(repository: &Repository) -> Result<()> {
+    if repository.has_legacy_index() {
+        let mut index = repository.read_index()?;
+        repository.write_index(&mut index)?;
+        println!(
+            "{}",
+            tr_n!(
+                index.entries.len(),
+                "Converted the index to git's format ({} entry).",
+                "Converted the index to git's format ({} entries).",
+                index.entries.len()
+            )
+        );
+    } else {
+        println!("{}", tr!("The index is already in git's format."));
+    }
+
+    repository.pack_refs(true, true)?;
+    println!("{}", tr!("Packed the refs."));
+    Ok(())
+}
+
+/// Copies the contents of `from` into `to`, recursing into directories and
+/// skipping any file `to` already has.
+fn copy_template(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create {}", to.display()))?;
+
+    for entry in fs::read_dir(from)
+        .with_context(|| format!("Failed to read template directory {}", from.display()))?
+    {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_template(&entry.path(), &target)?;
+        } else if !target.exists() {
+            fs::copy(entry.path(), &target).with_context(|| {
+                format!("Failed to copy template file {}", entry.path().display())
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_write_tree(repository: &Repository) -> Result<()> {
+    let (_, hash_str) = repository.write_tree()?;
+    println!("{hash_str}");
+
+    Ok(())
+}
+
+fn handle_commit_tree(
+    target_tree_hash: String,
+    parent_hash_hexes: &[String],
+    repository: &Repository,
+) -> Result<()> {
+    let mut commit_message = String::new();
+    io::stdin()
+        .read_to_string(&mut commit_message)
+        .context("Failed to read commit message from stdin")?;
+    commit_message = commit_message.trim_end_matches('\n').to_string();
+
+    let resolve = |name: &str| {
+        repository
+            .resolve_object_name(name)?
+            .ok_or_else(|| anyhow!("fatal: Not a valid object name: {}", name))
+    };
+    let target_tree_hash = encode(resolve(&target_tree_hash)?);
+    let parent_sha1_bytes: Vec<[u8; 20]> = parent_hash_hexes
+        .iter()
+        .map(|name| resolve(name))
+        .collect::<Result<_>>()?;
+
+    let (_, hash_str) =
+        repository.commit_tree(commit_message, target_tree_hash, parent_sha1_bytes)?;
+
+    println!("{hash_str}");
+    Ok(())
+}
+
+#[doc(hidden)]
+pub fn run(cli: Cli) -> Result<ExitStatus> {
+    for directory in &cli.directories {
+        env::set_current_dir(directory)
+            .with_context(|| format!("fatal: cannot change to '{}'", directory.display()))?;
+    }
+    let repository = Repository::new(cli.git_dir, cli.work_tree)?;
+    let mut status = ExitStatus::Success;
+
+    if cli.profile {
+        profile::enable();
+    }
+    let started = Instant::now();
+
+    if !matches!(
+        cli.command,
+        Commands::Migrate | Commands::Init { .. } | Commands::Clone { .. }
+    ) && repository.has_legacy_index()
+    {
+        eprintln!(
+            "hint: The index was written by an older mini-git, which git cannot read.\n\
+hint: Run 'mini-git migrate' to convert it."
+        );
+    }
+
+    match cli.command {
+        Commands::Clone {
+            branch,
+            single_branch,
+            no_local,
+            no_hardlinks,
+            recurse_submodules,
+            jobs,
+            repository: url,
+            directory,
+        } => clone::handle_clone_command(
+            url,
+            directory,
+            clone::CloneOptions {
+                branch,
+                single_branch,
+                no_local,
+                no_hardlinks,
+                recurse_submodules,
+                jobs,
+            },
+        )?,
+        Commands::Fetch { remote, refspecs } => {
+            fetch::handle_fetch_command(remote, refspecs, &repository)?
+        }
+        Commands::Pull {
+            rebase,
+            remote,
+            branch,
+        } => status = pull::handle_pull_command(remote, branch, rebase, &repository)?,
+        Commands::Push {
+            force,
+            remote,
+            refspecs,
+        } => push::handle_push_command(remote, refspecs, force, &repository)?,
+        Commands::Init { template } => {
+            repository.init(template.map(PathBuf::from))?;
+        }
+        Commands::Migrate => handle_migrate_command(&repository)?,
+        Commands::Config { global, key, value } => {
+            status = config::handle_config_command(global, key, value, &repository)?;
+        }
+        Commands::HashObject {
+            file_path,
+            write,
+            object_type,
+            literally,
+        } => handle_hash_object_command(file_path, write, &object_type, literally, &repository)?,
+        Commands::CatFile {
+            object_hash_input,
+            show_type,
+            print_content,
+        } => handle_cat_file_command(object_hash_input, show_type, print_content, &repository)?,
+        Commands::UpdateIndex {
+            add,
+            pathspec_args,
+            paths,
+        } => handle_update_index_command(pathspec_args.resolve(paths)?, add, &repository)?,
+        Commands::LsFiles {
+            stage,
+            others,
+            exclude_standard,
+            debug,
+        } => {
+            handle_ls_files_command(stage, others, exclude_standard, debug, &repository)?;
+        }
+        Commands::CheckAttr {
+            all,
+            stdin,
+            attrs,
+            paths,
+        } => attributes::handle_check_attr_command(attrs, paths, all, stdin, &repository)?,
+        Commands::CheckIgnore {
+            verbose,
+            non_matching,
+            stdin,
+            no_index,
+            paths,
+        } => {
+            status = ignore::handle_check_ignore_command(
+                paths,
+                verbose,
+                non_matching,
+                stdin,
+                no_index,
+                &repository,
+            )?;
+        }
+        Commands::WriteTree => handle_write_tree(&repository)?,
+        Commands::CommitTree {
+            tree_hash_input,
+            parent,
+            use_index,
+        } => {
+            let tree_hash = match tree_hash_input {
+                Some(tree_hash) if !use_index => tree_hash,
+                _ => repository.write_tree()?.1,
+            };
+            handle_commit_tree(tree_hash, &parent, &repository)?
+        }
+        Commands::Commit {
+            message,
+            no_verify,
+            gpg_sign,
+            no_gpg_sign,
+        } => commit::handle_commit_command(message, no_verify, gpg_sign, no_gpg_sign, &repository)?,
+        Commands::Log {
+            revision,
+            max_count,
+            show_signature,
+            topo_order,
+            date_order: _,
+            reverse,
+            cursor,
+            show_cursor,
+            diff_args,
+        } => log::handle_log_command(
+            revision,
+            log::LogOptions {
+                max_count,
+                show_signature,
+                order: if topo_order {
+                    walk::WalkOrder::Topo
+                } else {
+                    walk::WalkOrder::Date
+                },
+                reverse,
+                cursor: cursor.as_deref().map(str::parse).transpose()?,
+                show_cursor,
+            },
+            diff_args,
+            &repository,
+        )?,
+        Commands::Diff {
+            cached,
+            exit_code,
+            quiet,
+            commits,
+            diff_args,
+        } => {
+            status = changes::handle_diff_command(
+                cached,
+                commits,
+                diff_args,
+                exit_code || quiet,
+                quiet,
+                &repository,
+            )?
+        }
+        Commands::DiffTree {
+            recursive,
+            root,
+            stdin,
+            tree_ishes,
+        } => changes::handle_diff_tree_command(tree_ishes, recursive, root, stdin, &repository)?,
+        Commands::DiffIndex { cached, tree_ish } => {
+            changes::handle_diff_index_command(&tree_ish, cached, &repository)?
+        }
+        Commands::DiffFiles => changes::handle_diff_files_command(&repository)?,
+        Commands::Clean {
+            dry_run,
+            force,
+            directories,
+            ignored,
+        } => clean::handle_clean_command(dry_run, force, directories, ignored, &repository)?,
+        Commands::Apply {
+            check,
+            cached,
+            index,
+            reverse,
+            three_way,
+            patch,
+        } => {
+            status = apply::handle_apply_command(
+                patch,
+                check,
+                cached,
+                index,
+                reverse,
+                three_way,
+                &repository,
+            )?
+        }
+        Commands::Am {
+            resolved,
+            skip,
+            abort,
+            mboxes,
+        } => am::handle_am_command(mboxes, resolved, skip, abort, &repository)?,
+        Commands::FormatPatch {
+            output_directory,
+            stdout,
+            numbered,
+            root,
+            range,
+        } => format_patch::handle_format_patch_command(
+            &range,
+            format_patch::FormatPatchOptions {
+                output_directory,
+                stdout,
+                numbered,
+                root,
+            },
+            &repository,
+        )?,
+        Commands::Shortlog {
+            summary,
+            numbered,
+            email,
+            revision,
+        } => shortlog::handle_shortlog_command(revision, summary, numbered, email, &repository)?,
+        Commands::Grep {
+            line_number,
+            ignore_case,
+            cached,
+            pattern,
+            revision,
+        } => {
+            status = grep::handle_grep_command(
+                &pattern,
+                revision,
+                cached,
+                line_number,
+                ignore_case,
+                &repository,
+            )?;
+        }
+        Commands::Show {
+            revision,
+            show_signature,
+            diff_args,
+        } => changes::handle_show_command(revision, show_signature, diff_args, &repository)?,
+        Commands::PackObjects {
+            stdout,
+            window,
+            depth,
+            threads,
+            base_name,
+        } => pack::handle_pack_objects_command(
+            stdout,
+            base_name,
+            window,
+            depth,
+            threads,
+            &repository,
+        )?,
+        Commands::Remote { verbose, command } => {
+            remote::handle_remote_command(command, verbose, &repository)?
+        }
+        Commands::PrunePacked { dry_run } => {
+            repack::handle_prune_packed_command(dry_run, &repository)?
+        }
+        Commands::Repack { all, delete } => {
+            repack::handle_repack_command(all, delete, &repository)?
+        }
+        Commands::PackRefs { all, no_prune } => {
+            packed_refs::handle_pack_refs_command(all, no_prune, &repository)?
+        }
+        Commands::Gc => repack::handle_gc_command(&repository)?,
+        Commands::Fsck { stats } => status = fsck::handle_fsck_command(stats, &repository)?,
+        Commands::VerifyPack {
+            verbose,
+            object_offsets,
+            idx_files,
+        } => pack_index::handle_verify_pack_command(idx_files, verbose, object_offsets)?,
+        Commands::ShowIndex => pack_index::handle_show_index_command()?,
+        Commands::Daemon {
+            listen,
+            port,
+            base_path,
+            export_all,
+            directories,
+        } => daemon::handle_daemon_command(
+            &listen,
+            port,
+            daemon::DaemonOptions {
+                base_path,
+                export_all,
+                allowlist: directories,
+            },
+        )?,
+        Commands::UploadPack { directory } => upload_pack::handle_upload_pack_command(&directory)?,
+        Commands::ReceivePack { directory } => {
+            receive_pack::handle_receive_pack_command(&directory)?
+        }
+        Commands::FetchPack {
+            all,
+            filter,
+            repository: url,
+            refs,
+        } => transport::handle_fetch_pack_command(url, refs, all, filter, &repository)?,
+        Commands::SendPack {
+            all,
+            force,
+            repository: url,
+            refspecs,
+        } => transport::handle_send_pack_command(url, refspecs, all, force, &repository)?,
+        Commands::UnpackObjects { dry_run } => {
+            pack::handle_unpack_objects_command(dry_run, &repository)?
+        }
+        Commands::IndexPack {
+            pack_file,
+            output,
+            stdin,
+        } => pack_index::handle_index_pack_command(pack_file, output, stdin, &repository)?,
+        Commands::Merge {
+            branches,
+            message,
+            abort,
+            no_verify,
+        } => {
+            status = merge::handle_merge_command(&branches, message, abort, no_verify, &repository)?
+        }
+        Commands::Rebase {
+            upstream,
+            resolved,
+            abort,
+            autostash,
+        } => {
+            status =
+                rebase::handle_rebase_command(upstream, resolved, abort, autostash, &repository)?
+        }
+        Commands::NameRev {
+            name_only,
+            tags,
+            stdin,
+            revisions,
+        } => name_rev::handle_name_rev_command(revisions, name_only, tags, stdin, &repository)?,
+        Commands::MergeBase {
+            all,
+            is_ancestor,
+            first,
+            second,
+        } => {
+            status =
+                merge::handle_merge_base_command(&first, &second, all, is_ancestor, &repository)?
+        }
+        Commands::Stripspace {
+            strip_comments,
+            comment_lines,
+        } => stripspace::handle_stripspace_command(strip_comments, comment_lines)?,
+        Commands::MergeFile {
+            label,
+            ours,
+            theirs,
+            union,
+            marker_size,
+            stdout,
+            current,
+            base,
+            other,
+        } => {
+            let resolution = if ours {
+                merge::Resolution::Ours
+            } else if theirs {
+                merge::Resolution::Theirs
+            } else if union {
+                merge::Resolution::Union
+            } else {
+                merge::Resolution::Conflict
+            };
+            status = merge::handle_merge_file_command(
+                [current, base, other],
+                &label,
+                resolution,
+                marker_size,
+                stdout,
+            )?;
+        }
+        Commands::SelfTest { command } => {
+            status = self_test::handle_self_test_command(command, &repository)?
+        }
+        Commands::Branch {
+            delete,
+            force_delete,
+            args,
+        } => {
+            branch::handle_branch_command(args, delete || force_delete, force_delete, &repository)?
+        }
+        Commands::Switch {
+            target,
+            create,
+            detach,
+        } => switch::handle_switch_command(target, create, detach, &repository)?,
+        Commands::Checkout {
+            target,
+            create,
+            detach,
+            no_overlay,
+            pathspec_args,
+            paths,
+        } => switch::handle_checkout_command(
+            target,
+            create,
+            detach,
+            paths,
+            &pathspec_args,
+            !no_overlay,
+            &repository,
+        )?,
+        Commands::Stash { command } => stash::handle_stash_command(command, &repository)?,
+        Commands::Notes { command } => notes::handle_notes_command(command, &repository)?,
+        Commands::Worktree { command } => worktree::handle_worktree_command(command, &repository)?,
+        Commands::Submodule { command } => {
+            submodule::handle_submodule_command(command, &repository)?
+        }
+        Commands::Bundle { command } => bundle::handle_bundle_command(command, &repository)?,
+        Commands::Archive {
+            format,
+            prefix,
+            output,
+            tree_ish,
+        } => archive::handle_archive_command(
+            tree_ish,
+            archive::ArchiveOptions {
+                format,
+                prefix,
+                output,
+            },
+            &repository,
+        )?,
+        Commands::Describe {
+            tags,
+            dirty,
+            commit_ish,
+        } => describe::handle_describe_command(commit_ish, tags, dirty, &repository)?,
+        Commands::Tag {
+            list,
+            points_at,
+            annotate,
+            message,
+            sign,
+            local_user,
+            args,
+        } => tag::handle_tag_command(
+            args,
+            list,
+            points_at,
+            tag::TagOptions {
+                annotate,
+                message,
+                sign,
+                local_user,
+            },
+            &repository,
+        )?,
+        Commands::VerifyCommit {
+            verbose,
+            raw,
+            commits,
+        } => {
+            status = signature::handle_verify_commit_command(commits, verbose, raw, &repository)?;
+        }
+        Commands::VerifyTag { verbose, raw, tags } => {
+            status = signature::handle_verify_tag_command(tags, verbose, raw, &repository)?;
+        }
+        Commands::ForEachRef {
+            points_at,
+            patterns,
+        } => ref_filter::handle_for_each_ref_command(patterns, points_at, &repository)?,
+        Commands::UpdateRef {
+            delete,
+            stdin,
+            args,
+        } => update_ref::handle_update_ref_command(args, delete, stdin, &repository)?,
+    }
+
+    profile::print_summary(started.elapsed());
+
+    Ok(status)
+}
@@ -0,0 +1,183 @@
+use anyhow::Result;
+use hex::encode;
+use std::io::{self, ErrorKind, Write};
+
+use crate::{
+    CommitObject, Repository, TagObject,
+    changes::{DiffOutputArgs, commit_files, compare, parent_files, write_changes},
+    config::Config,
+    ident::Ident,
+    notes::Notes,
+    signature::SignatureCache,
+    walk::{CommitWalk, WalkCursor, WalkOrder},
+};
+
+/// How `log` walks history and which commits it shows.
+pub struct LogOptions {
+    pub max_count: Option<usize>,
+    pub show_signature: bool,
+    pub order: WalkOrder,
+    pub reverse: bool,
+    /// Resume an earlier walk instead of starting from a revision.
+    pub cursor: Option<WalkCursor>,
+    /// Report where the walk stopped, for fetching the next page.
+    pub show_cursor: bool,
+}
+
+pub fn handle_log_command(
+    revision: Option<String>,
+    options: LogOptions,
+    diff_args: DiffOutputArgs,
+    repository: &Repository,
+) -> Result<()> {
+    let mut signatures = SignatureCache::new(Config::load(repository)?);
+    if options.show_signature {
+        signatures = signatures.with_tags(repository)?;
+    }
+    let notes = Notes::load(repository)?;
+
+    let walk = match &options.cursor {
+        Some(cursor) => CommitWalk::resume(repository, cursor, options.order)?,
+        None => {
+            let start = repository.resolve_commitish(revision.as_deref().unwrap_or("HEAD"))?;
+            CommitWalk::new(repository, &[start], options.order)?
+        }
+    };
+    let mut walk = walk.limit(options.max_count).reverse(options.reverse);
+
+    // A reader that stops early, such as `head`, closes the pipe; that ends
+    // the walk like running out of commits does.
+    let mut out = io::stdout().lock();
+    let shown = walk.by_ref().enumerate().try_for_each(|(shown, entry)| {
+        let (hash, commit) = entry?;
+
+        if shown > 0 {
+            writeln!(out)?;
+        }
+        print_commit(
+            &mut out,
+            &hash,
+            &commit,
+            options.show_signature.then_some(&mut signatures),
+            &notes,
+        )?;
+
+        let parents = commit.parents()?;
+        if (diff_args.name_only || diff_args.name_status || diff_args.stat) && parents.len() <= 1 {
+            let changes = compare(
+                &parent_files(repository, &parents)?,
+                &commit_files(repository, &hash)?,
+            );
+            if !changes.is_empty() {
+                writeln!(out)?;
+                write_changes(repository, &changes, diff_args, &mut out)?;
+            }
+        }
+        Ok(())
+    });
+    match shown {
+        Err(error) if is_broken_pipe(&error) => return Ok(()),
+        shown => shown?,
+    }
+
+    if options.show_cursor
+        && let Some(cursor) = walk.cursor()
+        && !cursor.is_done()
+    {
+        eprintln!("cursor {cursor}");
+    }
+
+    Ok(())
+}
+
+/// Prints a commit the way `log` and `show` do: id, optional signature
+/// checks and merge parents, author, date, the indented message, and the
+/// commit's note if it has one. The signatures checked are the commit's
+/// and those of the signed tags the cache knows to point at it.
+pub fn print_commit(
+    out: &mut impl Write,
+    hash: &[u8; 20],
+    commit: &CommitObject,
+    signatures: Option<&mut SignatureCache>,
+    notes: &Notes,
+) -> Result<()> {
+    writeln!(out, "commit {}", encode(hash))?;
+
+    if let Some(signatures) = signatures {
+        if let Some(check) = signatures.verify_commit(commit)? {
+            write!(out, "{}", check.output)?;
+        }
+        for (name, check) in signatures.verify_tags_of(hash)? {
+            writeln!(out, "tag '{name}'")?;
+            write!(out, "{}", check.output)?;
+        }
+    }
+
+    let parents = commit.parents()?;
+    if parents.len() > 1 {
+        let abbreviated: Vec<String> = parents
+            .iter()
+            .map(|parent| encode(parent)[..7].to_string())
+            .collect();
+        writeln!(out, "Merge: {}", abbreviated.join(" "))?;
+    }
+
+    if let Some(author) = commit.header("author") {
+        let author = Ident::parse(&author)?;
+        writeln!(out, "Author: {} <{}>", author.name, author.email)?;
+        writeln!(out, "Date:   {}", author.format_date())?;
+    }
+
+    writeln!(out)?;
+    for line in commit.message().trim_end().lines() {
+        writeln!(out, "    {line}")?;
+    }
+
+    if let Some(note) = notes.get(hash)? {
+        writeln!(out)?;
+        writeln!(out, "Notes:")?;
+        for line in note.trim_end().lines() {
+            writeln!(out, "    {line}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints an annotated tag the way `show` does: its name, optional
+/// signature check, tagger, date and message.
+pub fn print_tag(
+    out: &mut impl Write,
+    hash: &[u8; 20],
+    content: &[u8],
+    signatures: Option<&mut SignatureCache>,
+) -> Result<()> {
+    let tag = TagObject::from_raw_content(content)?;
+    writeln!(out, "tag {}", tag.tag)?;
+
+    if let Some(signatures) = signatures
+        && let Some(check) = signatures.verify_tag(*hash, content)?
+    {
+        write!(out, "{}", check.output)?;
+    }
+
+    if let Some(tagger) = &tag.tagger {
+        let tagger = Ident::parse(tagger)?;
+        writeln!(out, "Tagger: {} <{}>", tagger.name, tagger.email)?;
+        writeln!(out, "Date:   {}", tagger.format_date())?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "{}", tag.message.trim_end())?;
+
+    Ok(())
+}
+
+/// Whether `error` is a write to a pipe whose reader has gone away.
+pub fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|error| error.kind() == ErrorKind::BrokenPipe)
+    })
+}
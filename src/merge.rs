@@ -0,0 +1,1051 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    GitObjectsArgs, IndexEntry, IndexFile, Repository, StatData, TreeEntry,
+    attributes::{AttrState, Attributes},
+    changes::blob_hash,
+    config::Config,
+    diff::{Hunk, diff_lines, split_lines},
+    exit::ExitStatus,
+    filter::Filters,
+    hooks::run_hook,
+    messages::tr,
+    walk::commit_time,
+};
+
+pub const CONFLICT_MARKER_SIZE: usize = 7;
+
+const NOT_COMMITTING: &str = "Not committing merge; use 'mini-git commit' to complete the merge.";
+
+/// How `merge_text` settles a region that both sides changed differently.
+#[derive(Clone, Copy, Debug)]
+pub enum Resolution {
+    /// Keep both versions between conflict markers.
+    Conflict,
+    Ours,
+    Theirs,
+    /// Keep both versions, ours first, without markers.
+    Union,
+}
+
+enum MergeDriver {
+    Text,
+    Union,
+    Binary,
+    External(String),
+}
+
+pub struct MergeOutcome {
+    pub entries: Vec<TreeEntry>,
+    pub conflicted_files: Vec<(PathBuf, Vec<u8>)>,
+    pub conflicts: Vec<String>,
+}
+
+/// Merges `branches` into HEAD, fast-forwarding when HEAD has nothing of
+/// its own. Before a merge commit is made the `pre-merge-commit` hook runs,
+/// unless `no_verify` is set, and can stop it, leaving the merge to be
+/// committed or aborted like a conflicted one. `post-merge` runs after any
+/// merge that completes.
+pub fn handle_merge_command(
+    branches: &[String],
+    message: Option<String>,
+    abort: bool,
+    no_verify: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    if abort {
+        abort_merge(repository)?;
+        return Ok(ExitStatus::Success);
+    }
+
+    if repository.git_dir.join("MERGE_HEAD").exists() {
+        return Err(anyhow!(
+            "fatal: You have not concluded your merge (MERGE_HEAD exists).\n\
+Please, commit your changes before you merge, or run 'mini-git merge --abort'."
+        ));
+    }
+
+    match branches {
+        [] => Err(anyhow!("fatal: no branch specified to merge")),
+        [branch] => merge_branch(branch, message, no_verify, repository),
+        _ => merge_octopus(branches, message, no_verify, repository),
+    }
+}
+
+/// Runs the `post-merge` hook, whose status cannot change anything.
+fn run_post_merge(repository: &Repository) -> Result<()> {
+    // The argument says whether this was a squash merge, which it never is.
+    run_hook(repository, "post-merge", &["0"], &[], &[])?;
+    Ok(())
+}
+
+/// Merges into `current` the changes from `base` to `other`, three plain
+/// files that need not belong to a repository, and writes the result back
+/// to `current` or, with `stdout`, prints it. The conflict markers are
+/// labelled with `labels`, the current and other file names by default.
+/// Exits with status 1 when the result has conflicts.
+pub fn handle_merge_file_command(
+    [current, base, other]: [PathBuf; 3],
+    labels: &[String],
+    resolution: Resolution,
+    marker_size: usize,
+    stdout: bool,
+) -> Result<ExitStatus> {
+    if labels.len() > 3 {
+        return Err(anyhow!("error: too many labels on the command line"));
+    }
+
+    let read = |path: &Path| {
+        fs::read(path).with_context(|| format!("error: could not read {}", path.display()))
+    };
+    let (current_content, base_content, other_content) =
+        (read(&current)?, read(&base)?, read(&other)?);
+    if [&current_content, &base_content, &other_content]
+        .iter()
+        .any(|content| content.contains(&0))
+    {
+        return Err(anyhow!(
+            "error: Cannot merge binary files: {}",
+            current.display()
+        ));
+    }
+
+    let label = |i: usize, path: &Path| {
+        labels
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| path.display().to_string())
+    };
+    let (merged, conflicted) = merge_text(
+        (&base_content, &current_content, &other_content),
+        &label(0, &current),
+        &label(2, &other),
+        resolution,
+        marker_size,
+    );
+
+    if stdout {
+        io::stdout().write_all(&merged)?;
+    } else {
+        fs::write(&current, merged)
+            .with_context(|| format!("error: could not write {}", current.display()))?;
+    }
+
+    Ok(if conflicted {
+        ExitStatus::Differences
+    } else {
+        ExitStatus::Success
+    })
+}
+
+fn merge_branch(
+    branch: &str,
+    message: Option<String>,
+    no_verify: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let theirs = repository.resolve_commitish(branch)?;
+    let head = repository.resolve_head()?;
+
+    let Some(head) = head else {
+        let target_entries = repository.read_tree_recursive(&tree_of(repository, &theirs)?)?;
+        checkout_entries(repository, &[], &target_entries, "merge")?;
+        repository.update_head(&theirs)?;
+        println!("{}", tr!("Fast-forward"));
+        run_post_merge(repository)?;
+        return Ok(ExitStatus::Success);
+    };
+
+    let base = merge_base(repository, &head, &theirs)?;
+
+    if base == Some(theirs) {
+        println!("{}", tr!("Already up to date."));
+        return Ok(ExitStatus::Success);
+    }
+
+    let ours_entries = repository.read_tree_recursive(&tree_of(repository, &head)?)?;
+    ensure_index_matches(repository, &ours_entries, "merge")?;
+    repository.write_ref("ORIG_HEAD", &head)?;
+
+    if base == Some(head) {
+        let target_entries = repository.read_tree_recursive(&tree_of(repository, &theirs)?)?;
+        checkout_entries(repository, &ours_entries, &target_entries, "merge")?;
+        repository.update_head(&theirs)?;
+        println!(
+            "{}",
+            tr!("Updating {}..{}", &encode(head)[..7], &encode(theirs)[..7])
+        );
+        println!("{}", tr!("Fast-forward"));
+        run_post_merge(repository)?;
+        return Ok(ExitStatus::Success);
+    }
+
+    let base_entries = match base {
+        Some(base) => repository.read_tree_recursive(&tree_of(repository, &base)?)?,
+        None => Vec::new(),
+    };
+    let theirs_entries = repository.read_tree_recursive(&tree_of(repository, &theirs)?)?;
+
+    let outcome = merge_trees(
+        repository,
+        &base_entries,
+        &ours_entries,
+        &theirs_entries,
+        branch,
+    )?;
+
+    let orig_index = repository.git_dir.join("ORIG_INDEX");
+    fs::copy(&repository.index_file, &orig_index).context("Failed to save ORIG_INDEX")?;
+
+    checkout_entries(repository, &ours_entries, &outcome.entries, "merge")?;
+
+    let message = message.unwrap_or_else(|| default_merge_message(&[branch]));
+
+    if !outcome.conflicts.is_empty() {
+        let mut filters = Filters::load(repository)?;
+        for (path, content) in &outcome.conflicted_files {
+            let content = filters.smudge(path, content.clone())?;
+            let path = repository.work_dir().join(path);
+            fs::write(&path, content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        for conflict in &outcome.conflicts {
+            println!("{conflict}");
+        }
+
+        let conflicted: Vec<&Path> = outcome
+            .conflicted_files
+            .iter()
+            .map(|(path, _)| path.as_path())
+            .collect();
+        write_merge_state(repository, &[theirs], &message, &conflicted)?;
+
+        // Stopping for the conflicts to be resolved is not a failure.
+        println!(
+            "{}",
+            tr!("Automatic merge failed; fix conflicts and then commit the result.")
+        );
+        return Ok(ExitStatus::Differences);
+    }
+
+    if !no_verify && !run_hook(repository, "pre-merge-commit", &[], &[], &[])? {
+        write_merge_state(repository, &[theirs], &message, &[])?;
+        return Err(anyhow!(NOT_COMMITTING));
+    }
+
+    let (_, tree_hash) = repository.write_tree()?;
+    let (commit_hash, _) = repository.commit_tree(message, tree_hash, vec![head, theirs])?;
+    repository.update_head(&commit_hash)?;
+    fs::remove_file(&orig_index).context("Failed to remove ORIG_INDEX")?;
+
+    println!("{}", tr!("Merge made by the '{}' strategy.", "recursive"));
+    run_post_merge(repository)?;
+    Ok(ExitStatus::Success)
+}
+
+/// Merges several branches at once into a single commit with one parent per
+/// branch. Every branch must merge cleanly; the working tree is only touched
+/// once all of them have been combined in memory.
+fn merge_octopus(
+    branches: &[String],
+    message: Option<String>,
+    no_verify: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let head = repository.resolve_head()?.ok_or_else(|| {
+        anyhow!("fatal: cannot do an octopus merge without a commit on the current branch")
+    })?;
+
+    let ours_entries = repository.read_tree_recursive(&tree_of(repository, &head)?)?;
+    ensure_index_matches(repository, &ours_entries, "merge")?;
+    repository.write_ref("ORIG_HEAD", &head)?;
+
+    let mut merged_entries = ours_entries.clone();
+    let mut parents = vec![head];
+    let mut merged_branches = Vec::new();
+
+    for branch in branches {
+        let theirs = repository.resolve_commitish(branch)?;
+        let base = merge_base(repository, &head, &theirs)?;
+
+        if base == Some(theirs) || parents.contains(&theirs) {
+            println!("{}", tr!("Already up to date with {}", branch));
+            continue;
+        }
+
+        println!("{}", tr!("Trying simple merge with {}", branch));
+
+        let base_entries = match base {
+            Some(base) => repository.read_tree_recursive(&tree_of(repository, &base)?)?,
+            None => Vec::new(),
+        };
+        let theirs_entries = repository.read_tree_recursive(&tree_of(repository, &theirs)?)?;
+        let outcome = merge_trees(
+            repository,
+            &base_entries,
+            &merged_entries,
+            &theirs_entries,
+            branch,
+        )?;
+
+        if !outcome.conflicts.is_empty() {
+            for conflict in &outcome.conflicts {
+                println!("{conflict}");
+            }
+            // Nothing has been touched yet, so there is nothing to abort.
+            eprintln!(
+                "Automated merge did not work.\n\
+Should not be doing an octopus.\n\
+Merge with strategy octopus failed.\n\
+hint: merge the branches one at a time and resolve the conflicts by hand."
+            );
+            return Ok(ExitStatus::Differences);
+        }
+
+        merged_entries = outcome.entries;
+        parents.push(theirs);
+        merged_branches.push(branch.as_str());
+    }
+
+    if merged_branches.is_empty() {
+        println!("{}", tr!("Already up to date."));
+        return Ok(ExitStatus::Success);
+    }
+
+    let orig_index = repository.git_dir.join("ORIG_INDEX");
+    fs::copy(&repository.index_file, &orig_index).context("Failed to save ORIG_INDEX")?;
+
+    checkout_entries(repository, &ours_entries, &merged_entries, "merge")?;
+
+    let message = message.unwrap_or_else(|| default_merge_message(&merged_branches));
+    if !no_verify && !run_hook(repository, "pre-merge-commit", &[], &[], &[])? {
+        write_merge_state(repository, &parents[1..], &message, &[])?;
+        return Err(anyhow!(NOT_COMMITTING));
+    }
+
+    let (_, tree_hash) = repository.write_tree()?;
+    let (commit_hash, _) = repository.commit_tree(message, tree_hash, parents)?;
+    repository.update_head(&commit_hash)?;
+    fs::remove_file(&orig_index).context("Failed to remove ORIG_INDEX")?;
+
+    println!("{}", tr!("Merge made by the '{}' strategy.", "octopus"));
+    run_post_merge(repository)?;
+    Ok(ExitStatus::Success)
+}
+
+/// Records a merge that stopped before its commit, so that `commit` can
+/// finish it with `theirs` as further parents and `message`, or `merge
+/// --abort` undo it, restoring the `conflicted` paths with the rest.
+fn write_merge_state(
+    repository: &Repository,
+    theirs: &[[u8; 20]],
+    message: &str,
+    conflicted: &[&Path],
+) -> Result<()> {
+    let merge_heads: String = theirs
+        .iter()
+        .map(|hash| format!("{}\n", encode(hash)))
+        .collect();
+    fs::write(repository.git_dir.join("MERGE_HEAD"), merge_heads)
+        .context("Failed to write MERGE_HEAD")?;
+    fs::write(repository.git_dir.join("MERGE_MSG"), format!("{message}\n"))
+        .context("Failed to write MERGE_MSG")?;
+
+    let conflicted: String = conflicted
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect();
+    fs::write(repository.git_dir.join("MERGE_CONFLICTS"), conflicted)
+        .context("Failed to write MERGE_CONFLICTS")
+}
+
+/// Restores HEAD, the index and every path touched by a conflicted merge to
+/// the state saved when the merge started. Local changes to files the merge
+/// did not touch are left alone.
+fn abort_merge(repository: &Repository) -> Result<()> {
+    let git_dir = &repository.git_dir;
+
+    if !git_dir.join("MERGE_HEAD").exists() {
+        return Err(anyhow!(
+            "fatal: There is no merge to abort (MERGE_HEAD missing)."
+        ));
+    }
+
+    let orig_head = repository
+        .read_ref("ORIG_HEAD")?
+        .ok_or_else(|| anyhow!("fatal: cannot abort merge: ORIG_HEAD is missing"))?;
+    let orig_index = git_dir.join("ORIG_INDEX");
+    if !orig_index.is_file() {
+        return Err(anyhow!("fatal: cannot abort merge: ORIG_INDEX is missing"));
+    }
+
+    let merged: BTreeMap<PathBuf, IndexEntry> = repository
+        .read_index()?
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+
+    fs::copy(&orig_index, &repository.index_file).context("Failed to restore index")?;
+    let original: BTreeMap<PathBuf, IndexEntry> = repository
+        .read_index()?
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+
+    let conflicts_file = git_dir.join("MERGE_CONFLICTS");
+    let conflicted: Vec<PathBuf> = if conflicts_file.is_file() {
+        fs::read_to_string(&conflicts_file)?
+            .lines()
+            .map(PathBuf::from)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let touched: BTreeSet<&PathBuf> = merged
+        .keys()
+        .chain(original.keys())
+        .filter(|path| {
+            let staged = |entries: &BTreeMap<PathBuf, IndexEntry>| {
+                entries.get(*path).map(|entry| (entry.mode, entry.sha1))
+            };
+            staged(&merged) != staged(&original)
+        })
+        .chain(conflicted.iter())
+        .collect();
+
+    let work_dir = repository.work_dir();
+    let mut filters = Filters::load(repository)?;
+    for path in touched {
+        let file = work_dir.join(path);
+        match original.get(path) {
+            Some(entry) => {
+                let content = read_blob_bytes(repository, &entry.sha1)?;
+                fs::write(&file, filters.smudge(path, content)?)
+                    .with_context(|| format!("Failed to restore {}", file.display()))?;
+            }
+            None if file.exists() => {
+                fs::remove_file(&file)
+                    .with_context(|| format!("Failed to remove {}", file.display()))?;
+            }
+            None => {}
+        }
+    }
+
+    repository.update_head(&orig_head)?;
+    clear_merge_state(repository)
+}
+
+/// Forgets a merge in progress, once it has been committed or aborted.
+pub fn clear_merge_state(repository: &Repository) -> Result<()> {
+    for state_file in ["MERGE_HEAD", "MERGE_MSG", "MERGE_CONFLICTS", "ORIG_INDEX"] {
+        let state_file = repository.git_dir.join(state_file);
+        if state_file.exists() {
+            fs::remove_file(&state_file)
+                .with_context(|| format!("Failed to remove {}", state_file.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The best common ancestor of `a` and `b`: when there are several, as
+/// after criss-cross merges, the one committed last.
+pub fn merge_base(repository: &Repository, a: &[u8; 20], b: &[u8; 20]) -> Result<Option<[u8; 20]>> {
+    Ok(merge_bases(repository, a, b)?.into_iter().next())
+}
+
+/// The common ancestors of `a` and `b` that are not ancestors of another
+/// one, newest first.
+pub fn merge_bases(repository: &Repository, a: &[u8; 20], b: &[u8; 20]) -> Result<Vec<[u8; 20]>> {
+    let ancestors_of_a = ancestors(repository, [*a])?;
+    let common: HashSet<[u8; 20]> = ancestors(repository, [*b])?
+        .into_iter()
+        .filter(|commit| ancestors_of_a.contains(commit))
+        .collect();
+
+    // Whatever can be reached from a parent of a common ancestor is common
+    // too, and further from both sides than that one.
+    let mut parents = Vec::new();
+    for commit in &common {
+        parents.extend(repository.read_commit(commit)?.parents()?);
+    }
+    let stale = ancestors(repository, parents)?;
+
+    let mut bases = Vec::new();
+    for commit in common.into_iter().filter(|commit| !stale.contains(commit)) {
+        bases.push((commit_time(&repository.read_commit(&commit)?), commit));
+    }
+    bases.sort_by(|a, b| b.cmp(a));
+
+    Ok(bases.into_iter().map(|(_, commit)| commit).collect())
+}
+
+/// Whether `ancestor` can be reached from `descendant`, counting a commit
+/// as its own ancestor.
+pub fn is_ancestor(
+    repository: &Repository,
+    ancestor: &[u8; 20],
+    descendant: &[u8; 20],
+) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([*descendant]);
+
+    while let Some(commit) = queue.pop_front() {
+        if commit == *ancestor {
+            return Ok(true);
+        }
+        if seen.insert(commit) {
+            queue.extend(repository.read_commit(&commit)?.parents()?);
+        }
+    }
+
+    Ok(false)
+}
+
+/// `tips` and every commit reachable from them.
+fn ancestors(
+    repository: &Repository,
+    tips: impl IntoIterator<Item = [u8; 20]>,
+) -> Result<HashSet<[u8; 20]>> {
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<[u8; 20]> = tips.into_iter().collect();
+
+    while let Some(commit) = queue.pop_front() {
+        if seen.insert(commit) {
+            queue.extend(repository.read_commit(&commit)?.parents()?);
+        }
+    }
+
+    Ok(seen)
+}
+
+/// Prints the best common ancestor of two commits, or with `all`, every
+/// one. With `is_ancestor`, prints nothing and only tells by the exit
+/// status whether the first commit is an ancestor of the second.
+pub fn handle_merge_base_command(
+    a: &str,
+    b: &str,
+    all: bool,
+    is_ancestor_only: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let a = repository.resolve_commitish(a)?;
+    let b = repository.resolve_commitish(b)?;
+
+    if is_ancestor_only {
+        return Ok(if is_ancestor(repository, &a, &b)? {
+            ExitStatus::Success
+        } else {
+            ExitStatus::Differences
+        });
+    }
+
+    let mut bases = merge_bases(repository, &a, &b)?;
+    if bases.is_empty() {
+        return Ok(ExitStatus::Differences);
+    }
+    if !all {
+        bases.truncate(1);
+    }
+    for base in bases {
+        println!("{}", encode(base));
+    }
+
+    Ok(ExitStatus::Success)
+}
+
+pub fn tree_of(repository: &Repository, commit: &[u8; 20]) -> Result<[u8; 20]> {
+    crate::refs::parse_hash(&repository.read_commit(commit)?.tree_hash()?)
+}
+
+fn default_merge_message(branches: &[&str]) -> String {
+    let is_commit = |name: &str| name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit());
+    let quoted: Vec<String> = branches.iter().map(|name| format!("'{name}'")).collect();
+
+    match quoted.as_slice() {
+        [single] if is_commit(branches[0]) => format!("Merge commit {single}"),
+        [single] => format!("Merge branch {single}"),
+        [init @ .., last] => format!("Merge branches {} and {last}", init.join(", ")),
+        [] => String::from("Merge"),
+    }
+}
+
+/// Refuses to go on with `operation` when the index has changes that are
+/// not in `head_entries`, since moving to another tree would lose them.
+pub fn ensure_index_matches(
+    repository: &Repository,
+    head_entries: &[TreeEntry],
+    operation: &str,
+) -> Result<()> {
+    let index = repository.read_index()?;
+    let matches = index.entries.len() == head_entries.len()
+        && index.entries.iter().all(|entry| {
+            head_entries.iter().any(|head_entry| {
+                head_entry.path == entry.path
+                    && head_entry.sha1 == entry.sha1
+                    && head_entry.mode == entry.mode
+            })
+        });
+
+    if !matches {
+        return Err(anyhow!(
+            "error: Your local changes to the index would be overwritten by {}.\n\
+Please commit your changes before you {}.",
+            operation,
+            operation_phrase(operation)
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn merge_trees(
+    repository: &Repository,
+    base: &[TreeEntry],
+    ours: &[TreeEntry],
+    theirs: &[TreeEntry],
+    theirs_label: &str,
+) -> Result<MergeOutcome> {
+    let by_path = |entries: &[TreeEntry]| -> BTreeMap<PathBuf, TreeEntry> {
+        entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.clone()))
+            .collect()
+    };
+    let base = by_path(base);
+    let ours = by_path(ours);
+    let theirs = by_path(theirs);
+
+    let paths: BTreeSet<&PathBuf> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+
+    let config = Config::load(repository)?;
+    let mut attributes = Attributes::load(repository)?;
+    let mut outcome = MergeOutcome {
+        entries: Vec::new(),
+        conflicted_files: Vec::new(),
+        conflicts: Vec::new(),
+    };
+
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+
+        let resolved = if o == t || b == t {
+            o.cloned()
+        } else if b == o {
+            t.cloned()
+        } else {
+            match (o, t) {
+                (Some(o), Some(t)) => {
+                    let base_content = match b {
+                        Some(b) => read_blob_bytes(repository, &b.sha1)?,
+                        None => Vec::new(),
+                    };
+                    let ours_content = read_blob_bytes(repository, &o.sha1)?;
+                    let theirs_content = read_blob_bytes(repository, &t.sha1)?;
+
+                    let driver = driver_for(&config, &mut attributes, path)?;
+                    let (merged, conflicted) = run_driver(
+                        repository,
+                        &driver,
+                        path,
+                        (&base_content, &ours_content, &theirs_content),
+                        theirs_label,
+                    )?;
+
+                    if conflicted {
+                        let kind = if b.is_some() { "content" } else { "add/add" };
+                        outcome.conflicts.push(format!(
+                            "CONFLICT ({kind}): Merge conflict in {}",
+                            path.display()
+                        ));
+                        outcome.conflicted_files.push((path.clone(), merged));
+                        Some(o.clone())
+                    } else {
+                        let (sha1, _) = repository.write_object(&GitObjectsArgs::Blob(merged))?;
+                        Some(TreeEntry {
+                            mode: o.mode,
+                            path: path.clone(),
+                            sha1,
+                        })
+                    }
+                }
+                (Some(o), None) => {
+                    outcome.conflicts.push(format!(
+                        "CONFLICT (modify/delete): {} deleted in {theirs_label} and modified in HEAD.",
+                        path.display()
+                    ));
+                    Some(o.clone())
+                }
+                (None, Some(t)) => {
+                    outcome.conflicts.push(format!(
+                        "CONFLICT (modify/delete): {} deleted in HEAD and modified in {theirs_label}.",
+                        path.display()
+                    ));
+                    outcome
+                        .conflicted_files
+                        .push((path.clone(), read_blob_bytes(repository, &t.sha1)?));
+                    None
+                }
+                (None, None) => None,
+            }
+        };
+
+        if let Some(entry) = resolved {
+            outcome.entries.push(entry);
+        }
+    }
+
+    Ok(outcome)
+}
+
+pub fn read_blob_bytes(repository: &Repository, sha1: &[u8; 20]) -> Result<Vec<u8>> {
+    Ok(repository.read_blob(sha1)?.raw_content)
+}
+
+fn driver_for(config: &Config, attributes: &mut Attributes, path: &Path) -> Result<MergeDriver> {
+    Ok(match attributes.get(path, "merge")? {
+        AttrState::Unset => MergeDriver::Binary,
+        AttrState::Set | AttrState::Unspecified => MergeDriver::Text,
+        AttrState::Value(name) => match name.as_str() {
+            "text" => MergeDriver::Text,
+            "union" => MergeDriver::Union,
+            "binary" => MergeDriver::Binary,
+            custom => match config.get(&format!("merge.{custom}.driver")) {
+                Some(command) => MergeDriver::External(command.to_string()),
+                None => MergeDriver::Text,
+            },
+        },
+    })
+}
+
+fn run_driver(
+    repository: &Repository,
+    driver: &MergeDriver,
+    path: &Path,
+    (base, ours, theirs): (&[u8], &[u8], &[u8]),
+    theirs_label: &str,
+) -> Result<(Vec<u8>, bool)> {
+    match driver {
+        MergeDriver::Text => Ok(merge_text(
+            (base, ours, theirs),
+            "HEAD",
+            theirs_label,
+            Resolution::Conflict,
+            CONFLICT_MARKER_SIZE,
+        )),
+        MergeDriver::Union => Ok(merge_text(
+            (base, ours, theirs),
+            "HEAD",
+            theirs_label,
+            Resolution::Union,
+            CONFLICT_MARKER_SIZE,
+        )),
+        MergeDriver::Binary => Ok((ours.to_vec(), true)),
+        MergeDriver::External(command) => {
+            run_external_driver(repository, command, path, (base, ours, theirs))
+        }
+    }
+}
+
+fn run_external_driver(
+    repository: &Repository,
+    command: &str,
+    path: &Path,
+    (base, ours, theirs): (&[u8], &[u8], &[u8]),
+) -> Result<(Vec<u8>, bool)> {
+    let temp_file = |suffix: &str| {
+        repository
+            .git_dir
+            .join(format!(".merge_file_{}_{suffix}", std::process::id()))
+    };
+    let (base_file, ours_file, theirs_file) =
+        (temp_file("base"), temp_file("ours"), temp_file("theirs"));
+
+    fs::write(&base_file, base).context("Failed to write merge base temp file")?;
+    fs::write(&ours_file, ours).context("Failed to write merge ours temp file")?;
+    fs::write(&theirs_file, theirs).context("Failed to write merge theirs temp file")?;
+
+    let mut expanded = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('O') => expanded.push_str(&base_file.to_string_lossy()),
+            Some('A') => expanded.push_str(&ours_file.to_string_lossy()),
+            Some('B') => expanded.push_str(&theirs_file.to_string_lossy()),
+            Some('L') => expanded.push_str(&CONFLICT_MARKER_SIZE.to_string()),
+            Some('P') => expanded.push_str(&path.to_string_lossy()),
+            Some(other) => expanded.push(other),
+            None => expanded.push('%'),
+        }
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(&expanded)
+        .status()
+        .with_context(|| format!("Failed to run merge driver '{command}'"));
+    let result = fs::read(&ours_file).context("Failed to read merge driver result");
+
+    for file in [&base_file, &ours_file, &theirs_file] {
+        let _ = fs::remove_file(file);
+    }
+
+    Ok((result?, !status?.success()))
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`, settling the
+/// regions both changed as `resolution` says. Returns the result and
+/// whether it has conflict markers, `marker_size` characters wide.
+pub fn merge_text(
+    (base, ours, theirs): (&[u8], &[u8], &[u8]),
+    ours_label: &str,
+    theirs_label: &str,
+    resolution: Resolution,
+    marker_size: usize,
+) -> (Vec<u8>, bool) {
+    let base_lines = split_lines(base);
+    let ours_lines = split_lines(ours);
+    let theirs_lines = split_lines(theirs);
+
+    let mut hunks: Vec<(Hunk, bool)> = diff_lines(&base_lines, &ours_lines)
+        .into_iter()
+        .map(|hunk| (hunk, true))
+        .chain(
+            diff_lines(&base_lines, &theirs_lines)
+                .into_iter()
+                .map(|hunk| (hunk, false)),
+        )
+        .collect();
+    hunks.sort_by_key(|(hunk, is_ours)| (hunk.old_start, !is_ours));
+
+    let mut output = Vec::new();
+    let mut conflicted = false;
+    let mut base_pos = 0;
+    let (mut ours_delta, mut theirs_delta) = (0isize, 0isize);
+    let mut i = 0;
+
+    while i < hunks.len() {
+        let start = hunks[i].0.old_start;
+        let mut end = start + hunks[i].0.old_len;
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].0.old_start <= end {
+            end = end.max(hunks[j].0.old_start + hunks[j].0.old_len);
+            j += 1;
+        }
+        let group = &hunks[i..j];
+
+        for line in &base_lines[base_pos..start] {
+            output.extend_from_slice(line);
+        }
+
+        let ours_range = side_range(group, true, start, end, &mut ours_delta);
+        let theirs_range = side_range(group, false, start, end, &mut theirs_delta);
+        let ours_side = &ours_lines[ours_range];
+        let theirs_side = &theirs_lines[theirs_range];
+
+        let ours_changed = group.iter().any(|(_, is_ours)| *is_ours);
+        let theirs_changed = group.iter().any(|(_, is_ours)| !*is_ours);
+
+        if !theirs_changed || ours_side == theirs_side {
+            ours_side
+                .iter()
+                .for_each(|line| output.extend_from_slice(line));
+        } else if !ours_changed {
+            theirs_side
+                .iter()
+                .for_each(|line| output.extend_from_slice(line));
+        } else {
+            match resolution {
+                Resolution::Ours => push_lines(&mut output, ours_side),
+                Resolution::Theirs => push_lines(&mut output, theirs_side),
+                Resolution::Union => {
+                    push_lines(&mut output, ours_side);
+                    push_lines(&mut output, theirs_side);
+                }
+                Resolution::Conflict => {
+                    conflicted = true;
+                    let marker = |c: char| c.to_string().repeat(marker_size);
+                    output.extend_from_slice(format!("{} {ours_label}\n", marker('<')).as_bytes());
+                    push_lines(&mut output, ours_side);
+                    output.extend_from_slice(format!("{}\n", marker('=')).as_bytes());
+                    push_lines(&mut output, theirs_side);
+                    output
+                        .extend_from_slice(format!("{} {theirs_label}\n", marker('>')).as_bytes());
+                }
+            }
+        }
+
+        base_pos = end;
+        i = j;
+    }
+
+    for line in &base_lines[base_pos..] {
+        output.extend_from_slice(line);
+    }
+
+    (output, conflicted)
+}
+
+fn side_range(
+    group: &[(Hunk, bool)],
+    ours: bool,
+    start: usize,
+    end: usize,
+    delta: &mut isize,
+) -> std::ops::Range<usize> {
+    let side: Vec<&Hunk> = group
+        .iter()
+        .filter(|(_, is_ours)| *is_ours == ours)
+        .map(|(hunk, _)| hunk)
+        .collect();
+
+    match (side.first(), side.last()) {
+        (Some(first), Some(last)) => {
+            let side_start = first.new_start - (first.old_start - start);
+            let side_end = last.new_start + last.new_len + (end - (last.old_start + last.old_len));
+            *delta = side_end as isize - end as isize;
+            side_start..side_end
+        }
+        _ => (start as isize + *delta) as usize..(end as isize + *delta) as usize,
+    }
+}
+
+fn push_lines(output: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        output.extend_from_slice(line);
+    }
+    if output.last().is_some_and(|&b| b != b'\n') {
+        output.push(b'\n');
+    }
+}
+
+/// Moves the working tree from `current` to `target`, refusing to clobber
+/// files whose content no longer matches what `current` recorded. The
+/// refusal names `operation` as what would have overwritten them.
+pub fn checkout_entries(
+    repository: &Repository,
+    current: &[TreeEntry],
+    target: &[TreeEntry],
+    operation: &str,
+) -> Result<()> {
+    let work_dir = repository.work_dir();
+    let current: BTreeMap<&PathBuf, &TreeEntry> =
+        current.iter().map(|entry| (&entry.path, entry)).collect();
+    let target: BTreeMap<&PathBuf, &TreeEntry> =
+        target.iter().map(|entry| (&entry.path, entry)).collect();
+
+    let changed: Vec<&PathBuf> = current
+        .keys()
+        .chain(target.keys())
+        .filter(|path| current.get(*path) != target.get(*path))
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut filters = Filters::load(repository)?;
+    let mut dirty = Vec::new();
+    for path in &changed {
+        // A submodule is a repository of its own, left for `submodule
+        // update` to move.
+        if current.get(path).is_some_and(|entry| entry.mode == 160000) {
+            continue;
+        }
+        let file = work_dir.join(path);
+        let on_disk = file
+            .is_file()
+            .then(|| fs::read(&file))
+            .transpose()?
+            .map(|content| filters.clean(path, content))
+            .transpose()?
+            .map(|content| blob_hash(&content));
+
+        if on_disk != current.get(path).map(|entry| entry.sha1) {
+            dirty.push(path.display().to_string());
+        }
+    }
+
+    if !dirty.is_empty() {
+        return Err(anyhow!(
+            "error: Your local changes to the following files would be overwritten by {}:\n\t{}\n\
+Please commit your changes or stash them before you {}.",
+            operation,
+            dirty.join("\n\t"),
+            operation_phrase(operation)
+        ));
+    }
+
+    for path in changed {
+        let file = work_dir.join(path);
+        match target.get(path) {
+            Some(entry) if entry.mode == 160000 => {
+                if file.is_file() {
+                    fs::remove_file(&file)
+                        .with_context(|| format!("Failed to remove {}", file.display()))?;
+                }
+                fs::create_dir_all(&file)
+                    .with_context(|| format!("Failed to create {}", file.display()))?;
+            }
+            None if current.get(path).is_some_and(|entry| entry.mode == 160000) => {
+                // Like git, only an empty submodule directory goes away.
+                let _ = fs::remove_dir(&file);
+            }
+            Some(entry) => {
+                if current.get(path).is_some_and(|entry| entry.mode == 160000) {
+                    let _ = fs::remove_dir(&file);
+                }
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let content = read_blob_bytes(repository, &entry.sha1)?;
+                fs::write(&file, filters.smudge(path, content)?)
+                    .with_context(|| format!("Failed to write {}", file.display()))?;
+            }
+            None => {
+                fs::remove_file(&file)
+                    .with_context(|| format!("Failed to remove {}", file.display()))?;
+            }
+        }
+    }
+
+    let mut index = IndexFile {
+        entries: target
+            .values()
+            .map(|entry| IndexEntry {
+                mode: entry.mode,
+                sha1: entry.sha1,
+                path: entry.path.clone(),
+                stat: StatData::default(),
+            })
+            .collect(),
+    };
+    repository.write_index(&mut index)
+}
+
+/// What the user was doing, for the advice that follows a refusal.
+fn operation_phrase(operation: &str) -> &str {
+    match operation {
+        "checkout" => "switch branches",
+        "am" => "apply patches",
+        operation => operation,
+    }
+}
@@ -0,0 +1,548 @@
+//! The messages mini-git shows people, in their language when there is a
+//! catalog for it.
+//!
+//! Messages are templates in which each `{}` stands for the next argument.
+//! `tr!` fills one in and `tr_n!` picks the singular or plural form by a
+//! count first, so that a handler never glues words together itself:
+//!
+//! ```text
+//! tr!("Switched to branch '{}'", name)
+//! tr_n!(count, "{} file changed", "{} files changed", count)
+//! ```
+//!
+//! The catalog is a gettext `.po` file, `<lang>.po` in the directory
+//! `MINI_GIT_LOCALEDIR` names, for the first language `LANGUAGE`, `LC_ALL`,
+//! `LC_MESSAGES` or `LANG` asks for: `de_DE.UTF-8` looks for `de_DE.po`,
+//! then `de.po`. A translation may refer to arguments by position, `{0}`,
+//! `{1}`, when its language orders them differently, and plural forms are
+//! chosen by the catalog's `Plural-Forms` expression. Without a catalog, or
+//! for a message it lacks, the English template is used.
+//!
+//! Errors are already formatted by the time they are reported, so
+//! `translate_report` matches them against the catalog's templates as a
+//! whole instead.
+
+use anyhow::{Result, anyhow};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::{Display, Write},
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Fills in a message template, translated if possible, with the
+/// arguments given after it.
+macro_rules! tr {
+    ($template:literal $(, $arg:expr)* $(,)?) => {
+        $crate::messages::message(
+            $template,
+            &[$(&$arg as &dyn ::std::fmt::Display),*],
+        )
+    };
+}
+
+/// Like `tr!`, with the template for a count of one and the one for any
+/// other count, which the catalog may have more forms of.
+macro_rules! tr_n {
+    ($count:expr, $singular:literal, $plural:literal $(, $arg:expr)* $(,)?) => {
+        $crate::messages::plural_message(
+            $count as u64,
+            $singular,
+            $plural,
+            &[$(&$arg as &dyn ::std::fmt::Display),*],
+        )
+    };
+}
+
+pub(crate) use {tr, tr_n};
+
+/// Translations read from a `.po` file.
+struct Catalog {
+    /// Each translated template's forms, by the English template, or for a
+    /// plural message its singular.
+    messages: HashMap<String, Vec<String>>,
+    plural_forms: PluralExpr,
+}
+
+pub fn message(template: &str, args: &[&dyn Display]) -> String {
+    let translated = catalog()
+        .and_then(|catalog| catalog.messages.get(template))
+        .and_then(|forms| forms.first());
+    fill(translated.map_or(template, String::as_str), args)
+}
+
+pub fn plural_message(count: u64, singular: &str, plural: &str, args: &[&dyn Display]) -> String {
+    let translated = catalog().and_then(|catalog| {
+        let form = catalog.plural_forms.eval(count);
+        catalog
+            .messages
+            .get(singular)
+            .and_then(|forms| forms.get(usize::try_from(form).ok()?))
+    });
+    let english = if count == 1 { singular } else { plural };
+    fill(translated.map_or(english, String::as_str), args)
+}
+
+/// The report of `error` as it is printed, with its message translated
+/// when the catalog has a template that produces it.
+pub fn translate_report(error: &anyhow::Error) -> String {
+    let report = format!("{error:?}");
+    let message = error.to_string();
+    let translated = catalog().and_then(|catalog| catalog.translate_formatted(&message));
+    match (translated, report.strip_prefix(&message)) {
+        (Some(translated), Some(rest)) => translated + rest,
+        _ => report,
+    }
+}
+
+/// The catalog for the user's language, read once when first needed. One
+/// that cannot be read is reported and then done without.
+fn catalog() -> Option<&'static Catalog> {
+    static CATALOG: OnceLock<Option<Catalog>> = OnceLock::new();
+
+    CATALOG
+        .get_or_init(|| {
+            let path = catalog_path()?;
+            Catalog::load(&path)
+                .inspect_err(|error| eprintln!("warning: {error}"))
+                .ok()
+        })
+        .as_ref()
+}
+
+fn catalog_path() -> Option<PathBuf> {
+    let dir = PathBuf::from(env::var_os("MINI_GIT_LOCALEDIR")?);
+    let languages = ["LANGUAGE", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())?;
+
+    for language in languages.split(':') {
+        // `de_DE.UTF-8@euro` is `de_DE`, or failing that `de`.
+        let language = language.split(['.', '@']).next().unwrap_or_default();
+        if language.is_empty() || language == "C" || language == "POSIX" {
+            continue;
+        }
+        let candidates = [
+            Some(language),
+            language.split_once('_').map(|(lang, _)| lang),
+        ];
+        for candidate in candidates.into_iter().flatten() {
+            let path = dir.join(format!("{candidate}.po"));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+impl Catalog {
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| anyhow!("cannot read {}: {error}", path.display()))?;
+        let entries = parse_po(&content).map_err(|error| anyhow!("{}: {error}", path.display()))?;
+
+        let mut catalog = Catalog {
+            messages: HashMap::new(),
+            plural_forms: PluralExpr::parse("n != 1")?,
+        };
+        for entry in entries {
+            if entry.msgid.is_empty() {
+                let header = entry.msgstr.first().map(String::as_str).unwrap_or_default();
+                if let Some(expr) = header
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Plural-Forms:"))
+                    .and_then(|forms| {
+                        forms
+                            .split(';')
+                            .find_map(|part| part.trim().strip_prefix("plural="))
+                    })
+                {
+                    catalog.plural_forms = PluralExpr::parse(expr)
+                        .map_err(|error| anyhow!("{}: {error}", path.display()))?;
+                }
+                continue;
+            }
+            if entry.fuzzy || entry.msgstr.iter().all(String::is_empty) {
+                continue;
+            }
+            catalog.messages.insert(entry.msgid, entry.msgstr);
+        }
+
+        Ok(catalog)
+    }
+
+    /// The translation of a message already formatted from one of the
+    /// catalog's templates, found by matching each `{}` to whatever text
+    /// stands in its place.
+    fn translate_formatted(&self, message: &str) -> Option<String> {
+        if let Some(forms) = self.messages.get(message) {
+            return forms.first().cloned();
+        }
+
+        // Of the templates that fit, the one spelling out most of the
+        // message is the likeliest source.
+        let (args, forms) = self
+            .messages
+            .iter()
+            .filter_map(|(template, forms)| Some((match_template(template, message)?, forms)))
+            .max_by_key(|(args, _)| {
+                message.len() - args.iter().map(|arg| arg.len()).sum::<usize>()
+            })?;
+        let args: Vec<&dyn Display> = args.iter().map(|arg| arg as &dyn Display).collect();
+        Some(fill(forms.first()?, &args))
+    }
+}
+
+/// The arguments `message` was formatted with if it comes from `template`.
+fn match_template<'a>(template: &str, message: &'a str) -> Option<Vec<&'a str>> {
+    let pieces: Vec<&str> = template.split("{}").collect();
+    if pieces.len() < 2 {
+        return None;
+    }
+
+    let (first, rest) = pieces.split_first()?;
+    let (last, middle) = rest.split_last()?;
+    let mut remaining = message.strip_prefix(first)?.strip_suffix(last)?;
+    let mut args = Vec::new();
+    for piece in middle {
+        let end = remaining.find(piece)?;
+        args.push(&remaining[..end]);
+        remaining = &remaining[end + piece.len()..];
+    }
+    args.push(remaining);
+
+    Some(args)
+}
+
+/// Replaces each `{}` in `template` with the next of `args`, and each
+/// `{<n>}` with the `n`th; `{{` and `}}` stand for braces.
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("{{") {
+            filled.push('{');
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("}}") {
+            filled.push('}');
+            rest = after;
+            continue;
+        }
+
+        let placeholder = rest
+            .strip_prefix('{')
+            .and_then(|after| after.split_once('}'))
+            .and_then(|(inside, after)| {
+                let index = if inside.is_empty() {
+                    next += 1;
+                    next - 1
+                } else {
+                    inside.parse().ok()?
+                };
+                Some((index, after))
+            });
+        match placeholder {
+            Some((index, after)) => {
+                if let Some(arg) = args.get(index) {
+                    let _ = write!(filled, "{arg}");
+                }
+                rest = after;
+            }
+            None => {
+                filled.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+/// One message of a `.po` file.
+#[derive(Default)]
+struct PoEntry {
+    msgid: String,
+    /// The translation, or each plural form of it.
+    msgstr: Vec<String>,
+    fuzzy: bool,
+}
+
+/// Which part of an entry a string continues.
+enum PoField {
+    Msgid,
+    MsgidPlural,
+    Msgstr(usize),
+}
+
+fn parse_po(content: &str) -> Result<Vec<PoEntry>> {
+    let mut entries = Vec::new();
+    let mut entry = PoEntry::default();
+    let mut field = None;
+    let mut fuzzy = false;
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let error = |message: &str| anyhow!("line {}: {message}", number + 1);
+
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(flags) = line.strip_prefix("#,") {
+            fuzzy |= flags.split(',').any(|flag| flag.trim() == "fuzzy");
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, value) = match line.split_once(char::is_whitespace) {
+            Some((keyword, value)) if !line.starts_with('"') => (keyword, value.trim()),
+            _ => ("", line),
+        };
+        let value = unquote(value).ok_or_else(|| error("expected a quoted string"))?;
+
+        match keyword {
+            "msgid" => {
+                if field.is_some() {
+                    entries.push(std::mem::take(&mut entry));
+                }
+                entry.fuzzy = std::mem::take(&mut fuzzy);
+                entry.msgid = value;
+                field = Some(PoField::Msgid);
+            }
+            "msgid_plural" => field = Some(PoField::MsgidPlural),
+            "msgstr" => {
+                entry.msgstr = vec![value];
+                field = Some(PoField::Msgstr(0));
+            }
+            "" => match field {
+                Some(PoField::Msgid) => entry.msgid.push_str(&value),
+                Some(PoField::MsgidPlural) => {}
+                Some(PoField::Msgstr(form)) => entry.msgstr[form].push_str(&value),
+                None => return Err(error("string outside of an entry")),
+            },
+            keyword => {
+                let form: usize = keyword
+                    .strip_prefix("msgstr[")
+                    .and_then(|rest| rest.strip_suffix(']'))
+                    .and_then(|form| form.parse().ok())
+                    .ok_or_else(|| error(&format!("unknown keyword '{keyword}'")))?;
+                if entry.msgstr.len() <= form {
+                    entry.msgstr.resize(form + 1, String::new());
+                }
+                entry.msgstr[form] = value;
+                field = Some(PoField::Msgstr(form));
+            }
+        }
+    }
+    if field.is_some() {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// The content of a C-style quoted string.
+fn unquote(quoted: &str) -> Option<String> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        unquoted.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            other => other,
+        });
+    }
+    Some(unquoted)
+}
+
+/// A `Plural-Forms` expression: C arithmetic on `n` giving the index of
+/// the form to use.
+enum PluralExpr {
+    N,
+    Number(u64),
+    Not(Box<PluralExpr>),
+    Binary(Box<PluralExpr>, String, Box<PluralExpr>),
+    Conditional(Box<PluralExpr>, Box<PluralExpr>, Box<PluralExpr>),
+}
+
+impl PluralExpr {
+    fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = ExprParser {
+            tokens: &tokens,
+            pos: 0,
+        };
+        let expr = parser.conditional()?;
+        if parser.pos != tokens.len() {
+            return Err(anyhow!("bad plural expression '{source}'"));
+        }
+        Ok(expr)
+    }
+
+    fn eval(&self, n: u64) -> u64 {
+        match self {
+            PluralExpr::N => n,
+            PluralExpr::Number(value) => *value,
+            PluralExpr::Not(operand) => u64::from(operand.eval(n) == 0),
+            PluralExpr::Conditional(condition, then, otherwise) => {
+                if condition.eval(n) != 0 {
+                    then.eval(n)
+                } else {
+                    otherwise.eval(n)
+                }
+            }
+            PluralExpr::Binary(left, operator, right) => {
+                let (left, right) = (left.eval(n), right.eval(n));
+                match operator.as_str() {
+                    "||" => u64::from(left != 0 || right != 0),
+                    "&&" => u64::from(left != 0 && right != 0),
+                    "==" => u64::from(left == right),
+                    "!=" => u64::from(left != right),
+                    "<" => u64::from(left < right),
+                    "<=" => u64::from(left <= right),
+                    ">" => u64::from(left > right),
+                    ">=" => u64::from(left >= right),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" => left.checked_div(right).unwrap_or(0),
+                    "%" => left.checked_rem(right).unwrap_or(0),
+                    _ => 0,
+                }
+            }
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.trim_end_matches(';').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {}
+            '0'..='9' => {
+                let mut number = c.to_string();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    number.push(digit);
+                }
+                tokens.push(number);
+            }
+            '|' | '&' | '=' | '!' | '<' | '>' => {
+                let mut operator = c.to_string();
+                if let Some(second) = chars.next_if(|&next| next == '=' || (next == c && c != '='))
+                {
+                    operator.push(second);
+                }
+                tokens.push(operator);
+            }
+            'n' | '?' | ':' | '(' | ')' | '+' | '-' | '*' | '/' | '%' => tokens.push(c.to_string()),
+            _ => return Err(anyhow!("bad plural expression '{source}'")),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses tokens by C's precedence, loosest first.
+struct ExprParser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    const LEVELS: &'static [&'static [&'static str]] = &[
+        &["||"],
+        &["&&"],
+        &["==", "!="],
+        &["<", "<=", ">", ">="],
+        &["+", "-"],
+        &["*", "/", "%"],
+    ];
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.peek() != Some(token) {
+            return Err(anyhow!("bad plural expression: expected '{token}'"));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn conditional(&mut self) -> Result<PluralExpr> {
+        let condition = self.binary(0)?;
+        if self.peek() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.conditional()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+
+        Ok(PluralExpr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<PluralExpr> {
+        let Some(operators) = Self::LEVELS.get(level) else {
+            return self.unary();
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self.peek().filter(|token| operators.contains(token)) {
+            let operator = operator.to_string();
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = PluralExpr::Binary(Box::new(left), operator, Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<PluralExpr> {
+        let token = self
+            .peek()
+            .ok_or_else(|| anyhow!("bad plural expression: unexpected end"))?
+            .to_string();
+        self.pos += 1;
+
+        match token.as_str() {
+            "n" => Ok(PluralExpr::N),
+            "!" => Ok(PluralExpr::Not(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.conditional()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            number => number
+                .parse()
+                .map(PluralExpr::Number)
+                .map_err(|_| anyhow!("bad plural expression: unexpected '{number}'")),
+        }
+    }
+}
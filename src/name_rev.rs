@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use hex::encode;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    Repository, ident::Ident, messages::tr, refs::parse_hash, upload_pack::peel_tag,
+    walk::commit_time,
+};
+
+/// How much further a name through the second or a later parent of a merge
+/// counts than one along first parents, as in git, so that such names are
+/// only used when nothing else reaches the commit.
+const MERGE_TRAVERSAL_WEIGHT: u64 = 65535;
+
+/// The name found for a commit: `generation` first parents back from
+/// `tip_name`, which is a ref or a path through merges from one.
+struct RevName {
+    tip_name: String,
+    generation: u64,
+    distance: u64,
+    tagger_date: i64,
+    from_tag: bool,
+}
+
+impl RevName {
+    fn name(&self) -> String {
+        if self.generation == 0 {
+            self.tip_name.clone()
+        } else {
+            format!("{}~{}", self.tip_name, self.generation)
+        }
+    }
+
+    /// Whether a name with the given properties should replace this one:
+    /// one from a tag beats any other, the older of two tags wins, and
+    /// otherwise the shorter path, then the older tip.
+    fn is_worse_than(&self, tagger_date: i64, distance: u64, from_tag: bool) -> bool {
+        if from_tag && self.from_tag {
+            return self.tagger_date > tagger_date
+                || (self.tagger_date == tagger_date && self.distance > distance);
+        }
+        if self.from_tag != from_tag {
+            return from_tag;
+        }
+        if self.distance != distance {
+            return self.distance > distance;
+        }
+        self.tagger_date > tagger_date
+    }
+}
+
+/// A ref to name commits after.
+struct Tip {
+    name: String,
+    commit: [u8; 20],
+    tagger_date: i64,
+    from_tag: bool,
+}
+
+/// Names each of `revisions`, or the commits named in lines read from
+/// stdin, relative to the refs that reach them, like `main~3` or
+/// `tags/v1.0~2^2`. A commit that is the target of an annotated tag is
+/// `tags/<name>^0`. With `tags`, only tags are used; with `name_only`,
+/// only the names are printed.
+///
+/// Lines read from stdin are printed back with ` (<name>)` after every
+/// full hash of a commit that has a name, or with `name_only`, the name in
+/// place of the hash.
+pub fn handle_name_rev_command(
+    revisions: Vec<String>,
+    name_only: bool,
+    tags: bool,
+    stdin: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let names = name_commits(repository, tags, name_only)?;
+    let mut out = io::stdout().lock();
+
+    if stdin {
+        for line in io::stdin().lock().lines() {
+            let line = line.context("Failed to read from stdin")?;
+            writeln!(out, "{}", annotate_line(&line, &names, name_only))?;
+        }
+        return Ok(());
+    }
+
+    for revision in revisions {
+        let Ok(hash) = repository.resolve_commitish(&revision) else {
+            eprintln!("{}", tr!("Could not get sha1 for {}. Skipping.", revision));
+            continue;
+        };
+        let commit = peel_tag(repository, &hash)?.unwrap_or(hash);
+        let name = names
+            .get(&commit)
+            .map_or_else(|| "undefined".to_string(), RevName::name);
+        if name_only {
+            writeln!(out, "{name}")?;
+        } else {
+            writeln!(out, "{revision} {name}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The best name for every commit the refs reach.
+fn name_commits(
+    repository: &Repository,
+    tags_only: bool,
+    name_only: bool,
+) -> Result<HashMap<[u8; 20], RevName>> {
+    let mut tips = Vec::new();
+    for (refname, hash) in repository.list_refs()? {
+        let from_tag = refname.starts_with("refs/tags/");
+        if tags_only && !from_tag {
+            continue;
+        }
+
+        let name = if tags_only && name_only {
+            refname.trim_start_matches("refs/tags/")
+        } else if let Some(branch) = refname.strip_prefix("refs/heads/") {
+            branch
+        } else {
+            refname.trim_start_matches("refs/")
+        };
+        let (commit, name, tagger_date) = match peel_tag(repository, &hash)? {
+            Some(target) => (
+                target,
+                format!("{name}^0"),
+                Some(tagger_date(repository, &hash)?),
+            ),
+            None => (hash, name.to_string(), None),
+        };
+        if repository.read_raw_object(&encode(commit))?.0 != "commit" {
+            continue;
+        }
+        // A tip that is not an annotated tag dates from its commit.
+        let tagger_date = match tagger_date {
+            Some(date) => date,
+            None => commit_time(&repository.read_commit(&commit)?),
+        };
+
+        tips.push(Tip {
+            name,
+            commit,
+            tagger_date,
+            from_tag,
+        });
+    }
+    // Tags first and older tips first, so that the names they give are in
+    // place before the ones that could only replace them on distance.
+    tips.sort_by_key(|tip| (!tip.from_tag, tip.tagger_date));
+
+    let mut names = HashMap::new();
+    for tip in tips {
+        name_from_tip(repository, tip, &mut names)?;
+    }
+
+    Ok(names)
+}
+
+/// Gives the commits `tip` reaches its names where they beat the ones they
+/// have. First parents keep the name and count generations; a later
+/// parent starts a new name, `<name>^<n>`.
+fn name_from_tip(
+    repository: &Repository,
+    tip: Tip,
+    names: &mut HashMap<[u8; 20], RevName>,
+) -> Result<()> {
+    let start = RevName {
+        tip_name: tip.name,
+        generation: 0,
+        distance: 0,
+        tagger_date: tip.tagger_date,
+        from_tag: tip.from_tag,
+    };
+    if !update_name(names, tip.commit, start) {
+        return Ok(());
+    }
+
+    let mut stack = vec![tip.commit];
+    while let Some(commit) = stack.pop() {
+        let parents = repository.read_commit(&commit)?.parents()?;
+        let mut named = Vec::new();
+        for (i, parent) in parents.into_iter().enumerate() {
+            let name = &names[&commit];
+            let parent_name = if i == 0 {
+                RevName {
+                    tip_name: name.tip_name.clone(),
+                    generation: name.generation + 1,
+                    distance: name.distance + 1,
+                    tagger_date: name.tagger_date,
+                    from_tag: name.from_tag,
+                }
+            } else {
+                RevName {
+                    tip_name: format!("{}^{}", name.name(), i + 1),
+                    generation: 0,
+                    distance: name.distance + MERGE_TRAVERSAL_WEIGHT,
+                    tagger_date: name.tagger_date,
+                    from_tag: name.from_tag,
+                }
+            };
+            if update_name(names, parent, parent_name) {
+                named.push(parent);
+            }
+        }
+        // The first parent is walked first, as git does.
+        stack.extend(named.into_iter().rev());
+    }
+
+    Ok(())
+}
+
+/// Names `commit` `name` unless it already has a name at least as good.
+fn update_name(names: &mut HashMap<[u8; 20], RevName>, commit: [u8; 20], name: RevName) -> bool {
+    let better = names.get(&commit).is_none_or(|current| {
+        current.is_worse_than(name.tagger_date, name.distance, name.from_tag)
+    });
+    if better {
+        names.insert(commit, name);
+    }
+    better
+}
+
+/// When the annotated tag `tag` was made.
+fn tagger_date(repository: &Repository, tag: &[u8; 20]) -> Result<i64> {
+    let (_, content) = repository.read_raw_object(&encode(tag))?;
+    Ok(String::from_utf8_lossy(&content)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix("tagger "))
+        .and_then(|tagger| Ident::parse(tagger).ok())
+        .map_or(0, |tagger| tagger.timestamp))
+}
+
+/// `line` with the name of every commit whose full hash appears in it.
+fn annotate_line(line: &str, names: &HashMap<[u8; 20], RevName>, name_only: bool) -> String {
+    let mut annotated = String::with_capacity(line.len());
+    let mut rest = line;
+    while !rest.is_empty() {
+        let start = rest
+            .find(|c: char| c.is_ascii_hexdigit())
+            .unwrap_or(rest.len());
+        annotated.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+        rest = after;
+
+        let name = (word.len() == 40)
+            .then(|| parse_hash(word).ok())
+            .flatten()
+            .and_then(|hash| names.get(&hash));
+        match name {
+            Some(name) if name_only => annotated.push_str(&name.name()),
+            Some(name) => annotated.push_str(&format!("{word} ({})", name.name())),
+            None => annotated.push_str(word),
+        }
+    }
+    annotated
+}
@@ -0,0 +1,154 @@
+use anyhow::{Result, anyhow};
+use clap::Subcommand;
+use hex::encode;
+use std::collections::BTreeMap;
+
+use crate::{Repository, messages::tr, refs::parse_hash, stripspace::stripspace_str};
+
+const NOTES_REF: &str = "refs/notes/commits";
+
+#[derive(Subcommand, Debug)]
+pub enum NotesCommands {
+    /// Attach a note to an object, HEAD by default
+    Add {
+        /// The note; several are joined as separate paragraphs
+        #[arg(short, long, required = true)]
+        message: Vec<String>,
+        /// Replace a note the object already has
+        #[arg(short, long)]
+        force: bool,
+        object: Option<String>,
+    },
+    /// Print the note attached to an object
+    Show { object: Option<String> },
+    /// Delete the notes attached to objects
+    Remove { objects: Vec<String> },
+}
+
+/// The notes in `refs/notes/commits`: a commit whose tree holds one blob per
+/// annotated object, named by the object's hash. Trees made by git may split
+/// the names into fanout directories such as `ab/cdef...`.
+pub struct Notes<'a> {
+    repository: &'a Repository,
+    commit: Option<[u8; 20]>,
+    blobs: BTreeMap<String, [u8; 20]>,
+}
+
+pub fn handle_notes_command(command: NotesCommands, repository: &Repository) -> Result<()> {
+    let mut notes = Notes::load(repository)?;
+
+    match command {
+        NotesCommands::Add {
+            message,
+            force,
+            object,
+        } => {
+            let object = repository.resolve_commitish(object.as_deref().unwrap_or("HEAD"))?;
+            let existing = notes.blobs.contains_key(&encode(object));
+            if existing && !force {
+                return Err(anyhow!(
+                    "error: Cannot add notes. Found existing notes for object {}. Use '-f' to overwrite existing notes",
+                    encode(object)
+                ));
+            }
+            if existing {
+                eprintln!(
+                    "{}",
+                    tr!("Overwriting existing notes for object {}", encode(object))
+                );
+            }
+
+            let note = stripspace_str(&message.join("\n\n"), false);
+            if note.is_empty() {
+                return Err(anyhow!("error: refusing to add an empty note"));
+            }
+            let blob = repository.write_raw_object("blob", note.as_bytes())?;
+            notes.blobs.insert(encode(object), blob);
+            notes.save("Notes added by 'mini-git notes add'")
+        }
+        NotesCommands::Show { object } => {
+            let object = repository.resolve_commitish(object.as_deref().unwrap_or("HEAD"))?;
+            match notes.get(&object)? {
+                Some(note) => {
+                    print!("{note}");
+                    Ok(())
+                }
+                None => Err(anyhow!(
+                    "error: no note found for object {}.",
+                    encode(object)
+                )),
+            }
+        }
+        NotesCommands::Remove { objects } => {
+            let objects = if objects.is_empty() {
+                vec!["HEAD".to_string()]
+            } else {
+                objects
+            };
+
+            for name in &objects {
+                let object = repository.resolve_commitish(name)?;
+                eprintln!("{}", tr!("Removing note for object {}", name));
+                if notes.blobs.remove(&encode(object)).is_none() {
+                    return Err(anyhow!("error: Object {} has no note", encode(object)));
+                }
+            }
+            notes.save("Notes removed by 'mini-git notes remove'")
+        }
+    }
+}
+
+impl<'a> Notes<'a> {
+    pub fn load(repository: &'a Repository) -> Result<Self> {
+        let commit = repository.read_ref(NOTES_REF)?;
+        let mut blobs = BTreeMap::new();
+
+        if let Some(commit) = commit {
+            let tree = parse_hash(&repository.read_commit(&commit)?.tree_hash()?)?;
+            let mut pending = vec![(String::new(), tree)];
+            while let Some((prefix, tree)) = pending.pop() {
+                for entry in repository.read_tree(&tree)? {
+                    let name = format!("{prefix}{}", entry.path.display());
+                    if entry.mode == 40000 {
+                        pending.push((name, entry.sha1));
+                    } else if name.len() == 40 {
+                        blobs.insert(name, entry.sha1);
+                    }
+                }
+            }
+        }
+
+        Ok(Notes {
+            repository,
+            commit,
+            blobs,
+        })
+    }
+
+    /// The note attached to `object`, if it has one.
+    pub fn get(&self, object: &[u8; 20]) -> Result<Option<String>> {
+        let Some(blob) = self.blobs.get(&encode(object)) else {
+            return Ok(None);
+        };
+        let (_, content) = self.repository.read_raw_object(&encode(blob))?;
+        Ok(Some(String::from_utf8_lossy(&content).into_owned()))
+    }
+
+    /// Records the notes as a new commit on top of the current notes ref.
+    /// The tree is written flat, which git reads as well as its own fanout.
+    fn save(&self, message: &str) -> Result<()> {
+        let mut tree = Vec::new();
+        for (name, blob) in &self.blobs {
+            tree.extend_from_slice(format!("100644 {name}\0").as_bytes());
+            tree.extend_from_slice(blob);
+        }
+        let tree = self.repository.write_raw_object("tree", &tree)?;
+
+        let (commit, _) = self.repository.commit_tree(
+            format!("{message}\n"),
+            encode(tree),
+            self.commit.into_iter().collect(),
+        )?;
+        self.repository.write_ref(NOTES_REF, &commit)
+    }
+}
@@ -0,0 +1,781 @@
+use anyhow::{Context, Result, anyhow};
+use flate2::{
+    Compression, Decompress, FlushDecompress, Status, read::ZlibDecoder, write::ZlibEncoder,
+};
+use hex::encode;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    Repository, compress_content,
+    config::Config,
+    delta::{apply_delta, create_delta},
+    hash_content, hash_untrusted,
+    messages::tr,
+    pack_index::{PackIndexEntry, write_pack_index},
+    pool::{self, default_threads},
+    profile::{self, Phase},
+    refs::parse_hash,
+    stream_object, temp_object_name,
+};
+
+pub const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+pub const PACK_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackObjectType {
+    Commit = 1,
+    Tree = 2,
+    Blob = 3,
+    Tag = 4,
+    OfsDelta = 6,
+    RefDelta = 7,
+}
+
+impl PackObjectType {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "commit" => Ok(PackObjectType::Commit),
+            "tree" => Ok(PackObjectType::Tree),
+            "blob" => Ok(PackObjectType::Blob),
+            "tag" => Ok(PackObjectType::Tag),
+            _ => Err(anyhow!("Object type \"{}\" cannot be packed", name)),
+        }
+    }
+
+    pub fn from_code(code: u8) -> Result<Self> {
+        match code {
+            1 => Ok(PackObjectType::Commit),
+            2 => Ok(PackObjectType::Tree),
+            3 => Ok(PackObjectType::Blob),
+            4 => Ok(PackObjectType::Tag),
+            6 => Ok(PackObjectType::OfsDelta),
+            7 => Ok(PackObjectType::RefDelta),
+            _ => Err(anyhow!("Malformed pack: invalid object type {}", code)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PackObjectType::Commit => "commit",
+            PackObjectType::Tree => "tree",
+            PackObjectType::Blob => "blob",
+            PackObjectType::Tag => "tag",
+            PackObjectType::OfsDelta => "ofs-delta",
+            PackObjectType::RefDelta => "ref-delta",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaBase {
+    Offset(usize),
+    Hash([u8; 20]),
+}
+
+/// A single entry as stored in a pack: either a whole object or a delta
+/// against some base, with its data already inflated.
+pub struct PackEntry {
+    pub offset: usize,
+    pub end: usize,
+    pub object_type: PackObjectType,
+    pub base: Option<DeltaBase>,
+    pub data: Vec<u8>,
+}
+
+pub struct ResolvedObject {
+    pub object_type: PackObjectType,
+    pub hash: [u8; 20],
+    pub content: Vec<u8>,
+    pub depth: usize,
+}
+
+/// Forwards writes to `inner` while hashing everything written, so the pack
+/// trailer can be produced without buffering the whole pack.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha1,
+    written: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Forwards writes to `inner` while computing their CRC-32, which the pack
+/// index records for each entry.
+struct CrcWriter<W: Write> {
+    inner: W,
+    crc: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn encode_entry_header(object_type: PackObjectType, size: usize) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut byte = ((object_type as u8) << 4) | (size & 0x0f) as u8;
+    let mut remaining = size >> 4;
+
+    while remaining > 0 {
+        header.push(byte | 0x80);
+        byte = (remaining & 0x7f) as u8;
+        remaining >>= 7;
+    }
+    header.push(byte);
+
+    header
+}
+
+pub fn encode_ofs_delta_offset(mut offset: usize) -> Vec<u8> {
+    let mut bytes = vec![(offset & 0x7f) as u8];
+    offset >>= 7;
+
+    while offset > 0 {
+        offset -= 1;
+        bytes.push(0x80 | (offset & 0x7f) as u8);
+        offset >>= 7;
+    }
+    bytes.reverse();
+
+    bytes
+}
+
+pub fn parse_entry_header(data: &[u8], mut pos: usize) -> Result<(PackObjectType, usize, usize)> {
+    let truncated = || anyhow!("Malformed pack: truncated entry header");
+
+    let mut byte = *data.get(pos).ok_or_else(truncated)?;
+    pos += 1;
+
+    let object_type = PackObjectType::from_code((byte >> 4) & 0x07)?;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+
+    while byte & 0x80 != 0 {
+        byte = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    Ok((object_type, size, pos))
+}
+
+pub fn parse_ofs_delta_offset(data: &[u8], mut pos: usize) -> Result<(usize, usize)> {
+    let truncated = || anyhow!("Malformed pack: truncated delta offset");
+
+    let mut byte = *data.get(pos).ok_or_else(truncated)?;
+    pos += 1;
+    let mut offset = (byte & 0x7f) as usize;
+
+    while byte & 0x80 != 0 {
+        byte = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        offset = ((offset + 1) << 7) | (byte & 0x7f) as usize;
+    }
+
+    Ok((offset, pos))
+}
+
+/// Inflates the zlib stream starting at `pos`, returning the data and the
+/// position just past the end of the stream.
+pub fn inflate_at(data: &[u8], pos: usize, expected_size: usize) -> Result<(Vec<u8>, usize)> {
+    let _span = profile::span(Phase::Compression);
+    let mut decompress = Decompress::new(true);
+    let mut output = Vec::with_capacity(expected_size.max(1));
+
+    loop {
+        let consumed = decompress.total_in() as usize;
+        let produced = decompress.total_out();
+        let status = decompress
+            .decompress_vec(&data[pos + consumed..], &mut output, FlushDecompress::None)
+            .context("Malformed pack: corrupt zlib stream")?;
+
+        match status {
+            Status::StreamEnd => break,
+            _ if output.len() == output.capacity() => output.reserve(4096),
+            _ if decompress.total_in() as usize == consumed
+                && decompress.total_out() == produced =>
+            {
+                return Err(anyhow!("Malformed pack: truncated zlib stream"));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((output, pos + decompress.total_in() as usize))
+}
+
+/// Splits a packfile into its entries, verifying the header and trailing
+/// checksum.
+pub fn parse_pack(data: &[u8]) -> Result<Vec<PackEntry>> {
+    if data.len() < 32 || &data[0..4] != PACK_SIGNATURE {
+        return Err(anyhow!("fatal: not a valid packfile"));
+    }
+
+    let version = u32::from_be_bytes(data[4..8].try_into()?);
+    if version != 2 && version != 3 {
+        return Err(anyhow!("fatal: unsupported pack version {}", version));
+    }
+
+    let (body, trailer) = data.split_at(data.len() - 20);
+    if hash_content(body) != trailer {
+        return Err(anyhow!("fatal: pack trailer checksum mismatch"));
+    }
+
+    let count = u32::from_be_bytes(data[8..12].try_into()?) as usize;
+    let mut entries = Vec::with_capacity(count);
+    let mut pos = 12;
+
+    for _ in 0..count {
+        let offset = pos;
+        let (object_type, size, data_start) = parse_entry_header(body, pos)?;
+
+        let (base, data_start) = match object_type {
+            PackObjectType::OfsDelta => {
+                let (relative, next) = parse_ofs_delta_offset(body, data_start)?;
+                let base_offset = offset
+                    .checked_sub(relative)
+                    .ok_or_else(|| anyhow!("Malformed pack: delta base before start of pack"))?;
+                (Some(DeltaBase::Offset(base_offset)), next)
+            }
+            PackObjectType::RefDelta => {
+                let hash = body
+                    .get(data_start..data_start + 20)
+                    .ok_or_else(|| anyhow!("Malformed pack: truncated delta base"))?;
+                (Some(DeltaBase::Hash(hash.try_into()?)), data_start + 20)
+            }
+            _ => (None, data_start),
+        };
+
+        let (inflated, end) = inflate_at(body, data_start, size)?;
+        if inflated.len() != size {
+            return Err(anyhow!(
+                "Malformed pack: entry at offset {} inflated to {} bytes, expected {}",
+                offset,
+                inflated.len(),
+                size
+            ));
+        }
+
+        entries.push(PackEntry {
+            offset,
+            end,
+            object_type,
+            base,
+            data: inflated,
+        });
+        pos = end;
+    }
+
+    if pos != body.len() {
+        return Err(anyhow!("Malformed pack: trailing garbage after last entry"));
+    }
+
+    Ok(entries)
+}
+
+/// Reconstructs every object in `entries`, applying deltas against bases
+/// found earlier in the pack or, for bases outside it, via `lookup_external`.
+pub fn resolve_entries(
+    entries: &[PackEntry],
+    lookup_external: impl Fn(&[u8; 20]) -> Result<Option<(PackObjectType, Vec<u8>)>>,
+) -> Result<Vec<ResolvedObject>> {
+    let by_offset: HashMap<usize, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.offset, i))
+        .collect();
+
+    let mut resolved: Vec<Option<ResolvedObject>> = entries.iter().map(|_| None).collect();
+    let mut by_hash: HashMap<[u8; 20], usize> = HashMap::new();
+    let mut remaining = entries.len();
+
+    while remaining > 0 {
+        let mut progressed = false;
+
+        for (i, entry) in entries.iter().enumerate() {
+            if resolved[i].is_some() {
+                continue;
+            }
+
+            let (object_type, content, depth) = match entry.base {
+                None => (entry.object_type, entry.data.clone(), 0),
+                Some(DeltaBase::Offset(base_offset)) => {
+                    let base_index = *by_offset.get(&base_offset).ok_or_else(|| {
+                        anyhow!(
+                            "Malformed pack: no entry at delta base offset {}",
+                            base_offset
+                        )
+                    })?;
+                    let Some(base) = &resolved[base_index] else {
+                        continue;
+                    };
+                    (
+                        base.object_type,
+                        apply_delta(&base.content, &entry.data)?,
+                        base.depth + 1,
+                    )
+                }
+                Some(DeltaBase::Hash(base_hash)) => match by_hash.get(&base_hash) {
+                    Some(&base_index) => {
+                        let base = resolved[base_index].as_ref().unwrap();
+                        (
+                            base.object_type,
+                            apply_delta(&base.content, &entry.data)?,
+                            base.depth + 1,
+                        )
+                    }
+                    None => match lookup_external(&base_hash)? {
+                        Some((object_type, base_content)) => {
+                            (object_type, apply_delta(&base_content, &entry.data)?, 1)
+                        }
+                        None => continue,
+                    },
+                },
+            };
+
+            let header = format!("{} {}\0", object_type.name(), content.len());
+            let mut full_content = header.into_bytes();
+            full_content.extend_from_slice(&content);
+            let hash = hash_untrusted(&full_content)?;
+
+            by_hash.insert(hash, i);
+            resolved[i] = Some(ResolvedObject {
+                object_type,
+                hash,
+                content,
+                depth,
+            });
+            remaining -= 1;
+            progressed = true;
+        }
+
+        if !progressed {
+            return Err(anyhow!("fatal: pack has {} unresolved deltas", remaining));
+        }
+    }
+
+    Ok(resolved.into_iter().flatten().collect())
+}
+
+/// Where `core.bigFileThreshold` puts the line between ordinary and big
+/// files when it is not set.
+pub const DEFAULT_BIG_FILE_THRESHOLD: u64 = 512 << 20;
+
+/// Limits for the delta search: how many preceding objects are tried as a
+/// base, and how long a chain of deltas may grow. Objects larger than
+/// `big_file_threshold` are stored whole, since searching them for deltas
+/// costs more than it saves. The search and the compression of the
+/// results are spread over `threads` threads.
+pub struct PackOptions {
+    pub window: usize,
+    pub depth: usize,
+    pub threads: usize,
+    pub big_file_threshold: u64,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        PackOptions {
+            window: 10,
+            depth: 50,
+            threads: default_threads(0),
+            big_file_threshold: DEFAULT_BIG_FILE_THRESHOLD,
+        }
+    }
+}
+
+impl PackOptions {
+    /// The defaults, with the thread count taken from `pack.threads` where
+    /// it is set, where zero means one thread per core, and the big file
+    /// threshold from `core.bigFileThreshold`.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut options = PackOptions::default();
+
+        if let Some(threads) = config.get("pack.threads") {
+            let threads = threads.parse().map_err(|_| {
+                anyhow!(
+                    "fatal: bad numeric config value '{}' for 'pack.threads'",
+                    threads
+                )
+            })?;
+            options.threads = default_threads(threads);
+        }
+        if let Some(threshold) = config.get_size("core.bigFileThreshold")? {
+            options.big_file_threshold = threshold;
+        }
+
+        Ok(options)
+    }
+}
+
+struct PackCandidate {
+    hash: [u8; 20],
+    object_type: PackObjectType,
+    name: String,
+    content: Vec<u8>,
+}
+
+/// Picks a delta base for each object from the `window` objects sorted
+/// just before it. Objects are grouped by type and path so that versions of
+/// the same file sit next to each other, largest first, which lets newer
+/// (usually larger) versions be stored whole and older ones as deltas.
+///
+/// Each thread searches its own run of objects and only looks for bases
+/// within it, since it cannot know the delta depth of objects another
+/// thread has yet to reach.
+fn find_deltas(
+    candidates: &mut [PackCandidate],
+    options: &PackOptions,
+) -> Vec<Option<(usize, Vec<u8>)>> {
+    candidates.sort_by(|a, b| {
+        (a.object_type as u8)
+            .cmp(&(b.object_type as u8))
+            .then_with(|| file_name(&a.name).cmp(file_name(&b.name)))
+            .then_with(|| b.content.len().cmp(&a.content.len()))
+    });
+    let candidates = &*candidates;
+
+    pool::run(options.threads, candidates.len(), |queue, worker| {
+        let mut deltas = Vec::new();
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        let mut run_start = 0;
+        let mut previous = None;
+
+        while let Some(i) = queue.next(worker) {
+            if previous.is_none_or(|previous| previous + 1 != i) {
+                run_start = i;
+                depths.clear();
+            }
+            previous = Some(i);
+
+            let mut best: Option<(usize, Vec<u8>)> = None;
+            let big = |candidate: &PackCandidate| {
+                candidate.content.len() as u64 > options.big_file_threshold
+            };
+            for j in i.saturating_sub(options.window).max(run_start)..i {
+                let (base, target) = (&candidates[j], &candidates[i]);
+                if base.object_type != target.object_type
+                    || depths[&j] >= options.depth
+                    || big(base)
+                    || big(target)
+                {
+                    continue;
+                }
+
+                let delta = create_delta(&base.content, &target.content);
+                let limit = best
+                    .as_ref()
+                    .map_or(target.content.len(), |(_, best)| best.len());
+                if delta.len() + 20 < limit {
+                    best = Some((j, delta));
+                }
+            }
+
+            let depth = best.as_ref().map_or(0, |(base, _)| depths[base] + 1);
+            depths.insert(i, depth);
+            deltas.push((i, best));
+        }
+
+        deltas
+    })
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// Writes a version 2 packfile containing `objects` to `out`, storing
+/// objects as offset deltas against similar ones where that is smaller.
+/// Each object may carry the path it was reached through, which is used to
+/// pair up versions of the same file. Returns the trailing SHA-1 checksum of
+/// the pack and the index entry of each object.
+pub fn write_pack(
+    repository: &Repository,
+    objects: &[([u8; 20], Option<String>)],
+    options: &PackOptions,
+    out: &mut impl Write,
+) -> Result<([u8; 20], Vec<PackIndexEntry>)> {
+    let mut candidates = Vec::with_capacity(objects.len());
+    for (hash, name) in objects {
+        let (object_type, content) = repository.read_raw_object(&encode(hash))?;
+        candidates.push(PackCandidate {
+            hash: *hash,
+            object_type: PackObjectType::from_name(&object_type)?,
+            name: name.clone().unwrap_or_default(),
+            content,
+        });
+    }
+    let deltas = find_deltas(&mut candidates, options);
+
+    let compressed = pool::run(options.threads, candidates.len(), |queue, worker| {
+        let mut compressed = Vec::new();
+        while let Some(i) = queue.next(worker) {
+            let data = match &deltas[i] {
+                Some((_, delta)) => delta,
+                None => &candidates[i].content,
+            };
+            compressed.push((i, compress_content(data)));
+        }
+        compressed
+    });
+
+    let mut writer = HashingWriter {
+        inner: out,
+        hasher: Sha1::new(),
+        written: 0,
+    };
+    let mut index_entries: Vec<PackIndexEntry> = Vec::with_capacity(candidates.len());
+
+    writer.write_all(PACK_SIGNATURE)?;
+    writer.write_all(&PACK_VERSION.to_be_bytes())?;
+    writer.write_all(&(candidates.len() as u32).to_be_bytes())?;
+
+    for ((candidate, delta), data) in candidates.iter().zip(deltas).zip(compressed) {
+        let offset = writer.written;
+
+        let mut entry = match delta {
+            Some((base, delta)) => {
+                let base_offset = index_entries[base].offset;
+                let mut entry = encode_entry_header(PackObjectType::OfsDelta, delta.len());
+                entry.extend(encode_ofs_delta_offset((offset - base_offset) as usize));
+                entry
+            }
+            None => encode_entry_header(candidate.object_type, candidate.content.len()),
+        };
+        entry.extend_from_slice(&data?);
+
+        index_entries.push(PackIndexEntry {
+            hash: candidate.hash,
+            offset,
+            crc32: crc32fast::hash(&entry),
+        });
+        writer.write_all(&entry)?;
+    }
+
+    let checksum: [u8; 20] = writer.hasher.finalize().into();
+    writer.inner.write_all(&checksum)?;
+    writer.inner.flush()?;
+
+    Ok((checksum, index_entries))
+}
+
+/// Stores one object in a pack of its own under `objects/pack`, written as
+/// it is compressed rather than first as a loose object. Big files go
+/// this way. The `size` bytes of `content` are read a chunk at a time, so
+/// the object is never held in memory. Returns the object's name.
+pub fn write_object_pack(
+    repository: &Repository,
+    object_type: PackObjectType,
+    size: u64,
+    content: impl Read,
+) -> Result<[u8; 20]> {
+    let pack_dir = repository.objects_dir.join("pack");
+    fs::create_dir_all(&pack_dir).context("Failed to create objects/pack directory")?;
+    let temp_path = pack_dir.join(temp_object_name("tmp_pack"));
+    let file = fs::File::create(&temp_path)
+        .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+
+    let written = write_single_object_pack(file, object_type, size, content);
+    let (hash, checksum, crc32) = match written {
+        Ok(written) => written,
+        Err(error) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error);
+        }
+    };
+    // The name is only known once the content has been read through.
+    if repository.has_object(&hash)? {
+        let verified = stored_entry(&temp_path, object_type, size)
+            .and_then(|entry| repository.verify_existing_object(&hash, entry));
+        fs::remove_file(&temp_path)
+            .with_context(|| format!("Failed to remove {}", temp_path.display()))?;
+        return verified.map(|()| hash);
+    }
+
+    let pack_path = pack_dir.join(format!("pack-{}.pack", encode(checksum)));
+    let idx_path = pack_path.with_extension("idx");
+    let index_entry = PackIndexEntry {
+        hash,
+        offset: 12,
+        crc32,
+    };
+    fs::rename(&temp_path, &pack_path)
+        .with_context(|| format!("Failed to write pack file {}", pack_path.display()))?;
+    fs::write(&idx_path, write_pack_index(&[index_entry], &checksum))
+        .with_context(|| format!("Failed to write index file {}", idx_path.display()))?;
+
+    Ok(hash)
+}
+
+/// The object in a pack written by `write_single_object_pack`, read back
+/// with its loose-object header, for comparing without inflating it all at
+/// once.
+fn stored_entry(path: &Path, object_type: PackObjectType, size: u64) -> Result<impl Read> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let header_len = encode_entry_header(object_type, size as usize).len();
+    file.seek(SeekFrom::Start(12 + header_len as u64))?;
+    let header = format!("{} {size}\0", object_type.name()).into_bytes();
+    Ok(io::Cursor::new(header).chain(ZlibDecoder::new(file)))
+}
+
+/// Writes a pack holding the one object to `file`. Returns the object's
+/// name, the pack checksum and the CRC-32 of the entry.
+fn write_single_object_pack(
+    file: fs::File,
+    object_type: PackObjectType,
+    size: u64,
+    content: impl Read,
+) -> Result<([u8; 20], [u8; 20], u32)> {
+    let mut writer = HashingWriter {
+        inner: io::BufWriter::new(file),
+        hasher: Sha1::new(),
+        written: 0,
+    };
+    writer.write_all(PACK_SIGNATURE)?;
+    writer.write_all(&PACK_VERSION.to_be_bytes())?;
+    writer.write_all(&1u32.to_be_bytes())?;
+
+    let mut entry = CrcWriter {
+        inner: &mut writer,
+        crc: crc32fast::Hasher::new(),
+    };
+    entry.write_all(&encode_entry_header(object_type, size as usize))?;
+    let mut encoder = ZlibEncoder::new(entry, Compression::default());
+    let hash = stream_object(object_type.name(), size, content, |chunk| {
+        encoder.write_all(chunk)
+    })?;
+    let crc32 = encoder.finish()?.crc.finalize();
+
+    let HashingWriter {
+        mut inner, hasher, ..
+    } = writer;
+    let checksum: [u8; 20] = hasher.finalize().into();
+    inner.write_all(&checksum)?;
+    inner.flush()?;
+
+    Ok((hash, checksum, crc32))
+}
+
+pub fn handle_pack_objects_command(
+    stdout: bool,
+    base_name: Option<String>,
+    window: usize,
+    depth: usize,
+    threads: Option<usize>,
+    repository: &Repository,
+) -> Result<()> {
+    if stdout == base_name.is_some() {
+        return Err(anyhow!(
+            "usage: mini-git pack-objects [--stdout | <base-name>] < object-list"
+        ));
+    }
+
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .context("Failed to read object list from stdin")?;
+
+    let configured = PackOptions::from_config(&Config::load(repository)?)?;
+    let options = PackOptions {
+        window,
+        depth,
+        threads: threads.map_or(configured.threads, default_threads),
+        big_file_threshold: configured.big_file_threshold,
+    };
+
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    for line in input.lines() {
+        let mut fields = line.splitn(2, ' ');
+        let Some(hash) = fields.next().filter(|hash| !hash.is_empty()) else {
+            continue;
+        };
+        let hash = parse_hash(hash)?;
+        if seen.insert(hash) {
+            objects.push((hash, fields.next().map(str::to_string)));
+        }
+    }
+
+    if stdout {
+        write_pack(repository, &objects, &options, &mut io::stdout().lock())?;
+    } else if let Some(base_name) = base_name {
+        let mut pack = Vec::new();
+        let (checksum, index_entries) = write_pack(repository, &objects, &options, &mut pack)?;
+        let pack_path = format!("{base_name}-{}.pack", encode(checksum));
+        let idx_path = format!("{base_name}-{}.idx", encode(checksum));
+
+        fs::write(&pack_path, &pack)
+            .with_context(|| format!("Failed to write pack file {pack_path}"))?;
+        fs::write(&idx_path, write_pack_index(&index_entries, &checksum))
+            .with_context(|| format!("Failed to write index file {idx_path}"))?;
+        println!("{}", encode(checksum));
+    }
+
+    eprintln!("{}", tr!("Total {}", objects.len()));
+    Ok(())
+}
+
+pub fn handle_unpack_objects_command(dry_run: bool, repository: &Repository) -> Result<()> {
+    let mut data = Vec::new();
+    io::stdin()
+        .read_to_end(&mut data)
+        .context("Failed to read pack from stdin")?;
+
+    let (unpacked, total) = unpack_pack(repository, &data, dry_run)?;
+
+    eprintln!(
+        "{}",
+        tr!("Unpacking objects: 100% ({}/{}), done.", unpacked, total)
+    );
+    Ok(())
+}
+
+/// Stores every object of the pack in `data` as a loose object, skipping
+/// ones the repository already has. Returns the number of objects resolved
+/// and the number of entries in the pack.
+pub fn unpack_pack(repository: &Repository, data: &[u8], dry_run: bool) -> Result<(usize, usize)> {
+    let entries = parse_pack(data)?;
+    let objects = resolve_entries(&entries, |hash| {
+        if !repository.has_object(hash)? {
+            return Ok(None);
+        }
+        let (object_type, content) = repository.read_raw_object(&encode(hash))?;
+        Ok(Some((PackObjectType::from_name(&object_type)?, content)))
+    })?;
+
+    if !dry_run {
+        for object in &objects {
+            if !repository.has_object(&object.hash)? {
+                repository.write_raw_object(object.object_type.name(), &object.content)?;
+            }
+        }
+    }
+
+    Ok((objects.len(), entries.len()))
+}
@@ -0,0 +1,419 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Repository, hash_content,
+    messages::tr_n,
+    pack::{DeltaBase, parse_pack, resolve_entries},
+    pack_reader::map_file,
+};
+
+const IDX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+const IDX_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackIndexEntry {
+    pub hash: [u8; 20],
+    pub offset: u64,
+    pub crc32: u32,
+}
+
+/// A parsed version 2 `.idx` file, searchable by object id.
+pub struct PackIndex {
+    pub entries: Vec<PackIndexEntry>,
+    pub pack_checksum: [u8; 20],
+}
+
+impl PackIndex {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 8 + 256 * 4 + 40 || &data[0..4] != IDX_SIGNATURE {
+            return Err(anyhow!("fatal: not a version 2 pack index"));
+        }
+        let version = u32::from_be_bytes(data[4..8].try_into()?);
+        if version != IDX_VERSION {
+            return Err(anyhow!("fatal: unsupported pack index version {}", version));
+        }
+
+        let word = |pos: usize| -> Result<u32> {
+            let bytes = data
+                .get(pos..pos + 4)
+                .ok_or_else(|| anyhow!("Malformed pack index: truncated"))?;
+            Ok(u32::from_be_bytes(bytes.try_into()?))
+        };
+
+        let count = word(8 + 255 * 4)? as usize;
+        let names_start = 8 + 256 * 4;
+        let crcs_start = names_start + count * 20;
+        let offsets_start = crcs_start + count * 4;
+        let large_offsets_start = offsets_start + count * 4;
+
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let hash: [u8; 20] = data
+                .get(names_start + i * 20..names_start + (i + 1) * 20)
+                .ok_or_else(|| anyhow!("Malformed pack index: truncated"))?
+                .try_into()?;
+            let crc32 = word(crcs_start + i * 4)?;
+            let small_offset = word(offsets_start + i * 4)?;
+
+            let offset = if small_offset & 0x8000_0000 == 0 {
+                small_offset as u64
+            } else {
+                let slot = large_offsets_start + (small_offset & 0x7fff_ffff) as usize * 8;
+                let bytes = data
+                    .get(slot..slot + 8)
+                    .ok_or_else(|| anyhow!("Malformed pack index: truncated"))?;
+                u64::from_be_bytes(bytes.try_into()?)
+            };
+
+            entries.push(PackIndexEntry {
+                hash,
+                offset,
+                crc32,
+            });
+        }
+
+        let trailer = data.len() - 40;
+        let pack_checksum: [u8; 20] = data[trailer..trailer + 20].try_into()?;
+
+        Ok(PackIndex {
+            entries,
+            pack_checksum,
+        })
+    }
+
+    pub fn lookup(&self, hash: &[u8; 20]) -> Option<u64> {
+        self.entries
+            .binary_search_by(|entry| entry.hash.cmp(hash))
+            .ok()
+            .map(|i| self.entries[i].offset)
+    }
+
+    /// The ids in the index that start with the lowercase hex `prefix`.
+    pub fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = [u8; 20]> + 'a {
+        let start = self
+            .entries
+            .partition_point(|entry| encode(entry.hash).as_str() < prefix);
+        self.entries[start..]
+            .iter()
+            .map(|entry| entry.hash)
+            .take_while(move |hash| encode(hash).starts_with(prefix))
+    }
+}
+
+/// Serializes a version 2 `.idx` file: fanout table, sorted object names,
+/// CRC32s, 31-bit offsets with a 64-bit overflow table, and both checksums.
+pub fn write_pack_index(entries: &[PackIndexEntry], pack_checksum: &[u8; 20]) -> Vec<u8> {
+    let mut sorted = entries.to_vec();
+    sorted.sort_by_key(|entry| entry.hash);
+
+    let mut idx = Vec::new();
+    idx.extend_from_slice(IDX_SIGNATURE);
+    idx.extend_from_slice(&IDX_VERSION.to_be_bytes());
+
+    let mut fanout = [0u32; 256];
+    for entry in &sorted {
+        fanout[entry.hash[0] as usize] += 1;
+    }
+    let mut running = 0;
+    for count in fanout {
+        running += count;
+        idx.extend_from_slice(&running.to_be_bytes());
+    }
+
+    for entry in &sorted {
+        idx.extend_from_slice(&entry.hash);
+    }
+    for entry in &sorted {
+        idx.extend_from_slice(&entry.crc32.to_be_bytes());
+    }
+
+    let mut large_offsets = Vec::new();
+    for entry in &sorted {
+        if entry.offset < 0x8000_0000 {
+            idx.extend_from_slice(&(entry.offset as u32).to_be_bytes());
+        } else {
+            let slot = 0x8000_0000 | large_offsets.len() as u32;
+            idx.extend_from_slice(&slot.to_be_bytes());
+            large_offsets.push(entry.offset);
+        }
+    }
+    for offset in large_offsets {
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    idx.extend_from_slice(pack_checksum);
+    let idx_checksum = hash_content(&idx);
+    idx.extend_from_slice(&idx_checksum);
+
+    idx
+}
+
+/// Computes index entries for every object in a packfile, returning them
+/// along with the pack's trailing checksum.
+pub fn index_pack(data: &[u8]) -> Result<(Vec<PackIndexEntry>, [u8; 20])> {
+    let entries = parse_pack(data)?;
+    let objects = resolve_entries(&entries, |_| Ok(None))?;
+
+    let index_entries = entries
+        .iter()
+        .zip(&objects)
+        .map(|(entry, object)| PackIndexEntry {
+            hash: object.hash,
+            offset: entry.offset as u64,
+            crc32: crc32fast::hash(&data[entry.offset..entry.end]),
+        })
+        .collect();
+
+    let pack_checksum: [u8; 20] = data[data.len() - 20..].try_into()?;
+    Ok((index_entries, pack_checksum))
+}
+
+pub fn handle_index_pack_command(
+    pack_file: Option<PathBuf>,
+    output: Option<PathBuf>,
+    stdin: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let data = match (&pack_file, stdin) {
+        (Some(path), false) => fs::read(path)
+            .with_context(|| format!("Failed to read pack file {}", path.display()))?,
+        (None, true) => {
+            let mut data = Vec::new();
+            io::stdin()
+                .read_to_end(&mut data)
+                .context("Failed to read pack from stdin")?;
+            data
+        }
+        _ => {
+            return Err(anyhow!(
+                "usage: mini-git index-pack [-o <index-file>] (<pack-file> | --stdin)"
+            ));
+        }
+    };
+
+    let (entries, pack_checksum) = index_pack(&data)?;
+    let idx = write_pack_index(&entries, &pack_checksum);
+
+    let idx_path = match pack_file {
+        Some(pack_file) => output.unwrap_or_else(|| pack_file.with_extension("idx")),
+        None => {
+            let pack_dir = repository.objects_dir.join("pack");
+            fs::create_dir_all(&pack_dir).context("Failed to create objects/pack directory")?;
+
+            let pack_path = pack_dir.join(format!("pack-{}.pack", encode(pack_checksum)));
+            fs::write(&pack_path, &data)
+                .with_context(|| format!("Failed to write pack file {}", pack_path.display()))?;
+            output.unwrap_or_else(|| pack_path.with_extension("idx"))
+        }
+    };
+
+    fs::write(&idx_path, &idx)
+        .with_context(|| format!("Failed to write index file {}", idx_path.display()))?;
+    println!("{}", encode(pack_checksum));
+
+    Ok(())
+}
+
+/// Dumps a pack index read from stdin, one object per line in name order:
+/// its offset in the pack, its id and, from a version 2 index, the CRC32
+/// of its packed data.
+pub fn handle_show_index_command() -> Result<()> {
+    let mut data = Vec::new();
+    io::stdin()
+        .read_to_end(&mut data)
+        .context("Failed to read pack index from stdin")?;
+
+    if data.starts_with(IDX_SIGNATURE) {
+        for entry in PackIndex::parse(&data)?.entries {
+            println!(
+                "{} {} ({:08x})",
+                entry.offset,
+                encode(entry.hash),
+                entry.crc32
+            );
+        }
+        return Ok(());
+    }
+
+    // A version 1 index has no header: the fanout table is followed by
+    // offset and name pairs.
+    let count = data
+        .get(255 * 4..256 * 4)
+        .ok_or_else(|| anyhow!("fatal: unable to read header"))?;
+    let count = u32::from_be_bytes(count.try_into()?) as usize;
+    for i in 0..count {
+        let start = 256 * 4 + i * 24;
+        let entry = data
+            .get(start..start + 24)
+            .ok_or_else(|| anyhow!("fatal: unable to read entry {}/{}", i, count))?;
+        println!(
+            "{} {}",
+            u32::from_be_bytes(entry[..4].try_into()?),
+            encode(&entry[4..])
+        );
+    }
+
+    Ok(())
+}
+
+pub fn handle_verify_pack_command(
+    idx_files: Vec<PathBuf>,
+    verbose: bool,
+    object_offsets: bool,
+) -> Result<()> {
+    for idx_file in idx_files {
+        let idx_path = if idx_file.extension().is_some_and(|ext| ext == "pack") {
+            idx_file.with_extension("idx")
+        } else {
+            idx_file
+        };
+        let pack_path = idx_path.with_extension("pack");
+
+        verify_pack(&idx_path, &pack_path, verbose, object_offsets)
+            .with_context(|| format!("{}: bad", pack_path.display()))?;
+
+        if verbose {
+            println!("{}: ok", pack_path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a pack and its index describe the same objects, printing
+/// one line per object in pack order followed by a delta chain summary
+/// when `verbose` is set, or each object's id and offset with
+/// `object_offsets`.
+pub fn verify_pack(
+    idx_path: &Path,
+    pack_path: &Path,
+    verbose: bool,
+    object_offsets: bool,
+) -> Result<()> {
+    let idx_data = map_file(idx_path)
+        .with_context(|| format!("Failed to read pack index {}", idx_path.display()))?;
+    let data = map_file(pack_path)
+        .with_context(|| format!("Failed to read pack file {}", pack_path.display()))?;
+
+    let index = PackIndex::parse(&idx_data)?;
+    let trailer = idx_data.len() - 20;
+    if hash_content(&idx_data[..trailer]) != idx_data[trailer..] {
+        return Err(anyhow!("fatal: index checksum mismatch"));
+    }
+
+    let entries = parse_pack(&data)?;
+    if data[data.len() - 20..] != index.pack_checksum {
+        return Err(anyhow!("fatal: packfile checksum does not match its index"));
+    }
+    if entries.len() != index.entries.len() {
+        return Err(anyhow!(
+            "fatal: pack has {} objects but index has {}",
+            entries.len(),
+            index.entries.len()
+        ));
+    }
+
+    let objects = resolve_entries(&entries, |_| Ok(None))?;
+    let by_offset: HashMap<u64, &PackIndexEntry> = index
+        .entries
+        .iter()
+        .map(|entry| (entry.offset, entry))
+        .collect();
+    let hash_at: HashMap<usize, [u8; 20]> = entries
+        .iter()
+        .zip(&objects)
+        .map(|(entry, object)| (entry.offset, object.hash))
+        .collect();
+
+    let mut chain_lengths: BTreeMap<usize, usize> = BTreeMap::new();
+
+    for (entry, object) in entries.iter().zip(&objects) {
+        let indexed = by_offset.get(&(entry.offset as u64)).ok_or_else(|| {
+            anyhow!(
+                "fatal: object at offset {} is missing from the index",
+                entry.offset
+            )
+        })?;
+        if indexed.hash != object.hash {
+            return Err(anyhow!(
+                "fatal: index names {} at offset {} but the pack holds {}",
+                encode(indexed.hash),
+                entry.offset,
+                encode(object.hash)
+            ));
+        }
+        if indexed.crc32 != crc32fast::hash(&data[entry.offset..entry.end]) {
+            return Err(anyhow!(
+                "fatal: CRC mismatch for object {}",
+                encode(object.hash)
+            ));
+        }
+
+        *chain_lengths.entry(object.depth).or_default() += 1;
+
+        if object_offsets {
+            println!("{} {}", encode(object.hash), entry.offset);
+        }
+
+        if verbose {
+            let mut line = format!(
+                "{} {:<6} {} {} {}",
+                encode(object.hash),
+                object.object_type.name(),
+                entry.data.len(),
+                entry.end - entry.offset,
+                entry.offset
+            );
+            match entry.base {
+                Some(DeltaBase::Offset(base_offset)) => {
+                    line.push_str(&format!(
+                        " {} {}",
+                        object.depth,
+                        encode(hash_at[&base_offset])
+                    ));
+                }
+                Some(DeltaBase::Hash(base_hash)) => {
+                    line.push_str(&format!(" {} {}", object.depth, encode(base_hash)));
+                }
+                None => {}
+            }
+            println!("{line}");
+        }
+    }
+
+    if verbose {
+        for (depth, count) in chain_lengths {
+            if depth == 0 {
+                println!(
+                    "{}",
+                    tr_n!(
+                        count,
+                        "non delta: {} object",
+                        "non delta: {} objects",
+                        count
+                    )
+                );
+            } else {
+                println!(
+                    "{}",
+                    tr_n!(
+                        count,
+                        "chain length = {}: {} object",
+                        "chain length = {}: {} objects",
+                        depth,
+                        count
+                    )
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use memmap2::Mmap;
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use crate::{
+    Repository,
+    delta::apply_delta,
+    pack::{PackObjectType, inflate_at, parse_entry_header, parse_ofs_delta_offset},
+    pack_index::PackIndex,
+    profile::{self, Phase},
+};
+
+/// Maps the file at `path` into memory read-only, so that it is paged in
+/// as it is used rather than copied whole into a buffer.
+pub fn map_file(path: &Path) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: objects, packs and their indexes are never changed in place:
+    // they are written under a temporary name and renamed into place, and
+    // a file that is deleted stays mapped until the mapping is dropped.
+    unsafe { Mmap::map(&file) }
+}
+
+/// A packfile and its index, loaded so that objects can be read by id. The
+/// pack is mapped rather than read, and an object is only inflated when it
+/// is asked for.
+pub struct PackFile {
+    pub index: PackIndex,
+    pub data: Mmap,
+}
+
+impl PackFile {
+    pub fn open(idx_path: &Path) -> Result<Self> {
+        let pack_path = idx_path.with_extension("pack");
+        let (idx_data, data) = {
+            let _span = profile::span(Phase::DiskIo);
+            (
+                map_file(idx_path)
+                    .with_context(|| format!("Failed to read pack index {}", idx_path.display()))?,
+                map_file(&pack_path)
+                    .with_context(|| format!("Failed to read pack file {}", pack_path.display()))?,
+            )
+        };
+        let index = PackIndex::parse(&idx_data)?;
+
+        if data.len() < 20 || data[data.len() - 20..] != index.pack_checksum {
+            return Err(anyhow!(
+                "fatal: pack {} does not match its index",
+                pack_path.display()
+            ));
+        }
+
+        Ok(PackFile { index, data })
+    }
+
+    /// Reads the object stored at `offset`, following delta chains back to
+    /// their base. Ref-delta bases are looked up through `repository`, so
+    /// they may live in another pack or as loose objects.
+    pub fn read_at(
+        &self,
+        offset: usize,
+        repository: &Repository,
+    ) -> Result<(PackObjectType, Vec<u8>)> {
+        let (object_type, size, data_start) = parse_entry_header(&self.data, offset)?;
+
+        match object_type {
+            PackObjectType::OfsDelta => {
+                let (relative, data_start) = parse_ofs_delta_offset(&self.data, data_start)?;
+                let base_offset = offset
+                    .checked_sub(relative)
+                    .ok_or_else(|| anyhow!("Malformed pack: delta base before start of pack"))?;
+                let (base_type, base) = self.read_at(base_offset, repository)?;
+                let (delta, _) = inflate_at(&self.data, data_start, size)?;
+
+                Ok((base_type, apply_delta(&base, &delta)?))
+            }
+            PackObjectType::RefDelta => {
+                let base_hash: [u8; 20] = self
+                    .data
+                    .get(data_start..data_start + 20)
+                    .ok_or_else(|| anyhow!("Malformed pack: truncated delta base"))?
+                    .try_into()?;
+                let (base_type, base) = repository.read_raw_object(&encode(base_hash))?;
+                let (delta, _) = inflate_at(&self.data, data_start + 20, size)?;
+
+                Ok((
+                    PackObjectType::from_name(&base_type)?,
+                    apply_delta(&base, &delta)?,
+                ))
+            }
+            _ => {
+                let (content, _) = inflate_at(&self.data, data_start, size)?;
+                Ok((object_type, content))
+            }
+        }
+    }
+
+    /// The type of the object stored at `offset`, read from entry headers
+    /// alone: a delta has the type of its base.
+    pub fn type_at(&self, offset: usize, repository: &Repository) -> Result<PackObjectType> {
+        let (object_type, _, data_start) = parse_entry_header(&self.data, offset)?;
+
+        match object_type {
+            PackObjectType::OfsDelta => {
+                let (relative, _) = parse_ofs_delta_offset(&self.data, data_start)?;
+                let base_offset = offset
+                    .checked_sub(relative)
+                    .ok_or_else(|| anyhow!("Malformed pack: delta base before start of pack"))?;
+                self.type_at(base_offset, repository)
+            }
+            PackObjectType::RefDelta => {
+                let base_hash = self
+                    .data
+                    .get(data_start..data_start + 20)
+                    .ok_or_else(|| anyhow!("Malformed pack: truncated delta base"))?;
+                PackObjectType::from_name(&repository.read_object_type(&encode(base_hash))?)
+            }
+            _ => Ok(object_type),
+        }
+    }
+}
+
+pub fn load_packs(objects_dir: &Path) -> Result<Vec<PackFile>> {
+    let pack_dir = objects_dir.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut idx_paths: Vec<_> = fs::read_dir(&pack_dir)
+        .with_context(|| format!("Failed to read {}", pack_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .filter(|path| path.with_extension("pack").is_file())
+        .collect();
+    idx_paths.sort();
+
+    idx_paths.iter().map(|path| PackFile::open(path)).collect()
+}
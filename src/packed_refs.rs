@@ -0,0 +1,143 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use crate::{Repository, refs::parse_hash, upload_pack::peel_tag};
+
+/// The first line of `packed-refs`, as git writes it: annotated tags are
+/// followed by the object they peel to, and the refs are sorted.
+const HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+impl Repository {
+    fn packed_refs_path(&self) -> PathBuf {
+        self.mini_git_dir.join("packed-refs")
+    }
+
+    /// The refs kept in `packed-refs`, which a loose ref of the same name
+    /// overrides.
+    pub fn read_packed_refs(&self) -> Result<BTreeMap<String, [u8; 20]>> {
+        let path = self.packed_refs_path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(error) => {
+                return Err(error).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        let mut refs = BTreeMap::new();
+        for line in content.lines() {
+            // The header, and the peeled value of the tag on the line before.
+            if line.is_empty() || line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            let (hash, name) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("fatal: unexpected line in {}: {line}", path.display()))?;
+            refs.insert(name.to_string(), parse_hash(hash)?);
+        }
+
+        Ok(refs)
+    }
+
+    /// Rewrites `packed-refs` without `names`, leaving it alone when none of
+    /// them are packed.
+    pub fn remove_packed_refs(&self, names: &[&str]) -> Result<()> {
+        let mut refs = self.read_packed_refs()?;
+        let before = refs.len();
+        for name in names {
+            refs.remove(*name);
+        }
+        if refs.len() == before {
+            return Ok(());
+        }
+
+        self.write_packed_refs(&refs)
+    }
+
+    /// Replaces `packed-refs` with `refs`, through a lock file so that
+    /// readers see either the old file or the new one.
+    fn write_packed_refs(&self, refs: &BTreeMap<String, [u8; 20]>) -> Result<()> {
+        let path = self.packed_refs_path();
+        let lock = self.mini_git_dir.join("packed-refs.lock");
+
+        let mut content = String::from(HEADER);
+        for (name, hash) in refs {
+            content.push_str(&format!("{} {name}\n", encode(hash)));
+            if let Some(peeled) = peel_tag(self, hash)? {
+                content.push_str(&format!("^{}\n", encode(peeled)));
+            }
+        }
+
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(anyhow!(
+                    "fatal: Unable to create '{}': File exists.",
+                    lock.display()
+                ));
+            }
+            Err(error) => {
+                return Err(anyhow!(
+                    "fatal: Unable to create '{}': {error}",
+                    lock.display()
+                ));
+            }
+        };
+        let written = file
+            .write_all(content.as_bytes())
+            .and_then(|()| fs::rename(&lock, &path));
+        if let Err(error) = written {
+            let _ = fs::remove_file(&lock);
+            return Err(error).with_context(|| format!("Failed to write {}", path.display()));
+        }
+
+        Ok(())
+    }
+
+    /// Moves loose refs into `packed-refs`: tags and refs already packed,
+    /// or every ref with `all`. With `prune` the loose files are deleted,
+    /// along with the directories they leave empty.
+    pub fn pack_refs(&self, all: bool, prune: bool) -> Result<()> {
+        let mut packed = self.read_packed_refs()?;
+        let loose: Vec<(String, [u8; 20])> = self
+            .read_loose_refs()?
+            .into_iter()
+            .filter(|(name, _)| all || name.starts_with("refs/tags/") || packed.contains_key(name))
+            .collect();
+
+        packed.extend(loose.iter().cloned());
+        self.write_packed_refs(&packed)?;
+
+        if prune {
+            let refs_dir = self.mini_git_dir.join("refs");
+            for (name, _) in &loose {
+                let ref_file = self.mini_git_dir.join(name);
+                fs::remove_file(&ref_file)
+                    .with_context(|| format!("Failed to delete ref {}", ref_file.display()))?;
+
+                // `refs/heads` and the like stay, even when empty.
+                let mut dir = ref_file.parent();
+                while let Some(parent) = dir
+                    && parent.parent() != Some(refs_dir.as_path())
+                    && parent != refs_dir
+                    && fs::remove_dir(parent).is_ok()
+                {
+                    dir = parent.parent();
+                }
+            }
+        }
+
+        self.invalidate_refs();
+        Ok(())
+    }
+}
+
+pub fn handle_pack_refs_command(all: bool, no_prune: bool, repository: &Repository) -> Result<()> {
+    repository.pack_refs(all, !no_prune)
+}
@@ -0,0 +1,89 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Args;
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use crate::wildmatch::wildmatch;
+
+// Lets a command take its pathspecs from a file instead of the command
+// line, for lists too long or too awkward to pass as arguments. Not a doc
+// comment, which clap would make the help of the commands flattening it.
+#[derive(Args, Debug, Clone)]
+pub struct PathspecArgs {
+    /// Read pathspecs from this file, or from stdin for `-`, one per line
+    #[arg(long, value_name = "FILE")]
+    pub pathspec_from_file: Option<PathBuf>,
+    /// Pathspecs in the file are separated by NUL instead of newlines
+    #[arg(long, requires = "pathspec_from_file")]
+    pub pathspec_file_nul: bool,
+}
+
+impl PathspecArgs {
+    /// The pathspecs to use: those given on the command line, or those
+    /// read from `--pathspec-from-file`, which cannot be combined.
+    pub fn resolve(&self, paths: Vec<String>) -> Result<Vec<String>> {
+        let Some(file) = &self.pathspec_from_file else {
+            return Ok(paths);
+        };
+        if !paths.is_empty() {
+            return Err(anyhow!(
+                "fatal: '--pathspec-from-file' and pathspec arguments cannot be used together"
+            ));
+        }
+
+        let mut content = Vec::new();
+        if file.as_os_str() == "-" {
+            io::stdin()
+                .read_to_end(&mut content)
+                .context("Failed to read from stdin")?;
+        } else {
+            content = fs::read(file).with_context(|| {
+                format!("fatal: could not open '{}' for reading", file.display())
+            })?;
+        }
+
+        let separator = if self.pathspec_file_nul { 0 } else { b'\n' };
+        let pathspecs: Vec<String> = content
+            .split(|&b| b == separator)
+            .map(|line| {
+                let line = if self.pathspec_file_nul {
+                    line
+                } else {
+                    line.strip_suffix(b"\r").unwrap_or(line)
+                };
+                String::from_utf8_lossy(line).into_owned()
+            })
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        if pathspecs.is_empty() {
+            return Err(anyhow!(
+                "fatal: '--pathspec-from-file' requires at least one pathspec"
+            ));
+        }
+
+        Ok(pathspecs)
+    }
+}
+
+/// Whether `path`, relative to the top of the working tree, is selected by
+/// `pathspec`: the path itself, a directory above it, `.` for everything,
+/// or a glob whose `*` may also match `/`, as in git.
+pub fn matches(pathspec: &str, path: &[u8]) -> bool {
+    let pathspec = pathspec.trim_end_matches('/');
+    if pathspec.is_empty() || pathspec == "." {
+        return true;
+    }
+    if path == pathspec.as_bytes()
+        || path
+            .strip_prefix(pathspec.as_bytes())
+            .is_some_and(|rest| rest.first() == Some(&b'/'))
+    {
+        return true;
+    }
+
+    pathspec.contains(['*', '?', '[']) && wildmatch(pathspec, &String::from_utf8_lossy(path), false)
+}
@@ -0,0 +1,127 @@
+use std::{
+    ops::Range,
+    sync::{Mutex, MutexGuard},
+    thread,
+};
+
+/// Hands out the indices `0..len` to a fixed set of workers. Each worker
+/// starts with an equal, contiguous share and takes indices from its front;
+/// a worker that runs dry steals the back half of the largest share left,
+/// so no thread sits idle while another still has a long run of work.
+pub struct WorkQueue {
+    shares: Vec<Mutex<Range<usize>>>,
+}
+
+impl WorkQueue {
+    fn new(len: usize, workers: usize) -> Self {
+        let share = len.div_ceil(workers.max(1));
+
+        WorkQueue {
+            shares: (0..workers.max(1))
+                .map(|worker| {
+                    Mutex::new((worker * share).min(len)..((worker + 1) * share).min(len))
+                })
+                .collect(),
+        }
+    }
+
+    /// Takes the next index for `worker`. Indices within a worker's share
+    /// come in order, so one that does not follow the previous index is the
+    /// start of freshly stolen work.
+    pub fn next(&self, worker: usize) -> Option<usize> {
+        loop {
+            {
+                let mut own = self.share(worker);
+                if let Some(index) = own.next() {
+                    return Some(index);
+                }
+            }
+
+            if !self.steal(worker) {
+                return None;
+            }
+        }
+    }
+
+    fn steal(&self, thief: usize) -> bool {
+        let victim = (0..self.shares.len())
+            .filter(|&worker| worker != thief)
+            .max_by_key(|&worker| self.share(worker).len());
+        let Some(victim) = victim else {
+            return false;
+        };
+
+        let stolen = {
+            let mut share = self.share(victim);
+            // The back half is taken, rounding down, so that a single index
+            // stays with its owner.
+            let middle = share.start + share.len().div_ceil(2);
+            let stolen = middle..share.end;
+            share.end = middle;
+            stolen
+        };
+        if stolen.is_empty() {
+            return false;
+        }
+
+        *self.share(thief) = stolen;
+        true
+    }
+
+    fn share(&self, worker: usize) -> MutexGuard<'_, Range<usize>> {
+        // A worker that panicked leaves its share consistent; the panic
+        // itself surfaces when the thread is joined.
+        self.shares[worker]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Runs `task` on `threads` workers sharing the indices `0..len`, and
+/// gathers the `(index, result)` pairs they return into a vector ordered by
+/// index. Every index must be answered exactly once.
+pub fn run<T: Send>(
+    threads: usize,
+    len: usize,
+    task: impl Fn(&WorkQueue, usize) -> Vec<(usize, T)> + Sync,
+) -> Vec<T> {
+    let threads = threads.clamp(1, len.max(1));
+    let queue = WorkQueue::new(len, threads);
+
+    let answers: Vec<(usize, T)> = if threads == 1 {
+        task(&queue, 0)
+    } else {
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|worker| {
+                    let (queue, task) = (&queue, &task);
+                    scope.spawn(move || task(queue, worker))
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect()
+        })
+    };
+
+    let mut results: Vec<Option<T>> = (0..len).map(|_| None).collect();
+    for (index, result) in answers {
+        results[index] = Some(result);
+    }
+    results.into_iter().flatten().collect()
+}
+
+/// The number of threads to use when `configured` asks for zero, meaning
+/// one per available core.
+pub fn default_threads(configured: usize) -> usize {
+    match configured {
+        0 => thread::available_parallelism().map_or(1, |cores| cores.get()),
+        threads => threads,
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// The kinds of work a command's time is broken down into by `--profile`.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    DiskIo,
+    Compression,
+    Hashing,
+    Diffing,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [
+        Phase::DiskIo,
+        Phase::Compression,
+        Phase::Hashing,
+        Phase::Diffing,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::DiskIo => "disk I/O",
+            Phase::Compression => "compression",
+            Phase::Hashing => "hashing",
+            Phase::Diffing => "diffing",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TOTALS: Mutex<[(Duration, usize); 4]> = Mutex::new([(Duration::ZERO, 0); 4]);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Times the enclosing scope as part of `phase` until the returned guard is
+/// dropped. Costs nothing beyond a flag check unless profiling is enabled.
+/// Spans are meant for leaf operations; nesting them counts time twice.
+pub fn span(phase: Phase) -> Span {
+    Span {
+        phase,
+        start: ENABLED.load(Ordering::Relaxed).then(Instant::now),
+    }
+}
+
+pub struct Span {
+    phase: Phase,
+    start: Option<Instant>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let elapsed = start.elapsed();
+
+        if let Ok(mut totals) = TOTALS.lock() {
+            let (time, calls) = &mut totals[self.phase as usize];
+            *time += elapsed;
+            *calls += 1;
+        }
+    }
+}
+
+/// Prints the time recorded for each phase, and what was left over, as a
+/// share of the command's `total` running time.
+pub fn print_summary(total: Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Ok(totals) = TOTALS.lock() else {
+        return;
+    };
+
+    let share = |time: Duration| {
+        if total.is_zero() {
+            0.0
+        } else {
+            time.as_secs_f64() / total.as_secs_f64() * 100.0
+        }
+    };
+    let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+
+    eprintln!();
+    eprintln!(
+        "{:<12} {:>8} {:>12} {:>7}",
+        "phase", "calls", "time (ms)", "share"
+    );
+
+    let mut measured = Duration::ZERO;
+    for phase in Phase::ALL {
+        let (time, calls) = totals[phase as usize];
+        measured += time;
+        eprintln!(
+            "{:<12} {:>8} {:>12.3} {:>6.1}%",
+            phase.name(),
+            calls,
+            millis(time),
+            share(time)
+        );
+    }
+
+    let other = total.saturating_sub(measured);
+    eprintln!(
+        "{:<12} {:>8} {:>12.3} {:>6.1}%",
+        "other",
+        "",
+        millis(other),
+        share(other)
+    );
+    eprintln!("{:<12} {:>8} {:>12.3}", "total", "", millis(total));
+}
@@ -0,0 +1,458 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+use crate::refs::parse_hash;
+
+/// Writes `data` as a pkt-line: four hex digits giving the length of the
+/// line including themselves, followed by the data.
+pub fn write_pkt_line(out: &mut impl Write, data: &[u8]) -> Result<()> {
+    if data.len() > 65516 {
+        return Err(anyhow!(
+            "fatal: pkt-line of {} bytes is too long",
+            data.len()
+        ));
+    }
+
+    write!(out, "{:04x}", data.len() + 4)?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+pub fn write_flush(out: &mut impl Write) -> Result<()> {
+    out.write_all(b"0000")?;
+    Ok(())
+}
+
+/// Writes a delimiter packet, which separates the sections of a protocol v2
+/// request or response.
+pub fn write_delim(out: &mut impl Write) -> Result<()> {
+    out.write_all(b"0001")?;
+    Ok(())
+}
+
+/// One packet of a pkt-line stream.
+pub enum Packet {
+    Flush,
+    Delim,
+    Data(Vec<u8>),
+}
+
+/// Reads one packet, returning `None` if the input ends cleanly before it.
+pub fn read_packet(input: &mut impl Read) -> Result<Option<Packet>> {
+    let mut length = [0u8; 4];
+    let mut read = 0;
+    while read < length.len() {
+        match input.read(&mut length[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(anyhow!("fatal: the remote end hung up unexpectedly")),
+            n => read += n,
+        }
+    }
+
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| anyhow!("fatal: protocol error: bad line length character"))?;
+
+    match length {
+        0 => Ok(Some(Packet::Flush)),
+        1 => Ok(Some(Packet::Delim)),
+        2..=3 => Err(anyhow!("fatal: protocol error: bad line length {}", length)),
+        _ => {
+            let mut data = vec![0u8; length - 4];
+            input
+                .read_exact(&mut data)
+                .context("fatal: the remote end hung up unexpectedly")?;
+            Ok(Some(Packet::Data(data)))
+        }
+    }
+}
+
+/// Reads one pkt-line, returning `None` for a flush packet.
+pub fn read_pkt_line(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    match read_packet(input)? {
+        Some(Packet::Data(data)) => Ok(Some(data)),
+        Some(Packet::Flush) => Ok(None),
+        Some(Packet::Delim) => Err(anyhow!("fatal: protocol error: unexpected delim packet")),
+        None => Err(anyhow!("fatal: the remote end hung up unexpectedly")),
+    }
+}
+
+/// Writes everything given to it as band 1 of a side-band stream, in
+/// pkt-lines of at most [`SIDEBAND_DATA_MAX`] bytes of data each.
+pub struct SidebandWriter<W: Write> {
+    inner: W,
+}
+
+/// The most data one side-band pkt-line can carry after its band byte.
+pub const SIDEBAND_DATA_MAX: usize = 65515;
+
+impl<W: Write> SidebandWriter<W> {
+    pub fn new(inner: W) -> Self {
+        SidebandWriter { inner }
+    }
+}
+
+impl<W: Write> Write for SidebandWriter<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let chunk = &data[..data.len().min(SIDEBAND_DATA_MAX)];
+        if chunk.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.inner, "{:04x}", chunk.len() + 5)?;
+        self.inner.write_all(&[1])?;
+        self.inner.write_all(chunk)?;
+        Ok(chunk.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The refs and capabilities an upload-pack server announces before any
+/// negotiation takes place.
+pub struct Advertisement {
+    pub refs: Vec<(String, [u8; 20])>,
+    /// What each annotated tag points to, from the `<tag>^{}` lines.
+    pub peeled: HashMap<String, [u8; 20]>,
+    pub capabilities: Vec<String>,
+    /// The protocol version the server answered in. Under version 2 the
+    /// refs are not advertised up front but listed by an `ls-refs` command.
+    pub version: u8,
+}
+
+impl Advertisement {
+    /// Where the server says `name` points, e.g. `HEAD` to its default branch.
+    pub fn symref(&self, name: &str) -> Option<&str> {
+        self.capabilities.iter().find_map(|capability| {
+            capability
+                .strip_prefix("symref=")?
+                .strip_prefix(name)?
+                .strip_prefix(':')
+        })
+    }
+
+    /// Returns `filter` if the server can filter what it sends, which takes
+    /// protocol version 2, and warns that it will be ignored otherwise.
+    pub fn accept_filter<'a>(&self, filter: Option<&'a str>) -> Option<&'a str> {
+        let filter = filter?;
+        let supported = self.version == 2
+            && self.capabilities.iter().any(|capability| {
+                capability
+                    .strip_prefix("fetch=")
+                    .is_some_and(|features| features.split(' ').any(|feature| feature == "filter"))
+            });
+
+        if !supported {
+            eprintln!("warning: filtering not recognized by server, ignoring");
+        }
+        supported.then_some(filter)
+    }
+}
+
+/// Reads a ref advertisement up to its closing flush packet. The first line
+/// carries the capabilities after a NUL; an empty repository advertises them
+/// on a placeholder `capabilities^{}` line instead of a ref. A server that
+/// answers in protocol version 2 lists only its capabilities, one per line.
+pub fn read_advertisement(input: &mut impl Read) -> Result<Advertisement> {
+    let mut advertisement = Advertisement {
+        refs: Vec::new(),
+        peeled: HashMap::new(),
+        capabilities: Vec::new(),
+        version: 0,
+    };
+
+    let mut first = true;
+    while let Some(line) = read_pkt_line(input)? {
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\n');
+
+        if std::mem::take(&mut first) {
+            match line {
+                "version 2" => {
+                    advertisement.version = 2;
+                    while let Some(line) = read_pkt_line(input)? {
+                        let line = String::from_utf8_lossy(&line);
+                        advertisement
+                            .capabilities
+                            .push(line.trim_end_matches('\n').to_string());
+                    }
+                    return Ok(advertisement);
+                }
+                "version 1" => {
+                    advertisement.version = 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        let line = match line.split_once('\0') {
+            Some((line, capabilities)) => {
+                advertisement.capabilities = capabilities.split(' ').map(str::to_string).collect();
+                line
+            }
+            None => line,
+        };
+
+        if let Some(message) = line.strip_prefix("ERR ") {
+            return Err(anyhow!("fatal: remote error: {}", message));
+        }
+
+        let (hash, name) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("fatal: protocol error: unexpected '{}'", line))?;
+        if name == "capabilities^{}" {
+            continue;
+        }
+
+        let hash = parse_hash(hash)?;
+        match name.strip_suffix("^{}") {
+            Some(tag) => {
+                advertisement.peeled.insert(tag.to_string(), hash);
+            }
+            None => advertisement.refs.push((name.to_string(), hash)),
+        }
+    }
+
+    Ok(advertisement)
+}
+
+/// Writes an upload-pack request asking for `wants` and naming `haves` as
+/// commits the client already has, ending the negotiation in one round.
+pub fn write_fetch_request(
+    out: &mut impl Write,
+    wants: &[[u8; 20]],
+    haves: &[[u8; 20]],
+    capabilities: &[&str],
+) -> Result<()> {
+    for (i, want) in wants.iter().enumerate() {
+        let line = if i == 0 {
+            format!("want {} {}\n", encode(want), capabilities.join(" "))
+        } else {
+            format!("want {}\n", encode(want))
+        };
+        write_pkt_line(out, line.as_bytes())?;
+    }
+    write_flush(out)?;
+
+    for have in haves {
+        write_pkt_line(out, format!("have {}\n", encode(have)).as_bytes())?;
+    }
+    write_pkt_line(out, b"done\n")
+}
+
+/// Skips the ACK/NAK lines that precede the pack in an upload-pack response
+/// and returns the pack itself.
+pub fn pack_from_response(mut response: &[u8]) -> Result<&[u8]> {
+    while !response.starts_with(b"PACK") {
+        let Some(line) = read_pkt_line(&mut response)? else {
+            continue;
+        };
+        if let Some(message) = line.strip_prefix(b"ERR ") {
+            return Err(anyhow!(
+                "fatal: remote error: {}",
+                String::from_utf8_lossy(message).trim_end()
+            ));
+        }
+    }
+
+    Ok(response)
+}
+
+/// Writes a protocol version 2 command: its name and capabilities, then
+/// after a delimiter its arguments, one per line.
+pub fn write_command_request(
+    out: &mut impl Write,
+    command: &str,
+    arguments: &[String],
+) -> Result<()> {
+    write_pkt_line(out, format!("command={command}\n").as_bytes())?;
+    write_pkt_line(out, b"agent=mini-git/0.1\n")?;
+    write_pkt_line(out, b"object-format=sha1\n")?;
+    write_delim(out)?;
+
+    for argument in arguments {
+        write_pkt_line(out, format!("{argument}\n").as_bytes())?;
+    }
+    write_flush(out)
+}
+
+/// Writes an `ls-refs` command asking for every ref, with where symbolic
+/// refs point and what annotated tags peel to.
+pub fn write_ls_refs_request(out: &mut impl Write) -> Result<()> {
+    write_command_request(out, "ls-refs", &["symrefs".to_string(), "peel".to_string()])
+}
+
+/// Reads the response to an `ls-refs` command into `advertisement`: one
+/// `<hash> <ref>` line per ref, with `symref-target:` and `peeled:`
+/// attributes where they apply.
+pub fn read_ls_refs(input: &mut impl Read, advertisement: &mut Advertisement) -> Result<()> {
+    while let Some(line) = read_pkt_line(input)? {
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\n');
+        if let Some(message) = line.strip_prefix("ERR ") {
+            return Err(anyhow!("fatal: remote error: {}", message));
+        }
+
+        let mut fields = line.split(' ');
+        let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+            return Err(anyhow!("fatal: protocol error: unexpected '{}'", line));
+        };
+        // An unborn HEAD has no object yet.
+        if hash == "unborn" {
+            continue;
+        }
+        let hash = parse_hash(hash)?;
+
+        for attribute in fields {
+            if let Some(target) = attribute.strip_prefix("symref-target:") {
+                advertisement
+                    .capabilities
+                    .push(format!("symref={name}:{target}"));
+            } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                advertisement
+                    .peeled
+                    .insert(name.to_string(), parse_hash(peeled)?);
+            }
+        }
+        advertisement.refs.push((name.to_string(), hash));
+    }
+
+    Ok(())
+}
+
+/// Writes a protocol version 2 `fetch` command asking for `wants`, naming
+/// `haves` as commits the client already has and ending the negotiation in
+/// one round. A `filter` spec asks the server to leave some objects out.
+pub fn write_fetch_request_v2(
+    out: &mut impl Write,
+    wants: &[[u8; 20]],
+    haves: &[[u8; 20]],
+    filter: Option<&str>,
+) -> Result<()> {
+    let mut arguments = vec!["ofs-delta".to_string(), "no-progress".to_string()];
+    arguments.extend(wants.iter().map(|want| format!("want {}", encode(want))));
+    arguments.extend(haves.iter().map(|have| format!("have {}", encode(have))));
+    if let Some(filter) = filter {
+        arguments.push(format!("filter {filter}"));
+    }
+    arguments.push("done".to_string());
+
+    write_command_request(out, "fetch", &arguments)
+}
+
+/// Finds the `packfile` section of a protocol version 2 `fetch` response
+/// and returns the pack it carries on band 1. Progress on band 2 is shown
+/// as it comes; an error on band 3 ends the fetch.
+pub fn packfile_from_response(mut response: &[u8]) -> Result<Vec<u8>> {
+    loop {
+        match read_packet(&mut response)? {
+            Some(Packet::Data(line)) if line == b"packfile\n" => break,
+            Some(Packet::Data(line)) => {
+                if let Some(message) = line.strip_prefix(b"ERR ") {
+                    return Err(anyhow!(
+                        "fatal: remote error: {}",
+                        String::from_utf8_lossy(message).trim_end()
+                    ));
+                }
+            }
+            Some(Packet::Flush | Packet::Delim) => {}
+            None => return Err(anyhow!("fatal: protocol error: expected packfile section")),
+        }
+    }
+
+    let mut pack = Vec::new();
+    while let Some(line) = read_pkt_line(&mut response)? {
+        match line.split_first() {
+            Some((1, data)) => pack.extend_from_slice(data),
+            Some((2, progress)) => eprint!("remote: {}", String::from_utf8_lossy(progress)),
+            Some((3, message)) => {
+                return Err(anyhow!(
+                    "fatal: remote error: {}",
+                    String::from_utf8_lossy(message).trim_end()
+                ));
+            }
+            _ => return Err(anyhow!("fatal: protocol error: bad band")),
+        }
+    }
+
+    Ok(pack)
+}
+
+/// A ref a push asks the server to move from `old` to `new`. A missing
+/// `old` creates the ref and a missing `new` deletes it.
+pub struct RefUpdate {
+    pub name: String,
+    pub old: Option<[u8; 20]>,
+    pub new: Option<[u8; 20]>,
+}
+
+/// Writes the command list of a receive-pack request, one `<old> <new>
+/// <ref>` line per update with the capabilities after a NUL on the first.
+pub fn write_push_request(
+    out: &mut impl Write,
+    updates: &[RefUpdate],
+    capabilities: &[&str],
+) -> Result<()> {
+    let zero = [0u8; 20];
+
+    for (i, update) in updates.iter().enumerate() {
+        let mut line = format!(
+            "{} {} {}",
+            encode(update.old.unwrap_or(zero)),
+            encode(update.new.unwrap_or(zero)),
+            update.name
+        );
+        if i == 0 {
+            line.push('\0');
+            line.push_str(&capabilities.join(" "));
+        }
+        line.push('\n');
+        write_pkt_line(out, line.as_bytes())?;
+    }
+
+    write_flush(out)
+}
+
+/// Reads a `report-status` response: whether the pack was unpacked, then an
+/// `ok <ref>` or `ng <ref> <reason>` line per ref. Returns each ref with the
+/// reason it was refused, if any.
+pub fn read_report_status(input: &mut impl Read) -> Result<Vec<(String, Option<String>)>> {
+    let unpack = read_pkt_line(input)?.unwrap_or_default();
+    let unpack = String::from_utf8_lossy(&unpack);
+    let unpack = unpack.trim_end();
+    match unpack.strip_prefix("unpack ") {
+        Some("ok") => {}
+        Some(error) => return Err(anyhow!("error: remote unpack failed: {}", error)),
+        None => {
+            return Err(anyhow!(
+                "fatal: protocol error: expected unpack status, got '{}'",
+                unpack
+            ));
+        }
+    }
+
+    let mut statuses = Vec::new();
+    while let Some(line) = read_pkt_line(input)? {
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+
+        if let Some(name) = line.strip_prefix("ok ") {
+            statuses.push((name.to_string(), None));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            statuses.push((name.to_string(), Some(reason.to_string())));
+        } else {
+            return Err(anyhow!("fatal: protocol error: unexpected '{}'", line));
+        }
+    }
+
+    Ok(statuses)
+}
@@ -0,0 +1,84 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::fs;
+
+use crate::{
+    Repository,
+    config::Config,
+    exit::ExitStatus,
+    fetch::{fetch, remote_source, report_fetch},
+    merge::handle_merge_command,
+    rebase::rebase_onto,
+    refs::parse_hash,
+    transport::short_ref_name,
+};
+
+/// Fetches `branch` from `remote`, defaulting to what the current branch is
+/// configured to track, and merges it into the current branch or rebases
+/// the current branch onto it.
+pub fn handle_pull_command(
+    remote: Option<String>,
+    branch: Option<String>,
+    rebase: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let config = Config::load(repository)?;
+    let current = repository
+        .head_ref()?
+        .map(|head| short_ref_name(&head).to_string());
+    let tracked = |key: &str| {
+        current
+            .as_ref()
+            .and_then(|current| config.get(&format!("branch.{current}.{key}")))
+            .map(str::to_string)
+    };
+
+    let remote = remote
+        .or_else(|| tracked("remote"))
+        .unwrap_or_else(|| "origin".to_string());
+    let branch = branch
+        .or_else(|| tracked("merge").map(|merge| short_ref_name(&merge).to_string()))
+        .or(current)
+        .ok_or_else(|| {
+            anyhow!("fatal: You are not currently on a branch; name the branch to pull")
+        })?;
+
+    let (url, mut refspecs) = remote_source(&config, &remote)?;
+    refspecs.push(format!("refs/heads/{branch}"));
+
+    let result = fetch(repository, &url, &refspecs)?;
+    report_fetch(&url, &result)?;
+
+    let fetched = fetched_branch(repository, &branch)?;
+    if rebase {
+        rebase_onto(repository, &fetched, false)
+    } else {
+        handle_merge_command(
+            &[encode(fetched)],
+            Some(format!("Merge branch '{branch}' of {url}")),
+            false,
+            false,
+            repository,
+        )
+    }
+}
+
+/// Finds what `branch` pointed to on the remote in the `FETCH_HEAD` left by
+/// the last fetch.
+fn fetched_branch(repository: &Repository, branch: &str) -> Result<[u8; 20]> {
+    let fetch_head_file = repository.git_dir.join("FETCH_HEAD");
+    let fetch_head = fs::read_to_string(&fetch_head_file)
+        .with_context(|| format!("Failed to read {}", fetch_head_file.display()))?;
+    let description = format!("branch '{branch}' of ");
+
+    fetch_head
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let hash = fields.next()?;
+            fields.next()?;
+            fields.next()?.starts_with(&description).then_some(hash)
+        })
+        .ok_or_else(|| anyhow!("fatal: couldn't find remote ref {}", branch))
+        .and_then(parse_hash)
+}
@@ -0,0 +1,74 @@
+use anyhow::{Result, anyhow};
+
+use crate::{
+    Repository,
+    config::Config,
+    transport::{Refspec, remote_urls, send_pack, short_ref_name},
+};
+
+/// Pushes `refspecs` to every push URL of `remote`, defaulting to the
+/// current branch, and moves the matching remote-tracking refs along with
+/// whatever the remote accepted.
+pub fn handle_push_command(
+    remote: Option<String>,
+    refspecs: Vec<String>,
+    force: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let config = Config::load(repository)?;
+    let head = repository.head_ref()?;
+
+    let remote = match remote {
+        Some(remote) => remote,
+        None => head
+            .as_deref()
+            .and_then(|head| config.get(&format!("branch.{}.remote", short_ref_name(head))))
+            .unwrap_or("origin")
+            .to_string(),
+    };
+
+    let specs = if refspecs.is_empty() {
+        let head = head.ok_or_else(|| {
+            anyhow!("fatal: You are not currently on a branch; name the branch to push")
+        })?;
+        vec![Refspec::parse(&format!("{head}:{head}"))?]
+    } else {
+        refspecs
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let tracking = config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut failed = Vec::new();
+    for url in remote_urls(&config, &remote, true) {
+        let result = send_pack(repository, &config, &url, &specs, force)?;
+        if result.rejected {
+            failed.push(url);
+        }
+
+        for (name, new) in result.updated {
+            let Some(local) = tracking.iter().find_map(|spec| spec.map(&name)) else {
+                continue;
+            };
+            match new {
+                Some(new) => repository.write_ref(&local, &new)?,
+                None => repository.delete_ref(&local)?,
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "error: failed to push some refs to '{}'",
+            failed.join("', '")
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,523 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    IndexEntry, IndexFile, Repository, StatData, TreeEntry,
+    am::reset_hard,
+    changes::blob_hash,
+    config::Config,
+    exit::ExitStatus,
+    filter::Filters,
+    ident::Ident,
+    merge::{
+        checkout_entries, ensure_index_matches, merge_base, merge_trees, read_blob_bytes, tree_of,
+    },
+    messages::tr,
+    refs::parse_hash,
+    walk::{CommitWalk, WalkOrder},
+};
+
+/// Where a rebase that stopped keeps what it needs to go on or to be
+/// undone: `head-name` and `orig-head` for the branch and where it was,
+/// `todo` for the commits still to replay, one per line, `stopped-sha` for
+/// the commit whose changes are in the index, `conflicts` for the paths
+/// written with conflict markers and `autostash` for the stash of local
+/// changes to put back at the end.
+const STATE_DIR: &str = "rebase-merge";
+
+const RESOLVE_HINT: &str = "hint: Resolve all conflicts manually, mark them as resolved with
+hint: \"mini-git update-index --add <pathspec>\", then run \"mini-git rebase --continue\".
+hint: To abort and get back to the state before \"mini-git rebase\", run \"mini-git rebase --abort\".";
+
+/// Rebases the current branch onto `upstream`, or goes on with or undoes
+/// a rebase that stopped on conflicts. With `autostash`, or with
+/// `rebase.autostash` set, local changes are stashed first and put back
+/// once the rebase is done or aborted.
+pub fn handle_rebase_command(
+    upstream: Option<String>,
+    resolved: bool,
+    abort: bool,
+    autostash: bool,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    let state = repository.git_dir.join(STATE_DIR);
+
+    if resolved || abort {
+        if !state.is_dir() {
+            return Err(anyhow!("fatal: No rebase in progress?"));
+        }
+        if abort {
+            abort_rebase(repository, &state)?;
+            return Ok(ExitStatus::Success);
+        }
+        return continue_rebase(repository, &state);
+    }
+
+    let upstream = upstream
+        .ok_or_else(|| anyhow!("fatal: no upstream given; name the branch to rebase onto"))?;
+    rebase_onto(
+        repository,
+        &repository.resolve_commitish(&upstream)?,
+        autostash,
+    )
+}
+
+/// Replays the commits on the current branch that `upstream` lacks on top
+/// of it, oldest first, leaving merge commits out. A commit that does not
+/// apply cleanly stops the rebase with HEAD detached at the commits
+/// replayed so far and the conflicts in the working tree, its state kept
+/// for `--continue` or `--abort`.
+pub fn rebase_onto(
+    repository: &Repository,
+    upstream: &[u8; 20],
+    autostash: bool,
+) -> Result<ExitStatus> {
+    let state = repository.git_dir.join(STATE_DIR);
+    if state.is_dir() {
+        return Err(anyhow!(
+            "fatal: It seems that there is already a {} directory, and\n\
+I wonder if you are in the middle of another rebase.\n\
+Try 'mini-git rebase --continue' or 'mini-git rebase --abort'.",
+            STATE_DIR
+        ));
+    }
+
+    let Some(head) = repository.resolve_head()? else {
+        let target_entries = repository.read_tree_recursive(&tree_of(repository, upstream)?)?;
+        checkout_entries(repository, &[], &target_entries, "rebase")?;
+        repository.update_head(upstream)?;
+        return Ok(ExitStatus::Success);
+    };
+
+    let base = merge_base(repository, &head, upstream)?;
+    if base == Some(*upstream) {
+        println!("{}", tr!("Current branch is up to date."));
+        return Ok(ExitStatus::Success);
+    }
+
+    let head_entries = repository.read_tree_recursive(&tree_of(repository, &head)?)?;
+    let head_name = repository
+        .head_ref()?
+        .unwrap_or_else(|| "detached HEAD".to_string());
+    let config = Config::load(repository)?;
+    let autostash = autostash
+        || config
+            .get("rebase.autostash")
+            .is_some_and(|value| matches!(value, "true" | "yes" | "on" | "1"));
+    let stash = if autostash {
+        create_autostash(repository, &head, &head_name, &head_entries)?
+    } else {
+        None
+    };
+
+    ensure_index_matches(repository, &head_entries, "rebase")?;
+    repository.write_ref("ORIG_HEAD", &head)?;
+
+    let upstream_history: HashSet<[u8; 20]> =
+        CommitWalk::new(repository, &[*upstream], WalkOrder::Date)?
+            .map(|entry| entry.map(|(hash, _)| hash))
+            .collect::<Result<_>>()?;
+    let mut todo = Vec::new();
+    for entry in CommitWalk::new(repository, &[head], WalkOrder::Topo)?.reverse(true) {
+        let (hash, commit) = entry?;
+        if !upstream_history.contains(&hash) && commit.parents()?.len() == 1 {
+            todo.push(hash);
+        }
+    }
+
+    fs::create_dir_all(&state).context("Failed to create rebase state")?;
+    write_state(&state, "head-name", &head_name)?;
+    write_state(&state, "orig-head", &encode(head))?;
+    write_state(&state, "onto", &encode(upstream))?;
+    if let Some(stash) = stash {
+        write_state(&state, "autostash", &encode(stash))?;
+    }
+
+    replay(repository, &state, &todo, *upstream, &head_entries)
+}
+
+/// Commits the index for the commit the rebase stopped at, unless it adds
+/// nothing to HEAD, and replays the commits left after it.
+fn continue_rebase(repository: &Repository, state: &Path) -> Result<ExitStatus> {
+    let mut filters = Filters::load(repository)?;
+    let index = repository.read_index()?;
+    let work_dir = repository.work_dir();
+    for path in read_state(state, "conflicts")?.lines().map(PathBuf::from) {
+        let file = work_dir.join(&path);
+        let on_disk = file
+            .is_file()
+            .then(|| fs::read(&file))
+            .transpose()?
+            .map(|content| filters.clean(&path, content))
+            .transpose()?
+            .map(|content| blob_hash(&content));
+        let staged = index
+            .entries
+            .iter()
+            .find(|entry| entry.path == path)
+            .map(|entry| entry.sha1);
+        if on_disk != staged {
+            return Err(anyhow!(
+                "error: you must edit all merge conflicts and then\n\
+mark them as resolved using 'mini-git update-index --add'\n\
+{}\n{}",
+                path.display(),
+                RESOLVE_HINT
+            ));
+        }
+    }
+
+    let mut tip = repository
+        .resolve_head()?
+        .ok_or_else(|| anyhow!("fatal: rebase state is corrupt: HEAD is unborn"))?;
+    let (tree, tree_hex) = repository.write_tree()?;
+    if tree != tree_of(repository, &tip)? {
+        let stopped = parse_hash(read_state(state, "stopped-sha")?.trim())?;
+        let commit = repository.read_commit(&stopped)?;
+        let committer = Ident::committer(&Config::load(repository)?)?;
+        tip = repository
+            .write_raw_object("commit", &commit.rewrite(&tree_hex, &[tip], &committer))?;
+        repository.update_head(&tip)?;
+    }
+
+    let todo = read_state(state, "todo")?
+        .lines()
+        .map(parse_hash)
+        .collect::<Result<Vec<_>>>()?;
+    let current = repository.read_tree_recursive(&tree)?;
+    replay(repository, state, &todo, tip, &current)
+}
+
+/// Replays `todo` on top of `tip` in memory, then moves the working tree
+/// from `current` to the result and the branch to the last commit. The
+/// first commit that conflicts is stopped at instead, with the commits
+/// before it kept and the ones after it saved in `todo`.
+fn replay(
+    repository: &Repository,
+    state: &Path,
+    todo: &[[u8; 20]],
+    mut tip: [u8; 20],
+    current: &[TreeEntry],
+) -> Result<ExitStatus> {
+    let mut entries = repository.read_tree_recursive(&tree_of(repository, &tip)?)?;
+
+    for (i, hash) in todo.iter().enumerate() {
+        let commit = repository.read_commit(hash)?;
+        let message = commit.message();
+        let subject = message.lines().next().unwrap_or_default();
+        let label = format!("{}... {}", &encode(hash)[..7], subject);
+
+        let parent_entries =
+            repository.read_tree_recursive(&tree_of(repository, &commit.parents()?[0])?)?;
+        let theirs_entries = repository.read_tree_recursive(&tree_of(repository, hash)?)?;
+        let outcome = merge_trees(
+            repository,
+            &parent_entries,
+            &entries,
+            &theirs_entries,
+            &label,
+        )?;
+
+        if !outcome.conflicts.is_empty() {
+            checkout_entries(repository, current, &outcome.entries, "rebase")?;
+            let mut filters = Filters::load(repository)?;
+            for (path, content) in &outcome.conflicted_files {
+                let content = filters.smudge(path, content.clone())?;
+                let path = repository.work_dir().join(path);
+                fs::write(&path, content)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+            }
+            detach_head(repository, &tip)?;
+
+            let remaining: String = todo[i + 1..]
+                .iter()
+                .map(|hash| format!("{}\n", encode(hash)))
+                .collect();
+            fs::write(state.join("todo"), remaining).context("Failed to write rebase state")?;
+            write_state(state, "stopped-sha", &encode(hash))?;
+            let conflicted: String = outcome
+                .conflicted_files
+                .iter()
+                .map(|(path, _)| format!("{}\n", path.display()))
+                .collect();
+            fs::write(state.join("conflicts"), conflicted)
+                .context("Failed to write rebase state")?;
+
+            for conflict in &outcome.conflicts {
+                println!("{conflict}");
+            }
+            eprintln!("{}", tr!("error: could not apply {}", label));
+            eprintln!("{RESOLVE_HINT}");
+            return Ok(ExitStatus::Differences);
+        }
+
+        entries = outcome.entries;
+        let tree_hash = repository.write_tree_entries(
+            &entries
+                .iter()
+                .map(|entry| IndexEntry {
+                    mode: entry.mode,
+                    sha1: entry.sha1,
+                    path: entry.path.clone(),
+                    stat: StatData::default(),
+                })
+                .collect::<Vec<_>>(),
+        )?;
+        let committer = Ident::committer(&Config::load(repository)?)?;
+        tip = repository.write_raw_object(
+            "commit",
+            &commit.rewrite(&encode(tree_hash), &[tip], &committer),
+        )?;
+    }
+
+    checkout_entries(repository, current, &entries, "rebase")?;
+
+    let head_name = read_state(state, "head-name")?;
+    let head_name = head_name.trim();
+    if head_name.starts_with("refs/") {
+        fs::write(
+            repository.git_dir.join("HEAD"),
+            format!("ref: {head_name}\n"),
+        )
+        .context("Failed to write HEAD")?;
+        repository.write_ref(head_name, &tip)?;
+        println!("{}", tr!("Successfully rebased and updated {}.", head_name));
+    } else {
+        repository.update_head(&tip)?;
+        println!("{}", tr!("Successfully rebased."));
+    }
+
+    finish(repository, state)
+}
+
+/// Puts HEAD back on the branch the rebase started from, at the commit it
+/// was at, and the index and the paths the rebase touched back to that
+/// commit. Local changes to other files are left alone.
+fn abort_rebase(repository: &Repository, state: &Path) -> Result<()> {
+    let orig_head = parse_hash(read_state(state, "orig-head")?.trim())?;
+    let original: BTreeMap<PathBuf, TreeEntry> = repository
+        .read_tree_recursive(&tree_of(repository, &orig_head)?)?
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+    let rebased: BTreeMap<PathBuf, IndexEntry> = repository
+        .read_index()?
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+    let conflicted: Vec<PathBuf> = read_state(state, "conflicts")?
+        .lines()
+        .map(PathBuf::from)
+        .collect();
+
+    let touched: BTreeSet<&PathBuf> = original
+        .keys()
+        .chain(rebased.keys())
+        .filter(|path| {
+            original.get(*path).map(|entry| (entry.mode, entry.sha1))
+                != rebased.get(*path).map(|entry| (entry.mode, entry.sha1))
+        })
+        .chain(conflicted.iter())
+        .collect();
+
+    let work_dir = repository.work_dir();
+    let mut filters = Filters::load(repository)?;
+    for path in touched {
+        let file = work_dir.join(path);
+        match original.get(path) {
+            Some(entry) if entry.mode == 160000 => {}
+            Some(entry) => {
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let content = read_blob_bytes(repository, &entry.sha1)?;
+                fs::write(&file, filters.smudge(path, content)?)
+                    .with_context(|| format!("Failed to restore {}", file.display()))?;
+            }
+            None if file.is_file() => {
+                fs::remove_file(&file)
+                    .with_context(|| format!("Failed to remove {}", file.display()))?;
+            }
+            None => {}
+        }
+    }
+
+    let mut index = IndexFile {
+        entries: original
+            .values()
+            .map(|entry| IndexEntry {
+                mode: entry.mode,
+                sha1: entry.sha1,
+                path: entry.path.clone(),
+                stat: StatData::default(),
+            })
+            .collect(),
+    };
+    repository.write_index(&mut index)?;
+
+    let head_name = read_state(state, "head-name")?;
+    let head_name = head_name.trim();
+    if head_name.starts_with("refs/") {
+        fs::write(
+            repository.git_dir.join("HEAD"),
+            format!("ref: {head_name}\n"),
+        )
+        .context("Failed to write HEAD")?;
+    }
+    repository.update_head(&orig_head)?;
+
+    finish(repository, state).map(|_| ())
+}
+
+/// Forgets the rebase and puts back the changes it stashed, if any.
+fn finish(repository: &Repository, state: &Path) -> Result<ExitStatus> {
+    let stash = state.join("autostash");
+    let stash = if stash.is_file() {
+        Some(parse_hash(read_state(state, "autostash")?.trim())?)
+    } else {
+        None
+    };
+    fs::remove_dir_all(state).context("Failed to remove rebase state")?;
+
+    if let Some(stash) = stash {
+        apply_autostash(repository, &stash)?;
+    }
+    Ok(ExitStatus::Success)
+}
+
+/// Saves the changes in the index and the tracked files as a stash commit
+/// on top of `head`, laid out as `stash` does, and resets both to `head`.
+/// Gives `None` when there is nothing to save.
+fn create_autostash(
+    repository: &Repository,
+    head: &[u8; 20],
+    head_name: &str,
+    head_entries: &[TreeEntry],
+) -> Result<Option<[u8; 20]>> {
+    let index = repository.read_index()?;
+    let work_dir = repository.work_dir();
+    let mut filters = Filters::load(repository)?;
+    let mut worktree = Vec::new();
+    for entry in &index.entries {
+        let file = work_dir.join(&entry.path);
+        let sha1 = if entry.mode == 160000 {
+            entry.sha1
+        } else if file.is_file() {
+            let content = filters.clean(&entry.path, fs::read(&file)?)?;
+            repository.write_raw_object("blob", &content)?
+        } else {
+            continue;
+        };
+        worktree.push(IndexEntry {
+            mode: entry.mode,
+            sha1,
+            path: entry.path.clone(),
+            stat: StatData::default(),
+        });
+    }
+
+    let head_tree = tree_of(repository, head)?;
+    let (index_tree, index_tree_hex) = repository.write_tree()?;
+    let worktree_tree = repository.write_tree_entries(&worktree)?;
+    if index_tree == head_tree && worktree_tree == head_tree {
+        return Ok(None);
+    }
+
+    let branch = head_name.strip_prefix("refs/heads/").unwrap_or(head_name);
+    let subject = repository.read_commit(head)?.message();
+    let subject = subject.lines().next().unwrap_or_default();
+    let (index_commit, _) = repository.commit_tree(
+        format!("index on {branch}: {} {subject}", &encode(head)[..7]),
+        index_tree_hex,
+        vec![*head],
+    )?;
+    let (stash, _) = repository.commit_tree(
+        format!("On {branch}: autostash"),
+        encode(worktree_tree),
+        vec![*head, index_commit],
+    )?;
+
+    reset_hard(repository, head_entries)?;
+    println!("{}", tr!("Created autostash: {}", &encode(stash)[..7]));
+    Ok(Some(stash))
+}
+
+/// Merges the changes saved in `stash` into the working tree. When they
+/// conflict with the rebased branch nothing is touched and the stash is
+/// kept in `refs/stash` instead.
+fn apply_autostash(repository: &Repository, stash: &[u8; 20]) -> Result<()> {
+    let commit = repository.read_commit(stash)?;
+    let base = repository.read_tree_recursive(&tree_of(repository, &commit.parents()?[0])?)?;
+    let theirs = repository.read_tree_recursive(&tree_of(repository, stash)?)?;
+    let head_entries = match repository.resolve_head()? {
+        Some(head) => repository.read_tree_recursive(&tree_of(repository, &head)?)?,
+        None => Vec::new(),
+    };
+
+    let outcome = merge_trees(repository, &base, &head_entries, &theirs, "autostash")?;
+    if !outcome.conflicts.is_empty() {
+        repository.write_ref("refs/stash", stash)?;
+        println!(
+            "{}",
+            tr!("Applying autostash resulted in conflicts.\nYour changes are safe in the stash.")
+        );
+        return Ok(());
+    }
+
+    checkout_entries(repository, &head_entries, &outcome.entries, "rebase")?;
+
+    // Like `stash apply`, the changes are left unstaged, apart from new
+    // files, which would otherwise be lost track of.
+    let tracked: HashSet<&PathBuf> = head_entries.iter().map(|entry| &entry.path).collect();
+    let mut index = IndexFile {
+        entries: head_entries
+            .iter()
+            .chain(
+                outcome
+                    .entries
+                    .iter()
+                    .filter(|entry| !tracked.contains(&entry.path)),
+            )
+            .map(|entry| IndexEntry {
+                mode: entry.mode,
+                sha1: entry.sha1,
+                path: entry.path.clone(),
+                stat: StatData::default(),
+            })
+            .collect(),
+    };
+    repository.write_index(&mut index)?;
+
+    println!("{}", tr!("Applied autostash."));
+    Ok(())
+}
+
+/// Points HEAD straight at `commit`, off any branch, as a rebase does
+/// while it replays.
+fn detach_head(repository: &Repository, commit: &[u8; 20]) -> Result<()> {
+    let old = repository.resolve_head()?;
+    fs::write(
+        repository.git_dir.join("HEAD"),
+        format!("{}\n", encode(commit)),
+    )
+    .context("Failed to write HEAD")?;
+    repository.log_ref_update("HEAD", old, Some(*commit))
+}
+
+fn read_state(state: &Path, name: &str) -> Result<String> {
+    let path = state.join(name);
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    fs::read_to_string(&path).with_context(|| format!("fatal: could not read {}", path.display()))
+}
+
+fn write_state(state: &Path, name: &str, value: &str) -> Result<()> {
+    fs::write(state.join(name), format!("{value}\n")).context("Failed to write rebase state")
+}
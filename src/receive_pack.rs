@@ -0,0 +1,304 @@
+use anyhow::{Result, anyhow};
+use hex::encode;
+use std::{
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    Repository,
+    config::Config,
+    hooks::run_hook,
+    merge::is_ancestor,
+    pack::unpack_pack,
+    protocol::{RefUpdate, read_pkt_line, write_flush, write_pkt_line},
+    refs::parse_hash,
+};
+
+const CAPABILITIES: &[&str] = &[
+    "report-status",
+    "delete-refs",
+    "ofs-delta",
+    "agent=mini-git/0.1",
+];
+
+/// One ref update a client asked for, and why it was refused, if it was.
+struct Command {
+    update: RefUpdate,
+    error: Option<String>,
+}
+
+/// Receives a push into the repository at `directory` over stdin and
+/// stdout, for running at the far end of an ssh connection.
+pub fn handle_receive_pack_command(directory: &Path) -> Result<()> {
+    let repository = Repository::open(directory)?;
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    serve_receive_pack(&repository, &mut io::stdin().lock(), &mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Serves one push into `repository`: advertises its refs, reads the
+/// client's ref updates and the pack that goes with them, and applies the
+/// updates the hooks and the repository's config allow. The `pre-receive`
+/// hook sees every update and can refuse them all, `update` runs per ref,
+/// and `post-receive` is told what was applied. The updates that pass are
+/// applied together: if one cannot be written, the others are put back.
+pub fn serve_receive_pack(
+    repository: &Repository,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<()> {
+    write_ref_advertisement(repository, output)?;
+    output.flush()?;
+
+    let mut commands = Vec::new();
+    let mut report_status = false;
+    while let Some(line) = read_pkt_line(input)? {
+        let line = String::from_utf8_lossy(&line);
+        let (line, capabilities) = line.split_once('\0').unwrap_or((&line, ""));
+        if capabilities
+            .split(' ')
+            .any(|capability| capability == "report-status")
+        {
+            report_status = true;
+        }
+
+        let mut fields = line.trim_end().splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(anyhow!(
+                "fatal: protocol error: expected old/new/ref, got '{}'",
+                line
+            ));
+        };
+        let (old, new) = (parse_hash(old)?, parse_hash(new)?);
+
+        commands.push(Command {
+            update: RefUpdate {
+                name: name.to_string(),
+                old: (old != [0u8; 20]).then_some(old),
+                new: (new != [0u8; 20]).then_some(new),
+            },
+            error: None,
+        });
+    }
+
+    // A flush in place of any commands means the client only wanted the refs.
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let unpack_error = if commands.iter().any(|command| command.update.new.is_some()) {
+        let mut pack = Vec::new();
+        input.read_to_end(&mut pack)?;
+        unpack_pack(repository, &pack, false)
+            .err()
+            .map(|error| error.to_string())
+    } else {
+        None
+    };
+
+    match &unpack_error {
+        Some(_) => {
+            for command in &mut commands {
+                command.error = Some("unpacker error".to_string());
+            }
+        }
+        None => process_commands(repository, &mut commands)?,
+    }
+
+    if report_status {
+        let unpack = match &unpack_error {
+            Some(error) => format!("unpack {error}\n"),
+            None => "unpack ok\n".to_string(),
+        };
+        write_pkt_line(output, unpack.as_bytes())?;
+
+        for command in &commands {
+            let line = match &command.error {
+                Some(reason) => format!("ng {} {reason}\n", command.update.name),
+                None => format!("ok {}\n", command.update.name),
+            };
+            write_pkt_line(output, line.as_bytes())?;
+        }
+        write_flush(output)?;
+    }
+
+    Ok(())
+}
+
+fn process_commands(repository: &Repository, commands: &mut [Command]) -> Result<()> {
+    let config = Config::load(repository)?;
+    for command in commands.iter_mut() {
+        command.error = check_command(repository, &config, &command.update)?;
+    }
+
+    let accepted = |commands: &[Command]| -> Vec<usize> {
+        (0..commands.len())
+            .filter(|&i| commands[i].error.is_none())
+            .collect()
+    };
+
+    let pre_receive: Vec<usize> = accepted(commands);
+    if !pre_receive.is_empty()
+        && !run_hook(
+            repository,
+            "pre-receive",
+            &[],
+            &[],
+            hook_input(commands, &pre_receive).as_bytes(),
+        )?
+    {
+        for &i in &pre_receive {
+            commands[i].error = Some("pre-receive hook declined".to_string());
+        }
+        return Ok(());
+    }
+
+    for &i in &pre_receive {
+        let update = &commands[i].update;
+        let (old, new) = (hex_or_zero(update.old), hex_or_zero(update.new));
+        if !run_hook(repository, "update", &[&update.name, &old, &new], &[], &[])? {
+            commands[i].error = Some("hook declined".to_string());
+        }
+    }
+
+    let applying = accepted(commands);
+    let mut applied: Vec<usize> = Vec::new();
+    for &i in &applying {
+        let update = &commands[i].update;
+        let result = match update.new {
+            Some(new) => repository.write_ref(&update.name, &new),
+            None => repository.delete_ref(&update.name),
+        };
+
+        if let Err(error) = result {
+            // Put back what was already changed, so that either every
+            // accepted update happens or none does.
+            for &j in applied.iter().rev() {
+                let update = &commands[j].update;
+                match update.old {
+                    Some(old) => repository.write_ref(&update.name, &old)?,
+                    None => repository.delete_ref(&update.name)?,
+                }
+            }
+            for &j in &applying {
+                commands[j].error = Some("transaction failed".to_string());
+            }
+            commands[i].error = Some(format!("failed to update ref: {error}"));
+            return Ok(());
+        }
+        applied.push(i);
+    }
+
+    if !applied.is_empty() {
+        // The push has already happened, so a failing hook changes nothing.
+        run_hook(
+            repository,
+            "post-receive",
+            &[],
+            &[],
+            hook_input(commands, &applied).as_bytes(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Why `update` cannot be applied, if it cannot: the client's idea of the
+/// ref is out of date, or the repository's config forbids the change.
+fn check_command(
+    repository: &Repository,
+    config: &Config,
+    update: &RefUpdate,
+) -> Result<Option<String>> {
+    let refuses = |key: &str, default: bool| {
+        config.get(key).map_or(default, |value| {
+            !matches!(value, "false" | "no" | "off" | "0" | "ignore" | "warn")
+        })
+    };
+
+    if !update.name.starts_with("refs/") {
+        return Ok(Some("funny refname".to_string()));
+    }
+    if repository.read_ref(&update.name)? != update.old {
+        return Ok(Some("failed to lock".to_string()));
+    }
+
+    let current_branch = repository.head_ref()?.as_deref() == Some(update.name.as_str());
+
+    let Some(new) = update.new else {
+        if refuses("receive.denydeletes", false) {
+            return Ok(Some("deletion prohibited".to_string()));
+        }
+        if current_branch && refuses("receive.denydeletecurrent", true) {
+            return Ok(Some(
+                "deletion of the current branch prohibited".to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+
+    if !repository.has_object(&new)? {
+        return Ok(Some("missing necessary objects".to_string()));
+    }
+    if current_branch && !repository.is_bare() && refuses("receive.denycurrentbranch", true) {
+        return Ok(Some("branch is currently checked out".to_string()));
+    }
+    if let Some(old) = update.old
+        && refuses("receive.denynonfastforwards", false)
+        && !is_ancestor(repository, &old, &new)?
+    {
+        return Ok(Some("non-fast-forward".to_string()));
+    }
+
+    Ok(None)
+}
+
+/// The `<old> <new> <ref>` lines that `pre-receive` and `post-receive`
+/// read on stdin.
+fn hook_input(commands: &[Command], indices: &[usize]) -> String {
+    indices
+        .iter()
+        .map(|&i| {
+            let update = &commands[i].update;
+            format!(
+                "{} {} {}\n",
+                hex_or_zero(update.old),
+                hex_or_zero(update.new),
+                update.name
+            )
+        })
+        .collect()
+}
+
+fn hex_or_zero(hash: Option<[u8; 20]>) -> String {
+    encode(hash.unwrap_or([0u8; 20]))
+}
+
+/// Writes every ref with the capabilities on the first line. An empty
+/// repository advertises only its capabilities, on a placeholder line.
+fn write_ref_advertisement(repository: &Repository, output: &mut impl Write) -> Result<()> {
+    let refs = repository.list_refs()?;
+    let capabilities = CAPABILITIES.join(" ");
+
+    if refs.is_empty() {
+        let line = format!("{} capabilities^{{}}\0{capabilities}\n", encode([0u8; 20]));
+        write_pkt_line(output, line.as_bytes())?;
+        return write_flush(output);
+    }
+
+    for (i, (name, hash)) in refs.iter().enumerate() {
+        let line = if i == 0 {
+            format!("{} {name}\0{capabilities}\n", encode(hash))
+        } else {
+            format!("{} {name}\n", encode(hash))
+        };
+        write_pkt_line(output, line.as_bytes())?;
+    }
+
+    write_flush(output)
+}
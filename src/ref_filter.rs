@@ -0,0 +1,74 @@
+use anyhow::Result;
+use hex::encode;
+use std::collections::HashMap;
+
+use crate::{Repository, upload_pack::peel_tag, wildmatch::wildmatch};
+
+/// Which refs point at each object. An annotated tag's ref points both at
+/// the tag and at the object it tags, so that asking what points at a
+/// release commit finds its tags too.
+pub struct RefTargets {
+    by_target: HashMap<[u8; 20], Vec<String>>,
+}
+
+impl RefTargets {
+    pub fn load(repository: &Repository) -> Result<Self> {
+        let mut by_target: HashMap<[u8; 20], Vec<String>> = HashMap::new();
+        for (name, hash) in repository.list_refs()? {
+            if let Some(target) = peel_tag(repository, &hash)? {
+                by_target.entry(target).or_default().push(name.clone());
+            }
+            by_target.entry(hash).or_default().push(name);
+        }
+
+        Ok(RefTargets { by_target })
+    }
+
+    /// The refs pointing at `target`, directly or through a tag.
+    pub fn pointing_at(&self, target: &[u8; 20]) -> &[String] {
+        self.by_target.get(target).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Lists the refs matching any of `patterns`, or every ref, as
+/// `<hash> <type>\t<name>`. With `points_at`, only the refs pointing at
+/// that object are listed.
+pub fn handle_for_each_ref_command(
+    patterns: Vec<String>,
+    points_at: Option<String>,
+    repository: &Repository,
+) -> Result<()> {
+    let selected = match points_at {
+        Some(object) => {
+            let target = repository.resolve_object(&object)?;
+            Some(RefTargets::load(repository)?.pointing_at(&target).to_vec())
+        }
+        None => None,
+    };
+
+    for (name, hash) in repository.list_refs()? {
+        if !patterns.is_empty() && !patterns.iter().any(|pattern| matches_ref(pattern, &name)) {
+            continue;
+        }
+        if selected
+            .as_ref()
+            .is_some_and(|selected| !selected.contains(&name))
+        {
+            continue;
+        }
+
+        let (object_type, _) = repository.read_raw_object(&encode(hash))?;
+        println!("{} {object_type}\t{name}", encode(hash));
+    }
+
+    Ok(())
+}
+
+/// Whether `pattern` matches the ref `name` the way for-each-ref takes
+/// patterns: as a glob, or literally, in whole or up to a `/`.
+fn matches_ref(pattern: &str, name: &str) -> bool {
+    match name.strip_prefix(pattern) {
+        Some(rest) => rest.is_empty() || pattern.ends_with('/') || rest.starts_with('/'),
+        None => wildmatch(pattern, name, true),
+    }
+}
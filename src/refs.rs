@@ -0,0 +1,752 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::DateTime;
+use hex::{decode_to_slice, encode};
+use std::{
+    cell::Ref,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Repository, config::Config, ident::Ident, revision::is_revision_expression,
+    upload_pack::peel_tag,
+};
+
+impl Repository {
+    pub fn head_ref(&self) -> Result<Option<String>> {
+        let head_file = self.git_dir.join("HEAD");
+        let head = fs::read_to_string(&head_file)
+            .with_context(|| format!("Failed to read HEAD file {}", head_file.display()))?;
+
+        Ok(head
+            .trim()
+            .strip_prefix("ref: ")
+            .map(|target| target.to_string()))
+    }
+
+    /// What `ref_name` points to. A ref outside `refs/`, such as `HEAD`,
+    /// may be a symbolic ref, which is followed.
+    pub fn read_ref(&self, ref_name: &str) -> Result<Option<[u8; 20]>> {
+        if ref_name.starts_with("refs/") {
+            return Ok(self.ref_cache()?.get(ref_name).copied());
+        }
+
+        let ref_file = self.ref_path(ref_name);
+        if let Ok(content) = fs::read_to_string(&ref_file)
+            && let Some(target) = content.trim().strip_prefix("ref: ")
+        {
+            return self.read_ref(target);
+        }
+        read_ref_file(&ref_file)
+    }
+
+    pub fn write_ref(&self, ref_name: &str, sha1: &[u8; 20]) -> Result<()> {
+        let ref_file = self.ref_path(ref_name);
+        let old = self.read_ref(ref_name)?;
+
+        if let Some(parent) = ref_file.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+
+        fs::write(&ref_file, format!("{}\n", hex::encode(sha1)))
+            .with_context(|| format!("Failed to write ref {}", ref_file.display()))?;
+
+        if let Some(refs) = self.refs.borrow_mut().as_mut()
+            && ref_name.starts_with("refs/")
+        {
+            refs.insert(ref_name.to_string(), *sha1);
+        }
+
+        self.log_ref_update(ref_name, old, Some(*sha1))
+    }
+
+    pub fn delete_ref(&self, ref_name: &str) -> Result<()> {
+        let ref_file = self.ref_path(ref_name);
+
+        if ref_file.is_file() {
+            fs::remove_file(&ref_file)
+                .with_context(|| format!("Failed to delete ref {}", ref_file.display()))?;
+        }
+        if ref_name.starts_with("refs/") {
+            self.remove_packed_refs(&[ref_name])?;
+        }
+
+        if let Some(refs) = self.refs.borrow_mut().as_mut() {
+            refs.remove(ref_name);
+        }
+
+        self.log_ref_update(ref_name, None, None)
+    }
+
+    /// Records in the reflog of `ref_name` that it moved from `old` to
+    /// `new`, which is what `@{N}` looks back through. As in git, only
+    /// `HEAD`, branches and the stash are logged, and a move of the branch
+    /// `HEAD` is on is logged for `HEAD` too. Deleting a ref deletes its
+    /// reflog.
+    pub fn log_ref_update(
+        &self,
+        ref_name: &str,
+        old: Option<[u8; 20]>,
+        new: Option<[u8; 20]>,
+    ) -> Result<()> {
+        if ref_name != "HEAD" && ref_name != "refs/stash" && !ref_name.starts_with("refs/heads/") {
+            return Ok(());
+        }
+        let Some(new) = new else {
+            let reflog_file = self.reflog_path(ref_name);
+            if reflog_file.is_file() {
+                fs::remove_file(&reflog_file)
+                    .with_context(|| format!("Failed to delete {}", reflog_file.display()))?;
+            }
+            return Ok(());
+        };
+        // Without an identity to record there is no entry to write; the
+        // ref itself has still moved.
+        let Ok(committer) = Config::load(self).and_then(|config| Ident::committer(&config)) else {
+            return Ok(());
+        };
+
+        let entry = format!(
+            "{} {} {committer}\n",
+            encode(old.unwrap_or([0; 20])),
+            encode(new)
+        );
+        let on_branch = self.head_ref().ok().flatten().as_deref() == Some(ref_name);
+        for name in std::iter::once(ref_name).chain(on_branch.then_some("HEAD")) {
+            let reflog_file = self.reflog_path(name);
+            if let Some(parent) = reflog_file.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&reflog_file)
+                .and_then(|mut file| file.write_all(entry.as_bytes()))
+                .with_context(|| format!("Failed to write {}", reflog_file.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Where the reflog of `ref_name` is kept, beside the ref itself under
+    /// `logs/`.
+    pub fn reflog_path(&self, ref_name: &str) -> PathBuf {
+        if ref_name.starts_with("refs/") {
+            self.mini_git_dir.join("logs").join(ref_name)
+        } else {
+            self.git_dir.join("logs").join(ref_name)
+        }
+    }
+
+    /// Starts a transaction to change several refs at once.
+    pub fn transaction(&self) -> RefTransaction<'_> {
+        RefTransaction {
+            repository: self,
+            updates: Vec::new(),
+        }
+    }
+
+    /// Lists every ref under `refs/` with the object it points to, sorted by
+    /// name.
+    pub fn list_refs(&self) -> Result<Vec<(String, [u8; 20])>> {
+        Ok(self
+            .ref_cache()?
+            .iter()
+            .map(|(name, sha1)| (name.clone(), *sha1))
+            .collect())
+    }
+
+    /// Forgets the refs read so far, for when something other than
+    /// `write_ref` and `delete_ref` may have changed them: a hook, or refs
+    /// being packed.
+    pub fn invalidate_refs(&self) {
+        self.refs.borrow_mut().take();
+    }
+
+    /// The refs under `refs/`, read from disk all at once the first time
+    /// they are needed and kept up to date by `write_ref` and `delete_ref`,
+    /// so that commands looking up many refs do not go to the filesystem
+    /// for each one. A loose ref takes precedence over a packed one.
+    fn ref_cache(&self) -> Result<Ref<'_, BTreeMap<String, [u8; 20]>>> {
+        if self.refs.borrow().is_none() {
+            let mut refs = self.read_packed_refs()?;
+            let mut symbolic = BTreeMap::new();
+            for (name, content) in self.loose_ref_files()? {
+                match content.strip_prefix("ref: ") {
+                    Some(target) => {
+                        symbolic.insert(name, target.to_string());
+                    }
+                    None => {
+                        if let Some(hash) = parse_loose_ref(&name, &content) {
+                            refs.insert(name, hash);
+                        }
+                    }
+                }
+            }
+
+            // A symbolic ref such as `refs/remotes/origin/HEAD` has the
+            // value of the ref it points to, followed a few levels deep;
+            // one that leads nowhere is left out.
+            for (name, target) in &symbolic {
+                let mut target = target;
+                for _ in 0..5 {
+                    if let Some(&hash) = refs.get(target) {
+                        refs.insert(name.clone(), hash);
+                        break;
+                    }
+                    match symbolic.get(target) {
+                        Some(next) => target = next,
+                        None => break,
+                    }
+                }
+            }
+            *self.refs.borrow_mut() = Some(refs);
+        }
+
+        Ok(Ref::map(self.refs.borrow(), |refs| {
+            refs.as_ref().expect("ref cache was just filled")
+        }))
+    }
+
+    /// The refs stored as files of their own under `refs/`. Symbolic refs
+    /// are left out.
+    pub fn read_loose_refs(&self) -> Result<BTreeMap<String, [u8; 20]>> {
+        Ok(self
+            .loose_ref_files()?
+            .into_iter()
+            .filter(|(_, content)| !content.starts_with("ref: "))
+            .filter_map(|(name, content)| Some((name.clone(), parse_loose_ref(&name, &content)?)))
+            .collect())
+    }
+
+    /// The files under `refs/`, as ref names and their trimmed content.
+    fn loose_ref_files(&self) -> Result<Vec<(String, String)>> {
+        let mut refs = Vec::new();
+        let mut pending = vec![self.mini_git_dir.join("refs")];
+
+        while let Some(dir) = pending.pop() {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)
+                .with_context(|| format!("Failed to read directory {}", dir.display()))?
+            {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                // A ref being changed by a transaction.
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "lock")
+                {
+                    continue;
+                }
+
+                let Ok(name) = path.strip_prefix(&self.mini_git_dir) else {
+                    continue;
+                };
+                let content = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read ref {}", path.display()))?;
+                refs.push((
+                    name.to_string_lossy().replace('\\', "/"),
+                    content.trim().to_string(),
+                ));
+            }
+        }
+
+        Ok(refs)
+    }
+
+    pub fn resolve_head(&self) -> Result<Option<[u8; 20]>> {
+        match self.head_ref()? {
+            Some(target) => self.read_ref(&target),
+            None => {
+                let head = fs::read_to_string(self.git_dir.join("HEAD"))?;
+                parse_hash(head.trim()).map(Some)
+            }
+        }
+    }
+
+    pub fn update_head(&self, sha1: &[u8; 20]) -> Result<()> {
+        match self.head_ref()? {
+            Some(target) => self.write_ref(&target, sha1),
+            None => {
+                let old = self.resolve_head()?;
+                fs::write(
+                    self.git_dir.join("HEAD"),
+                    format!("{}\n", hex::encode(sha1)),
+                )
+                .context("Failed to write HEAD")?;
+                self.log_ref_update("HEAD", old, Some(*sha1))
+            }
+        }
+    }
+
+    /// Where the ref `ref_name` is stored: refs under `refs/` are shared by
+    /// every working tree, while others such as `ORIG_HEAD` belong to the
+    /// current one.
+    fn ref_path(&self, ref_name: &str) -> PathBuf {
+        if ref_name.starts_with("refs/") {
+            self.mini_git_dir.join(ref_name)
+        } else {
+            self.git_dir.join(ref_name)
+        }
+    }
+
+    /// The commit `name` stands for, peeling annotated tags down to the
+    /// commit they point to, as git does wherever it wants a commit.
+    pub fn resolve_commitish(&self, name: &str) -> Result<[u8; 20]> {
+        let hash = self.resolve_object(name)?;
+        Ok(peel_tag(self, &hash)?.unwrap_or(hash))
+    }
+
+    /// The object `name` stands for, with an annotated tag left as the tag
+    /// itself, for commands that act on the object named.
+    pub fn resolve_object(&self, name: &str) -> Result<[u8; 20]> {
+        if name == "HEAD" {
+            return self
+                .resolve_head()?
+                .ok_or_else(|| anyhow!("fatal: HEAD does not point to a commit"));
+        }
+
+        self.resolve_object_name(name)?
+            .ok_or_else(|| anyhow!("fatal: '{}' is not a known branch or commit", name))
+    }
+
+    /// The object `name` stands for, looked up the way git does: as a ref
+    /// written out in full or a pseudo-ref such as `ORIG_HEAD`, then under
+    /// `refs/`, `refs/tags/`, `refs/heads/` and `refs/remotes/`, then as the
+    /// `HEAD` of a remote, and last as an object id or a prefix of one.
+    /// A name more than one ref answers to is warned about. Revision
+    /// expressions such as `HEAD~2` are resolved by `resolve_revision`.
+    pub fn resolve_object_name(&self, name: &str) -> Result<Option<[u8; 20]>> {
+        if is_revision_expression(name) {
+            return self.resolve_revision(name);
+        }
+
+        let mut found = None;
+        for candidate in ref_candidates(name) {
+            if let Some(sha1) = self.read_ref(&candidate)? {
+                if found.is_some() {
+                    eprintln!("warning: refname '{name}' is ambiguous.");
+                    break;
+                }
+                found = Some(sha1);
+            }
+        }
+        if found.is_some() {
+            return Ok(found);
+        }
+
+        self.resolve_object_prefix(name)
+    }
+
+    /// The full name of the ref `name` stands for, looked up as in
+    /// `resolve_object_name`.
+    pub fn full_ref_name(&self, name: &str) -> Result<Option<String>> {
+        for candidate in ref_candidates(name) {
+            if self.read_ref(&candidate)?.is_some() {
+                return Ok(Some(candidate));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The object whose id starts with `prefix`, 4 to 40 hex digits, found
+    /// among the loose objects and in the packs. A full id is taken as it
+    /// is. `None` when no object matches, or `prefix` is not hex; an error
+    /// listing the candidates when several do.
+    pub fn resolve_object_prefix(&self, prefix: &str) -> Result<Option<[u8; 20]>> {
+        if !(4..=40).contains(&prefix.len()) || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() == 40 {
+            return parse_hash(&prefix).map(Some);
+        }
+
+        let mut candidates = BTreeSet::new();
+        let (dir, rest) = prefix.split_at(2);
+        if let Ok(entries) = fs::read_dir(self.objects_dir.join(dir)) {
+            for entry in entries {
+                let name = entry?.file_name();
+                let name = name.to_string_lossy();
+                if name.len() == 38
+                    && name.starts_with(rest)
+                    && let Ok(sha1) = parse_hash(&format!("{dir}{name}"))
+                {
+                    candidates.insert(sha1);
+                }
+            }
+        }
+        for pack in self.packs()? {
+            candidates.extend(pack.index.with_prefix(&prefix));
+        }
+
+        if candidates.len() > 1 {
+            return Err(self.ambiguous_prefix(&prefix, &candidates));
+        }
+        Ok(candidates.pop_first())
+    }
+
+    /// The error for a prefix several objects share, listing them as git
+    /// does: tags, then commits, trees and blobs, each abbreviated just far
+    /// enough to tell them apart.
+    fn ambiguous_prefix(&self, prefix: &str, candidates: &BTreeSet<[u8; 20]>) -> anyhow::Error {
+        let hexes: Vec<String> = candidates.iter().map(encode).collect();
+        let length = (prefix.len().max(7)..40)
+            .find(|&length| {
+                let abbreviated: HashSet<&str> = hexes.iter().map(|hex| &hex[..length]).collect();
+                abbreviated.len() == hexes.len()
+            })
+            .unwrap_or(40);
+
+        let mut lines = Vec::new();
+        for hex in &hexes {
+            let abbreviated = &hex[..length];
+            let line = match self.read_raw_object(hex) {
+                Ok((object_type, content)) => {
+                    let rank = ["tag", "commit", "tree", "blob"]
+                        .iter()
+                        .position(|kind| *kind == object_type)
+                        .unwrap_or(4);
+                    (
+                        rank,
+                        describe_candidate(abbreviated, &object_type, &content),
+                    )
+                }
+                Err(_) => (5, format!("{abbreviated} [bad object]")),
+            };
+            lines.push(line);
+        }
+        lines.sort();
+
+        let mut message =
+            format!("error: short object ID {prefix} is ambiguous\nhint: The candidates are:\n");
+        for (_, line) in lines {
+            message.push_str(&format!("hint:   {line}\n"));
+        }
+        message.push_str(&format!("fatal: Not a valid object name {prefix}"));
+        anyhow!(message)
+    }
+}
+
+/// The refs `name` may be short for, in the order git tries them.
+fn ref_candidates(name: &str) -> impl Iterator<Item = String> {
+    let pseudo_ref = !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+    let exact = (pseudo_ref || name.starts_with("refs/")).then(|| name.to_string());
+
+    exact.into_iter().chain([
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ])
+}
+
+/// One candidate of an ambiguous prefix: its id and type, with the date and
+/// subject of a commit, or the name of a tag.
+fn describe_candidate(abbreviated: &str, object_type: &str, content: &[u8]) -> String {
+    let text = String::from_utf8_lossy(content);
+    let header = |key: &str| {
+        text.lines()
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+    };
+    let date = |key: &str| {
+        header(key)
+            .and_then(|line| Ident::parse(line).ok())
+            .and_then(|ident| {
+                DateTime::from_timestamp(ident.timestamp, 0).map(|date| {
+                    date.with_timezone(&ident.offset())
+                        .format("%Y-%m-%d")
+                        .to_string()
+                })
+            })
+            .unwrap_or_default()
+    };
+
+    match object_type {
+        "commit" => {
+            let subject = text
+                .split_once("\n\n")
+                .and_then(|(_, body)| body.lines().next())
+                .unwrap_or_default();
+            format!("{abbreviated} commit {} - {subject}", date("committer"))
+        }
+        "tag" => format!(
+            "{abbreviated} tag {} - {}",
+            date("tagger"),
+            header("tag").unwrap_or_default()
+        ),
+        other => format!("{abbreviated} {other}"),
+    }
+}
+
+/// What a ref must point to for a `RefTransaction` to go ahead.
+#[derive(Clone, Copy)]
+pub enum Expected {
+    Any,
+    Missing,
+    At([u8; 20]),
+}
+
+/// A queued change to one ref. `new` is `None` for a deletion, and
+/// `verify_only` leaves the ref as it is once checked.
+struct QueuedUpdate {
+    name: String,
+    new: Option<[u8; 20]>,
+    old: Expected,
+    verify_only: bool,
+}
+
+/// Changes to several refs made together or not at all.
+///
+/// Committing locks every ref by creating `<ref>.lock` beside it, which
+/// fails if another process holds the lock, and checks under the locks
+/// that each ref is still where it is expected to be. Only once all of
+/// that has succeeded are the new values moved into place, so a failed
+/// transaction leaves every ref untouched.
+pub struct RefTransaction<'a> {
+    repository: &'a Repository,
+    updates: Vec<QueuedUpdate>,
+}
+
+impl RefTransaction<'_> {
+    /// Queues pointing `name` at `new`, or deleting it if `new` is `None`.
+    /// `HEAD` stands for the branch it is on, if any.
+    pub fn update(&mut self, name: &str, new: Option<[u8; 20]>, old: Expected) -> Result<()> {
+        self.queue(name, new, old, false)
+    }
+
+    /// Queues a check that `name` is as `old` expects, changing nothing.
+    pub fn verify(&mut self, name: &str, old: Expected) -> Result<()> {
+        self.queue(name, None, old, true)
+    }
+
+    fn queue(
+        &mut self,
+        name: &str,
+        new: Option<[u8; 20]>,
+        old: Expected,
+        verify_only: bool,
+    ) -> Result<()> {
+        let name = match self.repository.head_ref()? {
+            Some(branch) if name == "HEAD" => branch,
+            _ => name.to_string(),
+        };
+        if !check_ref_format(&name) {
+            return Err(anyhow!("fatal: invalid ref format: {name}"));
+        }
+        if let Some(new) = new
+            && !self.repository.has_object(&new)?
+        {
+            return Err(anyhow!(
+                "fatal: cannot update ref '{name}': trying to write ref '{name}' with nonexistent object {}",
+                hex::encode(new)
+            ));
+        }
+        if self.updates.iter().any(|update| update.name == name) {
+            return Err(anyhow!(
+                "fatal: multiple updates for ref '{name}' not allowed"
+            ));
+        }
+
+        self.updates.push(QueuedUpdate {
+            name,
+            new,
+            old,
+            verify_only,
+        });
+        Ok(())
+    }
+
+    pub fn commit(self) -> Result<()> {
+        let mut locks = Vec::new();
+        let result = self.lock_all(&mut locks).and_then(|()| self.apply(&locks));
+        if result.is_err() {
+            for lock in &locks {
+                let _ = fs::remove_file(lock);
+            }
+        }
+        result
+    }
+
+    /// Takes the lock of every ref, in order, and checks its value.
+    fn lock_all(&self, locks: &mut Vec<PathBuf>) -> Result<()> {
+        for update in &self.updates {
+            let ref_file = self.repository.ref_path(&update.name);
+            let lock = lock_path(&ref_file);
+            let cannot_lock =
+                |reason: String| anyhow!("fatal: cannot lock ref '{}': {reason}", update.name);
+
+            if let Some(parent) = ref_file.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|error| cannot_lock(format!("{}: {error}", parent.display())))?;
+            }
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(file) => file,
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    return Err(cannot_lock(format!(
+                        "Unable to create '{}': File exists.",
+                        lock.display()
+                    )));
+                }
+                Err(error) => {
+                    return Err(cannot_lock(format!(
+                        "Unable to create '{}': {error}",
+                        lock.display()
+                    )));
+                }
+            };
+            locks.push(lock.clone());
+
+            let current = match read_ref_file(&ref_file)? {
+                Some(current) => Some(current),
+                None if update.name.starts_with("refs/") => self
+                    .repository
+                    .read_packed_refs()?
+                    .get(&update.name)
+                    .copied(),
+                None => None,
+            };
+            match (update.old, current) {
+                (Expected::Missing, Some(_)) => {
+                    return Err(cannot_lock("reference already exists".to_string()));
+                }
+                (Expected::At(_), None) => {
+                    return Err(cannot_lock(format!(
+                        "unable to resolve reference '{}'",
+                        update.name
+                    )));
+                }
+                (Expected::At(old), Some(current)) if old != current => {
+                    return Err(cannot_lock(format!(
+                        "is at {} but expected {}",
+                        hex::encode(current),
+                        hex::encode(old)
+                    )));
+                }
+                _ => {}
+            }
+
+            if let (Some(new), false) = (update.new, update.verify_only) {
+                writeln!(file, "{}", hex::encode(new))
+                    .with_context(|| format!("Failed to write {}", lock.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the locked values into place and releases the locks. Deleted
+    /// refs are dropped from `packed-refs` last, in one rewrite.
+    fn apply(&self, locks: &[PathBuf]) -> Result<()> {
+        let mut deleted = Vec::new();
+        for (update, lock) in self.updates.iter().zip(locks) {
+            let ref_file = self.repository.ref_path(&update.name);
+            if update.verify_only {
+                fs::remove_file(lock)
+                    .with_context(|| format!("Failed to remove {}", lock.display()))?;
+                continue;
+            }
+
+            match update.new {
+                Some(new) => {
+                    let old = read_ref_file(&ref_file)?;
+                    fs::rename(lock, &ref_file)
+                        .with_context(|| format!("Failed to write ref {}", ref_file.display()))?;
+                    self.repository
+                        .log_ref_update(&update.name, old, Some(new))?;
+                    if let Some(refs) = self.repository.refs.borrow_mut().as_mut()
+                        && update.name.starts_with("refs/")
+                    {
+                        refs.insert(update.name.clone(), new);
+                    }
+                }
+                None => {
+                    if ref_file.is_file() {
+                        fs::remove_file(&ref_file).with_context(|| {
+                            format!("Failed to delete ref {}", ref_file.display())
+                        })?;
+                    }
+                    fs::remove_file(lock)
+                        .with_context(|| format!("Failed to remove {}", lock.display()))?;
+                    if let Some(refs) = self.repository.refs.borrow_mut().as_mut() {
+                        refs.remove(&update.name);
+                    }
+                    self.repository.log_ref_update(&update.name, None, None)?;
+                    deleted.push(update.name.as_str());
+                }
+            }
+        }
+
+        self.repository.remove_packed_refs(&deleted)
+    }
+}
+
+fn lock_path(ref_file: &Path) -> PathBuf {
+    let mut lock = ref_file.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// The value of the ref stored in `ref_file`, if there is one.
+fn read_ref_file(ref_file: &Path) -> Result<Option<[u8; 20]>> {
+    if !ref_file.is_file() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(ref_file)
+        .with_context(|| format!("Failed to read ref {}", ref_file.display()))?;
+
+    // `FETCH_HEAD` follows the id with what it was fetched from, and lists
+    // every ref fetched; the first is the one meant.
+    parse_hash(content.split_whitespace().next().unwrap_or_default()).map(Some)
+}
+
+/// Whether `name` is a well-formed ref name, by git's rules: no component
+/// starts with `.` or ends with `.lock`, and there is no `..`, `@{`,
+/// `//`, control character, space or any of `~^:?*[\`. Refs are files,
+/// so this also keeps them inside the repository.
+pub fn check_ref_format(name: &str) -> bool {
+    !name.is_empty()
+        && name != "@"
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.chars().any(|c| {
+            c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+        })
+        && name.split('/').all(|component| {
+            !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+        })
+}
+
+pub fn parse_hash(hex_str: &str) -> Result<[u8; 20]> {
+    let mut sha1 = [0u8; 20];
+    decode_to_slice(hex_str, &mut sha1)
+        .map_err(|e| anyhow!("Invalid object hash '{}': {}", hex_str, e))?;
+
+    Ok(sha1)
+}
+
+/// The value of the loose ref `name`, or `None`, with a warning as git
+/// gives, when its file does not hold an object id.
+fn parse_loose_ref(name: &str, content: &str) -> Option<[u8; 20]> {
+    match parse_hash(content) {
+        Ok(hash) => Some(hash),
+        Err(_) => {
+            eprintln!("warning: ignoring broken ref {name}");
+            None
+        }
+    }
+}
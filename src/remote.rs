@@ -0,0 +1,342 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Subcommand;
+use std::fs;
+
+use crate::{
+    Repository,
+    config::Config,
+    fetch::handle_fetch_command,
+    messages::{tr, tr_n},
+    refs::Expected,
+    transport::{remote_urls, rewrite_url, short_ref_name},
+};
+
+#[derive(Subcommand, Debug)]
+pub enum RemoteCommands {
+    Add {
+        /// Fetch from the new remote right away
+        #[arg(short = 'f')]
+        fetch: bool,
+        /// Only track the given branch instead of all of them
+        #[arg(short = 't')]
+        track: Vec<String>,
+        name: String,
+        url: String,
+    },
+    #[command(visible_alias = "rm")]
+    Remove {
+        name: String,
+    },
+    Rename {
+        old: String,
+        new: String,
+    },
+    Show {
+        name: String,
+    },
+    GetUrl {
+        #[arg(long)]
+        push: bool,
+        #[arg(long)]
+        all: bool,
+        name: String,
+    },
+    SetUrl {
+        #[arg(long)]
+        push: bool,
+        #[arg(long, conflicts_with = "delete")]
+        add: bool,
+        #[arg(long)]
+        delete: bool,
+        name: String,
+        new_url: String,
+        old_url: Option<String>,
+    },
+}
+
+pub fn handle_remote_command(
+    command: Option<RemoteCommands>,
+    verbose: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let config = Config::load(repository)?;
+
+    let Some(command) = command else {
+        for name in remote_names(&config) {
+            if verbose {
+                println!("{name}\t{} (fetch)", remote_urls(&config, &name, false)[0]);
+                for url in remote_urls(&config, &name, true) {
+                    println!("{name}\t{url} (push)");
+                }
+            } else {
+                println!("{name}");
+            }
+        }
+        return Ok(());
+    };
+
+    match command {
+        RemoteCommands::Add {
+            fetch,
+            track,
+            name,
+            url,
+        } => {
+            if remote_names(&config).contains(&name) {
+                return Err(anyhow!("error: remote {} already exists.", name));
+            }
+            if name.is_empty()
+                || name.contains(|c: char| c.is_whitespace() || ":*?[\\^~".contains(c))
+            {
+                return Err(anyhow!("fatal: '{}' is not a valid remote name", name));
+            }
+
+            let refspecs: Vec<String> = if track.is_empty() {
+                vec![format!("+refs/heads/*:refs/remotes/{name}/*")]
+            } else {
+                track
+                    .iter()
+                    .map(|branch| format!("+refs/heads/{branch}:refs/remotes/{name}/{branch}"))
+                    .collect()
+            };
+            let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+
+            Config::set_all(repository, &format!("remote.{name}.url"), &[&url])?;
+            Config::set_all(repository, &format!("remote.{name}.fetch"), &refspecs)?;
+
+            if fetch {
+                println!("{}", tr!("Updating {}", name));
+                handle_fetch_command(Some(name), Vec::new(), repository)?;
+            }
+        }
+        RemoteCommands::Remove { name } => {
+            ensure_remote(&config, &name)?;
+
+            for branch in tracking_branches(&config, &name) {
+                Config::set_all(repository, &format!("branch.{branch}.remote"), &[])?;
+                Config::set_all(repository, &format!("branch.{branch}.merge"), &[])?;
+            }
+            Config::rename_section(repository, &format!("remote.{name}"), None)?;
+
+            let mut transaction = repository.transaction();
+            for (branch, _) in remote_refs(repository, &name)? {
+                transaction.update(
+                    &format!("refs/remotes/{name}/{branch}"),
+                    None,
+                    Expected::Any,
+                )?;
+            }
+            transaction.commit()?;
+            remove_refs_dir(repository, &name)?;
+        }
+        RemoteCommands::Rename { old, new } => {
+            ensure_remote(&config, &old)?;
+            if remote_names(&config).contains(&new) {
+                return Err(anyhow!("error: remote {} already exists.", new));
+            }
+
+            Config::rename_section(
+                repository,
+                &format!("remote.{old}"),
+                Some(&format!("remote.{new}")),
+            )?;
+
+            // Default refspecs follow the remote's name; custom ones are kept.
+            let old_prefix = format!(":refs/remotes/{old}/");
+            let new_prefix = format!(":refs/remotes/{new}/");
+            let refspecs: Vec<String> = config
+                .get_all(&format!("remote.{old}.fetch"))
+                .into_iter()
+                .map(|spec| spec.replace(&old_prefix, &new_prefix))
+                .collect();
+            let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+            Config::set_all(repository, &format!("remote.{new}.fetch"), &refspecs)?;
+
+            for branch in tracking_branches(&config, &old) {
+                Config::set_all(repository, &format!("branch.{branch}.remote"), &[&new])?;
+            }
+
+            let mut transaction = repository.transaction();
+            for (branch, sha1) in remote_refs(repository, &old)? {
+                transaction.update(
+                    &format!("refs/remotes/{new}/{branch}"),
+                    Some(sha1),
+                    Expected::Missing,
+                )?;
+                transaction.update(
+                    &format!("refs/remotes/{old}/{branch}"),
+                    None,
+                    Expected::At(sha1),
+                )?;
+            }
+            transaction.commit()?;
+            remove_refs_dir(repository, &old)?;
+        }
+        RemoteCommands::Show { name } => {
+            ensure_remote(&config, &name)?;
+
+            println!("* remote {name}");
+            println!("  Fetch URL: {}", remote_urls(&config, &name, false)[0]);
+            for url in remote_urls(&config, &name, true) {
+                println!("  Push  URL: {url}");
+            }
+
+            let prefix = format!("refs/remotes/{name}/");
+            let branches: Vec<String> = repository
+                .list_refs()?
+                .into_iter()
+                .filter_map(|(ref_name, _)| ref_name.strip_prefix(&prefix).map(str::to_string))
+                .collect();
+            match branches.as_slice() {
+                [] => {}
+                [branch] => println!("{}", tr!("  Remote branch:\n    {}", branch)),
+                _ => {
+                    println!("{}", tr!("  Remote branches:"));
+                    for branch in &branches {
+                        println!("    {branch}");
+                    }
+                }
+            }
+
+            let merges: Vec<(String, String)> = tracking_branches(&config, &name)
+                .into_iter()
+                .filter_map(|branch| {
+                    let merge = config.get(&format!("branch.{branch}.merge"))?;
+                    Some((branch, short_ref_name(merge).to_string()))
+                })
+                .collect();
+            if !merges.is_empty() {
+                println!(
+                    "{}",
+                    tr_n!(
+                        merges.len(),
+                        "  Local branch configured for 'mini-git pull':",
+                        "  Local branches configured for 'mini-git pull':"
+                    )
+                );
+                for (branch, merge) in merges {
+                    println!("{}", tr!("    {} merges with remote {}", branch, merge));
+                }
+            }
+        }
+        RemoteCommands::GetUrl { push, all, name } => {
+            ensure_remote(&config, &name)?;
+
+            let urls = remote_urls(&config, &name, push);
+            let urls = if all { &urls[..] } else { &urls[..1] };
+            for url in urls {
+                println!("{}", rewrite_url(&config, url, push));
+            }
+        }
+        RemoteCommands::SetUrl {
+            push,
+            add,
+            delete,
+            name,
+            new_url,
+            old_url,
+        } => {
+            ensure_remote(&config, &name)?;
+
+            let key = if push {
+                format!("remote.{name}.pushurl")
+            } else {
+                format!("remote.{name}.url")
+            };
+            let mut urls: Vec<&str> = config.get_all(&key);
+
+            if add {
+                urls.push(&new_url);
+            } else if delete {
+                let remaining: Vec<&str> = urls
+                    .iter()
+                    .copied()
+                    .filter(|url| !url.contains(new_url.as_str()))
+                    .collect();
+                if remaining.len() == urls.len() {
+                    return Err(anyhow!("fatal: No such URL found: {}", new_url));
+                }
+                if remaining.is_empty() && !push {
+                    return Err(anyhow!("fatal: Will not delete all non-push URLs"));
+                }
+                urls = remaining;
+            } else {
+                match old_url {
+                    Some(old_url) => {
+                        let position =
+                            urls.iter()
+                                .position(|url| url.contains(old_url.as_str()))
+                                .ok_or_else(|| anyhow!("fatal: No such URL found: {}", old_url))?;
+                        urls[position] = &new_url;
+                    }
+                    None => urls = vec![&new_url],
+                }
+            }
+
+            Config::set_all(repository, &key, &urls)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The remote-tracking refs of `remote`, by the branch they track. Some may
+/// be packed, so they are not simply the files under its directory.
+fn remote_refs(repository: &Repository, remote: &str) -> Result<Vec<(String, [u8; 20])>> {
+    let prefix = format!("refs/remotes/{remote}/");
+    Ok(repository
+        .list_refs()?
+        .into_iter()
+        .filter_map(|(name, sha1)| Some((name.strip_prefix(&prefix)?.to_string(), sha1)))
+        .collect())
+}
+
+/// Removes the directory the refs of `remote` were in, once they are gone.
+fn remove_refs_dir(repository: &Repository, remote: &str) -> Result<()> {
+    let refs_dir = repository.mini_git_dir.join("refs/remotes").join(remote);
+    if refs_dir.is_dir() {
+        fs::remove_dir_all(&refs_dir)
+            .with_context(|| format!("Failed to remove {}", refs_dir.display()))?;
+    }
+    Ok(())
+}
+
+fn ensure_remote(config: &Config, name: &str) -> Result<()> {
+    let prefix = format!("remote.{name}.");
+    if config.entries().any(|(key, _)| key.starts_with(&prefix)) {
+        Ok(())
+    } else {
+        Err(anyhow!("error: No such remote '{}'", name))
+    }
+}
+
+/// Names of every configured remote, in the order they first appear.
+fn remote_names(config: &Config) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (key, _) in config.entries() {
+        let Some(name) = key
+            .strip_prefix("remote.")
+            .and_then(|rest| rest.rsplit_once('.'))
+            .map(|(name, _)| name)
+        else {
+            continue;
+        };
+        if !names.iter().any(|existing| existing == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Local branches whose `branch.<name>.remote` points at `remote`.
+fn tracking_branches(config: &Config, remote: &str) -> Vec<String> {
+    config
+        .entries()
+        .filter(|(_, value)| *value == remote)
+        .filter_map(|(key, _)| {
+            key.strip_prefix("branch.")?
+                .strip_suffix(".remote")
+                .map(str::to_string)
+        })
+        .collect()
+}
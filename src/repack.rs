@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use hex::encode;
+use std::{collections::HashSet, fs};
+
+use crate::{
+    Repository,
+    config::Config,
+    messages::tr,
+    pack::{PackOptions, write_pack},
+    pack_index::write_pack_index,
+    refs::parse_hash,
+    transport::reachable_objects,
+};
+
+impl Repository {
+    /// Deletes the loose copy of an object, along with its fan-out directory
+    /// once that is empty. Returns whether a loose copy existed.
+    pub fn remove_loose_object(&self, sha1: &[u8; 20]) -> Result<bool> {
+        let path = self.get_object_path(&encode(sha1))?;
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove object file {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+
+        Ok(true)
+    }
+
+    /// Lists the ids of all loose objects.
+    pub fn loose_objects(&self) -> Result<Vec<[u8; 20]>> {
+        let mut objects = Vec::new();
+
+        for dir in fs::read_dir(&self.objects_dir)
+            .with_context(|| format!("Failed to read {}", self.objects_dir.display()))?
+        {
+            let dir = dir?;
+            let prefix = dir.file_name().to_string_lossy().into_owned();
+            if prefix.len() != 2 || !dir.path().is_dir() {
+                continue;
+            }
+
+            for file in fs::read_dir(dir.path())? {
+                let suffix = file?.file_name().to_string_lossy().into_owned();
+                if let Ok(sha1) = parse_hash(&format!("{prefix}{suffix}")) {
+                    objects.push(sha1);
+                }
+            }
+        }
+
+        objects.sort();
+        Ok(objects)
+    }
+
+    /// Removes loose objects that are also stored in a pack, returning the
+    /// ones removed (or, with `dry_run`, the ones that would be).
+    pub fn prune_packed(&self, dry_run: bool) -> Result<Vec<[u8; 20]>> {
+        let mut pruned = Vec::new();
+
+        for sha1 in self.loose_objects()? {
+            if !self
+                .packs()?
+                .iter()
+                .any(|pack| pack.index.lookup(&sha1).is_some())
+            {
+                continue;
+            }
+            if !dry_run {
+                self.remove_loose_object(&sha1)?;
+            }
+            pruned.push(sha1);
+        }
+
+        Ok(pruned)
+    }
+
+    /// Every object a ref, HEAD, or the index points at.
+    pub fn reachability_roots(&self) -> Result<Vec<[u8; 20]>> {
+        let mut roots: Vec<[u8; 20]> = self
+            .list_refs()?
+            .into_iter()
+            .map(|(_, sha1)| sha1)
+            .collect();
+        roots.extend(self.resolve_head()?);
+        roots.extend(self.read_index()?.entries.iter().map(|entry| entry.sha1));
+
+        Ok(roots)
+    }
+}
+
+pub fn handle_repack_command(all: bool, delete: bool, repository: &Repository) -> Result<()> {
+    let packed: HashSet<[u8; 20]> = repository
+        .packs()?
+        .iter()
+        .flat_map(|pack| pack.index.entries.iter().map(|entry| entry.hash))
+        .collect();
+
+    let objects = reachable_objects(repository, &repository.reachability_roots()?, |hash| {
+        Ok(!all && packed.contains(hash))
+    })?;
+
+    if objects.is_empty() {
+        println!("{}", tr!("Nothing new to pack."));
+        return Ok(());
+    }
+
+    let mut pack = Vec::new();
+    let (checksum, index_entries) = write_pack(
+        repository,
+        &objects,
+        &PackOptions::from_config(&Config::load(repository)?)?,
+        &mut pack,
+    )?;
+
+    let pack_dir = repository.objects_dir.join("pack");
+    fs::create_dir_all(&pack_dir).context("Failed to create objects/pack directory")?;
+    let pack_path = pack_dir.join(format!("pack-{}.pack", encode(checksum)));
+    let idx_path = pack_path.with_extension("idx");
+
+    fs::write(&pack_path, &pack)
+        .with_context(|| format!("Failed to write pack file {}", pack_path.display()))?;
+    fs::write(&idx_path, write_pack_index(&index_entries, &checksum))
+        .with_context(|| format!("Failed to write index file {}", idx_path.display()))?;
+
+    if delete {
+        if all {
+            for entry in fs::read_dir(&pack_dir)
+                .with_context(|| format!("Failed to read {}", pack_dir.display()))?
+            {
+                let path = entry?.path();
+                let is_pack_file = path
+                    .extension()
+                    .is_some_and(|ext| ext == "pack" || ext == "idx");
+
+                if is_pack_file && path.file_stem() != pack_path.file_stem() {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to remove {}", path.display()))?;
+                }
+            }
+        }
+
+        // The pack list is cached, so look again to see the new pack.
+        Repository::at(repository.mini_git_dir.clone()).prune_packed(false)?;
+    }
+
+    eprintln!("Total {}", objects.len());
+    Ok(())
+}
+
+/// Packs the refs, then repacks every reachable object into one pack,
+/// dropping the packs and loose objects it replaces.
+pub fn handle_gc_command(repository: &Repository) -> Result<()> {
+    repository.pack_refs(true, true)?;
+    handle_repack_command(true, true, repository)
+}
+
+pub fn handle_prune_packed_command(dry_run: bool, repository: &Repository) -> Result<()> {
+    for sha1 in repository.prune_packed(dry_run)? {
+        if dry_run {
+            println!(
+                "rm -f {}",
+                repository.get_object_path(&encode(sha1))?.display()
+            );
+        }
+    }
+
+    Ok(())
+}
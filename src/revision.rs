@@ -0,0 +1,236 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::fs;
+
+use crate::{Repository, refs::parse_hash};
+
+/// Whether `name` is a revision expression rather than a plain ref name or
+/// object id: it uses `^`, `~`, `:` or `@{`, or is `@` alone.
+pub fn is_revision_expression(name: &str) -> bool {
+    name == "@" || name.contains(['^', '~', ':']) || name.contains("@{")
+}
+
+impl Repository {
+    /// The object the revision expression `spec` names, following git's
+    /// syntax: a name, optionally with `@{N}` for the Nth previous value of
+    /// a ref in its reflog, then any number of `^N` for the Nth parent
+    /// (`^0` being the commit itself), `~N` for the Nth first-parent
+    /// ancestor and `^{type}` to peel to an object of that type, or `^{}`
+    /// to peel tags. `rev:path` names the blob or tree at `path` in the
+    /// tree of `rev`, and `:path` the blob staged in the index. `None` when
+    /// the name is unknown or the history is not that deep.
+    pub fn resolve_revision(&self, spec: &str) -> Result<Option<[u8; 20]>> {
+        if let Some((revision, path)) = spec.split_once(':') {
+            if revision.is_empty() {
+                return self.index_path(path.strip_prefix("0:").unwrap_or(path));
+            }
+            let Some(object) = self.resolve_revision(revision)? else {
+                return Ok(None);
+            };
+            let tree = self.peel(spec, object, "tree")?;
+            return self.tree_path(tree, revision, path).map(Some);
+        }
+
+        let (base, mut suffix) = spec.split_at(spec.find(['^', '~']).unwrap_or(spec.len()));
+        let Some(mut object) = self.resolve_base(base)? else {
+            return Ok(None);
+        };
+
+        while let Some(operator) = suffix.chars().next() {
+            suffix = &suffix[1..];
+            if operator == '^'
+                && let Some(rest) = suffix.strip_prefix('{')
+            {
+                let Some((kind, rest)) = rest.split_once('}') else {
+                    return Ok(None);
+                };
+                object = match kind {
+                    "object" => object,
+                    "" | "commit" | "tree" | "blob" | "tag" => self.peel(spec, object, kind)?,
+                    _ => return Ok(None),
+                };
+                suffix = rest;
+                continue;
+            }
+
+            let digits = suffix.len()
+                - suffix
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .len();
+            let count = match &suffix[..digits] {
+                "" => 1,
+                number => number
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("fatal: Not a valid object name {spec}"))?,
+            };
+            suffix = &suffix[digits..];
+
+            let commit = self.peel(spec, object, "commit")?;
+            let ancestor = if operator == '^' {
+                match count {
+                    0 => Some(commit),
+                    n => self.read_commit(&commit)?.parents()?.get(n - 1).copied(),
+                }
+            } else {
+                let mut ancestor = Some(commit);
+                for _ in 0..count {
+                    let Some(commit) = ancestor else { break };
+                    ancestor = self.read_commit(&commit)?.parents()?.first().copied();
+                }
+                ancestor
+            };
+            match ancestor {
+                Some(ancestor) => object = ancestor,
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(object))
+    }
+
+    /// The object a name without `^` or `~` stands for: `@` for `HEAD`,
+    /// `<ref>@{N}` for an entry in the reflog of a ref, or the current
+    /// branch with `<ref>` left out, and anything else as a ref or object
+    /// id.
+    fn resolve_base(&self, base: &str) -> Result<Option<[u8; 20]>> {
+        if base == "@" {
+            return self.resolve_head();
+        }
+
+        let Some((name, selector)) = base
+            .strip_suffix('}')
+            .and_then(|base| base.split_once("@{"))
+        else {
+            return self.resolve_object_name(base);
+        };
+        let index: usize = selector
+            .parse()
+            .map_err(|_| anyhow!("fatal: unsupported reflog selector '@{{{selector}}}'"))?;
+
+        let ref_name = match name {
+            "" => self.head_ref()?.unwrap_or_else(|| "HEAD".to_string()),
+            "@" => "HEAD".to_string(),
+            name => match self.full_ref_name(name)? {
+                Some(ref_name) => ref_name,
+                None => return Ok(None),
+            },
+        };
+        let display = match name {
+            "" => ref_name.strip_prefix("refs/heads/").unwrap_or(&ref_name),
+            name => name,
+        };
+
+        let reflog_file = self.reflog_path(&ref_name);
+        let reflog = if reflog_file.is_file() {
+            fs::read_to_string(&reflog_file)
+                .with_context(|| format!("Failed to read {}", reflog_file.display()))?
+        } else {
+            String::new()
+        };
+        let entries: Vec<&str> = reflog.lines().filter(|line| !line.is_empty()).collect();
+
+        // Each entry is `<old> <new> <ident>`. As in git, `@{N}` is what the
+        // ref was before the Nth most recent move, so a gap left by a move
+        // that went unlogged does not shift the entries before it.
+        let value = match index {
+            0 => entries.last().map(|line| line.split(' ').nth(1)),
+            n => entries
+                .iter()
+                .rev()
+                .nth(n - 1)
+                .map(|line| line.split(' ').next()),
+        };
+        match value.flatten() {
+            Some(hex) if hex.bytes().any(|byte| byte != b'0') => parse_hash(hex).map(Some),
+            // A ref nothing has logged yet still has its current value.
+            _ if index == 0 && entries.is_empty() => self.read_ref(&ref_name),
+            _ if entries.is_empty() => Err(anyhow!("fatal: log for '{display}' is empty")),
+            _ => Err(anyhow!(
+                "fatal: log for '{display}' only has {} entries",
+                entries.len()
+            )),
+        }
+    }
+
+    /// Follows `object` through tags, and from a commit to its tree, until
+    /// it reaches an object of type `kind`; any type will do for an empty
+    /// `kind`, which only peels tags.
+    fn peel(&self, spec: &str, mut object: [u8; 20], kind: &str) -> Result<[u8; 20]> {
+        loop {
+            let (object_type, content) = self.read_raw_object(&encode(object))?;
+            if object_type == kind {
+                return Ok(object);
+            }
+
+            let next = match object_type.as_str() {
+                "tag" => header(&content, "object"),
+                "commit" if kind != "commit" && !kind.is_empty() => header(&content, "tree"),
+                _ if kind.is_empty() => return Ok(object),
+                _ => None,
+            };
+            match next {
+                Some(next) => object = parse_hash(&next)?,
+                None => {
+                    return Err(anyhow!(
+                        "error: {spec}: expected {kind} type, but the object dereferences to {object_type} type"
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The entry at `path` under `tree`. Trees written with every path in
+    /// one level and trees nested by directory are both walked.
+    fn tree_path(&self, mut tree: [u8; 20], revision: &str, path: &str) -> Result<[u8; 20]> {
+        let mut rest = path.trim_end_matches('/');
+        if rest.is_empty() {
+            return Ok(tree);
+        }
+
+        'walk: loop {
+            for entry in self.read_tree(&tree)? {
+                let name = entry.path.to_string_lossy();
+                if rest == name {
+                    return Ok(entry.sha1);
+                }
+                if entry.mode == 40000
+                    && let Some(remainder) = rest
+                        .strip_prefix(name.as_ref())
+                        .and_then(|remainder| remainder.strip_prefix('/'))
+                {
+                    tree = entry.sha1;
+                    rest = remainder;
+                    continue 'walk;
+                }
+            }
+
+            return Err(anyhow!(
+                "fatal: path '{path}' does not exist in '{revision}'"
+            ));
+        }
+    }
+
+    /// The blob staged for `path` in the index.
+    fn index_path(&self, path: &str) -> Result<Option<[u8; 20]>> {
+        self.read_index()?
+            .entries
+            .iter()
+            .find(|entry| entry.path.to_string_lossy() == path)
+            .map(|entry| Some(entry.sha1))
+            .ok_or_else(|| {
+                anyhow!("fatal: path '{path}' does not exist (neither on disk nor in the index)")
+            })
+    }
+}
+
+/// The value of the header `key` of a commit or tag.
+fn header(content: &[u8], key: &str) -> Option<String> {
+    String::from_utf8_lossy(content)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            line.strip_prefix(key)?
+                .strip_prefix(' ')
+                .map(str::to_string)
+        })
+}
@@ -0,0 +1,299 @@
+use anyhow::Result;
+use clap::Subcommand;
+
+use crate::{Repository, exit::ExitStatus};
+
+#[derive(Subcommand, Debug)]
+pub enum SelfTestCommands {
+    /// Check that libgit2 reads this repository the way mini-git does:
+    /// every reachable object, every ref, and the changes each commit makes
+    Interop,
+}
+
+pub fn handle_self_test_command(
+    command: SelfTestCommands,
+    repository: &Repository,
+) -> Result<ExitStatus> {
+    match command {
+        SelfTestCommands::Interop => interop::run(repository),
+    }
+}
+
+#[cfg(not(feature = "interop"))]
+mod interop {
+    use anyhow::{Result, anyhow};
+
+    use crate::{Repository, exit::ExitStatus};
+
+    pub fn run(_repository: &Repository) -> Result<ExitStatus> {
+        Err(anyhow!(
+            "fatal: this mini-git was built without libgit2; rebuild with `--features interop`"
+        ))
+    }
+}
+
+#[cfg(feature = "interop")]
+mod interop {
+    use anyhow::{Context, Result};
+    use git2::{Delta, ObjectType, Oid};
+    use hex::encode;
+    use std::collections::{BTreeSet, HashSet};
+
+    use crate::{
+        Repository,
+        changes::{commit_files, compare, parent_files},
+        exit::ExitStatus,
+        hash_content, path_bytes,
+        walk::{CommitWalk, WalkOrder},
+    };
+
+    /// Mismatches found so far, printed as they are found.
+    #[derive(Default)]
+    struct Report {
+        mismatches: usize,
+    }
+
+    impl Report {
+        fn mismatch(&mut self, what: String) {
+            println!("mismatch: {what}");
+            self.mismatches += 1;
+        }
+
+        fn check<T: PartialEq + std::fmt::Debug>(&mut self, what: &str, ours: T, theirs: T) {
+            if ours != theirs {
+                self.mismatch(format!("{what}: mini-git {ours:?}, libgit2 {theirs:?}"));
+            }
+        }
+    }
+
+    pub fn run(repository: &Repository) -> Result<ExitStatus> {
+        let git = git2::Repository::open_bare(&repository.mini_git_dir).with_context(|| {
+            format!(
+                "fatal: libgit2 could not open {}",
+                repository.mini_git_dir.display()
+            )
+        })?;
+        let mut report = Report::default();
+
+        let refs = check_refs(repository, &git, &mut report)?;
+
+        let mut tips: Vec<[u8; 20]> = refs.into_iter().collect();
+        tips.extend(repository.resolve_head()?);
+        let mut objects = 0;
+        let mut commits = 0;
+        let mut seen = HashSet::new();
+        for entry in CommitWalk::new(repository, &tips, WalkOrder::Date)? {
+            let (hash, _) = entry?;
+            commits += 1;
+            objects += check_objects(repository, &git, &hash, &mut seen, &mut report)?;
+            check_changes(repository, &git, &hash, &mut report)?;
+        }
+
+        println!(
+            "checked {objects} objects, {} refs and {commits} commits: {} mismatches",
+            repository.list_refs()?.len(),
+            report.mismatches
+        );
+        Ok(if report.mismatches == 0 {
+            ExitStatus::Success
+        } else {
+            ExitStatus::Differences
+        })
+    }
+
+    /// Compares the refs each side lists and what they point to, HEAD
+    /// included. Returns the commits the refs point to, for walking.
+    fn check_refs(
+        repository: &Repository,
+        git: &git2::Repository,
+        report: &mut Report,
+    ) -> Result<BTreeSet<[u8; 20]>> {
+        let ours = repository.list_refs()?;
+        let mut theirs = BTreeSet::new();
+        for reference in git.references()? {
+            let reference = reference?;
+            if let Ok(name) = reference.name() {
+                theirs.insert(name.to_string());
+            }
+        }
+
+        let mut tips = BTreeSet::new();
+        for (name, hash) in &ours {
+            theirs.remove(name);
+            match git.refname_to_id(name) {
+                Ok(oid) => report.check(&format!("ref {name}"), encode(hash), oid.to_string()),
+                Err(error) => report.mismatch(format!("ref {name}: libgit2 {}", error.message())),
+            }
+            let peeled = git
+                .find_object(Oid::from_bytes(hash)?, None)
+                .and_then(|object| object.peel_to_commit());
+            if let Ok(commit) = peeled {
+                let mut id = [0u8; 20];
+                id.copy_from_slice(commit.id().as_bytes());
+                tips.insert(id);
+            }
+        }
+        for name in theirs {
+            report.mismatch(format!("ref {name}: only libgit2 lists it"));
+        }
+
+        let head = git
+            .head()
+            .ok()
+            .and_then(|head| head.target())
+            .map(|oid| oid.to_string());
+        report.check("HEAD", repository.resolve_head()?.map(encode), head);
+
+        Ok(tips)
+    }
+
+    /// Compares a commit, its tree and everything under it with what
+    /// libgit2 reads, skipping objects already in `seen`. Each object's
+    /// content must also hash to its name. Returns how many were checked.
+    fn check_objects(
+        repository: &Repository,
+        git: &git2::Repository,
+        commit: &[u8; 20],
+        seen: &mut HashSet<[u8; 20]>,
+        report: &mut Report,
+    ) -> Result<usize> {
+        let odb = git.odb()?;
+        let mut checked = 0;
+        let mut pending = vec![*commit];
+
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash) {
+                continue;
+            }
+            checked += 1;
+            let name = encode(hash);
+            let (kind, content) = repository.read_raw_object(&name)?;
+
+            let mut full = format!("{kind} {}\0", content.len()).into_bytes();
+            full.extend_from_slice(&content);
+            report.check(
+                &format!("hash of {name}"),
+                encode(hash_content(&full)),
+                name.clone(),
+            );
+
+            let object = match odb.read(Oid::from_bytes(&hash)?) {
+                Ok(object) => object,
+                Err(error) => {
+                    report.mismatch(format!("object {name}: libgit2 {}", error.message()));
+                    continue;
+                }
+            };
+            report.check(
+                &format!("type of {name}"),
+                kind.as_str(),
+                object.kind().str(),
+            );
+            if content != object.data() {
+                report.mismatch(format!("content of {name} differs"));
+            }
+
+            match object.kind() {
+                ObjectType::Commit => {
+                    let ours = repository.read_commit(&hash)?;
+                    let theirs = git.find_commit(Oid::from_bytes(&hash)?)?;
+                    report.check(
+                        &format!("tree of {name}"),
+                        ours.tree_hash()?,
+                        theirs.tree_id().to_string(),
+                    );
+                    report.check(
+                        &format!("parents of {name}"),
+                        ours.parents()?.iter().map(encode).collect::<Vec<_>>(),
+                        theirs.parent_ids().map(|oid| oid.to_string()).collect(),
+                    );
+                    let tree = theirs.tree_id();
+                    let mut id = [0u8; 20];
+                    id.copy_from_slice(tree.as_bytes());
+                    pending.push(id);
+                }
+                ObjectType::Tree => {
+                    let ours: Vec<(Vec<u8>, u32, String)> = repository
+                        .read_tree(&hash)?
+                        .iter()
+                        .map(|entry| {
+                            (
+                                path_bytes(&entry.path).into_owned(),
+                                entry.mode,
+                                encode(entry.sha1),
+                            )
+                        })
+                        .collect();
+                    let tree = git.find_tree(Oid::from_bytes(&hash)?)?;
+                    let theirs: Vec<(Vec<u8>, u32, String)> = tree
+                        .iter()
+                        .map(|entry| {
+                            (
+                                entry.name_bytes().to_vec(),
+                                format!("{:o}", entry.filemode()).parse().unwrap_or(0),
+                                entry.id().to_string(),
+                            )
+                        })
+                        .collect();
+                    report.check(&format!("entries of tree {name}"), &ours, &theirs);
+
+                    for entry in tree.iter() {
+                        // A gitlink names a commit in another repository.
+                        if entry.kind() != Some(ObjectType::Commit) {
+                            let mut id = [0u8; 20];
+                            id.copy_from_slice(entry.id().as_bytes());
+                            pending.push(id);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(checked)
+    }
+
+    /// Compares the files a commit changes relative to its first parent.
+    fn check_changes(
+        repository: &Repository,
+        git: &git2::Repository,
+        hash: &[u8; 20],
+        report: &mut Report,
+    ) -> Result<()> {
+        let commit = repository.read_commit(hash)?;
+        let parents = commit.parents()?;
+        let ours: Vec<(Vec<u8>, char)> = compare(
+            &parent_files(repository, &parents)?,
+            &commit_files(repository, hash)?,
+        )
+        .iter()
+        .map(|change| (change.path.clone(), change.status()))
+        .collect();
+
+        let new_tree = git.find_commit(Oid::from_bytes(hash)?)?.tree()?;
+        let old_tree = match parents.first() {
+            Some(parent) => Some(git.find_commit(Oid::from_bytes(parent)?)?.tree()?),
+            None => None,
+        };
+        let diff = git.diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), None)?;
+        let theirs: Vec<(Vec<u8>, char)> = diff
+            .deltas()
+            .map(|delta| {
+                let file = match delta.status() {
+                    Delta::Deleted => delta.old_file(),
+                    _ => delta.new_file(),
+                };
+                let status = match delta.status() {
+                    Delta::Added => 'A',
+                    Delta::Deleted => 'D',
+                    Delta::Typechange => 'T',
+                    _ => 'M',
+                };
+                (file.path_bytes().unwrap_or_default().to_vec(), status)
+            })
+            .collect();
+
+        report.check(&format!("changes in {}", encode(hash)), ours, theirs);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::{
+    Repository,
+    ident::Ident,
+    walk::{CommitWalk, WalkOrder},
+};
+
+/// Groups the commits reachable from `revision`, or HEAD, by author and
+/// prints each author with their count and commit subjects, oldest first.
+/// `summary` leaves the subjects out, `numbered` puts the authors with the
+/// most commits first instead of sorting by name, and `email` adds each
+/// author's address.
+pub fn handle_shortlog_command(
+    revision: Option<String>,
+    summary: bool,
+    numbered: bool,
+    email: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let start = repository.resolve_commitish(revision.as_deref().unwrap_or("HEAD"))?;
+
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in CommitWalk::new(repository, &[start], WalkOrder::Date)? {
+        let (_, commit) = entry?;
+        let Some(author) = commit.header("author") else {
+            continue;
+        };
+        let author = Ident::parse(&author)?;
+        let name = if email {
+            format!("{} <{}>", author.name, author.email)
+        } else {
+            author.name
+        };
+
+        let message = commit.message();
+        let subject = message
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        authors.entry(name).or_default().push(subject);
+    }
+
+    let mut authors: Vec<(String, Vec<String>)> = authors.into_iter().collect();
+    if numbered {
+        // Stable, so authors with as many commits stay in name order.
+        authors.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+    }
+
+    for (author, subjects) in authors {
+        if summary {
+            println!("{:6}\t{author}", subjects.len());
+            continue;
+        }
+
+        println!("{author} ({}):", subjects.len());
+        for subject in subjects.iter().rev() {
+            println!("      {subject}");
+        }
+        println!();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result, anyhow};
+use std::{
+    env,
+    io::{Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use crate::{
+    Repository,
+    config::Config,
+    pack::unpack_pack,
+    protocol::{
+        Advertisement, RefUpdate, pack_from_response, packfile_from_response, read_advertisement,
+        read_ls_refs, read_report_status, write_fetch_request, write_fetch_request_v2, write_flush,
+        write_ls_refs_request, write_push_request,
+    },
+    transport::{local_haves, write_push_pack},
+};
+
+/// A repository reached by running `git-upload-pack` or `git-receive-pack`
+/// on another host over `ssh`, with the refs it advertised when first
+/// contacted. Every exchange runs its own ssh session.
+pub struct SshRemote {
+    endpoint: Endpoint,
+    pub advertisement: Advertisement,
+}
+
+/// Where to run a service and how to get there.
+struct Endpoint {
+    ssh_command: Option<String>,
+    user_host: String,
+    port: Option<String>,
+    path: String,
+    /// The protocol version upload-pack is asked for through
+    /// `GIT_PROTOCOL`.
+    version: u8,
+}
+
+/// Whether `url` names a repository over ssh, either as
+/// `ssh://[user@]host[:port]/path` or in the scp-like `[user@]host:path`
+/// form. A colon only makes the scp-like form when no slash comes before it.
+pub fn is_ssh_url(url: &str) -> bool {
+    if url.starts_with("ssh://") {
+        return true;
+    }
+    if url.contains("://") {
+        return false;
+    }
+
+    match url.find(':') {
+        Some(colon) => colon > 0 && !url[..colon].contains('/'),
+        None => false,
+    }
+}
+
+impl SshRemote {
+    pub fn connect(config: &Config, url: &str, version: u8) -> Result<Self> {
+        Self::discover(config, url, "git-upload-pack", version)
+    }
+
+    /// Starts the remote's receive-pack service, whose advertisement lists
+    /// the refs a push would update.
+    pub fn connect_for_push(config: &Config, url: &str) -> Result<Self> {
+        Self::discover(config, url, "git-receive-pack", 0)
+    }
+
+    fn discover(config: &Config, url: &str, service: &str, version: u8) -> Result<Self> {
+        let (user_host, port, path) = match url.strip_prefix("ssh://") {
+            Some(rest) => {
+                let (authority, path) = rest
+                    .find('/')
+                    .map(|slash| rest.split_at(slash))
+                    .ok_or_else(|| anyhow!("fatal: no path specified in '{}'", url))?;
+                let (user_host, port) = match authority.rsplit_once(':') {
+                    Some((user_host, port)) => (user_host, Some(port.to_string())),
+                    None => (authority, None),
+                };
+                // `ssh://host/~user/repo` is relative to a home directory.
+                let path = path
+                    .strip_prefix("/~")
+                    .map_or(path.to_string(), |rest| format!("~{rest}"));
+                (user_host.to_string(), port, path)
+            }
+            None => {
+                let (user_host, path) = url
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("fatal: no path specified in '{}'", url))?;
+                (user_host.to_string(), None, path.to_string())
+            }
+        };
+
+        let mut endpoint = Endpoint {
+            ssh_command: config.get("core.sshcommand").map(str::to_string),
+            user_host,
+            port,
+            path: path.trim_end_matches('/').to_string(),
+            version,
+        };
+
+        let mut session = endpoint.start(service)?;
+        let mut advertisement = session.read_advertisement()?;
+        if advertisement.version == 2 {
+            write_ls_refs_request(&mut session.stdin)?;
+            read_ls_refs(&mut session.stdout, &mut advertisement)?;
+        }
+        // An empty request tells the server we only wanted the refs, or
+        // under version 2 that there are no more commands.
+        write_flush(&mut session.stdin)?;
+        session.finish()?;
+
+        // Later sessions speak whatever the server answered in.
+        endpoint.version = advertisement.version;
+        Ok(SshRemote {
+            endpoint,
+            advertisement,
+        })
+    }
+
+    /// Downloads a pack containing everything needed to reach `wants` that
+    /// `repository` lacks, less what `filter` leaves out, and stores its
+    /// objects. Returns the number of objects received.
+    pub fn fetch_objects(
+        &self,
+        repository: &Repository,
+        wants: &[[u8; 20]],
+        filter: Option<&str>,
+    ) -> Result<usize> {
+        let mut missing = Vec::new();
+        for want in wants {
+            if !repository.has_object(want)? && !missing.contains(want) {
+                missing.push(*want);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let filter = self.advertisement.accept_filter(filter);
+        let mut session = self.endpoint.start("git-upload-pack")?;
+        session.read_advertisement()?;
+        if self.endpoint.version == 2 {
+            write_fetch_request_v2(
+                &mut session.stdin,
+                &missing,
+                &local_haves(repository)?,
+                filter,
+            )?;
+            write_flush(&mut session.stdin)?;
+        } else {
+            write_fetch_request(
+                &mut session.stdin,
+                &missing,
+                &local_haves(repository)?,
+                &["ofs-delta", "agent=mini-git/0.1"],
+            )?;
+        }
+
+        let response = session.finish()?;
+        let pack = match self.endpoint.version {
+            2 => packfile_from_response(&response)?,
+            _ => pack_from_response(&response)?.to_vec(),
+        };
+        let (objects, _) = unpack_pack(repository, &pack, false)?;
+        Ok(objects)
+    }
+
+    /// Sends `updates` to the remote's receive-pack along with a pack of
+    /// every object it needs to accept them, and returns its verdict for
+    /// each ref: `None` when it was updated, or the reason it was refused.
+    pub fn push(
+        &self,
+        repository: &Repository,
+        updates: &[RefUpdate],
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let mut session = self.endpoint.start("git-receive-pack")?;
+        let advertisement = session.read_advertisement()?;
+
+        let mut request = Vec::new();
+        write_push_request(
+            &mut request,
+            updates,
+            &["report-status", "agent=mini-git/0.1"],
+        )?;
+        write_push_pack(repository, &advertisement.refs, updates, &mut request)?;
+        session.stdin.write_all(&request)?;
+
+        let response = session.finish()?;
+        read_report_status(&mut response.as_slice())
+    }
+}
+
+impl Endpoint {
+    /// Runs `service` on the remote end of a new ssh session. The command
+    /// comes from `GIT_SSH_COMMAND`, `core.sshCommand` or `GIT_SSH`, in that
+    /// order, falling back to `ssh`, which is told to pass `GIT_PROTOCOL`
+    /// on when protocol version 2 is wanted.
+    fn start(&self, service: &str) -> Result<Session> {
+        let version_2 = self.version == 2;
+        let mut args = Vec::new();
+        if let Some(port) = &self.port {
+            args.push("-p".to_string());
+            args.push(port.clone());
+        }
+        args.push(self.user_host.clone());
+        args.push(format!("{service} '{}'", self.path.replace('\'', "'\\''")));
+
+        let shell_command = env::var("GIT_SSH_COMMAND")
+            .ok()
+            .or_else(|| self.ssh_command.clone());
+        let mut command = match shell_command {
+            Some(shell_command) => {
+                let mut command = Command::new("sh");
+                command
+                    .arg("-c")
+                    .arg(format!("{shell_command} \"$@\""))
+                    .arg(shell_command);
+                command
+            }
+            None => match env::var("GIT_SSH") {
+                Ok(program) => Command::new(program),
+                Err(_) => {
+                    let mut command = Command::new("ssh");
+                    if version_2 {
+                        command.args(["-o", "SendEnv=GIT_PROTOCOL"]);
+                    }
+                    command
+                }
+            },
+        };
+        if version_2 {
+            command.env("GIT_PROTOCOL", "version=2");
+        }
+
+        let mut child = command
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("fatal: unable to run ssh")?;
+
+        Ok(Session {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: child.stdout.take().expect("stdout is piped"),
+            child,
+        })
+    }
+}
+
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl Session {
+    fn read_advertisement(&mut self) -> Result<Advertisement> {
+        read_advertisement(&mut self.stdout)
+            .context("fatal: Could not read from remote repository.")
+    }
+
+    /// Closes our end of the conversation and reads everything the remote
+    /// sends until it exits.
+    fn finish(mut self) -> Result<Vec<u8>> {
+        drop(self.stdin);
+
+        let mut response = Vec::new();
+        self.stdout
+            .read_to_end(&mut response)
+            .context("fatal: the remote end hung up unexpectedly")?;
+
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("fatal: the remote end hung up unexpectedly"));
+        }
+
+        Ok(response)
+    }
+}
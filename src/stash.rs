@@ -0,0 +1,84 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Subcommand;
+use std::{fs, io};
+
+use crate::{
+    Repository,
+    changes::{DiffOutputArgs, commit_files, compare, parent_files, write_changes},
+    refs::parse_hash,
+};
+
+const STASH_REF: &str = "refs/stash";
+
+#[derive(Subcommand, Debug)]
+pub enum StashCommands {
+    Show {
+        /// Show the changes as a patch instead of a diffstat
+        #[arg(short = 'p', long = "patch")]
+        patch: bool,
+        #[command(flatten)]
+        diff_args: DiffOutputArgs,
+        stash: Option<String>,
+    },
+}
+
+pub fn handle_stash_command(command: StashCommands, repository: &Repository) -> Result<()> {
+    match command {
+        StashCommands::Show {
+            patch,
+            mut diff_args,
+            stash,
+        } => {
+            let hash = resolve_stash(repository, stash.as_deref().unwrap_or("stash@{0}"))?;
+            let commit = repository.read_commit(&hash)?;
+
+            // Like git, a stash is summarized as a diffstat unless another
+            // output format was asked for.
+            if !patch && !diff_args.name_only && !diff_args.name_status {
+                diff_args.stat = true;
+            }
+
+            let changes = compare(
+                &parent_files(repository, &commit.parents()?)?,
+                &commit_files(repository, &hash)?,
+            );
+            write_changes(repository, &changes, diff_args, &mut io::stdout().lock())
+        }
+    }
+}
+
+/// Resolves `stash@{n}`, a bare `n`, or any commit-ish to a stash commit.
+/// Older entries are found in the stash reflog, newest last.
+fn resolve_stash(repository: &Repository, name: &str) -> Result<[u8; 20]> {
+    let index = match name
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .or_else(|| name.chars().all(|c| c.is_ascii_digit()).then_some(name))
+    {
+        Some(index) => index
+            .parse::<usize>()
+            .map_err(|_| anyhow!("fatal: '{}' is not a stash-like commit", name))?,
+        None => return repository.resolve_commitish(name),
+    };
+
+    let reflog_file = repository.mini_git_dir.join("logs").join(STASH_REF);
+    if reflog_file.is_file() {
+        let reflog = fs::read_to_string(&reflog_file)
+            .with_context(|| format!("Failed to read {}", reflog_file.display()))?;
+        let entries: Vec<&str> = reflog.lines().filter(|line| !line.is_empty()).collect();
+
+        return match entries.iter().rev().nth(index) {
+            Some(line) => {
+                let new = line.split(' ').nth(1).unwrap_or_default();
+                parse_hash(new)
+            }
+            None => Err(anyhow!("fatal: stash@{{{}}} does not exist", index)),
+        };
+    }
+
+    match (index, repository.read_ref(STASH_REF)?) {
+        (0, Some(hash)) => Ok(hash),
+        (0, None) => Err(anyhow!("fatal: no stash entries found")),
+        _ => Err(anyhow!("fatal: stash@{{{}}} does not exist", index)),
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use std::io::{self, Read, Write};
+
+const COMMENT_CHAR: u8 = b'#';
+
+/// Reads text from stdin and prints it cleaned up as `stripspace` does, or
+/// with `comment_lines`, turned into comment lines instead.
+pub fn handle_stripspace_command(strip_comments: bool, comment_lines: bool) -> Result<()> {
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .context("Failed to read from stdin")?;
+
+    let output = if comment_lines {
+        comment(&input)
+    } else {
+        stripspace(&input, strip_comments)
+    };
+    io::stdout().write_all(&output)?;
+
+    Ok(())
+}
+
+/// Cleans up a message the way git does before recording it: trailing
+/// whitespace is removed from every line, runs of blank lines are collapsed
+/// into one, blank lines at the start and the end are dropped, and the
+/// result ends with a newline unless it is empty. With `strip_comments`,
+/// lines starting with `#` are dropped first.
+pub fn stripspace(input: &[u8], strip_comments: bool) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut blank_lines = 0;
+
+    for line in input.split(|&b| b == b'\n') {
+        if strip_comments && line.first() == Some(&COMMENT_CHAR) {
+            continue;
+        }
+
+        let end = line
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        if end == 0 {
+            blank_lines += 1;
+            continue;
+        }
+
+        if blank_lines > 0 && !output.is_empty() {
+            output.push(b'\n');
+        }
+        blank_lines = 0;
+        output.extend_from_slice(&line[..end]);
+        output.push(b'\n');
+    }
+
+    output
+}
+
+/// `stripspace` for a message held as a string.
+pub fn stripspace_str(input: &str, strip_comments: bool) -> String {
+    String::from_utf8_lossy(&stripspace(input.as_bytes(), strip_comments)).into_owned()
+}
+
+/// Prefixes every line with `# `, or a bare `#` for an empty line.
+fn comment(input: &[u8]) -> Vec<u8> {
+    if input.is_empty() {
+        return Vec::new();
+    }
+
+    let mut output = Vec::with_capacity(input.len() * 2);
+    let input = input.strip_suffix(b"\n").unwrap_or(input);
+
+    for line in input.split(|&b| b == b'\n') {
+        output.push(COMMENT_CHAR);
+        if !line.is_empty() {
+            output.push(b' ');
+            output.extend_from_slice(line);
+        }
+        output.push(b'\n');
+    }
+
+    output
+}
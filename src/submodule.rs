@@ -0,0 +1,418 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Subcommand;
+use hex::encode;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    IndexEntry, Repository, StatData,
+    clone::{CloneOptions, default_directory, handle_clone_command},
+    config::Config,
+    fetch::handle_fetch_command,
+    merge::checkout_entries,
+    messages::tr,
+    pathspec::PathspecArgs,
+    pool::{self, default_threads},
+    refs::parse_hash,
+    transport::rewrite_url,
+};
+
+const GITMODULES: &str = ".gitmodules";
+
+#[derive(Subcommand, Debug)]
+pub enum SubmoduleCommands {
+    /// Clone a repository into `path` and record it as a submodule
+    Add {
+        url: String,
+        /// Where to put it; named after the repository by default
+        path: Option<String>,
+    },
+    /// Copy the URLs of submodules from `.gitmodules` into the config
+    Init {
+        #[command(flatten)]
+        pathspec_args: PathspecArgs,
+        paths: Vec<String>,
+    },
+    /// Clone missing submodules and check each out at its recorded commit
+    Update {
+        /// Initialize submodules that are not yet
+        #[arg(long)]
+        init: bool,
+        /// Initialize and update the submodules of each submodule too
+        #[arg(long)]
+        recursive: bool,
+        /// How many submodules to fetch at once
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        #[command(flatten)]
+        pathspec_args: PathspecArgs,
+        paths: Vec<String>,
+    },
+}
+
+/// A submodule as `.gitmodules` describes it.
+struct Submodule {
+    name: String,
+    path: String,
+    url: String,
+}
+
+/// A submodule `update` is to bring to `commit`, from `url`.
+struct Pending {
+    submodule: Submodule,
+    url: String,
+    commit: [u8; 20],
+    directory: PathBuf,
+}
+
+pub fn handle_submodule_command(command: SubmoduleCommands, repository: &Repository) -> Result<()> {
+    match command {
+        SubmoduleCommands::Add { url, path } => add_submodule(repository, &url, path),
+        SubmoduleCommands::Init {
+            pathspec_args,
+            paths,
+        } => init_submodules(repository, &pathspec_args.resolve(paths)?),
+        SubmoduleCommands::Update {
+            init,
+            recursive,
+            jobs,
+            pathspec_args,
+            paths,
+        } => {
+            let paths = pathspec_args.resolve(paths)?;
+            if init {
+                init_submodules(repository, &paths)?;
+            }
+            update_submodules(repository, &paths, recursive, jobs)
+        }
+    }
+}
+
+/// Initializes and updates every submodule of a fresh clone, and theirs in
+/// turn.
+pub fn clone_submodules(repository: &Repository, jobs: Option<usize>) -> Result<()> {
+    init_submodules(repository, &[])?;
+    update_submodules(repository, &[], true, jobs)
+}
+
+/// Reads the `[submodule "<name>"]` sections of `.gitmodules`, in file
+/// order. A section without a path is skipped, as git does.
+fn read_gitmodules(repository: &Repository) -> Result<Vec<Submodule>> {
+    let config = Config::load_file(&repository.work_dir().join(GITMODULES))?;
+
+    let mut submodules: Vec<Submodule> = Vec::new();
+    for (key, _) in config.entries() {
+        let Some(name) = key
+            .strip_prefix("submodule.")
+            .and_then(|rest| rest.rsplit_once('.'))
+            .map(|(name, _)| name)
+        else {
+            continue;
+        };
+        if submodules.iter().any(|submodule| submodule.name == name) {
+            continue;
+        }
+        if let Some(path) = config.get(&format!("submodule.{name}.path")) {
+            submodules.push(Submodule {
+                name: name.to_string(),
+                path: path.to_string(),
+                url: config
+                    .get(&format!("submodule.{name}.url"))
+                    .unwrap_or_default()
+                    .to_string(),
+            });
+        }
+    }
+
+    Ok(submodules)
+}
+
+/// Resolves a URL given relative to the superproject, `./sub` or
+/// `../sibling`, against the superproject's `origin`, or its own working
+/// tree when it has none. Other URLs are returned unchanged.
+fn resolve_url(repository: &Repository, config: &Config, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
+    }
+
+    let mut base = config
+        .get("remote.origin.url")
+        .map(str::to_string)
+        .unwrap_or_else(|| repository.work_dir().display().to_string());
+    let mut rest = url;
+    loop {
+        if let Some(after) = rest.strip_prefix("./") {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("../") {
+            base = base
+                .trim_end_matches('/')
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_string())
+                .unwrap_or_default();
+            rest = after;
+        } else {
+            break;
+        }
+    }
+
+    format!("{}/{rest}", base.trim_end_matches('/'))
+}
+
+/// Clones `url` into `path`, records the commit it is at as a gitlink in
+/// the index, and describes it in `.gitmodules`.
+fn add_submodule(repository: &Repository, url: &str, path: Option<String>) -> Result<()> {
+    let path = path.unwrap_or_else(|| default_directory(url).display().to_string());
+    let path = path.trim_end_matches('/').to_string();
+
+    let mut index = repository.read_index()?;
+    if index
+        .entries
+        .iter()
+        .any(|entry| entry.path == Path::new(&path))
+    {
+        return Err(anyhow!("fatal: '{}' already exists in the index", path));
+    }
+
+    let config = Config::load(repository)?;
+    let work_dir = repository.work_dir();
+    handle_clone_command(
+        resolve_url(repository, &config, url),
+        Some(work_dir.join(&path)),
+        CloneOptions::default(),
+    )?;
+
+    let head = Repository::open(&work_dir.join(&path))?
+        .resolve_head()?
+        .ok_or_else(|| anyhow!("fatal: You are on a branch yet to be born"))?;
+
+    let gitmodules = work_dir.join(GITMODULES);
+    Config::set_all_in_file(&gitmodules, &format!("submodule.{path}.path"), &[&path])?;
+    Config::set_all_in_file(&gitmodules, &format!("submodule.{path}.url"), &[url])?;
+    Config::set_all(
+        repository,
+        &format!("submodule.{path}.url"),
+        &[&resolve_url(repository, &config, url)],
+    )?;
+
+    let content = fs::read(&gitmodules).context("Failed to read .gitmodules")?;
+    let blob = repository.write_raw_object("blob", &content)?;
+    index
+        .entries
+        .retain(|entry| entry.path != Path::new(GITMODULES));
+    index.entries.push(IndexEntry {
+        mode: 100644,
+        sha1: blob,
+        path: PathBuf::from(GITMODULES),
+        stat: StatData::default(),
+    });
+    index.entries.push(IndexEntry {
+        mode: 160000,
+        sha1: head,
+        path: PathBuf::from(&path),
+        stat: StatData::default(),
+    });
+    repository.write_index(&mut index)
+}
+
+/// Registers the URL of each submodule, or of those under `paths`, in the
+/// config, which marks it as wanted by `update`. URLs already there are
+/// kept, so that a local override survives.
+fn init_submodules(repository: &Repository, paths: &[String]) -> Result<()> {
+    let config = Config::load(repository)?;
+
+    for submodule in select(read_gitmodules(repository)?, paths)? {
+        let key = format!("submodule.{}.url", submodule.name);
+        if config.get(&key).is_some() {
+            continue;
+        }
+        if submodule.url.is_empty() {
+            return Err(anyhow!(
+                "fatal: No url found for submodule path '{}' in .gitmodules",
+                submodule.path
+            ));
+        }
+
+        let url = resolve_url(repository, &config, &submodule.url);
+        Config::set_all(repository, &key, &[&url])?;
+        eprintln!(
+            "{}",
+            tr!(
+                "Submodule '{}' ({}) registered for path '{}'",
+                submodule.name,
+                url,
+                submodule.path
+            )
+        );
+    }
+
+    Ok(())
+}
+
+/// Brings each initialized submodule to the commit the index records for
+/// it: clones it if it is missing, fetches if the commit is not there
+/// yet, and checks the commit out on a detached HEAD. With `recursive`,
+/// the submodules of each are then initialized and updated the same way.
+///
+/// Clones and fetches run `jobs` at a time, `submodule.fetchJobs` by
+/// default, with 0 meaning one per core; checkouts happen one by one
+/// afterwards.
+fn update_submodules(
+    repository: &Repository,
+    paths: &[String],
+    recursive: bool,
+    jobs: Option<usize>,
+) -> Result<()> {
+    let config = Config::load(repository)?;
+    // The clone applies the user's `url.<base>.insteadOf` rules itself, but
+    // cannot see the superproject's own.
+    let local_config = Config::load_file(&repository.mini_git_dir.join("config"))?;
+    let index = repository.read_index()?;
+    let work_dir = repository.work_dir();
+
+    let mut pending = Vec::new();
+    for submodule in select(read_gitmodules(repository)?, paths)? {
+        let Some(url) = config.get(&format!("submodule.{}.url", submodule.name)) else {
+            continue;
+        };
+        let Some(commit) = index
+            .entries
+            .iter()
+            .find(|entry| entry.mode == 160000 && entry.path == Path::new(&submodule.path))
+            .map(|entry| entry.sha1)
+        else {
+            continue;
+        };
+
+        pending.push(Pending {
+            url: rewrite_url(&local_config, url, false),
+            commit,
+            directory: work_dir.join(&submodule.path),
+            submodule,
+        });
+    }
+
+    let jobs = match jobs {
+        Some(jobs) => jobs,
+        None => match config.get("submodule.fetchjobs") {
+            Some(value) => value.parse().map_err(|_| {
+                anyhow!("fatal: bad numeric config value '{value}' for 'submodule.fetchjobs'")
+            })?,
+            None => 1,
+        },
+    };
+    let fetched = pool::run(default_threads(jobs), pending.len(), |queue, worker| {
+        let mut fetched = Vec::new();
+        while let Some(i) = queue.next(worker) {
+            fetched.push((i, fetch_submodule(&pending[i])));
+        }
+        fetched
+    });
+    fetched.into_iter().collect::<Result<Vec<()>>>()?;
+
+    for Pending {
+        submodule,
+        commit,
+        directory,
+        ..
+    } in &pending
+    {
+        let nested = Repository::open(directory)?;
+        let head = nested.resolve_head()?;
+        if head != Some(*commit) {
+            let entries = |commit: &[u8; 20]| {
+                let tree = parse_hash(&nested.read_commit(commit)?.tree_hash()?)?;
+                nested.read_tree_recursive(&tree)
+            };
+            let current = match head {
+                Some(head) => entries(&head)?,
+                None => Vec::new(),
+            };
+            checkout_entries(&nested, &current, &entries(commit)?, "checkout")?;
+            fs::write(nested.git_dir.join("HEAD"), format!("{}\n", encode(commit)))?;
+
+            println!(
+                "{}",
+                tr!(
+                    "Submodule path '{}': checked out '{}'",
+                    submodule.path,
+                    encode(commit)
+                )
+            );
+        }
+
+        if recursive {
+            init_submodules(&nested, &[])?;
+            update_submodules(&nested, &[], true, Some(jobs))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Clones the submodule `pending` describes if it is missing, and fetches
+/// into it if it lacks the commit wanted.
+fn fetch_submodule(pending: &Pending) -> Result<()> {
+    let directory = &pending.directory;
+    if !directory.join(".mini-git").exists() {
+        // The checkout leaves an empty directory in its place.
+        if directory.is_dir() {
+            fs::remove_dir(directory).map_err(|_| {
+                anyhow!(
+                    "fatal: destination path '{}' already exists and is not an empty directory.",
+                    directory.display()
+                )
+            })?;
+        }
+        handle_clone_command(
+            pending.url.clone(),
+            Some(directory.clone()),
+            CloneOptions::default(),
+        )?;
+    }
+
+    let nested = Repository::open(directory)?;
+    if nested.has_object(&pending.commit)? {
+        return Ok(());
+    }
+    handle_fetch_command(None, Vec::new(), &nested)?;
+    if !nested.has_object(&pending.commit)? {
+        return Err(anyhow!(
+            "fatal: Fetched in submodule path '{}', but it did not contain {}. Direct fetching of that commit failed.",
+            pending.submodule.path,
+            encode(pending.commit)
+        ));
+    }
+
+    Ok(())
+}
+
+/// The submodules under any of `paths`, or all of them when none are given.
+fn select(submodules: Vec<Submodule>, paths: &[String]) -> Result<Vec<Submodule>> {
+    if paths.is_empty() {
+        return Ok(submodules);
+    }
+
+    for path in paths {
+        let path = path.trim_end_matches('/');
+        if !submodules.iter().any(|submodule| {
+            submodule.path == path || submodule.path.starts_with(&format!("{path}/"))
+        }) {
+            return Err(anyhow!(
+                "error: pathspec '{}' did not match any file(s) known to mini-git",
+                path
+            ));
+        }
+    }
+
+    Ok(submodules
+        .into_iter()
+        .filter(|submodule| {
+            paths.iter().any(|path| {
+                let path = path.trim_end_matches('/');
+                submodule.path == path || submodule.path.starts_with(&format!("{path}/"))
+            })
+        })
+        .collect())
+}
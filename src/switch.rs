@@ -0,0 +1,432 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    IndexEntry, Repository, StatData, TreeEntry,
+    changes::{index_files, tree_files},
+    config::Config,
+    filter::Filters,
+    hooks::run_post_checkout,
+    merge::{checkout_entries, ensure_index_matches},
+    messages::{tr, tr_n},
+    path_bytes, path_from_bytes,
+    pathspec::{self, PathspecArgs},
+    refs::parse_hash,
+    upload_pack::peel_tag,
+    walk::{CommitWalk, WalkOrder},
+    worktree::checked_out_elsewhere,
+};
+
+/// How many orphaned commits are listed by name before the rest are only
+/// counted.
+const ORPHAN_CUTOFF: usize = 4;
+
+/// Where HEAD should end up after a switch.
+enum Target {
+    Branch { name: String, create: bool },
+    Detached { name: String, advise: bool },
+}
+
+pub fn handle_switch_command(
+    target: Option<String>,
+    create: Option<String>,
+    detach: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let start = target.as_deref().unwrap_or("HEAD");
+
+    let (commit, target) = match (create, detach) {
+        (Some(name), _) => (
+            repository.resolve_commitish(start)?,
+            Target::Branch { name, create: true },
+        ),
+        (None, true) => (
+            repository.resolve_commitish(start)?,
+            Target::Detached {
+                name: start.to_string(),
+                advise: false,
+            },
+        ),
+        (None, false) => {
+            let name = target.ok_or_else(|| anyhow!("fatal: missing branch or commit argument"))?;
+            let Some(commit) = repository.read_ref(&format!("refs/heads/{name}"))? else {
+                repository.resolve_commitish(&name)?;
+                return Err(anyhow!(
+                    "fatal: a branch is expected, got commit '{name}'\n\
+hint: If you want to detach HEAD at the commit, try again with the --detach option."
+                ));
+            };
+            (
+                commit,
+                Target::Branch {
+                    name,
+                    create: false,
+                },
+            )
+        }
+    };
+
+    switch_to(repository, commit, target)
+}
+
+/// Like `switch`, except that anything which is not a branch is checked
+/// out on a detached HEAD. Given paths, HEAD stays where it is and only
+/// those paths are checked out, from `target` or the index.
+pub fn handle_checkout_command(
+    target: Option<String>,
+    create: Option<String>,
+    detach: bool,
+    paths: Vec<String>,
+    pathspec_args: &PathspecArgs,
+    overlay: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let pathspecs = pathspec_args.resolve(paths)?;
+    if !pathspecs.is_empty() {
+        if create.is_some() {
+            return Err(anyhow!("fatal: '-b' cannot be used with updating paths"));
+        }
+        if detach {
+            return Err(anyhow!(
+                "fatal: '--detach' cannot be used with updating paths"
+            ));
+        }
+        return checkout_paths(repository, target.as_deref(), &pathspecs, overlay);
+    }
+
+    let name = target.as_deref().unwrap_or("HEAD");
+    let is_branch = repository
+        .read_ref(&format!("refs/heads/{name}"))?
+        .is_some();
+
+    if create.is_some() || (is_branch && !detach) {
+        return handle_switch_command(target, create, false, repository);
+    }
+
+    switch_to(
+        repository,
+        repository.resolve_commitish(name)?,
+        Target::Detached {
+            name: name.to_string(),
+            advise: !detach,
+        },
+    )
+}
+
+/// Copies the files matching `pathspecs` from the tree-ish `source` into
+/// the index and the working tree, or from the index into the working
+/// tree when there is no source. In overlay mode that is all; without it,
+/// tracked files that match but are not in `source` are deleted as well,
+/// so that the paths end up exactly as they are there.
+fn checkout_paths(
+    repository: &Repository,
+    source: Option<&str>,
+    pathspecs: &[String],
+    overlay: bool,
+) -> Result<()> {
+    let mut index = repository.read_index()?;
+    let (files, from) = match source {
+        Some(name) => {
+            let hash = repository.resolve_commitish(name)?;
+            let hash = peel_tag(repository, &hash)?.unwrap_or(hash);
+            let tree = match repository.read_raw_object(&encode(hash))?.0.as_str() {
+                "commit" => parse_hash(&repository.read_commit(&hash)?.tree_hash()?)?,
+                "tree" => hash,
+                _ => return Err(anyhow!("fatal: reference is not a tree: {}", name)),
+            };
+            (
+                tree_files(repository, &tree)?,
+                encode(tree)[..7].to_string(),
+            )
+        }
+        None => (index_files(repository)?, "the index".to_string()),
+    };
+
+    let selected = |path: &[u8]| {
+        pathspecs
+            .iter()
+            .any(|pathspec| pathspec::matches(pathspec, path))
+    };
+    for pathspec in pathspecs {
+        let known = files.keys().any(|path| pathspec::matches(pathspec, path))
+            || (!overlay
+                && index
+                    .entries
+                    .iter()
+                    .any(|entry| pathspec::matches(pathspec, &path_bytes(&entry.path))));
+        if !known {
+            return Err(anyhow!(
+                "error: pathspec '{}' did not match any file(s) known to mini-git",
+                pathspec
+            ));
+        }
+    }
+
+    let work_dir = repository.work_dir();
+    let mut filters = Filters::load(repository)?;
+    let mut updated = 0;
+    for (path, (mode, sha1)) in files.iter().filter(|(path, _)| selected(path)) {
+        let path = path_from_bytes(path);
+        if source.is_some() {
+            index.entries.retain(|entry| entry.path != path);
+            index.entries.push(IndexEntry {
+                mode: *mode,
+                sha1: *sha1,
+                path: path.clone(),
+                stat: StatData::default(),
+            });
+        }
+        // A submodule is moved by `submodule update`, not here.
+        if *mode == 160000 {
+            continue;
+        }
+
+        let file = work_dir.join(&path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = repository.read_raw_object(&encode(sha1))?.1;
+        fs::write(&file, filters.smudge(&path, content)?)
+            .with_context(|| format!("Failed to write {}", file.display()))?;
+        updated += 1;
+    }
+
+    if !overlay {
+        let removed: Vec<PathBuf> = index
+            .entries
+            .iter()
+            .map(|entry| path_bytes(&entry.path).into_owned())
+            .filter(|path| selected(path) && !files.contains_key(path))
+            .map(|path| path_from_bytes(&path))
+            .collect();
+        for path in &removed {
+            remove_file(&work_dir.join(path))?;
+        }
+        index.entries.retain(|entry| !removed.contains(&entry.path));
+        updated += removed.len();
+    }
+
+    if source.is_some() || !overlay {
+        repository.write_index(&mut index)?;
+    }
+
+    eprintln!(
+        "{}",
+        tr_n!(
+            updated,
+            "Updated {} path from {}",
+            "Updated {} paths from {}",
+            updated,
+            from
+        )
+    );
+
+    let head = repository.resolve_head()?.unwrap_or_default();
+    run_post_checkout(repository, Some(head), head, false)
+}
+
+/// Deletes a file that may already be gone from the working tree.
+fn remove_file(file: &Path) -> Result<()> {
+    match fs::remove_file(file) {
+        Err(error) if error.kind() != ErrorKind::NotFound => {
+            Err(error).with_context(|| format!("Failed to remove {}", file.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn switch_to(repository: &Repository, commit: [u8; 20], target: Target) -> Result<()> {
+    let old_ref = repository.head_ref()?;
+    let old_head = repository.resolve_head()?;
+
+    if let Target::Branch {
+        name,
+        create: false,
+    } = &target
+        && old_ref.as_deref() == Some(format!("refs/heads/{name}").as_str())
+    {
+        eprintln!("{}", tr!("Already on '{}'", name));
+        return run_post_checkout(repository, old_head, commit, true);
+    }
+    if let Target::Branch {
+        name,
+        create: false,
+    } = &target
+        && let Some(other) = checked_out_elsewhere(repository, &format!("refs/heads/{name}"))?
+    {
+        return Err(anyhow!(
+            "fatal: '{}' is already used by worktree at '{}'",
+            name,
+            other.display()
+        ));
+    }
+    if let Target::Branch { name, create: true } = &target
+        && repository
+            .read_ref(&format!("refs/heads/{name}"))?
+            .is_some()
+    {
+        return Err(anyhow!("fatal: a branch named '{}' already exists", name));
+    }
+
+    let current = match old_head {
+        Some(head) => tree_entries(repository, &head)?,
+        None => Vec::new(),
+    };
+    ensure_index_matches(repository, &current, "checkout")?;
+    checkout_entries(
+        repository,
+        &current,
+        &tree_entries(repository, &commit)?,
+        "checkout",
+    )?;
+
+    if old_ref.is_none()
+        && let Some(old_head) = old_head
+        && old_head != commit
+    {
+        warn_orphans(repository, &old_head, &commit)?;
+    }
+
+    let head_file = repository.git_dir.join("HEAD");
+    match &target {
+        Target::Branch { name, create } => {
+            let branch = format!("refs/heads/{name}");
+            if *create {
+                repository.write_ref(&branch, &commit)?;
+            }
+            fs::write(&head_file, format!("ref: {branch}\n")).context("Failed to write HEAD")?;
+
+            if *create {
+                eprintln!("{}", tr!("Switched to a new branch '{}'", name));
+            } else {
+                eprintln!("{}", tr!("Switched to branch '{}'", name));
+            }
+        }
+        Target::Detached { name, advise } => {
+            fs::write(&head_file, format!("{}\n", encode(commit)))
+                .context("Failed to write HEAD")?;
+
+            let advice = Config::load(repository)?
+                .get("advice.detachedhead")
+                .is_none_or(|value| value != "false");
+            if *advise && old_ref.is_some() && advice {
+                eprint!("{}", detached_advice(name));
+            }
+            eprintln!(
+                "{}",
+                tr!("HEAD is now at {}", describe(repository, &commit)?)
+            );
+        }
+    }
+    repository.log_ref_update("HEAD", old_head, Some(commit))?;
+
+    run_post_checkout(repository, old_head, commit, true)
+}
+
+/// Warns about commits that were only reachable from the detached HEAD
+/// being left, and so will no longer be reachable from anything once
+/// HEAD moves to `new_head`.
+fn warn_orphans(repository: &Repository, old_head: &[u8; 20], new_head: &[u8; 20]) -> Result<()> {
+    // Annotated tags keep what they point to reachable; refs to trees and
+    // blobs keep no commit.
+    let mut tips = vec![*new_head];
+    for (_, hash) in repository.list_refs()? {
+        let target = peel_tag(repository, &hash)?.unwrap_or(hash);
+        if repository.read_object_type(&encode(target))? == "commit" {
+            tips.push(target);
+        }
+    }
+
+    let mut kept = HashSet::new();
+    for entry in CommitWalk::new(repository, &tips, WalkOrder::Date)? {
+        kept.insert(entry?.0);
+    }
+
+    let mut orphans = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![*old_head];
+    while let Some(hash) = pending.pop() {
+        if kept.contains(&hash) || !seen.insert(hash) {
+            continue;
+        }
+        pending.extend(repository.read_commit(&hash)?.parents()?);
+        orphans.push(hash);
+    }
+
+    if orphans.is_empty() {
+        eprintln!(
+            "{}",
+            tr!(
+                "Previous HEAD position was {}",
+                describe(repository, old_head)?
+            )
+        );
+        return Ok(());
+    }
+
+    let mut warning = tr_n!(
+        orphans.len(),
+        "Warning: you are leaving {} commit behind, not connected to\n\
+any of your branches:\n\n",
+        "Warning: you are leaving {} commits behind, not connected to\n\
+any of your branches:\n\n",
+        orphans.len()
+    );
+
+    let shown = if orphans.len() == ORPHAN_CUTOFF + 1 {
+        orphans.len()
+    } else {
+        ORPHAN_CUTOFF
+    };
+    for orphan in orphans.iter().take(shown) {
+        warning.push_str(&format!("  {}\n", describe(repository, orphan)?));
+    }
+    if orphans.len() > shown {
+        warning.push_str(&tr!(" ... and {} more.\n", orphans.len() - shown));
+    }
+
+    warning.push_str(&tr_n!(
+        orphans.len(),
+        "\nIf you want to keep it by creating a new branch, this may be a good time\n\
+to do so with:\n\n mini-git switch -c <new-branch-name> {}\n\n",
+        "\nIf you want to keep them by creating a new branch, this may be a good time\n\
+to do so with:\n\n mini-git switch -c <new-branch-name> {}\n\n",
+        &encode(old_head)[..7]
+    ));
+    eprint!("{warning}");
+
+    Ok(())
+}
+
+fn detached_advice(name: &str) -> String {
+    tr!(
+        "Note: switching to '{}'.\n\n\
+You are in 'detached HEAD' state. You can look around, make experimental\n\
+changes and commit them, and you can discard any commits you make in this\n\
+state without impacting any branches by switching back to a branch.\n\n\
+If you want to create a new branch to retain commits you create, you may\n\
+do so (now or later) by using -c with the switch command. Example:\n\n  \
+mini-git switch -c <new-branch-name>\n\n\
+Turn off this advice by setting config variable advice.detachedHead to false\n\n",
+        name
+    )
+}
+
+/// The abbreviated id and subject line of a commit.
+fn describe(repository: &Repository, commit: &[u8; 20]) -> Result<String> {
+    let message = repository.read_commit(commit)?.message();
+    let subject = message.lines().next().unwrap_or_default();
+
+    Ok(format!("{} {}", &encode(commit)[..7], subject))
+}
+
+fn tree_entries(repository: &Repository, commit: &[u8; 20]) -> Result<Vec<TreeEntry>> {
+    let tree = parse_hash(&repository.read_commit(commit)?.tree_hash()?)?;
+    repository.read_tree_recursive(&tree)
+}
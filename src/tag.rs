@@ -0,0 +1,109 @@
+use anyhow::{Context, Result, anyhow};
+use hex::encode;
+
+use crate::{
+    Repository, config::Config, ident::Ident, ref_filter::RefTargets, signature::sign_payload,
+    stripspace::stripspace_str, wildmatch::wildmatch,
+};
+
+/// How `tag` makes a new tag.
+pub struct TagOptions {
+    /// Make an annotated tag object rather than a lightweight tag
+    pub annotate: bool,
+    pub message: Option<String>,
+    /// Sign the tag object with gpg
+    pub sign: bool,
+    /// The key to sign with, which implies `sign`
+    pub local_user: Option<String>,
+}
+
+/// Lists the tags matching any of `args`, or all of them. With
+/// `points_at`, only the tags pointing at that object, directly or as an
+/// annotated tag, are listed.
+///
+/// Without `list` or `points_at`, `args` is `<name> [<commit-ish>]` instead
+/// and a tag is created at the commit-ish, or HEAD: a lightweight one, or a
+/// tag object when `options` ask for an annotated or signed tag.
+pub fn handle_tag_command(
+    args: Vec<String>,
+    list: bool,
+    points_at: Option<String>,
+    options: TagOptions,
+    repository: &Repository,
+) -> Result<()> {
+    if list || points_at.is_some() || args.is_empty() {
+        return list_tags(&args, points_at, repository);
+    }
+
+    let (name, target) = match args.as_slice() {
+        [name] => (name, "HEAD"),
+        [name, target] => (name, target.as_str()),
+        _ => return Err(anyhow!("fatal: too many arguments")),
+    };
+    let ref_name = format!("refs/tags/{name}");
+    if repository.read_ref(&ref_name)?.is_some() {
+        return Err(anyhow!("fatal: tag '{name}' already exists"));
+    }
+    let target = repository.resolve_object(target)?;
+
+    let sign = options.sign || options.local_user.is_some();
+    if !options.annotate && !sign && options.message.is_none() {
+        return repository.write_ref(&ref_name, &target);
+    }
+
+    let message = options
+        .message
+        .ok_or_else(|| anyhow!("fatal: no tag message given; use -m <message>"))?;
+    let config = Config::load(repository)?;
+    let (object_type, _) = repository.read_raw_object(&encode(target))?;
+    let mut content = format!(
+        "object {}\ntype {object_type}\ntag {name}\ntagger {}\n\n{}",
+        encode(target),
+        Ident::committer(&config)?,
+        stripspace_str(&message, false)
+    );
+    if sign {
+        let signature = sign_payload(&config, content.as_bytes(), options.local_user.as_deref())
+            .context("error: unable to sign the tag")?;
+        content.push_str(&signature);
+    }
+
+    let tag = repository.write_raw_object("tag", content.as_bytes())?;
+    repository.write_ref(&ref_name, &tag)
+}
+
+fn list_tags(
+    patterns: &[String],
+    points_at: Option<String>,
+    repository: &Repository,
+) -> Result<()> {
+    let selected = match points_at {
+        Some(object) => {
+            let target = repository.resolve_object(&object)?;
+            Some(RefTargets::load(repository)?.pointing_at(&target).to_vec())
+        }
+        None => None,
+    };
+
+    for (ref_name, _) in repository.list_refs()? {
+        let Some(name) = ref_name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        if !patterns.is_empty()
+            && !patterns
+                .iter()
+                .any(|pattern| wildmatch(pattern, name, false))
+        {
+            continue;
+        }
+        if selected
+            .as_ref()
+            .is_some_and(|selected| !selected.contains(&ref_name))
+        {
+            continue;
+        }
+        println!("{name}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,743 @@
+use anyhow::{Result, anyhow};
+use hex::encode;
+use std::{collections::HashSet, io::Write, path::Path};
+
+use crate::{
+    Repository,
+    bundle::{Bundle, is_bundle},
+    config::Config,
+    git_protocol::GitRemote,
+    http::HttpRemote,
+    merge::is_ancestor,
+    messages::tr,
+    pack::{PackOptions, unpack_pack, write_pack},
+    protocol::RefUpdate,
+    refs::parse_hash,
+    ssh::{SshRemote, is_ssh_url},
+    walk::{CommitWalk, WalkOrder},
+};
+
+/// How many local commits are offered as common ground when fetching.
+const MAX_HAVES: usize = 256;
+
+/// Opens the repository a transfer talks to, after applying any configured
+/// URL rewrites. Only local paths and `file://` URLs are understood.
+pub fn open_remote(config: &Config, url: &str, push: bool) -> Result<Repository> {
+    let url = rewrite_url(config, url, push);
+    let url = url.as_str();
+
+    let path = match url.split_once("://") {
+        Some(("file", path)) => path,
+        Some((scheme, _)) => {
+            return Err(anyhow!(
+                "fatal: unsupported protocol '{}' in '{}'",
+                scheme,
+                url
+            ));
+        }
+        None => url,
+    };
+
+    Repository::open(Path::new(path))
+}
+
+/// Where a fetch reads from: a repository on disk, an HTTP server, a git
+/// daemon, a host reached over ssh, or a bundle file.
+pub enum FetchSource {
+    Local(Repository),
+    Http(HttpRemote),
+    Git(GitRemote),
+    Ssh(SshRemote),
+    Bundle(Bundle),
+}
+
+impl FetchSource {
+    /// Contacts the repository at `url`. Servers are asked for the wire
+    /// protocol version set by `protocol.version`, 2 unless configured
+    /// otherwise; those that do not speak it answer in version 0.
+    pub fn open(config: &Config, url: &str) -> Result<Self> {
+        let version = match config.get("protocol.version") {
+            Some("0") | Some("1") => 0,
+            Some("2") | None => 2,
+            Some(version) => {
+                return Err(anyhow!(
+                    "fatal: unknown value for config 'protocol.version': {}",
+                    version
+                ));
+            }
+        };
+
+        let rewritten = rewrite_url(config, url, false);
+        if rewritten.starts_with("http://") {
+            return Ok(FetchSource::Http(HttpRemote::connect(&rewritten, version)?));
+        }
+        if rewritten.starts_with("git://") {
+            return Ok(FetchSource::Git(GitRemote::connect(&rewritten, version)?));
+        }
+        if is_ssh_url(&rewritten) {
+            return Ok(FetchSource::Ssh(SshRemote::connect(
+                config, &rewritten, version,
+            )?));
+        }
+        let path = Path::new(rewritten.strip_prefix("file://").unwrap_or(&rewritten));
+        if is_bundle(path) {
+            return Ok(FetchSource::Bundle(Bundle::open(path)?));
+        }
+
+        Ok(FetchSource::Local(open_remote(config, url, false)?))
+    }
+
+    pub fn refs(&self) -> Result<Vec<(String, [u8; 20])>> {
+        match self {
+            FetchSource::Local(remote) => remote.list_refs(),
+            FetchSource::Http(remote) => Ok(remote.advertisement.refs.clone()),
+            FetchSource::Git(remote) => Ok(remote.advertisement.refs.clone()),
+            FetchSource::Ssh(remote) => Ok(remote.advertisement.refs.clone()),
+            FetchSource::Bundle(bundle) => Ok(bundle.refs.clone()),
+        }
+    }
+
+    /// The ref the remote's `HEAD` points to, if it is a symbolic ref.
+    pub fn head_ref(&self) -> Result<Option<String>> {
+        match self {
+            FetchSource::Local(remote) => remote.head_ref(),
+            FetchSource::Http(remote) => {
+                Ok(remote.advertisement.symref("HEAD").map(str::to_string))
+            }
+            FetchSource::Git(remote) => Ok(remote.advertisement.symref("HEAD").map(str::to_string)),
+            FetchSource::Ssh(remote) => Ok(remote.advertisement.symref("HEAD").map(str::to_string)),
+            FetchSource::Bundle(bundle) => Ok(bundle.head_ref()),
+        }
+    }
+
+    /// The object a tag ref ultimately names: the target of an annotated
+    /// tag, or the ref's own object for a lightweight one.
+    pub fn peel_tag(&self, name: &str, hash: &[u8; 20]) -> Result<Option<[u8; 20]>> {
+        match self {
+            FetchSource::Local(remote) => {
+                let (object_type, content) = remote.read_raw_object(&encode(hash))?;
+                if object_type != "tag" {
+                    return Ok(Some(*hash));
+                }
+                String::from_utf8_lossy(&content)
+                    .lines()
+                    .find_map(|line| line.strip_prefix("object "))
+                    .map(parse_hash)
+                    .transpose()
+            }
+            FetchSource::Http(remote) => Ok(Some(
+                remote
+                    .advertisement
+                    .peeled
+                    .get(name)
+                    .copied()
+                    .unwrap_or(*hash),
+            )),
+            FetchSource::Git(remote) => Ok(Some(
+                remote
+                    .advertisement
+                    .peeled
+                    .get(name)
+                    .copied()
+                    .unwrap_or(*hash),
+            )),
+            FetchSource::Ssh(remote) => Ok(Some(
+                remote
+                    .advertisement
+                    .peeled
+                    .get(name)
+                    .copied()
+                    .unwrap_or(*hash),
+            )),
+            // A bundle holds no peeled values, but its tags arrive together
+            // with everything else, so a tag can be followed once fetched.
+            FetchSource::Bundle(_) => Ok(Some(*hash)),
+        }
+    }
+
+    /// Brings over everything `to` is missing to reach `tips`, returning the
+    /// number of objects transferred. A `filter` spec such as `blob:none`
+    /// asks a protocol version 2 server to leave some objects out.
+    pub fn fetch_objects(
+        &self,
+        to: &Repository,
+        tips: &[[u8; 20]],
+        filter: Option<&str>,
+    ) -> Result<usize> {
+        match self {
+            FetchSource::Local(remote) => {
+                if filter.is_some() {
+                    eprintln!(
+                        "{}",
+                        tr!("warning: filtering not recognized by server, ignoring")
+                    );
+                }
+                transfer_objects(remote, to, tips)
+            }
+            FetchSource::Http(remote) => remote.fetch_objects(to, tips, filter),
+            FetchSource::Git(remote) => remote.fetch_objects(to, tips, filter),
+            FetchSource::Ssh(remote) => remote.fetch_objects(to, tips, filter),
+            FetchSource::Bundle(bundle) => {
+                if filter.is_some() {
+                    eprintln!(
+                        "{}",
+                        tr!("warning: filtering not recognized by server, ignoring")
+                    );
+                }
+                bundle.fetch_objects(to, tips)
+            }
+        }
+    }
+}
+
+/// Rewrites `url` using the longest matching `url.<base>.insteadOf` prefix.
+/// For pushes, `url.<base>.pushInsteadOf` rules are tried first.
+pub fn rewrite_url(config: &Config, url: &str, push: bool) -> String {
+    let rules: &[&str] = if push {
+        &["pushinsteadof", "insteadof"]
+    } else {
+        &["insteadof"]
+    };
+
+    for rule in rules {
+        let best = config
+            .entries()
+            .filter_map(|(key, prefix)| {
+                let base = key
+                    .strip_prefix("url.")?
+                    .strip_suffix(rule)?
+                    .strip_suffix('.')?;
+                url.starts_with(prefix).then_some((base, prefix))
+            })
+            .max_by_key(|(_, prefix)| prefix.len());
+
+        if let Some((base, prefix)) = best {
+            return format!("{base}{}", &url[prefix.len()..]);
+        }
+    }
+
+    url.to_string()
+}
+
+/// Resolves a configured remote name to its URLs; anything else is taken to
+/// be a URL already. Pushes go to every `pushurl` when any are configured.
+pub fn remote_urls(config: &Config, remote: &str, push: bool) -> Vec<String> {
+    let push_urls = config.get_all(&format!("remote.{remote}.pushurl"));
+    let urls = config.get_all(&format!("remote.{remote}.url"));
+
+    let urls = if push && !push_urls.is_empty() {
+        push_urls
+    } else {
+        urls
+    };
+
+    if urls.is_empty() {
+        vec![remote.to_string()]
+    } else {
+        urls.into_iter().map(str::to_string).collect()
+    }
+}
+
+/// A `[+]<src>:<dst>` pair naming which ref to send and where it should land.
+pub struct Refspec {
+    pub force: bool,
+    pub src: String,
+    pub dst: String,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (src, dst) = spec.split_once(':').unwrap_or((spec, spec));
+
+        if dst.is_empty() {
+            return Err(anyhow!("fatal: invalid refspec '{}'", spec));
+        }
+
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst: full_ref_name(dst),
+        })
+    }
+
+    /// Maps a source ref name to its destination, expanding a trailing `*`
+    /// on both sides, or returns `None` if the refspec does not cover it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        match (self.src.strip_suffix('*'), self.dst.strip_suffix('*')) {
+            (Some(src_prefix), Some(dst_prefix)) => name
+                .strip_prefix(src_prefix)
+                .map(|rest| format!("{dst_prefix}{rest}")),
+            _ => (name == self.src
+                || name == full_ref_name(&self.src)
+                || name == format!("refs/tags/{}", self.src))
+            .then(|| self.dst.clone()),
+        }
+    }
+}
+
+fn full_ref_name(name: &str) -> String {
+    if name.starts_with("refs/") || name == "HEAD" {
+        name.to_string()
+    } else {
+        format!("refs/heads/{name}")
+    }
+}
+
+pub fn short_ref_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or_else(|| name.strip_prefix("refs/tags/"))
+        .unwrap_or(name)
+}
+
+/// Collects every object reachable from `tips` in `from` for which `have`
+/// returns false. Objects that `have` accepts are assumed to come with their
+/// whole history, so the walk stops there. Blobs and trees are named by the
+/// path they were reached through to help the pack writer find deltas.
+pub fn reachable_objects(
+    from: &Repository,
+    tips: &[[u8; 20]],
+    have: impl Fn(&[u8; 20]) -> Result<bool>,
+) -> Result<Vec<([u8; 20], Option<String>)>> {
+    let mut objects = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<[u8; 20]> = tips.to_vec();
+
+    while let Some(hash) = pending.pop() {
+        if seen.contains(&hash) || have(&hash)? {
+            continue;
+        }
+
+        let (object_type, content) = from.read_raw_object(&encode(hash))?;
+        if object_type == "tree" {
+            collect_tree(from, &have, &hash, "", &mut seen, &mut objects)?;
+            continue;
+        }
+        seen.insert(hash);
+        objects.push((hash, None));
+
+        match object_type.as_str() {
+            "commit" => {
+                let commit = from.read_commit(&hash)?;
+                pending.extend(commit.parents()?);
+                collect_tree(
+                    from,
+                    &have,
+                    &parse_hash(&commit.tree_hash()?)?,
+                    "",
+                    &mut seen,
+                    &mut objects,
+                )?;
+            }
+            "tag" => {
+                let target = String::from_utf8_lossy(&content)
+                    .lines()
+                    .find_map(|line| line.strip_prefix("object ").map(str::to_string))
+                    .ok_or_else(|| anyhow!("fatal: tag {} has no object", encode(hash)))?;
+                pending.push(parse_hash(&target)?);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(objects)
+}
+
+fn collect_tree(
+    from: &Repository,
+    have: &impl Fn(&[u8; 20]) -> Result<bool>,
+    tree: &[u8; 20],
+    prefix: &str,
+    seen: &mut HashSet<[u8; 20]>,
+    objects: &mut Vec<([u8; 20], Option<String>)>,
+) -> Result<()> {
+    if !seen.insert(*tree) || have(tree)? {
+        return Ok(());
+    }
+    objects.push((*tree, Some(prefix.to_string())));
+
+    for entry in from.read_tree(tree)? {
+        let path = format!("{prefix}{}", entry.path.display());
+
+        match entry.mode {
+            40000 => collect_tree(from, have, &entry.sha1, &format!("{path}/"), seen, objects)?,
+            160000 => {}
+            _ => {
+                if seen.insert(entry.sha1) && !have(&entry.sha1)? {
+                    objects.push((entry.sha1, Some(path)));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Recent commits reachable from the local branches and remote-tracking
+/// refs, which a server can use to leave out what is already here.
+pub fn local_haves(repository: &Repository) -> Result<Vec<[u8; 20]>> {
+    let tips: Vec<[u8; 20]> = repository
+        .list_refs()?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("refs/heads/") || name.starts_with("refs/remotes/"))
+        .map(|(_, hash)| hash)
+        .collect();
+
+    CommitWalk::new(repository, &tips, WalkOrder::Date)?
+        .limit(Some(MAX_HAVES))
+        .map(|entry| entry.map(|(hash, _)| hash))
+        .collect()
+}
+
+/// Writes the pack that goes with a push of `updates` to a remote whose
+/// refs are `remote_refs`. Everything reachable from a remote ref this
+/// repository also has is left out; a push that only deletes sends none.
+pub fn write_push_pack(
+    repository: &Repository,
+    remote_refs: &[(String, [u8; 20])],
+    updates: &[RefUpdate],
+    out: &mut impl Write,
+) -> Result<()> {
+    let wants: Vec<[u8; 20]> = updates.iter().filter_map(|update| update.new).collect();
+    if wants.is_empty() {
+        return Ok(());
+    }
+
+    let tips: Vec<[u8; 20]> = remote_refs
+        .iter()
+        .map(|(_, hash)| *hash)
+        .filter(|hash| repository.has_object(hash).unwrap_or(false))
+        .collect();
+    let common: HashSet<[u8; 20]> = reachable_objects(repository, &tips, |_| Ok(false))?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+
+    let objects = reachable_objects(repository, &wants, |hash| Ok(common.contains(hash)))?;
+    write_pack(repository, &objects, &PackOptions::default(), out)?;
+    Ok(())
+}
+
+/// Packs up everything `to` is missing to reach `tips` and unpacks it there,
+/// returning the number of objects transferred.
+pub fn transfer_objects(from: &Repository, to: &Repository, tips: &[[u8; 20]]) -> Result<usize> {
+    let objects = reachable_objects(from, tips, |hash| to.has_object(hash))?;
+    if objects.is_empty() {
+        return Ok(0);
+    }
+
+    let mut pack = Vec::new();
+    write_pack(from, &objects, &PackOptions::default(), &mut pack)?;
+    unpack_pack(to, &pack, false)?;
+
+    Ok(objects.len())
+}
+
+pub fn handle_fetch_pack_command(
+    url: String,
+    refs: Vec<String>,
+    all: bool,
+    filter: Option<String>,
+    repository: &Repository,
+) -> Result<()> {
+    let config = Config::load(repository)?;
+    let url = remote_urls(&config, &url, false).pop().unwrap_or(url);
+    let remote = FetchSource::open(&config, &url)?;
+    let remote_refs = remote.refs()?;
+
+    let wanted: Vec<(String, [u8; 20])> = if all {
+        remote_refs
+    } else {
+        refs.iter()
+            .map(|name| {
+                [
+                    name.clone(),
+                    format!("refs/heads/{name}"),
+                    format!("refs/tags/{name}"),
+                ]
+                .into_iter()
+                .find_map(|candidate| {
+                    remote_refs
+                        .iter()
+                        .find(|(remote_name, _)| *remote_name == candidate)
+                        .cloned()
+                })
+                .ok_or_else(|| anyhow!("fatal: no such remote ref {}", name))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    if wanted.is_empty() {
+        return Err(anyhow!("fatal: no matching remote head"));
+    }
+
+    let tips: Vec<[u8; 20]> = wanted.iter().map(|(_, sha1)| *sha1).collect();
+    remote.fetch_objects(repository, &tips, filter.as_deref())?;
+
+    for (name, sha1) in wanted {
+        println!("{} {}", encode(sha1), name);
+    }
+
+    Ok(())
+}
+
+pub fn handle_send_pack_command(
+    url: String,
+    refspecs: Vec<String>,
+    all: bool,
+    force: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let config = Config::load(repository)?;
+
+    let mut specs = refspecs
+        .iter()
+        .map(|spec| Refspec::parse(spec))
+        .collect::<Result<Vec<_>>>()?;
+    if all {
+        specs.extend(
+            repository
+                .list_refs()?
+                .into_iter()
+                .filter(|(name, _)| name.starts_with("refs/heads/"))
+                .map(|(name, _)| Refspec {
+                    force: false,
+                    src: name.clone(),
+                    dst: name,
+                }),
+        );
+    }
+    if specs.is_empty() {
+        return Err(anyhow!("fatal: no refs to push to '{}'", url));
+    }
+
+    let mut failed = Vec::new();
+    for push_url in remote_urls(&config, &url, true) {
+        if send_pack(repository, &config, &push_url, &specs, force)?.rejected {
+            failed.push(push_url);
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "error: failed to push some refs to '{}'",
+            failed.join("', '")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Where a push writes to: a repository on disk, a server speaking the
+/// smart HTTP protocol, or a host reached over ssh.
+enum PushTarget {
+    Local(Repository),
+    Http(HttpRemote),
+    Ssh(SshRemote),
+}
+
+impl PushTarget {
+    fn open(config: &Config, url: &str) -> Result<Self> {
+        let rewritten = rewrite_url(config, url, true);
+        if rewritten.starts_with("http://") {
+            return Ok(PushTarget::Http(HttpRemote::connect_for_push(&rewritten)?));
+        }
+        if is_ssh_url(&rewritten) {
+            return Ok(PushTarget::Ssh(SshRemote::connect_for_push(
+                config, &rewritten,
+            )?));
+        }
+
+        Ok(PushTarget::Local(open_remote(config, url, true)?))
+    }
+
+    fn refs(&self) -> Result<Vec<(String, [u8; 20])>> {
+        match self {
+            PushTarget::Local(remote) => remote.list_refs(),
+            PushTarget::Http(remote) => Ok(remote.advertisement.refs.clone()),
+            PushTarget::Ssh(remote) => Ok(remote.advertisement.refs.clone()),
+        }
+    }
+
+    /// Sends what the remote needs and applies `updates`, returning the
+    /// reason the remote refused each one, if it did.
+    fn update_refs(
+        &self,
+        repository: &Repository,
+        updates: &[RefUpdate],
+    ) -> Result<Vec<Option<String>>> {
+        match self {
+            PushTarget::Local(remote) => {
+                for update in updates {
+                    match update.new {
+                        Some(new) => {
+                            transfer_objects(repository, remote, &[new])?;
+                            remote.write_ref(&update.name, &new)?;
+                        }
+                        None => remote.delete_ref(&update.name)?,
+                    }
+                }
+                Ok(vec![None; updates.len()])
+            }
+            PushTarget::Http(remote) => {
+                Ok(match_statuses(updates, remote.push(repository, updates)?))
+            }
+            PushTarget::Ssh(remote) => {
+                Ok(match_statuses(updates, remote.push(repository, updates)?))
+            }
+        }
+    }
+}
+
+/// Lines up the statuses a server reported with the updates they are for.
+fn match_statuses(
+    updates: &[RefUpdate],
+    statuses: Vec<(String, Option<String>)>,
+) -> Vec<Option<String>> {
+    updates
+        .iter()
+        .map(|update| {
+            statuses
+                .iter()
+                .find(|(name, _)| *name == update.name)
+                .map_or(Some("no status reported".to_string()), |(_, reason)| {
+                    reason.clone()
+                })
+        })
+        .collect()
+}
+
+pub struct PushResult {
+    /// Refs the remote accepted, with what they now point to.
+    pub updated: Vec<(String, Option<[u8; 20]>)>,
+    pub rejected: bool,
+}
+
+/// Updates refs at `url` as described by `specs`, printing a summary line
+/// per ref. Updates that are not fast-forwards are refused unless forced.
+pub fn send_pack(
+    repository: &Repository,
+    config: &Config,
+    url: &str,
+    specs: &[Refspec],
+    force: bool,
+) -> Result<PushResult> {
+    let remote = PushTarget::open(config, url)?;
+    let remote_refs = remote.refs()?;
+    let mut lines = Vec::new();
+    let mut result = PushResult {
+        updated: Vec::new(),
+        rejected: false,
+    };
+    let mut updates = Vec::new();
+    let mut labels = Vec::new();
+
+    for spec in specs {
+        let old = remote_refs
+            .iter()
+            .find(|(name, _)| *name == spec.dst)
+            .map(|(_, hash)| *hash);
+        let label = if spec.src.is_empty() {
+            short_ref_name(&spec.dst).to_string()
+        } else {
+            format!(
+                "{} -> {}",
+                short_ref_name(&spec.src),
+                short_ref_name(&spec.dst)
+            )
+        };
+
+        if spec.src.is_empty() {
+            if old.is_some() {
+                updates.push(RefUpdate {
+                    name: spec.dst.clone(),
+                    old,
+                    new: None,
+                });
+                labels.push((label, false));
+            }
+            continue;
+        }
+
+        let new = repository.resolve_object(&spec.src)?;
+        if old == Some(new) {
+            continue;
+        }
+
+        let fast_forward = match old {
+            Some(old) => repository.has_object(&old)? && is_ancestor(repository, &old, &new)?,
+            None => true,
+        };
+
+        if !fast_forward && !force && !spec.force {
+            let reason = if old.is_some_and(|old| repository.has_object(&old).unwrap_or(false)) {
+                "non-fast-forward"
+            } else {
+                "fetch first"
+            };
+            lines.push(format!(" ! {:<17} {} ({})", "[rejected]", label, reason));
+            result.rejected = true;
+            continue;
+        }
+
+        updates.push(RefUpdate {
+            name: spec.dst.clone(),
+            old,
+            new: Some(new),
+        });
+        labels.push((label, fast_forward));
+    }
+
+    let statuses = if updates.is_empty() {
+        Vec::new()
+    } else {
+        remote.update_refs(repository, &updates)?
+    };
+
+    for ((update, (label, fast_forward)), status) in updates.iter().zip(labels).zip(statuses) {
+        if let Some(reason) = status {
+            lines.push(format!(
+                " ! {:<17} {} ({})",
+                "[remote rejected]", label, reason
+            ));
+            result.rejected = true;
+            continue;
+        }
+        result.updated.push((update.name.clone(), update.new));
+
+        match (update.old, update.new) {
+            (_, None) => lines.push(format!(" - {:<17} {}", "[deleted]", label)),
+            (None, Some(_)) => {
+                let kind = if update.name.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else {
+                    "[new branch]"
+                };
+                lines.push(format!(" * {kind:<17} {label}"));
+            }
+            (Some(old), Some(new)) => {
+                let range = format!("{}..{}", &encode(old)[..7], &encode(new)[..7]);
+                if fast_forward {
+                    lines.push(format!("   {range:<17} {label}"));
+                } else {
+                    lines.push(format!(" + {range:<17} {label} (forced update)"));
+                }
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        eprintln!("{}", tr!("Everything up-to-date"));
+        return Ok(result);
+    }
+
+    eprintln!("{}", tr!("To {}", url));
+    for line in lines {
+        eprintln!("{line}");
+    }
+
+    Ok(result)
+}
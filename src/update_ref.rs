@@ -0,0 +1,150 @@
+use anyhow::{Context, Result, anyhow};
+use std::io::{self, BufRead};
+
+use crate::{
+    Repository,
+    refs::{Expected, RefTransaction},
+};
+
+const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// Points the ref `args[0]` at `args[1]`, or with `delete` deletes it, in
+/// either case only if it is at the optional last argument, where the zero
+/// hash means the ref must not exist.
+///
+/// With `stdin`, the changes are read from stdin instead, one command per
+/// line, and made together or not at all once all of them have been read:
+///
+/// - `update <ref> <new> [<old>]`
+/// - `create <ref> <new>`
+/// - `delete <ref> [<old>]`
+/// - `verify <ref> [<old>]`, which fails the whole transaction unless the
+///   ref is at `<old>`, or without it, does not exist.
+pub fn handle_update_ref_command(
+    args: Vec<String>,
+    delete: bool,
+    stdin: bool,
+    repository: &Repository,
+) -> Result<()> {
+    let mut transaction = repository.transaction();
+
+    if stdin {
+        if !args.is_empty() {
+            return Err(anyhow!("fatal: --stdin takes no arguments"));
+        }
+        for line in io::stdin().lock().lines() {
+            let line = line.context("Failed to read from stdin")?;
+            queue_command(&mut transaction, &line, repository)?;
+        }
+        return transaction.commit();
+    }
+
+    match (delete, args.as_slice()) {
+        (true, [name, rest @ ..]) if rest.len() <= 1 => {
+            let old = match rest.first() {
+                Some(old) => expected(repository, old)
+                    .ok_or_else(|| anyhow!("fatal: {old}: not a valid SHA1"))?,
+                None => Expected::Any,
+            };
+            transaction.update(name, None, old)?;
+        }
+        (false, [name, new, rest @ ..]) if rest.len() <= 1 => {
+            let new = repository
+                .resolve_object(new)
+                .map_err(|_| anyhow!("fatal: {new}: not a valid SHA1"))?;
+            let old = match rest.first() {
+                Some(old) => expected(repository, old)
+                    .ok_or_else(|| anyhow!("fatal: {old}: not a valid SHA1"))?,
+                None => Expected::Any,
+            };
+            transaction.update(name, Some(new), old)?;
+        }
+        _ => {
+            return Err(anyhow!(
+                "usage: mini-git update-ref [-d] <ref> [<new>] [<old>]\n   \
+                 or: mini-git update-ref --stdin"
+            ));
+        }
+    }
+
+    transaction.commit()
+}
+
+/// Queues the change one line of `update-ref --stdin` input asks for.
+fn queue_command(
+    transaction: &mut RefTransaction,
+    line: &str,
+    repository: &Repository,
+) -> Result<()> {
+    if line.is_empty() {
+        return Err(anyhow!("fatal: empty command in input"));
+    }
+    let mut words = line.split(' ');
+    let command = words.next().unwrap_or_default();
+    if !matches!(command, "update" | "create" | "delete" | "verify") {
+        return Err(anyhow!("fatal: unknown command: {line}"));
+    }
+    let name = words
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("fatal: {command}: missing <ref>"))?;
+
+    let new = if matches!(command, "update" | "create") {
+        let new = words
+            .next()
+            .ok_or_else(|| anyhow!("fatal: {command} {name}: missing <newvalue>"))?;
+        if new == ZERO_HASH {
+            if command == "create" {
+                return Err(anyhow!("fatal: {command} {name}: zero <newvalue>"));
+            }
+            None
+        } else {
+            Some(
+                repository
+                    .resolve_object(new)
+                    .map_err(|_| anyhow!("fatal: {command} {name}: invalid <newvalue>: {new}"))?,
+            )
+        }
+    } else {
+        None
+    };
+
+    let old = if command == "create" {
+        Expected::Missing
+    } else {
+        match words.next() {
+            Some(old) => {
+                if command == "delete" && old == ZERO_HASH {
+                    return Err(anyhow!("fatal: {command} {name}: zero <oldvalue>"));
+                }
+                expected(repository, old)
+                    .ok_or_else(|| anyhow!("fatal: {command} {name}: invalid <oldvalue>: {old}"))?
+            }
+            None if command == "verify" => Expected::Missing,
+            None => Expected::Any,
+        }
+    };
+
+    let extra: Vec<&str> = words.collect();
+    if !extra.is_empty() {
+        return Err(anyhow!(
+            "fatal: {command} {name}: extra input: {}",
+            extra.join(" ")
+        ));
+    }
+
+    if command == "verify" {
+        transaction.verify(name, old)
+    } else {
+        transaction.update(name, new, old)
+    }
+}
+
+/// What an old value given as `value` expects of a ref: the zero hash
+/// means that it does not exist.
+fn expected(repository: &Repository, value: &str) -> Option<Expected> {
+    if value == ZERO_HASH {
+        return Some(Expected::Missing);
+    }
+    repository.resolve_object(value).ok().map(Expected::At)
+}
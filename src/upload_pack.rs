@@ -0,0 +1,509 @@
+use anyhow::{Result, anyhow};
+use hex::encode;
+use std::{
+    collections::HashSet,
+    env,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    Repository,
+    pack::{PackOptions, write_pack},
+    protocol::{
+        Packet, SIDEBAND_DATA_MAX, SidebandWriter, read_packet, read_pkt_line, write_delim,
+        write_flush, write_pkt_line,
+    },
+    refs::parse_hash,
+    transport::reachable_objects,
+};
+
+const CAPABILITIES: &[&str] = &["ofs-delta", "agent=mini-git/0.1"];
+
+/// What a protocol version 2 server offers, one line each.
+const CAPABILITIES_V2: &[&str] = &[
+    "version 2",
+    "agent=mini-git/0.1",
+    "ls-refs",
+    "fetch=filter",
+    "object-format=sha1",
+];
+
+/// Serves a fetch from the repository at `directory` over stdin and
+/// stdout, for running at the far end of an ssh connection. A client asks
+/// for protocol version 2 through `GIT_PROTOCOL`.
+pub fn handle_upload_pack_command(directory: &Path) -> Result<()> {
+    let repository = Repository::open(directory)?;
+    let version = env::var("GIT_PROTOCOL")
+        .ok()
+        .map_or(0, |parameters| requested_version(parameters.split(':')));
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    serve_upload_pack(&repository, version, &mut io::stdin().lock(), &mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// The protocol version asked for by the `version=<n>` parameter among
+/// `parameters`, if it is one this server speaks, or version 0.
+pub fn requested_version<'a>(parameters: impl Iterator<Item = &'a str>) -> u8 {
+    let mut versions = parameters.filter_map(|parameter| parameter.strip_prefix("version="));
+    if versions.any(|version| version == "2") {
+        2
+    } else {
+        0
+    }
+}
+
+/// Serves one fetch from `repository` over an upload-pack conversation:
+/// advertises its refs, reads the client's wants and haves, and sends a
+/// pack of everything the client lacks. The server does not keep state
+/// between rounds, so it answers as if the client had no `multi_ack`.
+/// Under protocol version 2 the conversation is a series of commands
+/// instead.
+pub fn serve_upload_pack(
+    repository: &Repository,
+    version: u8,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<()> {
+    if version == 2 {
+        return serve_v2(repository, input, output);
+    }
+
+    write_ref_advertisement(repository, output)?;
+    output.flush()?;
+
+    let mut wants = Vec::new();
+    while let Some(line) = read_pkt_line(input)? {
+        let line = String::from_utf8_lossy(&line);
+        let want = line
+            .trim_end()
+            .strip_prefix("want ")
+            .ok_or_else(|| anyhow!("fatal: protocol error: expected want, got '{}'", line))?;
+        let want = parse_hash(want.split(' ').next().unwrap_or_default())?;
+
+        if !repository.has_object(&want)? {
+            write_pkt_line(
+                output,
+                format!("ERR upload-pack: not our ref {}\n", encode(want)).as_bytes(),
+            )?;
+            return Err(anyhow!(
+                "fatal: client wants unknown object {}",
+                encode(want)
+            ));
+        }
+        wants.push(want);
+    }
+
+    // A flush in place of any wants means the client only wanted the refs.
+    if wants.is_empty() {
+        return Ok(());
+    }
+
+    let mut common = Vec::new();
+    loop {
+        let Some(line) = read_pkt_line(input)? else {
+            if common.is_empty() {
+                write_pkt_line(output, b"NAK\n")?;
+                output.flush()?;
+            }
+            continue;
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+
+        if line == "done" {
+            if common.is_empty() {
+                write_pkt_line(output, b"NAK\n")?;
+            }
+            break;
+        }
+
+        let have = line
+            .strip_prefix("have ")
+            .ok_or_else(|| anyhow!("fatal: protocol error: expected have, got '{}'", line))?;
+        let have = parse_hash(have)?;
+        if repository.has_object(&have)? {
+            if common.is_empty() {
+                write_pkt_line(output, format!("ACK {}\n", encode(have)).as_bytes())?;
+                output.flush()?;
+            }
+            common.push(have);
+        }
+    }
+
+    // Everything reachable from a commit the client has is left out.
+    let shared: HashSet<[u8; 20]> = reachable_objects(repository, &common, |_| Ok(false))?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    let objects = reachable_objects(repository, &wants, |hash| Ok(shared.contains(hash)))?;
+
+    write_pack(repository, &objects, &PackOptions::default(), output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Advertises the version 2 capabilities, then answers `ls-refs` and
+/// `fetch` commands until the client hangs up or sends a flush in place of
+/// a command. Each command stands alone: nothing is remembered between
+/// them.
+fn serve_v2(repository: &Repository, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+    for capability in CAPABILITIES_V2 {
+        write_pkt_line(output, format!("{capability}\n").as_bytes())?;
+    }
+    write_flush(output)?;
+    output.flush()?;
+
+    while let Some(request) = read_command(input)? {
+        match request.command.as_str() {
+            "ls-refs" => ls_refs(repository, &request.arguments, output)?,
+            "fetch" => fetch(repository, &request.arguments, output)?,
+            command => {
+                write_pkt_line(
+                    output,
+                    format!("ERR unknown command '{command}'\n").as_bytes(),
+                )?;
+                return Err(anyhow!("fatal: invalid command '{}'", command));
+            }
+        }
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// A protocol version 2 command with its arguments. The capabilities sent
+/// with it are not needed by any command this server runs.
+struct CommandRequest {
+    command: String,
+    arguments: Vec<String>,
+}
+
+/// Reads the next command, or `None` when the client is done.
+fn read_command(input: &mut impl Read) -> Result<Option<CommandRequest>> {
+    let mut command = None;
+    let mut arguments = Vec::new();
+    let mut in_arguments = false;
+
+    loop {
+        let line = match read_packet(input)? {
+            None | Some(Packet::Flush) if command.is_none() => return Ok(None),
+            None => return Err(anyhow!("fatal: the remote end hung up unexpectedly")),
+            Some(Packet::Flush) => break,
+            Some(Packet::Delim) => {
+                in_arguments = true;
+                continue;
+            }
+            Some(Packet::Data(line)) => String::from_utf8_lossy(&line).trim_end().to_string(),
+        };
+
+        if in_arguments {
+            arguments.push(line);
+        } else if let Some(name) = line.strip_prefix("command=") {
+            command = Some(name.to_string());
+        }
+    }
+
+    let command = command.ok_or_else(|| anyhow!("fatal: protocol error: no command requested"))?;
+    Ok(Some(CommandRequest { command, arguments }))
+}
+
+/// Lists HEAD and the refs matching any `ref-prefix` argument, or every
+/// ref without one. `symrefs` adds where HEAD points and `peel` what
+/// annotated tags point to.
+fn ls_refs(repository: &Repository, arguments: &[String], output: &mut impl Write) -> Result<()> {
+    let mut symrefs = false;
+    let mut peel = false;
+    let mut prefixes = Vec::new();
+    for argument in arguments {
+        match argument.as_str() {
+            "symrefs" => symrefs = true,
+            "peel" => peel = true,
+            "unborn" => {}
+            _ => match argument.strip_prefix("ref-prefix ") {
+                Some(prefix) => prefixes.push(prefix),
+                None => {
+                    return Err(anyhow!(
+                        "fatal: ls-refs: unexpected argument: '{}'",
+                        argument
+                    ));
+                }
+            },
+        }
+    }
+
+    let mut refs = Vec::new();
+    if let Some(head) = repository.resolve_head()? {
+        refs.push(("HEAD".to_string(), head));
+    }
+    refs.extend(repository.list_refs()?);
+
+    let head_ref = repository.head_ref()?;
+    for (name, hash) in refs {
+        if !prefixes.is_empty() && !prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+
+        let mut line = format!("{} {name}", encode(hash));
+        if symrefs
+            && name == "HEAD"
+            && let Some(target) = &head_ref
+        {
+            line.push_str(&format!(" symref-target:{target}"));
+        }
+        if peel && let Some(target) = peel_tag(repository, &hash)? {
+            line.push_str(&format!(" peeled:{}", encode(target)));
+        }
+        line.push('\n');
+        write_pkt_line(output, line.as_bytes())?;
+    }
+
+    write_flush(output)
+}
+
+/// Answers a `fetch` command. Until the client says `done`, each round is
+/// answered with the haves this server also has; as soon as one is found
+/// the server declares itself ready and sends the pack in the same reply.
+fn fetch(repository: &Repository, arguments: &[String], output: &mut impl Write) -> Result<()> {
+    let mut wants = Vec::new();
+    let mut haves = Vec::new();
+    let mut done = false;
+    let mut include_tag = false;
+    let mut filter = None;
+
+    for argument in arguments {
+        let (name, value) = argument.split_once(' ').unwrap_or((argument, ""));
+        match name {
+            "want" => wants.push(parse_hash(value)?),
+            "have" => haves.push(parse_hash(value)?),
+            "done" => done = true,
+            "include-tag" => include_tag = true,
+            "filter" => filter = Some(ObjectFilter::parse(value)?),
+            "ofs-delta" | "thin-pack" | "no-progress" => {}
+            _ => {
+                write_pkt_line(
+                    output,
+                    format!("ERR fetch: unexpected argument: '{argument}'\n").as_bytes(),
+                )?;
+                return Err(anyhow!("fatal: fetch: unexpected argument: '{}'", argument));
+            }
+        }
+    }
+
+    for want in &wants {
+        if !repository.has_object(want)? {
+            write_pkt_line(
+                output,
+                format!("ERR upload-pack: not our ref {}\n", encode(want)).as_bytes(),
+            )?;
+            return Err(anyhow!(
+                "fatal: client wants unknown object {}",
+                encode(want)
+            ));
+        }
+    }
+
+    let mut common = Vec::new();
+    for have in haves {
+        if repository.has_object(&have)? {
+            common.push(have);
+        }
+    }
+
+    if !done {
+        write_pkt_line(output, b"acknowledgments\n")?;
+        if common.is_empty() {
+            write_pkt_line(output, b"NAK\n")?;
+            return write_flush(output);
+        }
+        for have in &common {
+            write_pkt_line(output, format!("ACK {}\n", encode(have)).as_bytes())?;
+        }
+        write_pkt_line(output, b"ready\n")?;
+        write_delim(output)?;
+    }
+
+    let shared: HashSet<[u8; 20]> = reachable_objects(repository, &common, |_| Ok(false))?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    let mut objects = reachable_objects(repository, &wants, |hash| Ok(shared.contains(hash)))?;
+    if include_tag {
+        objects.extend(followed_tags(repository, &objects, &shared)?);
+    }
+    if let Some(filter) = &filter {
+        let mut kept = Vec::with_capacity(objects.len());
+        for object in objects {
+            if filter.keeps(repository, &object)? {
+                kept.push(object);
+            }
+        }
+        objects = kept;
+    }
+
+    write_pkt_line(output, b"packfile\n")?;
+    let mut sideband =
+        BufWriter::with_capacity(SIDEBAND_DATA_MAX, SidebandWriter::new(&mut *output));
+    write_pack(repository, &objects, &PackOptions::default(), &mut sideband)?;
+    sideband.flush()?;
+    drop(sideband);
+
+    write_flush(output)
+}
+
+/// Annotated tags the client does not have that point to one of `objects`,
+/// so that it can follow them without asking again.
+fn followed_tags(
+    repository: &Repository,
+    objects: &[([u8; 20], Option<String>)],
+    shared: &HashSet<[u8; 20]>,
+) -> Result<Vec<([u8; 20], Option<String>)>> {
+    let sent: HashSet<[u8; 20]> = objects.iter().map(|(hash, _)| *hash).collect();
+
+    let mut tags = Vec::new();
+    for (name, hash) in repository.list_refs()? {
+        if !name.starts_with("refs/tags/") || sent.contains(&hash) || shared.contains(&hash) {
+            continue;
+        }
+        if let Some(target) = peel_tag(repository, &hash)?
+            && sent.contains(&target)
+        {
+            tags.push((hash, None));
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Which objects a client asked to be left out of a fetch.
+enum ObjectFilter {
+    /// `blob:none`: no blobs at all.
+    NoBlobs,
+    /// `blob:limit=<n>`: no blobs of `n` bytes or more.
+    BlobLimit(usize),
+    /// `tree:<depth>`: no trees or blobs at `depth` or more below a root
+    /// tree.
+    TreeDepth(usize),
+}
+
+impl ObjectFilter {
+    fn parse(spec: &str) -> Result<Self> {
+        let invalid = || anyhow!("fatal: invalid filter-spec '{}'", spec);
+
+        if spec == "blob:none" {
+            return Ok(ObjectFilter::NoBlobs);
+        }
+        if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            let (digits, unit) = match limit.char_indices().last() {
+                Some((i, unit)) if unit.is_ascii_alphabetic() => (&limit[..i], unit),
+                _ => (limit, 'b'),
+            };
+            let scale = match unit.to_ascii_lowercase() {
+                'b' => 1,
+                'k' => 1 << 10,
+                'm' => 1 << 20,
+                'g' => 1 << 30,
+                _ => return Err(invalid()),
+            };
+            let limit: usize = digits.parse().map_err(|_| invalid())?;
+            return Ok(ObjectFilter::BlobLimit(limit.saturating_mul(scale)));
+        }
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            return Ok(ObjectFilter::TreeDepth(
+                depth.parse().map_err(|_| invalid())?,
+            ));
+        }
+
+        Err(invalid())
+    }
+
+    /// Whether `object`, as named by [`reachable_objects`], is still sent.
+    /// Objects reached other than through a tree, such as a blob a tag
+    /// points to, are always sent.
+    fn keeps(
+        &self,
+        repository: &Repository,
+        (hash, path): &([u8; 20], Option<String>),
+    ) -> Result<bool> {
+        let Some(path) = path else {
+            return Ok(true);
+        };
+        // Trees are named with a trailing slash and the root tree by an
+        // empty path, so a blob lies one level below its last slash.
+        let is_tree = path.is_empty() || path.ends_with('/');
+        let depth = path.matches('/').count() + usize::from(!is_tree);
+
+        Ok(match self {
+            ObjectFilter::NoBlobs => is_tree,
+            ObjectFilter::BlobLimit(limit) => {
+                is_tree || repository.read_raw_object(&encode(hash))?.1.len() < *limit
+            }
+            ObjectFilter::TreeDepth(max) => depth < *max,
+        })
+    }
+}
+
+/// Writes HEAD and every ref with the capabilities on the first line, plus
+/// a peeled `^{}` line after each annotated tag. An empty repository
+/// advertises only its capabilities, on a placeholder line.
+fn write_ref_advertisement(repository: &Repository, output: &mut impl Write) -> Result<()> {
+    let mut refs = Vec::new();
+    if let Some(head) = repository.resolve_head()? {
+        refs.push(("HEAD".to_string(), head));
+    }
+    refs.extend(repository.list_refs()?);
+
+    let mut capabilities = CAPABILITIES.join(" ");
+    if let Some(head_ref) = repository.head_ref()? {
+        capabilities.push_str(&format!(" symref=HEAD:{head_ref}"));
+    }
+
+    if refs.is_empty() {
+        let line = format!("{} capabilities^{{}}\0{capabilities}\n", encode([0u8; 20]));
+        write_pkt_line(output, line.as_bytes())?;
+        return write_flush(output);
+    }
+
+    for (i, (name, hash)) in refs.iter().enumerate() {
+        let line = if i == 0 {
+            format!("{} {name}\0{capabilities}\n", encode(hash))
+        } else {
+            format!("{} {name}\n", encode(hash))
+        };
+        write_pkt_line(output, line.as_bytes())?;
+
+        if let Some(target) = peel_tag(repository, hash)? {
+            write_pkt_line(
+                output,
+                format!("{} {name}^{{}}\n", encode(target)).as_bytes(),
+            )?;
+        }
+    }
+
+    write_flush(output)
+}
+
+/// The object an annotated tag ultimately points to, or `None` for anything
+/// that is not a tag object.
+pub fn peel_tag(repository: &Repository, hash: &[u8; 20]) -> Result<Option<[u8; 20]>> {
+    let mut target = *hash;
+    let mut peeled = false;
+
+    loop {
+        let (object_type, content) = repository.read_raw_object(&encode(target))?;
+        if object_type != "tag" {
+            return Ok(peeled.then_some(target));
+        }
+
+        let object = String::from_utf8_lossy(&content)
+            .lines()
+            .find_map(|line| line.strip_prefix("object ").map(str::to_string))
+            .ok_or_else(|| anyhow!("fatal: tag {} has no object", encode(target)))?;
+        target = parse_hash(&object)?;
+        peeled = true;
+    }
+}
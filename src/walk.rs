@@ -0,0 +1,332 @@
+use anyhow::{Result, anyhow};
+use hex::encode;
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use crate::{CommitObject, Repository, ident::Ident, refs::parse_hash};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Newest committer date first; parents may appear before all of their
+    /// children when clocks are skewed.
+    Date,
+    /// Children always before their parents, without intermixing commits
+    /// from different lines of history.
+    Topo,
+}
+
+/// Where a walk left off, so that the next page of history can be read
+/// without walking again from the original tips: the commits the walk was
+/// about to visit, how many it had already yielded, and the yielded ones
+/// the pending commits may still lead back to. Written as
+/// `<count>:<hash>[,<hash>...]`, followed by `:<hash>[,<hash>...]` when
+/// there are commits to skip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkCursor {
+    pub pending: Vec<[u8; 20]>,
+    pub count: usize,
+    pub skip: Vec<[u8; 20]>,
+}
+
+impl WalkCursor {
+    /// Whether the walk has nothing left to yield.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl fmt::Display for WalkCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pending: Vec<String> = self.pending.iter().map(encode).collect();
+        write!(f, "{}:{}", self.count, pending.join(","))?;
+        if !self.skip.is_empty() {
+            let skip: Vec<String> = self.skip.iter().map(encode).collect();
+            write!(f, ":{}", skip.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for WalkCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(cursor: &str) -> Result<Self> {
+        let invalid = || anyhow!("fatal: invalid cursor '{}'", cursor);
+
+        let (count, rest) = cursor.split_once(':').ok_or_else(invalid)?;
+        let (pending, skip) = rest.split_once(':').unwrap_or((rest, ""));
+        let hashes = |list: &str| -> Result<Vec<[u8; 20]>> {
+            match list {
+                "" => Ok(Vec::new()),
+                list => list
+                    .split(',')
+                    .map(parse_hash)
+                    .collect::<Result<_>>()
+                    .map_err(|_| invalid()),
+            }
+        };
+        let (pending, skip) = (hashes(pending)?, hashes(skip)?);
+
+        Ok(WalkCursor {
+            pending,
+            count: count.parse().map_err(|_| invalid())?,
+            skip,
+        })
+    }
+}
+
+/// Lazily walks the history reachable from a set of tips, reading commits
+/// only as they are needed. Topological order has to see the whole history
+/// before it can yield anything, and so does reversing the walk.
+pub struct CommitWalk<'a> {
+    repository: &'a Repository,
+    order: WalkOrder,
+    queue: BinaryHeap<(i64, [u8; 20])>,
+    /// The starting commits in the order given, which topological order
+    /// takes them in.
+    tips: Vec<[u8; 20]>,
+    seen: HashSet<[u8; 20]>,
+    limit: Option<usize>,
+    reverse: bool,
+    /// Commits yielded by the walks this one resumes.
+    offset: usize,
+    yielded: usize,
+    /// The commits yielded in date order, with their dates, including the
+    /// ones a resumed cursor said to skip.
+    visited: Vec<(i64, [u8; 20])>,
+    topo_ordered: Option<Vec<([u8; 20], CommitObject)>>,
+    reversed: Option<Vec<([u8; 20], CommitObject)>>,
+}
+
+impl<'a> CommitWalk<'a> {
+    pub fn new(repository: &'a Repository, tips: &[[u8; 20]], order: WalkOrder) -> Result<Self> {
+        let mut walk = CommitWalk {
+            repository,
+            order,
+            queue: BinaryHeap::new(),
+            tips: Vec::new(),
+            seen: HashSet::new(),
+            limit: None,
+            reverse: false,
+            offset: 0,
+            yielded: 0,
+            visited: Vec::new(),
+            topo_ordered: None,
+            reversed: None,
+        };
+
+        for tip in tips {
+            if walk.seen.insert(*tip) {
+                let commit = repository.read_commit(tip)?;
+                walk.queue.push((commit_time(&commit), *tip));
+                walk.tips.push(*tip);
+            }
+        }
+
+        Ok(walk)
+    }
+
+    /// Picks up a walk where `cursor` was taken. Commits the earlier walk
+    /// yielded are not yielded again: the ones the pending commits can
+    /// still reach are in the cursor's skip list.
+    pub fn resume(
+        repository: &'a Repository,
+        cursor: &WalkCursor,
+        order: WalkOrder,
+    ) -> Result<Self> {
+        let mut walk = Self::new(repository, &cursor.pending, order)?;
+        walk.offset = cursor.count;
+        for hash in &cursor.skip {
+            if walk.seen.insert(*hash) {
+                let commit = repository.read_commit(hash)?;
+                walk.visited.push((commit_time(&commit), *hash));
+            }
+        }
+        Ok(walk)
+    }
+
+    /// Where the walk stands now, for resuming it later. A reversed walk
+    /// has already visited everything by the time it yields, so it has no
+    /// cursor.
+    pub fn cursor(&self) -> Option<WalkCursor> {
+        if self.reverse {
+            return None;
+        }
+
+        let (pending, skip) = match &self.topo_ordered {
+            // Resuming from the commits whose children have all been
+            // yielded reaches exactly the ones that are left, and taking
+            // them in the order they were due keeps that order. No yielded
+            // commit is an ancestor of one that is left.
+            Some(ordered) => {
+                let mut parents = HashSet::new();
+                for (_, commit) in ordered {
+                    parents.extend(commit.parents().ok()?);
+                }
+                let pending = ordered
+                    .iter()
+                    .rev()
+                    .map(|(hash, _)| *hash)
+                    .filter(|hash| !parents.contains(hash))
+                    .collect();
+                (pending, Vec::new())
+            }
+            // A pending commit can lead back to a yielded one dated no
+            // later than itself, such as a commit made in the same second
+            // as its child, so those are skipped on resuming.
+            None => {
+                let mut queue = self.queue.clone().into_sorted_vec();
+                queue.reverse();
+                let newest = queue.first().map_or(i64::MIN, |(time, _)| *time);
+                let skip = self
+                    .visited
+                    .iter()
+                    .filter(|(time, _)| *time <= newest)
+                    .map(|(_, hash)| *hash)
+                    .collect();
+                (queue.into_iter().map(|(_, hash)| hash).collect(), skip)
+            }
+        };
+
+        Some(WalkCursor {
+            pending,
+            count: self.offset + self.yielded,
+            skip,
+        })
+    }
+
+    /// Stops after `limit` commits. The limit applies before reversing, so
+    /// a reversed walk yields the oldest of the newest `limit` commits first.
+    pub fn limit(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    fn next_by_date(&mut self) -> Result<Option<([u8; 20], CommitObject)>> {
+        let Some((_, hash)) = self.queue.pop() else {
+            return Ok(None);
+        };
+
+        let commit = self.repository.read_commit(&hash)?;
+        self.visited.push((commit_time(&commit), hash));
+        for parent in commit.parents()? {
+            if self.seen.insert(parent) {
+                let parent_commit = self.repository.read_commit(&parent)?;
+                self.queue.push((commit_time(&parent_commit), parent));
+            }
+        }
+
+        Ok(Some((hash, commit)))
+    }
+
+    /// Reads every reachable commit and counts how many children each has,
+    /// so that a commit is only yielded once all of its children have been.
+    fn load_for_topo(&mut self) -> Result<()> {
+        let mut commits = HashMap::new();
+        let mut children_left: HashMap<[u8; 20], usize> = HashMap::new();
+        let mut pending: Vec<[u8; 20]> = self.queue.iter().map(|(_, hash)| *hash).collect();
+
+        while let Some(hash) = pending.pop() {
+            if commits.contains_key(&hash) {
+                continue;
+            }
+            let commit = self.repository.read_commit(&hash)?;
+            for parent in commit.parents()? {
+                *children_left.entry(parent).or_default() += 1;
+                pending.push(parent);
+            }
+            commits.insert(hash, commit);
+        }
+
+        // Ready commits are taken from a stack rather than by date, which
+        // keeps each line of history together instead of interleaving them.
+        self.queue.clear();
+        let mut ready: Vec<[u8; 20]> = self
+            .tips
+            .iter()
+            .rev()
+            .copied()
+            .filter(|hash| !children_left.contains_key(hash))
+            .collect();
+
+        let mut ordered = Vec::with_capacity(commits.len());
+        while let Some(hash) = ready.pop() {
+            let commit = commits
+                .remove(&hash)
+                .expect("every ready commit was loaded");
+            for parent in commit.parents()? {
+                let left = children_left.entry(parent).or_default();
+                *left -= 1;
+                if *left == 0 {
+                    ready.push(parent);
+                }
+            }
+            ordered.push((hash, commit));
+        }
+
+        ordered.reverse();
+        self.topo_ordered = Some(ordered);
+        Ok(())
+    }
+
+    fn next_commit(&mut self) -> Result<Option<([u8; 20], CommitObject)>> {
+        if !self.reverse {
+            return self.next_unreversed();
+        }
+
+        if self.reversed.is_none() {
+            let mut commits = Vec::new();
+            while let Some(entry) = self.next_unreversed()? {
+                commits.push(entry);
+            }
+            self.reversed = Some(commits);
+        }
+
+        Ok(self.reversed.as_mut().and_then(Vec::pop))
+    }
+
+    fn next_unreversed(&mut self) -> Result<Option<([u8; 20], CommitObject)>> {
+        if self.limit.is_some_and(|limit| self.yielded >= limit) {
+            return Ok(None);
+        }
+
+        let next = match self.order {
+            WalkOrder::Date => self.next_by_date()?,
+            WalkOrder::Topo => {
+                if self.topo_ordered.is_none() {
+                    self.load_for_topo()?;
+                }
+                self.topo_ordered.as_mut().and_then(Vec::pop)
+            }
+        };
+
+        if next.is_some() {
+            self.yielded += 1;
+        }
+        Ok(next)
+    }
+}
+
+impl Iterator for CommitWalk<'_> {
+    type Item = Result<([u8; 20], CommitObject)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commit().transpose()
+    }
+}
+
+pub fn commit_time(commit: &CommitObject) -> i64 {
+    commit
+        .header("committer")
+        .and_then(|committer| Ident::parse(&committer).ok())
+        .map(|committer| committer.timestamp)
+        .unwrap_or(0)
+}
@@ -0,0 +1,143 @@
+/// Matches `text` against a gitignore-style glob `pattern`.
+///
+/// With `pathname` set, `*` and `?` never match `/`, while `**` surrounded by
+/// slashes (or at either end of the pattern) matches any number of directories.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    match_bytes(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+fn match_bytes(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let double = p + 1 < pattern.len() && pattern[p + 1] == b'*';
+
+                if double && pathname {
+                    let at_start = p == 0 || pattern[p - 1] == b'/';
+                    let mut rest = p + 2;
+                    while rest < pattern.len() && pattern[rest] == b'*' {
+                        rest += 1;
+                    }
+                    let at_end = rest == pattern.len() || pattern[rest] == b'/';
+
+                    if at_start && at_end {
+                        if rest == pattern.len() {
+                            return true;
+                        }
+                        // "**/" may match zero or more leading directories.
+                        let after_slash = &pattern[rest + 1..];
+                        if match_bytes(after_slash, &text[t..], pathname) {
+                            return true;
+                        }
+                        return (t..text.len())
+                            .filter(|&i| text[i] == b'/')
+                            .any(|i| match_bytes(after_slash, &text[i + 1..], pathname));
+                    }
+                }
+
+                let mut rest = p + 1;
+                while rest < pattern.len() && pattern[rest] == b'*' {
+                    rest += 1;
+                }
+                let crosses_dirs = double && !pathname;
+
+                for i in t..=text.len() {
+                    if match_bytes(&pattern[rest..], &text[i..], pathname) {
+                        return true;
+                    }
+                    if i < text.len() && text[i] == b'/' && pathname && !crosses_dirs {
+                        return false;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                match match_class(&pattern[p..], text[t]) {
+                    Some((true, consumed)) => {
+                        p += consumed;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+
+    t == text.len()
+}
+
+/// Returns whether `c` is in the bracket expression at the start of
+/// `pattern`, along with the number of pattern bytes consumed, or `None`
+/// when the bracket is never closed.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = i < pattern.len() && (pattern[i] == b'!' || pattern[i] == b'^');
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    while i < pattern.len() {
+        if pattern[i] == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        let mut lo = pattern[i];
+        if lo == b'\\' && i + 1 < pattern.len() {
+            i += 1;
+            lo = pattern[i];
+        }
+
+        if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            let hi = pattern[i + 2];
+            if lo <= c && c <= hi {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+
+    None
+}
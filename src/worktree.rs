@@ -0,0 +1,360 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Subcommand;
+use hex::encode;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Repository, changes::has_changes, hooks::run_post_checkout, ignore::Ignore,
+    merge::checkout_entries, messages::tr, refs::parse_hash, transport::short_ref_name,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum WorktreeCommands {
+    /// Check out a branch or commit in a new working tree at `path`
+    Add {
+        /// Create this branch at the commit and check it out
+        #[arg(short = 'b', conflicts_with = "detach")]
+        branch: Option<String>,
+        /// Check out the commit on a detached HEAD, even if it is a branch
+        #[arg(long)]
+        detach: bool,
+        path: PathBuf,
+        commit_ish: Option<String>,
+    },
+    /// List the main working tree and every linked one
+    List,
+    /// Delete a linked working tree
+    Remove {
+        /// Remove it even if it has changes or untracked files
+        #[arg(short, long)]
+        force: bool,
+        worktree: PathBuf,
+    },
+}
+
+/// A working tree of the repository, and what its HEAD points to.
+pub struct Worktree {
+    pub path: PathBuf,
+    /// Where a linked working tree keeps its own HEAD and index, under
+    /// `.mini-git/worktrees`; `None` for the main one.
+    pub admin_dir: Option<PathBuf>,
+    pub head: Option<[u8; 20]>,
+    pub branch: Option<String>,
+    pub bare: bool,
+}
+
+pub fn handle_worktree_command(command: WorktreeCommands, repository: &Repository) -> Result<()> {
+    match command {
+        WorktreeCommands::Add {
+            branch,
+            detach,
+            path,
+            commit_ish,
+        } => add_worktree(repository, &path, commit_ish, branch, detach),
+        WorktreeCommands::List => {
+            let worktrees = list_worktrees(repository)?;
+            let width = worktrees
+                .iter()
+                .map(|worktree| worktree.path.display().to_string().len())
+                .max()
+                .unwrap_or(0);
+
+            for worktree in worktrees {
+                let path = worktree.path.display().to_string();
+                if worktree.bare {
+                    println!("{path:<width$} (bare)");
+                    continue;
+                }
+                let head = encode(worktree.head.unwrap_or([0u8; 20]));
+                match &worktree.branch {
+                    Some(branch) => {
+                        println!("{path:<width$} {} [{}]", &head[..7], short_ref_name(branch))
+                    }
+                    None => println!("{path:<width$} {} (detached HEAD)", &head[..7]),
+                }
+            }
+            Ok(())
+        }
+        WorktreeCommands::Remove { force, worktree } => {
+            remove_worktree(repository, &worktree, force)
+        }
+    }
+}
+
+/// The main working tree, or the bare repository, followed by every linked
+/// working tree in name order.
+pub fn list_worktrees(repository: &Repository) -> Result<Vec<Worktree>> {
+    let main = Repository::at(repository.mini_git_dir.clone());
+    let mut worktrees = vec![read_worktree(
+        &main,
+        if main.is_bare() {
+            main.mini_git_dir.clone()
+        } else {
+            main.work_dir()
+        },
+        &main.mini_git_dir,
+        None,
+    )?];
+
+    let worktrees_dir = repository.mini_git_dir.join("worktrees");
+    if worktrees_dir.is_dir() {
+        let mut admin_dirs: Vec<PathBuf> = fs::read_dir(&worktrees_dir)
+            .with_context(|| format!("Failed to read {}", worktrees_dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        admin_dirs.sort();
+
+        for admin_dir in admin_dirs {
+            let Ok(gitdir) = fs::read_to_string(admin_dir.join("gitdir")) else {
+                continue;
+            };
+            let path = Path::new(gitdir.trim_end())
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            worktrees.push(read_worktree(
+                &main,
+                path,
+                &admin_dir,
+                Some(admin_dir.clone()),
+            )?);
+        }
+    }
+
+    Ok(worktrees)
+}
+
+fn read_worktree(
+    repository: &Repository,
+    path: PathBuf,
+    head_dir: &Path,
+    admin_dir: Option<PathBuf>,
+) -> Result<Worktree> {
+    let head = fs::read_to_string(head_dir.join("HEAD"))
+        .with_context(|| format!("Failed to read HEAD of worktree {}", path.display()))?;
+    let (head, branch) = match head.trim().strip_prefix("ref: ") {
+        Some(branch) => (repository.read_ref(branch)?, Some(branch.to_string())),
+        None => (Some(parse_hash(head.trim())?), None),
+    };
+
+    Ok(Worktree {
+        bare: admin_dir.is_none() && repository.is_bare(),
+        path,
+        admin_dir,
+        head,
+        branch,
+    })
+}
+
+/// The working tree other than the current one that has `branch` checked
+/// out, if any.
+pub fn checked_out_elsewhere(repository: &Repository, branch: &str) -> Result<Option<PathBuf>> {
+    checked_out(
+        repository,
+        branch,
+        Some(&fs::canonicalize(&repository.git_dir)?),
+    )
+}
+
+/// The working tree that has `branch` checked out, if any, leaving out the
+/// one whose HEAD lives in `except`.
+pub fn checked_out(
+    repository: &Repository,
+    branch: &str,
+    except: Option<&Path>,
+) -> Result<Option<PathBuf>> {
+    for worktree in list_worktrees(repository)? {
+        if worktree.bare || worktree.branch.as_deref() != Some(branch) {
+            continue;
+        }
+        let head_dir = worktree
+            .admin_dir
+            .clone()
+            .unwrap_or_else(|| repository.mini_git_dir.clone());
+        if except.is_none_or(|except| fs::canonicalize(&head_dir).is_ok_and(|dir| dir != except)) {
+            return Ok(Some(worktree.path));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Creates a working tree at `path` sharing this repository's objects and
+/// refs. Without a commit, a branch named after the directory is checked
+/// out, and created at HEAD if it does not exist yet.
+fn add_worktree(
+    repository: &Repository,
+    path: &Path,
+    commit_ish: Option<String>,
+    new_branch: Option<String>,
+    detach: bool,
+) -> Result<()> {
+    if path.exists() && fs::read_dir(path)?.next().is_some() {
+        return Err(anyhow!("fatal: '{}' already exists", path.display()));
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("fatal: invalid worktree path '{}'", path.display()))?;
+
+    let start = commit_ish.as_deref().unwrap_or("HEAD");
+    let (commit, branch, preparing) = match (new_branch, detach) {
+        (Some(branch), _) => {
+            if repository
+                .read_ref(&format!("refs/heads/{branch}"))?
+                .is_some()
+            {
+                return Err(anyhow!("fatal: a branch named '{}' already exists", branch));
+            }
+            let commit = repository.resolve_commitish(start)?;
+            repository.write_ref(&format!("refs/heads/{branch}"), &commit)?;
+            let preparing = format!("new branch '{branch}'");
+            (commit, Some(branch), preparing)
+        }
+        (None, true) => {
+            let commit = repository.resolve_commitish(start)?;
+            (
+                commit,
+                None,
+                format!("detached HEAD {}", &encode(commit)[..7]),
+            )
+        }
+        (None, false) => {
+            let branch = commit_ish.clone().unwrap_or_else(|| name.clone());
+            match repository.read_ref(&format!("refs/heads/{branch}"))? {
+                Some(commit) => {
+                    let preparing = format!("checking out '{branch}'");
+                    (commit, Some(branch), preparing)
+                }
+                None if commit_ish.is_none() => {
+                    let commit = repository.resolve_commitish("HEAD")?;
+                    repository.write_ref(&format!("refs/heads/{branch}"), &commit)?;
+                    let preparing = format!("new branch '{branch}'");
+                    (commit, Some(branch), preparing)
+                }
+                None => {
+                    let commit = repository.resolve_commitish(start)?;
+                    (
+                        commit,
+                        None,
+                        format!("detached HEAD {}", &encode(commit)[..7]),
+                    )
+                }
+            }
+        }
+    };
+
+    if let Some(branch) = &branch
+        && let Some(other) = checked_out(repository, &format!("refs/heads/{branch}"), None)?
+    {
+        return Err(anyhow!(
+            "fatal: '{}' is already used by worktree at '{}'",
+            branch,
+            other.display()
+        ));
+    }
+
+    eprintln!("{}", tr!("Preparing worktree ({})", preparing));
+
+    fs::create_dir_all(path)
+        .with_context(|| format!("fatal: could not create directory '{}'", path.display()))?;
+    let path = fs::canonicalize(path)?;
+
+    let worktrees_dir = repository.mini_git_dir.join("worktrees");
+    let mut admin_dir = worktrees_dir.join(&name);
+    let mut suffix = 1;
+    while admin_dir.exists() {
+        admin_dir = worktrees_dir.join(format!("{name}{suffix}"));
+        suffix += 1;
+    }
+    fs::create_dir_all(&admin_dir)
+        .with_context(|| format!("Failed to create {}", admin_dir.display()))?;
+    let admin_dir = fs::canonicalize(admin_dir)?;
+
+    let head = match &branch {
+        Some(branch) => format!("ref: refs/heads/{branch}\n"),
+        None => format!("{}\n", encode(commit)),
+    };
+    fs::write(admin_dir.join("HEAD"), head).context("Failed to write HEAD")?;
+    fs::write(admin_dir.join("commondir"), "../..\n").context("Failed to write commondir")?;
+    fs::write(
+        admin_dir.join("gitdir"),
+        format!("{}\n", path.join(".mini-git").display()),
+    )
+    .context("Failed to write gitdir")?;
+    fs::write(
+        path.join(".mini-git"),
+        format!("gitdir: {}\n", admin_dir.display()),
+    )
+    .context("Failed to write .mini-git file")?;
+
+    let worktree = Repository::open(&path)?;
+    let tree = parse_hash(&worktree.read_commit(&commit)?.tree_hash()?)?;
+    checkout_entries(
+        &worktree,
+        &[],
+        &worktree.read_tree_recursive(&tree)?,
+        "checkout",
+    )?;
+
+    let message = worktree.read_commit(&commit)?.message();
+    eprintln!(
+        "{}",
+        tr!(
+            "HEAD is now at {} {}",
+            &encode(commit)[..7],
+            message.lines().next().unwrap_or_default()
+        )
+    );
+
+    run_post_checkout(&worktree, None, commit, true)
+}
+
+/// Deletes a linked working tree and its administrative files, refusing
+/// to lose changes or untracked files unless `force` is set.
+fn remove_worktree(repository: &Repository, target: &Path, force: bool) -> Result<()> {
+    let target_path = fs::canonicalize(target).ok();
+    let worktree = list_worktrees(repository)?
+        .into_iter()
+        .find(|worktree| {
+            Some(&worktree.path) == target_path.as_ref()
+                || worktree
+                    .admin_dir
+                    .as_ref()
+                    .is_some_and(|dir| dir.file_name() == Some(target.as_os_str()))
+        })
+        .ok_or_else(|| anyhow!("fatal: '{}' is not a working tree", target.display()))?;
+
+    let Some(admin_dir) = &worktree.admin_dir else {
+        return Err(anyhow!(
+            "fatal: '{}' is a main working tree",
+            target.display()
+        ));
+    };
+
+    if worktree.path.is_dir() {
+        if !force {
+            let linked = Repository::open(&worktree.path)?;
+            let mut ignore = Ignore::load(&linked)?;
+            if has_changes(&linked)? || !linked.untracked_files(Some(&mut ignore))?.is_empty() {
+                return Err(anyhow!(
+                    "fatal: '{}' contains modified or untracked files, use --force to delete it",
+                    target.display()
+                ));
+            }
+        }
+        fs::remove_dir_all(&worktree.path)
+            .with_context(|| format!("Failed to delete '{}'", worktree.path.display()))?;
+    }
+    fs::remove_dir_all(admin_dir)
+        .with_context(|| format!("Failed to delete '{}'", admin_dir.display()))?;
+
+    // Like git, drop the worktrees directory once the last one is gone.
+    let _ = fs::remove_dir(repository.mini_git_dir.join("worktrees"));
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::TestRepo;
+use std::fs;
+
+/// A repository whose HEAD is back at "base" after a commit that changed
+/// the third line of `f`, and the patch for that commit.
+fn patch_of_third_line() -> (TestRepo, String) {
+    let repo = TestRepo::new();
+    repo.commit_file("f", "1\n2\n3\n4\n5\n", "base");
+    repo.commit_file("f", "1\n2\nthree\n4\n5\n", "change");
+    let patch = repo.run(&["format-patch", "--stdout", "HEAD~1"]);
+    repo.write("change.patch", &patch);
+    repo.run(&["checkout", "HEAD~1"]);
+    (repo, patch)
+}
+
+#[test]
+fn three_way_apply_merges_when_hunks_do_not_apply() {
+    let (repo, _) = patch_of_third_line();
+    repo.write("f", "one\n2\n3\n4\n5\n");
+    repo.run(&["update-index", "--add", "f"]);
+    assert!(!repo.output(&["apply", "change.patch"]).status.success());
+
+    let output = repo.output(&["apply", "--3way", "change.patch"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Applied patch to 'f' cleanly."));
+    assert_eq!(
+        fs::read_to_string(repo.dir.join("f")).unwrap(),
+        "one\n2\nthree\n4\n5\n"
+    );
+    assert_eq!(repo.run(&["diff"]), "");
+}
+
+#[test]
+fn three_way_apply_leaves_conflict_markers() {
+    let (repo, _) = patch_of_third_line();
+    repo.write("f", "1\n2\nTHREE\n4\n5\n");
+    repo.run(&["update-index", "--add", "f"]);
+
+    let output = repo.output(&["apply", "-3", "change.patch"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{stderr}");
+    assert!(stderr.contains("Applied patch to 'f' with conflicts."));
+    assert_eq!(
+        fs::read_to_string(repo.dir.join("f")).unwrap(),
+        "1\n2\n<<<<<<< ours\nTHREE\n=======\nthree\n>>>>>>> theirs\n4\n5\n"
+    );
+}
+
+#[test]
+fn three_way_apply_needs_the_preimage_blob() {
+    let (repo, patch) = patch_of_third_line();
+    let patch: String = patch
+        .lines()
+        .map(|line| {
+            if line.starts_with("index ") {
+                "index 1234567..7654321 100644\n".to_string()
+            } else {
+                format!("{line}\n")
+            }
+        })
+        .collect();
+    repo.write("change.patch", patch);
+    repo.write("f", "1\n2\nTHREE\n4\n5\n");
+    repo.run(&["update-index", "--add", "f"]);
+
+    let output = repo.output(&["apply", "--3way", "change.patch"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("lacks the necessary blob"), "{stderr}");
+    assert_eq!(
+        fs::read_to_string(repo.dir.join("f")).unwrap(),
+        "1\n2\nTHREE\n4\n5\n"
+    );
+}
+
+#[test]
+fn binary_files_round_trip_through_format_patch_and_am() {
+    let repo = TestRepo::new();
+    repo.commit_file("text", "base\n", "base");
+    let created: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    repo.commit_file("data.bin", &created, "add binary");
+    let changed: Vec<u8> = created.iter().rev().map(|b| b ^ 0x55).collect();
+    repo.commit_file("data.bin", &changed, "change binary");
+
+    let patches = repo.run(&["format-patch", "--stdout", "HEAD~2"]);
+    assert!(patches.contains("GIT binary patch\nliteral 1000\n"));
+    repo.write("series.mbox", &patches);
+    repo.run(&["checkout", "HEAD~2"]);
+    repo.run(&["am", "series.mbox"]);
+    assert_eq!(fs::read(repo.dir.join("data.bin")).unwrap(), changed);
+    assert_eq!(repo.run(&["diff", "main", "HEAD"]), "");
+
+    let last = repo.run(&["format-patch", "--stdout", "HEAD~1"]);
+    repo.write("last.patch", last);
+    repo.run(&["apply", "-R", "--index", "last.patch"]);
+    assert_eq!(fs::read(repo.dir.join("data.bin")).unwrap(), created);
+    let output = repo.output(&["apply", "-R", "last.patch"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("does not match the current contents")
+    );
+}
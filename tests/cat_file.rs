@@ -0,0 +1,25 @@
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn print_annotated_tag() {
+    let repo = TestRepo::new();
+    let commit = repo.commit_file("a", "1\n", "first");
+    repo.run(&["tag", "-a", "v1", "-m", "version one"]);
+
+    assert_eq!(repo.run(&["cat-file", "-t", "v1"]), "tag\n");
+    let printed = repo.run(&["cat-file", "-p", "v1"]);
+    let mut lines = printed.lines();
+    assert_eq!(lines.next(), Some(format!("object {commit}").as_str()));
+    assert_eq!(lines.next(), Some("type commit"));
+    assert_eq!(lines.next(), Some("tag v1"));
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with("tagger A U Thor <author@example.com> ")
+    );
+    assert_eq!(lines.next(), Some(""));
+    assert_eq!(lines.next(), Some("version one"));
+}
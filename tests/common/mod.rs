@@ -0,0 +1,97 @@
+//! Runs the `mini-git` binary in throwaway repositories.
+
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{Command, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT_REPO: AtomicUsize = AtomicUsize::new(0);
+
+/// A repository in its own temporary directory, with an identity
+/// configured, removed again when dropped.
+pub struct TestRepo {
+    pub dir: PathBuf,
+}
+
+impl TestRepo {
+    pub fn new() -> Self {
+        let dir = env::temp_dir().join(format!(
+            "mini-git-test-{}-{}",
+            std::process::id(),
+            NEXT_REPO.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let repo = TestRepo { dir };
+        repo.run(&["init"]);
+        repo.run(&["config", "user.name", "A U Thor"]);
+        repo.run(&["config", "user.email", "author@example.com"]);
+        repo
+    }
+
+    /// Runs `mini-git` with `args` in the repository, whatever its outcome.
+    pub fn output(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_mini-git"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .env("LANG", "C")
+            .env_remove("LANGUAGE")
+            .env_remove("LC_ALL")
+            .env_remove("LC_MESSAGES")
+            .env_remove("GIT_INDEX_FILE")
+            .output()
+            .unwrap()
+    }
+
+    /// Runs `mini-git` with `args`, failing the test unless it succeeds,
+    /// and returns what it printed.
+    pub fn run(&self, args: &[&str]) -> String {
+        let output = self.output(args);
+        assert!(
+            output.status.success(),
+            "mini-git {} failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    pub fn write(&self, path: &str, content: impl AsRef<[u8]>) {
+        let file = self.dir.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(file, content).unwrap();
+    }
+
+    /// Writes `path`, stages it and commits it with `message`, returning
+    /// the new commit's id.
+    pub fn commit_file(&self, path: &str, content: impl AsRef<[u8]>, message: &str) -> String {
+        self.write(path, content);
+        self.run(&["update-index", "--add", path]);
+        self.run(&["commit", "-m", message]);
+        self.head()
+    }
+
+    /// The id of the commit HEAD is at.
+    pub fn head(&self) -> String {
+        self.run(&["log", "-n", "1"])
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("commit "))
+            .unwrap()
+            .to_string()
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::TestRepo;
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+fn set_mtime(path: &Path, ago: u64) {
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(ago))
+        .unwrap();
+}
+
+/// A repository with `f` in the index, behind a clean filter that leaves a
+/// file named `cleaned` behind whenever it runs.
+fn repo_with_counted_file() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write(".gitattributes", "f filter=count\n");
+    let cleaned = repo.dir.join("cleaned");
+    repo.run(&[
+        "config",
+        "filter.count.clean",
+        &format!("touch '{}'; cat", cleaned.display()),
+    ]);
+    repo.write("f", "content\n");
+    set_mtime(&repo.dir.join("f"), 10);
+    repo.run(&["update-index", "--add", "f"]);
+    let _ = fs::remove_file(&cleaned);
+    repo
+}
+
+#[test]
+fn diff_trusts_unchanged_stat_data() {
+    let repo = repo_with_counted_file();
+    assert_eq!(repo.run(&["diff"]), "");
+    assert!(!repo.dir.join("cleaned").exists());
+
+    repo.write("f", "changed\nagain\n");
+    assert_eq!(repo.run(&["diff", "--name-only"]), "f\n");
+    assert!(repo.dir.join("cleaned").exists());
+}
+
+#[test]
+fn diff_rehashes_racily_clean_entries() {
+    let repo = repo_with_counted_file();
+    set_mtime(&repo.dir.join(".mini-git/index"), 20);
+    assert_eq!(repo.run(&["diff"]), "");
+    assert!(repo.dir.join("cleaned").exists());
+}
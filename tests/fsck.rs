@@ -0,0 +1,58 @@
+mod common;
+
+use common::TestRepo;
+use std::fs;
+
+#[test]
+fn fsck_finds_a_corrupt_loose_object() {
+    let repo = TestRepo::new();
+    let commit = repo.commit_file("a", "1\n", "first");
+    assert_eq!(repo.run(&["fsck"]), "");
+
+    let object = repo
+        .dir
+        .join(".mini-git/objects")
+        .join(&commit[..2])
+        .join(&commit[2..]);
+    fs::write(object, "not zlib").unwrap();
+    let output = repo.output(&["fsck"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains(&format!("error: object {commit} is corrupt"))
+    );
+}
+
+#[test]
+fn fsck_stats_report_chains_trees_paths_and_stray_files() {
+    let repo = TestRepo::new();
+    let lines: String = (0..100).map(|n| format!("line {n}\n")).collect();
+    repo.commit_file("top", &lines, "first");
+    repo.commit_file("top", format!("{lines}one more\n"), "second");
+    repo.commit_file("a/b/c/deep", "deep\n", "third");
+    repo.run(&["repack", "-a", "-d"]);
+    repo.write(".mini-git/objects/pack/tmp_pack_1", "");
+    repo.write(".mini-git/objects/pack/pack-0000.idx", "");
+
+    let stats = repo.run(&["fsck", "--stats"]);
+    let section = |heading: &str| -> Vec<String> {
+        stats
+            .split(&format!("{heading}\n"))
+            .nth(1)
+            .unwrap()
+            .lines()
+            .take_while(|line| line.starts_with(' '))
+            .map(|line| line.trim().to_string())
+            .collect()
+    };
+
+    let chains = section("Longest delta chains:");
+    assert!(!chains.is_empty());
+    assert!(chains.iter().all(|line| line.starts_with("1 ")));
+    assert!(section("Widest trees:")[0].starts_with("2 "));
+    assert_eq!(section("Deepest paths:"), ["4 a/b/c/deep", "1 top"]);
+    assert_eq!(
+        section("Stray pack files:"),
+        ["pack-0000.idx: no pack", "tmp_pack_1: temporary file"]
+    );
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::TestRepo;
+use mini_git::{CommitWalk, ExitStatus, Repository, WalkCursor, WalkOrder};
+
+#[test]
+fn history_can_be_walked_through_the_library() {
+    let repo = TestRepo::new();
+    let first = repo.commit_file("a", "1\n", "first");
+    let second = repo.commit_file("a", "2\n", "second");
+
+    let repository = Repository::open(&repo.dir).unwrap();
+    let head = repository.resolve_commitish("HEAD").unwrap();
+    let messages = |walk: CommitWalk| -> Vec<String> {
+        walk.map(|entry| entry.unwrap().1.message().trim().to_string())
+            .collect()
+    };
+
+    let walk = CommitWalk::new(&repository, &[head], WalkOrder::Topo).unwrap();
+    assert_eq!(messages(walk), ["second", "first"]);
+    let walk = CommitWalk::new(&repository, &[head], WalkOrder::Date).unwrap();
+    assert_eq!(messages(walk.reverse(true)), ["first", "second"]);
+
+    let mut walk = CommitWalk::new(&repository, &[head], WalkOrder::Date)
+        .unwrap()
+        .limit(Some(1));
+    assert_eq!(hex::encode(walk.next().unwrap().unwrap().0), second);
+    let cursor: WalkCursor = walk.cursor().unwrap().to_string().parse().unwrap();
+    let mut rest = CommitWalk::resume(&repository, &cursor, WalkOrder::Date).unwrap();
+    assert_eq!(hex::encode(rest.next().unwrap().unwrap().0), first);
+    assert!(rest.next().is_none());
+
+    assert_eq!(ExitStatus::Differences as u8, 1);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::TestRepo;
+
+/// A history of nine commits with two merges, all made within the same
+/// second or two, so that many commits share a date.
+fn merge_history() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.commit_file("base", "0\n", "base");
+    for round in 0..2 {
+        let side = format!("side{round}");
+        repo.run(&["branch", &side]);
+        repo.commit_file("main", format!("{round}\n"), &format!("main {round}"));
+        repo.run(&["switch", &side]);
+        repo.commit_file(&side, "1\n", &format!("{side} one"));
+        repo.commit_file(&side, "2\n", &format!("{side} two"));
+        repo.run(&["switch", "main"]);
+        repo.run(&["merge", &side]);
+    }
+    repo
+}
+
+fn commits(log: &str) -> Vec<String> {
+    log.lines()
+        .filter_map(|line| line.strip_prefix("commit "))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Pages through the history two commits at a time with cursors.
+fn paged(repo: &TestRepo, order: &[&str]) -> Vec<String> {
+    let mut seen = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut args = vec!["log", "-n", "2", "--show-cursor"];
+        args.extend(order);
+        if let Some(cursor) = &cursor {
+            args.extend(["--cursor", cursor]);
+        }
+        let output = repo.output(&args);
+        assert!(output.status.success());
+        seen.extend(commits(&String::from_utf8_lossy(&output.stdout)));
+
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        match stderr.lines().find_map(|line| line.strip_prefix("cursor ")) {
+            Some(next) => cursor = Some(next.to_string()),
+            None => return seen,
+        }
+    }
+}
+
+#[test]
+fn paging_by_date_yields_each_commit_once() {
+    let repo = merge_history();
+    let full = commits(&repo.run(&["log"]));
+    assert_eq!(full.len(), 9);
+    assert_eq!(paged(&repo, &[]), full);
+}
+
+#[test]
+fn paging_in_topological_order_yields_each_commit_once() {
+    let repo = merge_history();
+    let full = commits(&repo.run(&["log", "--topo-order"]));
+    assert_eq!(full.len(), 9);
+    assert_eq!(paged(&repo, &["--topo-order"]), full);
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::TestRepo;
+use std::process::Command;
+
+#[test]
+fn messages_come_from_the_catalog() {
+    let repo = TestRepo::new();
+    repo.commit_file("a", "1\n", "first");
+    repo.run(&["branch", "topic"]);
+    repo.write(
+        "de.po",
+        "msgid \"Deleted branch {} (was {}).\"\nmsgstr \"Branch {} entfernt (war {}).\"\n",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mini-git"))
+        .args(["branch", "-d", "topic"])
+        .current_dir(&repo.dir)
+        .env("HOME", &repo.dir)
+        .env("LANGUAGE", "de")
+        .env("MINI_GIT_LOCALEDIR", &repo.dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(
+        stdout.starts_with("Branch topic entfernt (war "),
+        "{stdout}"
+    );
+}
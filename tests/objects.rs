@@ -0,0 +1,75 @@
+mod common;
+
+use common::TestRepo;
+use std::{fs, path::PathBuf};
+
+fn object_path(repo: &TestRepo, hash: &str) -> PathBuf {
+    repo.dir
+        .join(".mini-git/objects")
+        .join(&hash[..2])
+        .join(&hash[2..])
+}
+
+/// Stores another blob's content under the name `path` will hash to, as a
+/// colliding object would be.
+fn plant_collision(repo: &TestRepo, path: &str) -> String {
+    repo.write("other", "something else entirely\n");
+    let other = repo.run(&["hash-object", "-w", "other"]);
+    let hash = repo.run(&["hash-object", path]);
+    let (other, hash) = (other.trim(), hash.trim().to_string());
+
+    let target = object_path(repo, &hash);
+    fs::create_dir_all(target.parent().unwrap()).unwrap();
+    fs::copy(object_path(repo, other), target).unwrap();
+    hash
+}
+
+#[test]
+fn writing_an_object_again_is_fine() {
+    let repo = TestRepo::new();
+    repo.write("a", "content\n");
+    let first = repo.run(&["hash-object", "-w", "a"]);
+    assert_eq!(repo.run(&["hash-object", "-w", "a"]), first);
+}
+
+#[test]
+fn streamed_object_is_checked_against_stored_one() {
+    let repo = TestRepo::new();
+    repo.write("a", "content\n");
+    let hash = plant_collision(&repo, "a");
+
+    let output = repo.output(&["hash-object", "-w", "a"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("SHA1 COLLISION FOUND WITH {hash}")),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn big_file_is_checked_against_stored_one() {
+    let repo = TestRepo::new();
+    repo.run(&["config", "core.bigFileThreshold", "16"]);
+    repo.write("big", "x".repeat(1000));
+    let hash = plant_collision(&repo, "big");
+
+    let output = repo.output(&["update-index", "--add", "big"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("SHA1 COLLISION FOUND WITH {hash}")),
+        "{stderr}"
+    );
+    assert!(
+        fs::read_dir(repo.dir.join(".mini-git/objects/pack"))
+            .unwrap()
+            .all(|entry| {
+                !entry
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("tmp_")
+            })
+    );
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::TestRepo;
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::{Command, Stdio},
+};
+
+/// A repository that signs with a fresh ssh key trusted for its committer,
+/// or `None` where ssh-keygen is not installed.
+fn signing_repo() -> Option<TestRepo> {
+    let repo = TestRepo::new();
+    let key = repo.dir.join("key");
+    let made = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key)
+        .status()
+        .ok()?;
+    assert!(made.success());
+
+    let public = std::fs::read_to_string(repo.dir.join("key.pub")).unwrap();
+    repo.write("allowed", format!("author@example.com {public}"));
+    repo.run(&["config", "gpg.format", "ssh"]);
+    repo.run(&["config", "user.signingKey", key.to_str().unwrap()]);
+    repo.run(&["config", "gpg.ssh.allowedSignersFile", "allowed"]);
+    Some(repo)
+}
+
+#[test]
+fn show_checks_the_signature_of_a_signed_tag() {
+    let Some(repo) = signing_repo() else {
+        return;
+    };
+    let commit = repo.commit_file("a", "1\n", "first");
+    repo.run(&["tag", "-s", "v1", "-m", "version one"]);
+
+    let shown = repo.run(&["show", "--show-signature", "v1"]);
+    let mut lines = shown.lines();
+    assert_eq!(lines.next(), Some("tag v1"));
+    assert!(lines.next().unwrap().starts_with("Good \"git\" signature"));
+    assert!(shown.contains("\nversion one\n"));
+    assert!(shown.contains(&format!("\ncommit {commit}\n")));
+
+    let shown = repo.run(&["show", "v1"]);
+    assert!(!shown.contains("Good \"git\" signature"));
+}
+
+#[test]
+fn log_checks_the_signatures_of_tags_on_each_commit() {
+    let Some(repo) = signing_repo() else {
+        return;
+    };
+    repo.commit_file("a", "1\n", "first");
+    repo.run(&["tag", "-s", "v1", "-m", "version one"]);
+    repo.commit_file("a", "2\n", "second");
+
+    let log = repo.run(&["log", "--show-signature"]);
+    let tag = log.find("tag 'v1'\nGood \"git\" signature").unwrap();
+    assert!(log.find("    second").unwrap() < tag);
+    assert!(tag < log.find("    first").unwrap());
+    assert_eq!(log.matches("tag 'v1'").count(), 1);
+}
+
+#[test]
+fn show_stops_quietly_when_the_reader_goes_away() {
+    let repo = TestRepo::new();
+    let lines: String = (0..100_000).map(|n| format!("{n}\n")).collect();
+    repo.commit_file("big", lines, "big");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mini-git"))
+        .arg("show")
+        .current_dir(&repo.dir)
+        .env("HOME", &repo.dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut first)
+        .unwrap();
+    assert!(first.starts_with("commit "));
+
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(child.wait().unwrap().success(), "{stderr}");
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn leaving_detached_head_with_annotated_tag() {
+    let repo = TestRepo::new();
+    repo.commit_file("a", "1\n", "first");
+    repo.commit_file("a", "2\n", "second");
+    repo.run(&["tag", "-a", "v1", "-m", "version one"]);
+
+    repo.run(&["checkout", "HEAD~1"]);
+    let orphan = repo.commit_file("b", "detached\n", "on detached HEAD");
+
+    let output = repo.output(&["switch", "main"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Warning: you are leaving 1 commit behind"));
+    assert!(stderr.contains(&orphan[..7]));
+}
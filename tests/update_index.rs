@@ -0,0 +1,52 @@
+mod common;
+
+use common::TestRepo;
+
+/// The paths in the index, one per line.
+fn indexed_paths(repo: &TestRepo) -> String {
+    repo.run(&["ls-files", "--stage"])
+        .lines()
+        .map(|line| format!("{}\n", line.splitn(3, ' ').nth(2).unwrap()))
+        .collect()
+}
+
+#[test]
+fn update_index_takes_paths_from_a_file() {
+    let repo = TestRepo::new();
+    repo.write("a", "a\n");
+    repo.write("with space", "b\n");
+    repo.write("dir/c", "c\n");
+    repo.write("list", "a\0with space\0dir/c\0");
+
+    repo.run(&[
+        "update-index",
+        "--add",
+        "--pathspec-from-file",
+        "list",
+        "--pathspec-file-nul",
+    ]);
+    assert_eq!(indexed_paths(&repo), "a\ndir/c\nwith space\n");
+
+    let output = repo.output(&["update-index", "--pathspec-from-file", "list", "a"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn update_index_needs_add_for_new_files() {
+    let repo = TestRepo::new();
+    repo.commit_file("a", "1\n", "first");
+    repo.write("a", "2\n");
+    repo.write("b", "new\n");
+
+    let output = repo.output(&["update-index", "a", "b"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("b: cannot add to the index - missing --add option?")
+    );
+    assert_eq!(repo.run(&["diff", "--name-only"]), "a\n");
+
+    repo.run(&["update-index", "a"]);
+    assert_eq!(repo.run(&["diff", "--name-only"]), "");
+    assert_eq!(indexed_paths(&repo), "a\n");
+}